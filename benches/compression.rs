@@ -1,50 +1,84 @@
 use criterion::{Criterion, Throughput, criterion_group, criterion_main};
-use docs_rs::storage::{CompressionAlgorithm, compress, decompress};
+use docs_rs::storage::{
+    CompressionAlgorithm, CompressionDictionary, compress, compress_with_dictionary, decompress,
+    decompress_with_dictionary,
+};
 use std::hint::black_box;
 
+const MAX_SIZE: usize = 5 * 1024 * 1024;
+
 pub fn regex_capture_matches(c: &mut Criterion) {
     // this isn't a great benchmark because it only tests on one file
     // ideally we would build a whole crate and compress each file, taking the average
     let html = std::fs::read_to_string("benches/struct.CaptureMatches.html").unwrap();
     let html_slice = html.as_bytes();
 
+    // The dictionary is trained on parts of the same file, so its numbers are optimistic.
+    // Dictionaries from `cratesfyi storage train-compression-dictionary` are trained on the
+    // HTML of many crates.
+    let samples: Vec<&[u8]> = html_slice.chunks(1024).collect();
+    let dictionary = CompressionDictionary::train(&samples, 16 * 1024).unwrap();
+
+    let compressed = |alg| compress(html_slice, alg).unwrap();
+    let zstd = compressed(CompressionAlgorithm::Zstd);
+    let bzip2 = compressed(CompressionAlgorithm::Bzip2);
+    let gzip = compressed(CompressionAlgorithm::Gzip);
+    let brotli = compressed(CompressionAlgorithm::Brotli);
+    let zstd_dictionary = compress_with_dictionary(html_slice, &dictionary).unwrap();
+
+    for (alg, compressed) in [
+        ("zstd", &zstd),
+        ("bzip2", &bzip2),
+        ("gzip", &gzip),
+        ("brotli", &brotli),
+        ("zstd dictionary", &zstd_dictionary),
+    ] {
+        println!(
+            "{alg}: {} bytes compressed to {} bytes",
+            html_slice.len(),
+            compressed.len()
+        );
+    }
+
     c.benchmark_group("regex html")
         .throughput(Throughput::Bytes(html_slice.len() as u64))
         .bench_function("compress zstd", |b| {
             b.iter(|| compress(black_box(html_slice), CompressionAlgorithm::Zstd));
         })
         .bench_function("decompress zstd", |b| {
-            b.iter(|| {
-                decompress(
-                    black_box(html_slice),
-                    CompressionAlgorithm::Zstd,
-                    5 * 1024 * 1024,
-                )
-            });
+            b.iter(|| decompress(black_box(&zstd[..]), CompressionAlgorithm::Zstd, MAX_SIZE));
         })
         .bench_function("compress bzip2", |b| {
             b.iter(|| compress(black_box(html_slice), CompressionAlgorithm::Bzip2));
         })
         .bench_function("decompress bzip2", |b| {
-            b.iter(|| {
-                decompress(
-                    black_box(html_slice),
-                    CompressionAlgorithm::Bzip2,
-                    5 * 1024 * 1024,
-                )
-            });
+            b.iter(|| decompress(black_box(&bzip2[..]), CompressionAlgorithm::Bzip2, MAX_SIZE));
         })
         .bench_function("compress gzip", |b| {
             b.iter(|| compress(black_box(html_slice), CompressionAlgorithm::Gzip));
         })
         .bench_function("decompress gzip", |b| {
+            b.iter(|| decompress(black_box(&gzip[..]), CompressionAlgorithm::Gzip, MAX_SIZE));
+        })
+        .bench_function("compress brotli", |b| {
+            b.iter(|| compress(black_box(html_slice), CompressionAlgorithm::Brotli));
+        })
+        .bench_function("decompress brotli", |b| {
             b.iter(|| {
                 decompress(
-                    black_box(html_slice),
-                    CompressionAlgorithm::Gzip,
-                    5 * 1024 * 1024,
+                    black_box(&brotli[..]),
+                    CompressionAlgorithm::Brotli,
+                    MAX_SIZE,
                 )
             });
+        })
+        .bench_function("compress zstd dictionary", |b| {
+            b.iter(|| compress_with_dictionary(black_box(html_slice), &dictionary));
+        })
+        .bench_function("decompress zstd dictionary", |b| {
+            b.iter(|| {
+                decompress_with_dictionary(black_box(&zstd_dictionary[..]), &dictionary, MAX_SIZE)
+            });
         });
 }
 
@@ -21,13 +21,13 @@
 //!
 //! // Next, learn what arguments we need to pass to `cargo`.
 //! let targets = metadata.targets(/* include_default_targets: */ true);
-//! let mut cargo_args = metadata.cargo_args(&[], &[]);
+//! let mut cargo_args = metadata.cargo_args(targets.default_target, &[], &[]);
 //! cargo_args.push(targets.default_target.into());
 //!
 //! // Now, set up the `Command`
 //! let mut cmd = Command::new("cargo");
 //! cmd.args(cargo_args);
-//! for (key, value) in metadata.environment_variables() {
+//! for (key, value) in metadata.environment_variables(targets.default_target) {
 //!     cmd.env(key, value);
 //! }
 //!
@@ -41,9 +41,9 @@ use std::collections::{HashMap, HashSet};
 use std::io;
 use std::path::Path;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use toml::Value;
+use toml::{value::Table, Value};
 
 /// The target that `metadata` is being built for.
 ///
@@ -78,6 +78,229 @@ pub enum MetadataError {
     Parse(#[from] toml::de::Error),
 }
 
+/// The keys docs.rs reads from the `[package.metadata.docs.rs]` table.
+const KNOWN_KEYS: &[&str] = &[
+    "features",
+    "all-features",
+    "no-default-features",
+    "default-target",
+    "targets",
+    "additional-targets",
+    "rustc-args",
+    "rustdoc-args",
+    "cargo-args",
+    "env",
+    "cfgs",
+    "target",
+];
+
+/// The keys docs.rs reads from the `[package.metadata.docs.rs.target.<triple>]` tables.
+const KNOWN_TARGET_KEYS: &[&str] = &[
+    "features",
+    "all-features",
+    "no-default-features",
+    "rustc-args",
+    "rustdoc-args",
+    "cargo-args",
+    "env",
+];
+
+/// Environment variables crates can't set with `env`, because they change how docs.rs
+/// runs the build instead of what the crate sees.
+///
+/// Variables starting with `CARGO_` or `RUSTUP_` are denied as well, they configure
+/// `cargo` and `rustup`.
+pub const DENIED_ENV_VARS: &[&str] = &[
+    "DOCS_RS",
+    "RUSTC",
+    "RUSTC_WRAPPER",
+    "RUSTC_WORKSPACE_WRAPPER",
+    "RUSTC_BOOTSTRAP",
+    "RUSTDOC",
+    "RUSTFLAGS",
+    "RUSTDOCFLAGS",
+    "PATH",
+    "HOME",
+    "LD_PRELOAD",
+    "LD_LIBRARY_PATH",
+];
+
+/// Whether crates can't set the environment variable `name` with `env`.
+///
+/// See [`DENIED_ENV_VARS`].
+pub fn is_denied_env_var(name: &str) -> bool {
+    name.is_empty()
+        || name.contains(['=', '\0'])
+        || name.starts_with("CARGO_")
+        || name.starts_with("RUSTUP_")
+        || DENIED_ENV_VARS.contains(&name)
+}
+
+/// Whether `cfg` is a valid argument for `--cfg`, either `name` or `name="value"`.
+fn is_valid_cfg(cfg: &str) -> bool {
+    fn is_ident(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    match cfg.split_once('=') {
+        None => is_ident(cfg),
+        Some((name, value)) => {
+            is_ident(name)
+                && value.len() >= 2
+                && value.starts_with('"')
+                && value.ends_with('"')
+                && !value[1..value.len() - 1].contains(['"', '\\'])
+        }
+    }
+}
+
+/// `cargo-args` that conflict with the arguments docs.rs passes itself, with the reason.
+///
+/// Short flags also match when the value is attached, like `-pfoo`.
+const UNSUPPORTED_CARGO_ARGS: &[(&str, &str)] = &[
+    ("--target", "use `targets` and `default-target` instead"),
+    ("--target-dir", "docs.rs chooses the target directory"),
+    (
+        "--manifest-path",
+        "docs.rs only documents the published package",
+    ),
+    ("--package", "docs.rs only documents the published package"),
+    ("-p", "docs.rs only documents the published package"),
+    (
+        "--workspace",
+        "docs.rs only documents the published package",
+    ),
+    ("--features", "use `features` instead"),
+    ("-F", "use `features` instead"),
+    ("--all-features", "use `all-features` instead"),
+    ("--no-default-features", "use `no-default-features` instead"),
+    (
+        "--jobs",
+        "docs.rs sets the number of jobs from the build limits",
+    ),
+    (
+        "-j",
+        "docs.rs sets the number of jobs from the build limits",
+    ),
+];
+
+/// A problem in the `[package.metadata.docs.rs]` table, found by [`Metadata::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// The table couldn't be parsed, for example because a key has the wrong type.
+    InvalidMetadata {
+        /// The error of the TOML parser.
+        message: String,
+    },
+    /// docs.rs doesn't know the key, and ignores it.
+    UnknownKey {
+        /// The unknown key.
+        key: String,
+    },
+    /// The target is not in the list of targets docs.rs can build.
+    UnknownTarget {
+        /// The key that contains the target, like `targets`.
+        key: &'static str,
+        /// The unknown target.
+        target: String,
+    },
+    /// Both `features` and `all-features` are set, so `features` has no effect.
+    ConflictingFeatures {
+        /// The target table that sets them, `None` for the settings of all targets.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+    /// An argument in `cargo-args` that docs.rs doesn't support.
+    UnsupportedCargoArg {
+        /// The unsupported argument.
+        arg: String,
+        /// Why docs.rs doesn't support the argument.
+        reason: &'static str,
+        /// The target table that sets the argument, `None` for the settings of all targets.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+    /// An environment variable in `env` that crates can't set, see [`DENIED_ENV_VARS`].
+    DeniedEnvVar {
+        /// The key that contains the variable, like `env`.
+        key: String,
+        /// The name of the variable.
+        name: String,
+    },
+    /// An entry in `cfgs` that isn't a valid `--cfg`.
+    InvalidCfg {
+        /// The invalid entry.
+        cfg: String,
+    },
+}
+
+impl Diagnostic {
+    /// Whether the build fails or doesn't work as expected because of the problem,
+    /// other problems are only warnings.
+    pub fn is_error(&self) -> bool {
+        match self {
+            Diagnostic::InvalidMetadata { .. }
+            | Diagnostic::UnknownTarget { .. }
+            | Diagnostic::UnsupportedCargoArg { .. }
+            | Diagnostic::DeniedEnvVar { .. }
+            | Diagnostic::InvalidCfg { .. } => true,
+            Diagnostic::UnknownKey { .. } | Diagnostic::ConflictingFeatures { .. } => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::InvalidMetadata { message } => {
+                write!(f, "invalid docs.rs metadata: {}", message.trim_end())
+            }
+            Diagnostic::UnknownKey { key } => {
+                write!(f, "unknown key `{key}`, docs.rs ignores it")
+            }
+            Diagnostic::UnknownTarget { key, target } => {
+                write!(f, "`{key}` contains `{target}`, which docs.rs can't build")
+            }
+            Diagnostic::ConflictingFeatures { target } => {
+                write!(
+                    f,
+                    "`features` has no effect, because `all-features` enables all features"
+                )?;
+                if let Some(target) = target {
+                    write!(f, " for `{target}`")?;
+                }
+                Ok(())
+            }
+            Diagnostic::UnsupportedCargoArg {
+                arg,
+                reason,
+                target,
+            } => {
+                write!(f, "`cargo-args` ")?;
+                if let Some(target) = target {
+                    write!(f, "for `{target}` ")?;
+                }
+                write!(f, "contains unsupported `{arg}`: {reason}")
+            }
+            Diagnostic::DeniedEnvVar { key, name } => {
+                write!(f, "`{key}` sets `{name}`, which docs.rs doesn't allow")
+            }
+            Diagnostic::InvalidCfg { cfg } => {
+                write!(
+                    f,
+                    "`cfgs` contains `{cfg}`, which is not `name` or `name=\"value\"`"
+                )
+            }
+        }
+    }
+}
+
 /// Metadata to set for custom builds.
 ///
 /// This metadata is read from `[package.metadata.docs.rs]` table in `Cargo.toml`.
@@ -97,6 +320,8 @@ pub enum MetadataError {
 /// additional-targets = [ "i686-apple-darwin" ]
 /// rustc-args = [ "--example-rustc-arg" ]
 /// rustdoc-args = [ "--example-rustdoc-arg" ]
+/// cfgs = [ "example_cfg", 'example_key="value"' ]
+/// env = { EXAMPLE_VARIABLE = "1" }
 /// ```
 ///
 /// You can define one or more fields in your `Cargo.toml`.
@@ -144,6 +369,44 @@ pub struct Metadata {
     /// List of additional targets to be generated. See [`BuildTargets`].
     #[serde(default)]
     additional_targets: Vec<String>,
+
+    /// Environment variables to set for the build, except the [`DENIED_ENV_VARS`].
+    #[serde(default)]
+    env: HashMap<String, String>,
+
+    /// `cfg`s to pass to `rustc` and `rustdoc`, in addition to `docsrs`.
+    #[serde(default)]
+    cfgs: Vec<String>,
+
+    /// Settings for single targets, from `[package.metadata.docs.rs.target.<triple>]`.
+    #[serde(default)]
+    target: HashMap<String, TargetMetadata>,
+}
+
+/// Settings that replace the ones of [`Metadata`] when building one target.
+///
+/// An example metadata:
+///
+/// ```text
+/// [package.metadata.docs.rs.target.x86_64-pc-windows-msvc]
+/// features = [ "windows" ]
+/// rustdoc-args = [ "--example-rustdoc-arg" ]
+/// env = { EXAMPLE_VARIABLE = "1" }
+/// ```
+///
+/// Unset fields fall back to the settings for all targets, the `env` is added to theirs.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TargetMetadata {
+    features: Option<Vec<String>>,
+    all_features: Option<bool>,
+    no_default_features: Option<bool>,
+    rustc_args: Option<Vec<String>>,
+    rustdoc_args: Option<Vec<String>>,
+    cargo_args: Option<Vec<String>>,
+    /// Environment variables to set when building the target.
+    #[serde(default)]
+    env: HashMap<String, String>,
 }
 
 /// The targets that should be built for a crate.
@@ -247,7 +510,7 @@ impl Metadata {
         }
     }
 
-    /// Return the arguments that should be passed to `cargo`.
+    /// Return the arguments that should be passed to `cargo` to build `target`.
     ///
     /// This will always include `rustdoc --lib`.
     /// This will never include `--target`.
@@ -259,20 +522,46 @@ impl Metadata {
     /// Note that this does not necessarily reproduce the HTML _output_ of docs.rs exactly.
     /// For example, the links may point somewhere different than they would on docs.rs.
     /// However, rustdoc will see exactly the same code as it would on docs.rs, even counting `cfg`s.
-    pub fn cargo_args(&self, additional_args: &[String], rustdoc_args: &[String]) -> Vec<String> {
+    pub fn cargo_args(
+        &self,
+        target: &str,
+        additional_args: &[String],
+        rustdoc_args: &[String],
+    ) -> Vec<String> {
+        let overrides = self.target.get(target);
+        let features = overrides
+            .and_then(|o| o.features.as_ref())
+            .or(self.features.as_ref());
+        let all_features = overrides
+            .and_then(|o| o.all_features)
+            .unwrap_or(self.all_features);
+        let no_default_features = overrides
+            .and_then(|o| o.no_default_features)
+            .unwrap_or(self.no_default_features);
+        let mut rustc_args = overrides
+            .and_then(|o| o.rustc_args.as_ref())
+            .unwrap_or(&self.rustc_args)
+            .clone();
+        let target_rustdoc_args = overrides
+            .and_then(|o| o.rustdoc_args.as_ref())
+            .unwrap_or(&self.rustdoc_args);
+        let target_cargo_args = overrides
+            .and_then(|o| o.cargo_args.as_ref())
+            .unwrap_or(&self.cargo_args);
+
         let mut cargo_args: Vec<String> =
             vec!["rustdoc".into(), "--lib".into(), "-Zrustdoc-map".into()];
 
-        if let Some(features) = &self.features {
+        if let Some(features) = features {
             cargo_args.push("--features".into());
             cargo_args.push(features.join(" "));
         }
 
-        if self.all_features {
+        if all_features {
             cargo_args.push("--all-features".into());
         }
 
-        if self.no_default_features {
+        if no_default_features {
             cargo_args.push("--no-default-features".into());
         }
 
@@ -281,13 +570,17 @@ impl Metadata {
         //
         // See https://github.com/rust-lang/docs.rs/issues/2389.
         let mut all_rustdoc_args = vec!["--cfg".into(), "docsrs".into()];
-        all_rustdoc_args.extend_from_slice(&self.rustdoc_args);
+        for cfg in &self.cfgs {
+            rustc_args.extend(["--cfg".into(), cfg.clone()]);
+            all_rustdoc_args.extend(["--cfg".into(), cfg.clone()]);
+        }
+        all_rustdoc_args.extend_from_slice(target_rustdoc_args);
         all_rustdoc_args.extend_from_slice(rustdoc_args);
 
         // Pass `RUSTFLAGS` and `RUSTDOCFLAGS` using `cargo --config`, which handles whitespace correctly.
-        if !self.rustc_args.is_empty() {
+        if !rustc_args.is_empty() {
             cargo_args.push("--config".into());
-            let rustflags = toml::Value::try_from(&self.rustc_args)
+            let rustflags = toml::Value::try_from(&rustc_args)
                 .expect("serializing a string should never fail")
                 .to_string();
             cargo_args.push(format!("build.rustflags={rustflags}"));
@@ -304,18 +597,204 @@ impl Metadata {
         cargo_args.push(format!("build.rustdocflags={rustdocflags}"));
 
         cargo_args.extend(additional_args.iter().map(|s| s.to_owned()));
-        cargo_args.extend_from_slice(&self.cargo_args);
+        cargo_args.extend_from_slice(target_cargo_args);
         cargo_args
     }
 
-    /// Return the environment variables that should be set when building this crate.
-    pub fn environment_variables(&self) -> HashMap<&'static str, String> {
+    /// Return the environment variables that should be set when building `target`.
+    ///
+    /// Variables from `env` that crates can't set are left out, see [`DENIED_ENV_VARS`].
+    pub fn environment_variables(&self, target: &str) -> HashMap<String, String> {
         let mut map = HashMap::new();
+        let target_env = self.target.get(target).map(|o| &o.env);
+        for (key, value) in self.env.iter().chain(target_env.into_iter().flatten()) {
+            if !is_denied_env_var(key) {
+                map.insert(key.clone(), value.clone());
+            }
+        }
         // For docs.rs detection from build scripts:
         // https://github.com/rust-lang/docs.rs/issues/147
-        map.insert("DOCS_RS", "1".into());
+        map.insert("DOCS_RS".into(), "1".into());
         map
     }
+
+    /// Return the `cfg`s that are set when building, `docsrs` first.
+    ///
+    /// `docsrs` is only passed to `rustdoc`, the other `cfg`s to `rustc` as well.
+    pub fn cfgs(&self) -> Vec<String> {
+        let mut cfgs = vec!["docsrs".to_owned()];
+        cfgs.extend(self.cfgs.iter().cloned());
+        cfgs
+    }
+
+    /// Check the `[package.metadata.docs.rs]` table of a manifest for problems that
+    /// [`Metadata::from_str`] silently accepts.
+    ///
+    /// `available_targets` are the targets docs.rs can build, usually the output of
+    /// `rustup target list`. When it is `None`, the targets are not checked.
+    ///
+    /// Only returns an `Err` when the manifest is not valid TOML.
+    ///
+    /// [`Metadata::from_str`]: std::str::FromStr
+    pub fn validate(
+        manifest: &str,
+        available_targets: Option<&[String]>,
+    ) -> Result<Vec<Diagnostic>, toml::de::Error> {
+        let manifest = manifest.parse::<Table>()?;
+        let Some(table) = docs_rs_table(&manifest) else {
+            return Ok(Vec::new());
+        };
+
+        let mut diagnostics: Vec<_> = table
+            .keys()
+            .filter(|key| !KNOWN_KEYS.contains(&key.as_str()))
+            .map(|key| Diagnostic::UnknownKey { key: key.clone() })
+            .collect();
+
+        let metadata: Metadata = match Value::Table(table.clone()).try_into() {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                diagnostics.push(Diagnostic::InvalidMetadata {
+                    message: err.message().to_owned(),
+                });
+                return Ok(diagnostics);
+            }
+        };
+
+        for (target, target_table) in table
+            .get("target")
+            .and_then(Value::as_table)
+            .into_iter()
+            .flatten()
+        {
+            let keys = target_table.as_table().into_iter().flat_map(|t| t.keys());
+            diagnostics.extend(
+                keys.filter(|key| !KNOWN_TARGET_KEYS.contains(&key.as_str()))
+                    .map(|key| Diagnostic::UnknownKey {
+                        key: format!("target.{target}.{key}"),
+                    }),
+            );
+        }
+
+        if let Some(available_targets) = available_targets {
+            let targets = metadata
+                .default_target
+                .iter()
+                .map(|target| ("default-target", target))
+                .chain(
+                    metadata
+                        .targets
+                        .iter()
+                        .flatten()
+                        .map(|target| ("targets", target)),
+                )
+                .chain(
+                    metadata
+                        .additional_targets
+                        .iter()
+                        .map(|target| ("additional-targets", target)),
+                )
+                .chain(metadata.target.keys().map(|target| ("target", target)));
+            for (key, target) in targets {
+                if !available_targets.contains(target) {
+                    diagnostics.push(Diagnostic::UnknownTarget {
+                        key,
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+
+        let mut env: Vec<_> = metadata
+            .env
+            .keys()
+            .map(|name| ("env".to_owned(), name))
+            .chain(metadata.target.iter().flat_map(|(target, overrides)| {
+                overrides
+                    .env
+                    .keys()
+                    .map(move |name| (format!("target.{target}.env"), name))
+            }))
+            .filter(|(_, name)| is_denied_env_var(name))
+            .collect();
+        env.sort();
+        diagnostics.extend(env.into_iter().map(|(key, name)| Diagnostic::DeniedEnvVar {
+            key,
+            name: name.clone(),
+        }));
+        diagnostics.extend(
+            metadata
+                .cfgs
+                .iter()
+                .filter(|cfg| !is_valid_cfg(cfg))
+                .map(|cfg| Diagnostic::InvalidCfg { cfg: cfg.clone() }),
+        );
+
+        if metadata.all_features && metadata.features.is_some() {
+            diagnostics.push(Diagnostic::ConflictingFeatures { target: None });
+        }
+        diagnostics.extend(unsupported_cargo_args(&metadata.cargo_args, None));
+
+        let mut target_overrides: Vec<_> = metadata.target.iter().collect();
+        target_overrides.sort_by_key(|(target, _)| *target);
+        for (target, overrides) in target_overrides {
+            let all_features = overrides.all_features.unwrap_or(metadata.all_features);
+            let has_features = overrides.features.is_some() || metadata.features.is_some();
+            // only report conflicts the target table causes, not the ones of all targets again.
+            if all_features
+                && has_features
+                && (overrides.all_features.is_some() || overrides.features.is_some())
+            {
+                diagnostics.push(Diagnostic::ConflictingFeatures {
+                    target: Some(target.clone()),
+                });
+            }
+            if let Some(cargo_args) = &overrides.cargo_args {
+                diagnostics.extend(unsupported_cargo_args(cargo_args, Some(target)));
+            }
+        }
+
+        Ok(diagnostics)
+    }
+}
+
+fn unsupported_cargo_args<'a>(
+    cargo_args: &'a [String],
+    target: Option<&'a String>,
+) -> impl Iterator<Item = Diagnostic> + 'a {
+    cargo_args.iter().filter_map(move |arg| {
+        let (_, reason) = UNSUPPORTED_CARGO_ARGS.iter().find(|(flag, _)| {
+            let is_short = flag.len() == 2;
+            arg == flag
+                || (is_short && arg.starts_with(flag))
+                || (!is_short && arg.starts_with(&format!("{flag}=")))
+        })?;
+        Some(Diagnostic::UnsupportedCargoArg {
+            arg: arg.clone(),
+            reason,
+            target: target.cloned(),
+        })
+    })
+}
+
+/// The `[package.metadata.docs.rs]` table of a manifest, also when the key is quoted.
+fn docs_rs_table(manifest: &Table) -> Option<&Table> {
+    fn table<'a>(manifest: &'a Table, table_name: &str) -> Option<&'a Table> {
+        match manifest.get(table_name) {
+            Some(Value::Table(table)) => Some(table),
+            _ => None,
+        }
+    }
+
+    let package_metadata = table(manifest, "package").and_then(|t| table(t, "metadata"));
+
+    let plain_table = package_metadata
+        .and_then(|t| table(t, "docs"))
+        .and_then(|t| table(t, "rs"));
+
+    let quoted_table = package_metadata.and_then(|t| table(t, "docs.rs"));
+
+    plain_table.or(quoted_table)
 }
 
 impl std::str::FromStr for Metadata {
@@ -323,45 +802,38 @@ impl std::str::FromStr for Metadata {
 
     /// Parse the given manifest as TOML.
     fn from_str(manifest: &str) -> Result<Metadata, Self::Err> {
-        use toml::value::Table;
-
         // the `Cargo.toml` is a full document, and:
         // > A TOML document is represented with the Table type which maps
         // > String to the Value enum.
         let manifest = manifest.parse::<Table>()?;
 
-        fn table<'a>(manifest: &'a Table, table_name: &str) -> Option<&'a Table> {
-            match manifest.get(table_name) {
-                Some(Value::Table(table)) => Some(table),
-                _ => None,
-            }
-        }
-
-        let package_metadata = table(&manifest, "package").and_then(|t| table(t, "metadata"));
-
-        let plain_table = package_metadata
-            .and_then(|t| table(t, "docs"))
-            .and_then(|t| table(t, "rs"));
-
-        let quoted_table = package_metadata.and_then(|t| table(t, "docs.rs"));
-
-        let mut metadata = if let Some(table) = plain_table {
-            Value::Table(table.clone()).try_into()?
-        } else if let Some(table) = quoted_table {
+        let mut metadata = if let Some(table) = docs_rs_table(&manifest) {
             Value::Table(table.clone()).try_into()?
         } else {
             Metadata::default()
         };
 
-        let proc_macro = table(&manifest, "lib")
-            .and_then(|table| table.get("proc-macro").or_else(|| table.get("proc_macro")))
-            .and_then(|val| val.as_bool());
+        let proc_macro = match manifest.get("lib") {
+            Some(Value::Table(table)) => table
+                .get("proc-macro")
+                .or_else(|| table.get("proc_macro"))
+                .and_then(|val| val.as_bool()),
+            _ => None,
+        };
         if let Some(proc_macro) = proc_macro {
             metadata.proc_macro = proc_macro;
         }
 
         metadata.rustdoc_args.push("-Z".into());
         metadata.rustdoc_args.push("unstable-options".into());
+        for rustdoc_args in metadata
+            .target
+            .values_mut()
+            .filter_map(|target| target.rustdoc_args.as_mut())
+        {
+            rustdoc_args.push("-Z".into());
+            rustdoc_args.push("unstable-options".into());
+        }
 
         Ok(metadata)
     }
@@ -523,6 +995,174 @@ mod test_parsing {
     }
 }
 
+#[cfg(test)]
+mod test_validation {
+    use super::*;
+
+    fn validate(metadata: &str) -> Vec<Diagnostic> {
+        let manifest =
+            format!("[package]\nname = \"test\"\n[package.metadata.docs.rs]\n{metadata}");
+        let targets = [
+            "x86_64-unknown-linux-gnu".into(),
+            "aarch64-apple-darwin".into(),
+        ];
+        Metadata::validate(&manifest, Some(&targets)).unwrap()
+    }
+
+    #[test]
+    fn valid_metadata() {
+        assert!(validate(
+            r#"
+            features = ["a"]
+            targets = ["x86_64-unknown-linux-gnu"]
+            cargo-args = ["-Zbuild-std", "--config", "foo=bar"]
+        "#
+        )
+        .is_empty());
+        assert!(Metadata::validate("[package]\nname = \"test\"", None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_toml() {
+        assert!(Metadata::validate("[package", None).is_err());
+    }
+
+    #[test]
+    fn finds_problems() {
+        let diagnostics = validate(
+            r#"
+            all-feature = true
+            features = ["a"]
+            all-features = true
+            default-target = "x86_64-unknown-linux-gnu"
+            targets = ["aarch64-apple-darwin", "x86_64-unknown-linux-gnux"]
+            additional-targets = ["wasm32-unknown-unknown"]
+            cargo-args = ["-Zbuild-std", "--target=x86_64-unknown-linux-gnu", "-pfoo", "--target-dir", "x"]
+        "#,
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::UnknownKey {
+                    key: "all-feature".into()
+                },
+                Diagnostic::UnknownTarget {
+                    key: "targets",
+                    target: "x86_64-unknown-linux-gnux".into()
+                },
+                Diagnostic::UnknownTarget {
+                    key: "additional-targets",
+                    target: "wasm32-unknown-unknown".into()
+                },
+                Diagnostic::ConflictingFeatures { target: None },
+                Diagnostic::UnsupportedCargoArg {
+                    arg: "--target=x86_64-unknown-linux-gnu".into(),
+                    reason: "use `targets` and `default-target` instead",
+                    target: None,
+                },
+                Diagnostic::UnsupportedCargoArg {
+                    arg: "-pfoo".into(),
+                    reason: "docs.rs only documents the published package",
+                    target: None,
+                },
+                Diagnostic::UnsupportedCargoArg {
+                    arg: "--target-dir".into(),
+                    reason: "docs.rs chooses the target directory",
+                    target: None,
+                },
+            ]
+        );
+        assert!(!diagnostics[0].is_error());
+        assert!(diagnostics[1].is_error());
+    }
+
+    #[test]
+    fn finds_denied_env_vars_and_invalid_cfgs() {
+        let diagnostics = validate(
+            r#"
+            cfgs = ["foo", 'bar="baz"', "1foo", "bar=baz", "foo bar"]
+            env = { FOO = "1", RUSTC_WRAPPER = "sccache", CARGO_TARGET_DIR = "x" }
+            [package.metadata.docs.rs.target.x86_64-unknown-linux-gnu]
+            env = { LD_PRELOAD = "x" }
+        "#,
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::DeniedEnvVar {
+                    key: "env".into(),
+                    name: "CARGO_TARGET_DIR".into()
+                },
+                Diagnostic::DeniedEnvVar {
+                    key: "env".into(),
+                    name: "RUSTC_WRAPPER".into()
+                },
+                Diagnostic::DeniedEnvVar {
+                    key: "target.x86_64-unknown-linux-gnu.env".into(),
+                    name: "LD_PRELOAD".into()
+                },
+                Diagnostic::InvalidCfg { cfg: "1foo".into() },
+                Diagnostic::InvalidCfg {
+                    cfg: "bar=baz".into()
+                },
+                Diagnostic::InvalidCfg {
+                    cfg: "foo bar".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_problems_in_target_tables() {
+        let diagnostics = validate(
+            r#"
+            features = ["a"]
+            [package.metadata.docs.rs.target.x86_64-unknown-linux-gnu]
+            all-features = true
+            env = { FOO = "1" }
+            target-dir = "x"
+            [package.metadata.docs.rs.target.x86_64-unknown-linux-gnux]
+            cargo-args = ["-j4"]
+        "#,
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::UnknownKey {
+                    key: "target.x86_64-unknown-linux-gnu.target-dir".into()
+                },
+                Diagnostic::UnknownTarget {
+                    key: "target",
+                    target: "x86_64-unknown-linux-gnux".into()
+                },
+                Diagnostic::ConflictingFeatures {
+                    target: Some("x86_64-unknown-linux-gnu".into())
+                },
+                Diagnostic::UnsupportedCargoArg {
+                    arg: "-j4".into(),
+                    reason: "docs.rs sets the number of jobs from the build limits",
+                    target: Some("x86_64-unknown-linux-gnux".into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_value() {
+        let diagnostics = validate("targets = \"x86_64-unknown-linux-gnu\"\nfoo = 1");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0], Diagnostic::UnknownKey { key: "foo".into() });
+        assert!(matches!(diagnostics[1], Diagnostic::InvalidMetadata { .. }));
+        assert!(
+            diagnostics[1].to_string().contains("invalid type"),
+            "{}",
+            diagnostics[1]
+        );
+    }
+}
+
 #[cfg(test)]
 mod test_targets {
     use super::*;
@@ -704,8 +1344,11 @@ mod test_calculations {
     #[test]
     fn test_defaults() {
         let metadata = Metadata::default();
-        assert_eq!(metadata.cargo_args(&[], &[]), default_cargo_args(&[]));
-        let env = metadata.environment_variables();
+        assert_eq!(
+            metadata.cargo_args(HOST_TARGET, &[], &[]),
+            default_cargo_args(&[])
+        );
+        let env = metadata.environment_variables(HOST_TARGET);
         assert_eq!(env.get("DOCS_RS").map(String::as_str), Some("1"));
         assert!(!env.contains_key("RUSTDOCFLAGS"));
         assert!(!env.contains_key("RUSTFLAGS"));
@@ -719,7 +1362,7 @@ mod test_calculations {
             ..Metadata::default()
         };
         let expected_args = default_cargo_args(&["--all-features".into()]);
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // no default features
         let metadata = Metadata {
@@ -727,7 +1370,7 @@ mod test_calculations {
             ..Metadata::default()
         };
         let expected_args = default_cargo_args(&["--no-default-features".into()]);
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // allow passing both even though it's nonsense; cargo will give an error anyway
         let metadata = Metadata {
@@ -737,7 +1380,7 @@ mod test_calculations {
         };
         let expected_args =
             default_cargo_args(&["--all-features".into(), "--no-default-features".into()]);
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // explicit empty vec
         let metadata = Metadata {
@@ -753,7 +1396,7 @@ mod test_calculations {
             "--config".into(),
             r#"build.rustdocflags=["--cfg", "docsrs"]"#.into(),
         ];
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // one feature
         let metadata = Metadata {
@@ -769,7 +1412,7 @@ mod test_calculations {
             "--config".into(),
             r#"build.rustdocflags=["--cfg", "docsrs"]"#.into(),
         ];
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // multiple features
         let metadata = Metadata {
@@ -785,7 +1428,7 @@ mod test_calculations {
             "--config".into(),
             r#"build.rustdocflags=["--cfg", "docsrs"]"#.into(),
         ];
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // rustdocflags
         let metadata = Metadata {
@@ -806,7 +1449,7 @@ mod test_calculations {
             "--config".into(),
             r#"build.rustdocflags=["--cfg", "docsrs", "-Z", "unstable-options", "--static-root-path", "/", "--cap-lints", "warn"]"#.into(),
         ];
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // rustcflags
         let metadata = Metadata {
@@ -826,7 +1469,7 @@ mod test_calculations {
             "--config".into(),
             "build.rustdocflags=[\"--cfg\", \"docsrs\"]".into(),
         ];
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
 
         // cargo flags
         let metadata = Metadata {
@@ -841,6 +1484,128 @@ mod test_calculations {
             "build.rustdocflags=[\"--cfg\", \"docsrs\"]".into(),
             "-Zbuild-std".into(),
         ];
-        assert_eq!(metadata.cargo_args(&[], &[]), expected_args);
+        assert_eq!(metadata.cargo_args(HOST_TARGET, &[], &[]), expected_args);
+    }
+
+    #[test]
+    fn test_target_overrides() {
+        use std::str::FromStr;
+
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata.docs.rs]
+            features = ["common"]
+            rustc-args = ["--cfg", "x"]
+
+            [package.metadata.docs.rs.target.x86_64-pc-windows-msvc]
+            features = ["windows"]
+            no-default-features = true
+            rustdoc-args = ["--windows-arg"]
+            cargo-args = ["-Zbuild-std"]
+            env = { WINDOWS = "1" }
+        "#,
+        )
+        .unwrap();
+
+        let windows = "x86_64-pc-windows-msvc";
+        assert_eq!(
+            metadata.cargo_args(windows, &[], &[]),
+            vec![
+                String::from("rustdoc"),
+                "--lib".into(),
+                "-Zrustdoc-map".into(),
+                "--features".into(),
+                "windows".into(),
+                "--no-default-features".into(),
+                "--config".into(),
+                "build.rustflags=[\"--cfg\", \"x\"]".into(),
+                "-Zhost-config".into(),
+                "-Ztarget-applies-to-host".into(),
+                "--config".into(),
+                "host.rustflags=[\"--cfg\", \"x\"]".into(),
+                "--config".into(),
+                r#"build.rustdocflags=["--cfg", "docsrs", "--windows-arg", "-Z", "unstable-options"]"#
+                    .into(),
+                "-Zbuild-std".into(),
+            ]
+        );
+        let env = metadata.environment_variables(windows);
+        assert_eq!(env.get("WINDOWS").map(String::as_str), Some("1"));
+        assert_eq!(env.get("DOCS_RS").map(String::as_str), Some("1"));
+
+        // other targets keep the settings for all targets.
+        let linux = "x86_64-unknown-linux-gnu";
+        assert_eq!(
+            metadata.cargo_args(linux, &[], &[]),
+            vec![
+                String::from("rustdoc"),
+                "--lib".into(),
+                "-Zrustdoc-map".into(),
+                "--features".into(),
+                "common".into(),
+                "--config".into(),
+                "build.rustflags=[\"--cfg\", \"x\"]".into(),
+                "-Zhost-config".into(),
+                "-Ztarget-applies-to-host".into(),
+                "--config".into(),
+                "host.rustflags=[\"--cfg\", \"x\"]".into(),
+                "--config".into(),
+                r#"build.rustdocflags=["--cfg", "docsrs", "-Z", "unstable-options"]"#.into(),
+            ]
+        );
+        assert!(!metadata
+            .environment_variables(linux)
+            .contains_key("WINDOWS"));
+    }
+
+    #[test]
+    fn test_env_and_cfgs() {
+        use std::str::FromStr;
+
+        let metadata = Metadata::from_str(
+            r#"
+            [package.metadata.docs.rs]
+            cfgs = ["foo", 'bar="baz"']
+            env = { FOO = "1", RUSTC_WRAPPER = "sccache", CARGO_HOME = "/tmp", DOCS_RS = "0" }
+
+            [package.metadata.docs.rs.target.x86_64-pc-windows-msvc]
+            env = { FOO = "2", RUSTFLAGS = "--cfg x" }
+        "#,
+        )
+        .unwrap();
+
+        let linux = "x86_64-unknown-linux-gnu";
+        assert_eq!(
+            metadata.cargo_args(linux, &[], &[]),
+            vec![
+                String::from("rustdoc"),
+                "--lib".into(),
+                "-Zrustdoc-map".into(),
+                "--config".into(),
+                r#"build.rustflags=["--cfg", "foo", "--cfg", 'bar="baz"']"#.into(),
+                "-Zhost-config".into(),
+                "-Ztarget-applies-to-host".into(),
+                "--config".into(),
+                r#"host.rustflags=["--cfg", "foo", "--cfg", 'bar="baz"']"#.into(),
+                "--config".into(),
+                r#"build.rustdocflags=["--cfg", "docsrs", "--cfg", "foo", "--cfg", 'bar="baz"', "-Z", "unstable-options"]"#
+                    .into(),
+            ]
+        );
+        assert_eq!(metadata.cfgs(), vec!["docsrs", "foo", r#"bar="baz""#]);
+
+        let env = |target| {
+            let mut env: Vec<_> = metadata.environment_variables(target).into_iter().collect();
+            env.sort();
+            env
+        };
+        assert_eq!(
+            env(linux),
+            vec![("DOCS_RS".into(), "1".into()), ("FOO".into(), "1".into())]
+        );
+        assert_eq!(
+            env("x86_64-pc-windows-msvc"),
+            vec![("DOCS_RS".into(), "1".into()), ("FOO".into(), "2".into())]
+        );
     }
 }
@@ -0,0 +1,5 @@
+DROP TABLE build_workers;
+
+ALTER TABLE queue
+    DROP COLUMN lease_expires_at,
+    DROP COLUMN worker;
@@ -0,0 +1,12 @@
+ALTER TABLE queue
+    ADD COLUMN worker TEXT,
+    ADD COLUMN lease_expires_at TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE build_workers (
+    name TEXT PRIMARY KEY,
+    first_seen TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_heartbeat TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    total_builds INTEGER NOT NULL DEFAULT 0,
+    failed_builds INTEGER NOT NULL DEFAULT 0,
+    expired_leases INTEGER NOT NULL DEFAULT 0
+);
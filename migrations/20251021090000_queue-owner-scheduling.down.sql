@@ -0,0 +1,2 @@
+DROP INDEX queue_claimable_idx;
+DROP FUNCTION queue_scheduling(INTEGER);
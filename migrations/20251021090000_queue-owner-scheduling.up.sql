@@ -0,0 +1,49 @@
+-- Where each claimable queue entry stands between the releases of the same owner.
+-- Releases of crates we don't know yet are grouped by their crate name.
+-- Entries whose attempts are used up stay in the queue, but must not hold others back.
+-- `max_attempts` is configured, so this is a function instead of a view.
+CREATE FUNCTION queue_scheduling(max_attempts INTEGER)
+RETURNS TABLE (id INTEGER, owner_key TEXT, owner_position BIGINT, owner_running BIGINT)
+LANGUAGE SQL STABLE
+AS $$
+  WITH keyed AS (
+    SELECT
+      queue.id,
+      queue.priority,
+      queue.attempt,
+      queue.worker,
+      COALESCE(crate_owner.login, queue.name) AS owner_key
+    FROM
+      queue
+      LEFT JOIN LATERAL (
+        SELECT MIN(owners.login) AS login
+        FROM
+          crates
+          INNER JOIN owner_rels ON owner_rels.cid = crates.id
+          INNER JOIN owners ON owners.id = owner_rels.oid
+        WHERE crates.name = queue.name
+      ) AS crate_owner ON TRUE
+    WHERE queue.attempt < max_attempts
+  ),
+  running AS (
+    SELECT keyed.owner_key, COUNT(*) AS running
+    FROM keyed
+    WHERE keyed.worker IS NOT NULL
+    GROUP BY keyed.owner_key
+  )
+  SELECT
+    keyed.id,
+    keyed.owner_key,
+    ROW_NUMBER() OVER (
+      PARTITION BY keyed.priority, keyed.owner_key
+      ORDER BY keyed.attempt, keyed.id
+    ) AS owner_position,
+    COALESCE(running.running, 0) AS owner_running
+  FROM
+    keyed
+    LEFT JOIN running ON running.owner_key = keyed.owner_key
+  WHERE keyed.worker IS NULL
+$$;
+
+-- the claimable entries, which the function and the claim query start from.
+CREATE INDEX queue_claimable_idx ON queue (priority, attempt, id) WHERE worker IS NULL;
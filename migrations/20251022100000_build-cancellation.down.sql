@@ -0,0 +1,17 @@
+ALTER TABLE queue DROP COLUMN cancel_requested;
+
+-- enum values can't be removed, so the type has to be re-created without it.
+UPDATE builds SET build_status = 'failure' WHERE build_status = 'cancelled';
+UPDATE release_build_status SET build_status = 'failure' WHERE build_status = 'cancelled';
+
+ALTER TYPE build_status RENAME TO build_status_old;
+CREATE TYPE build_status AS ENUM (
+    'in_progress',
+    'success',
+    'failure'
+);
+
+ALTER TABLE builds ALTER build_status TYPE build_status USING build_status::text::build_status;
+ALTER TABLE release_build_status ALTER build_status TYPE build_status USING build_status::text::build_status;
+
+DROP TYPE build_status_old;
@@ -0,0 +1,3 @@
+ALTER TYPE build_status ADD VALUE 'cancelled';
+
+ALTER TABLE queue ADD COLUMN cancel_requested BOOLEAN NOT NULL DEFAULT FALSE;
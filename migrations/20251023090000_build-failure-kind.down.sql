@@ -0,0 +1,5 @@
+ALTER TABLE queue DROP COLUMN next_attempt;
+
+ALTER TABLE builds DROP COLUMN failure_kind;
+
+DROP TYPE build_failure_kind;
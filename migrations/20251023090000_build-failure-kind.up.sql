@@ -0,0 +1,11 @@
+CREATE TYPE build_failure_kind AS ENUM (
+    'infrastructure',
+    'invalid_crate',
+    'rustdoc',
+    'out_of_memory',
+    'timeout'
+);
+
+ALTER TABLE builds ADD COLUMN failure_kind build_failure_kind;
+
+ALTER TABLE queue ADD COLUMN next_attempt TIMESTAMP WITH TIME ZONE;
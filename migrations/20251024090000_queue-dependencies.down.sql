@@ -0,0 +1,3 @@
+DROP TABLE queue_dependencies;
+
+ALTER TABLE queue DROP CONSTRAINT queue_pkey;
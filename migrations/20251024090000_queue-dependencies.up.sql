@@ -0,0 +1,11 @@
+ALTER TABLE queue ADD CONSTRAINT queue_pkey PRIMARY KEY (id);
+
+-- Queued releases that wait for dependencies which were published together with them.
+CREATE TABLE queue_dependencies (
+    queue_id INTEGER NOT NULL REFERENCES queue(id) ON DELETE CASCADE,
+    dependency_id INTEGER NOT NULL REFERENCES queue(id) ON DELETE CASCADE,
+    added TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (queue_id, dependency_id)
+);
+
+CREATE INDEX queue_dependencies_dependency_id_idx ON queue_dependencies (dependency_id);
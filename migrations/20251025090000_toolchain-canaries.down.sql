@@ -0,0 +1,2 @@
+DROP TABLE toolchain_canary_builds;
+DROP TABLE toolchain_canaries;
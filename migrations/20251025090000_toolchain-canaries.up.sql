@@ -0,0 +1,22 @@
+-- Test builds of a new toolchain, before it replaces the configured one.
+CREATE TABLE toolchain_canaries (
+    id SERIAL PRIMARY KEY,
+    toolchain TEXT NOT NULL,
+    rustc_version TEXT,
+    started TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    finished TIMESTAMP WITH TIME ZONE,
+    regression_rate DOUBLE PRECISION,
+    passed BOOLEAN
+);
+
+CREATE TABLE toolchain_canary_builds (
+    canary_id INTEGER NOT NULL REFERENCES toolchain_canaries(id) ON DELETE CASCADE,
+    rid INTEGER NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+    -- the build status of the release with the configured toolchain.
+    previous_status build_status NOT NULL,
+    successful BOOLEAN NOT NULL,
+    failure_kind build_failure_kind,
+    documentation_size BIGINT,
+    errors TEXT,
+    PRIMARY KEY (canary_id, rid)
+);
@@ -0,0 +1,2 @@
+DROP TABLE build_targets;
+ALTER TABLE builds DROP CONSTRAINT builds_pkey;
@@ -0,0 +1,15 @@
+-- `builds.id` comes from a sequence, but was never declared unique.
+ALTER TABLE builds ADD CONSTRAINT builds_pkey PRIMARY KEY (id);
+
+-- The outcome of every target of a build, the build log of a target is stored
+-- next to the other logs of the build.
+CREATE TABLE build_targets (
+    build_id INTEGER NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+    target TEXT NOT NULL,
+    build_status build_status NOT NULL,
+    failure_kind build_failure_kind,
+    build_started TIMESTAMP WITH TIME ZONE NOT NULL,
+    build_finished TIMESTAMP WITH TIME ZONE NOT NULL,
+    errors TEXT,
+    PRIMARY KEY (build_id, target)
+);
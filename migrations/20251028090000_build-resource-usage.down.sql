@@ -0,0 +1,11 @@
+ALTER TABLE builds
+    DROP COLUMN peak_memory,
+    DROP COLUMN cpu_time,
+    DROP COLUMN disk_usage,
+    DROP COLUMN network_bytes;
+
+ALTER TABLE build_targets
+    DROP COLUMN peak_memory,
+    DROP COLUMN cpu_time,
+    DROP COLUMN disk_usage,
+    DROP COLUMN network_bytes;
@@ -0,0 +1,13 @@
+-- Resources used by the sandbox of a build. `cpu_time` is in seconds, the other
+-- columns are in bytes. The columns on `builds` are aggregated over all targets.
+ALTER TABLE build_targets
+    ADD COLUMN peak_memory BIGINT,
+    ADD COLUMN cpu_time REAL,
+    ADD COLUMN disk_usage BIGINT,
+    ADD COLUMN network_bytes BIGINT;
+
+ALTER TABLE builds
+    ADD COLUMN peak_memory BIGINT,
+    ADD COLUMN cpu_time REAL,
+    ADD COLUMN disk_usage BIGINT,
+    ADD COLUMN network_bytes BIGINT;
@@ -0,0 +1,3 @@
+DROP TABLE sandbox_override_changes;
+DROP TABLE limit_requests;
+DROP TYPE limit_request_status;
@@ -0,0 +1,37 @@
+CREATE TYPE limit_request_status AS ENUM ('pending', 'approved', 'rejected');
+
+-- Crate owners ask for higher sandbox limits through crates.io,
+-- admins approve or reject the requests.
+CREATE TABLE limit_requests (
+    id SERIAL PRIMARY KEY,
+    crate_name VARCHAR NOT NULL,
+    requested_by VARCHAR NOT NULL,
+    justification TEXT NOT NULL,
+    max_memory_bytes BIGINT,
+    timeout_seconds INTEGER,
+    max_targets INTEGER,
+    status limit_request_status NOT NULL DEFAULT 'pending',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    reviewed_by VARCHAR,
+    reviewed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX limit_requests_crate_name_idx ON limit_requests (crate_name);
+
+-- Audit log of `sandbox_overrides`, all `new_*` columns are NULL when the
+-- overrides were removed.
+CREATE TABLE sandbox_override_changes (
+    id SERIAL PRIMARY KEY,
+    crate_name VARCHAR NOT NULL,
+    changed_by VARCHAR NOT NULL,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    limit_request_id INTEGER REFERENCES limit_requests(id) ON DELETE SET NULL,
+    old_max_memory_bytes BIGINT,
+    old_timeout_seconds INTEGER,
+    old_max_targets INTEGER,
+    new_max_memory_bytes BIGINT,
+    new_timeout_seconds INTEGER,
+    new_max_targets INTEGER
+);
+
+CREATE INDEX sandbox_override_changes_crate_name_idx ON sandbox_override_changes (crate_name);
@@ -0,0 +1,5 @@
+-- The environment variables per target and the `cfg`s a build used, so they
+-- can be shown next to the build log.
+ALTER TABLE builds
+    ADD COLUMN env JSONB,
+    ADD COLUMN cfgs TEXT[];
@@ -0,0 +1,11 @@
+-- Objects that `cratesfyi storage migrate` has already copied and verified, so an
+-- interrupted migration can continue where it stopped.
+CREATE TABLE storage_migration_checkpoints (
+    source TEXT NOT NULL,
+    target TEXT NOT NULL,
+    path TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    checksum TEXT NOT NULL,
+    migrated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (source, target, path)
+);
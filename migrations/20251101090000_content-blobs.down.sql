@@ -0,0 +1,2 @@
+DROP TABLE content_blob_references;
+DROP TABLE content_blobs;
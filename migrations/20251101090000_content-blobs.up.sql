@@ -0,0 +1,20 @@
+-- Files stored once per content and shared between archives, keyed by the SHA-256
+-- of their content. Their archive indexes point to them instead of a range in the archive.
+CREATE TABLE content_blobs (
+    hash TEXT PRIMARY KEY,
+    -- the size of the file, and of the stored blob, which is compressed.
+    size BIGINT NOT NULL,
+    compressed_size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- The archives using a content blob, for `count` of their files. A blob without
+-- references is deleted.
+CREATE TABLE content_blob_references (
+    archive_path TEXT NOT NULL,
+    hash TEXT NOT NULL REFERENCES content_blobs(hash),
+    count INTEGER NOT NULL,
+    PRIMARY KEY (archive_path, hash)
+);
+
+CREATE INDEX content_blob_references_hash_idx ON content_blob_references (hash);
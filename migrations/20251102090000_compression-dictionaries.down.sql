@@ -0,0 +1,2 @@
+ALTER TABLE compression_rels DROP COLUMN dictionary_id;
+DROP TABLE compression_dictionaries;
@@ -0,0 +1,14 @@
+-- zstd dictionaries for the `ZstdDictionary` compression algorithm, trained on
+-- rustdoc HTML. Every object compressed with one needs it to be decompressed, so
+-- they are never changed or deleted. `id` is the id zstd writes into each frame.
+CREATE TABLE compression_dictionaries (
+    id BIGINT PRIMARY KEY,
+    content BYTEA NOT NULL,
+    -- how many files the dictionary was trained on.
+    samples INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- the dictionary of a release compressed with `ZstdDictionary`.
+ALTER TABLE compression_rels
+    ADD COLUMN dictionary_id BIGINT REFERENCES compression_dictionaries(id);
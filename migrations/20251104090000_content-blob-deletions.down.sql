@@ -0,0 +1 @@
+ALTER TABLE content_blobs DROP COLUMN deleting_since;
@@ -0,0 +1,3 @@
+-- Set when the garbage collection decided to delete a content blob. The row is deleted
+-- after the stored object, and the blob can't be uploaded again until then.
+ALTER TABLE content_blobs ADD COLUMN deleting_since TIMESTAMPTZ;
@@ -0,0 +1,21 @@
+-- enum values can't be dropped, so the type is recreated without it.
+ALTER TYPE build_failure_kind RENAME TO build_failure_kind_old;
+CREATE TYPE build_failure_kind AS ENUM (
+    'infrastructure',
+    'invalid_crate',
+    'rustdoc',
+    'out_of_memory',
+    'timeout'
+);
+
+ALTER TABLE builds
+    ALTER COLUMN failure_kind TYPE build_failure_kind
+    USING replace(failure_kind::text, 'no_output', 'timeout')::build_failure_kind;
+ALTER TABLE build_targets
+    ALTER COLUMN failure_kind TYPE build_failure_kind
+    USING replace(failure_kind::text, 'no_output', 'timeout')::build_failure_kind;
+ALTER TABLE toolchain_canary_builds
+    ALTER COLUMN failure_kind TYPE build_failure_kind
+    USING replace(failure_kind::text, 'no_output', 'timeout')::build_failure_kind;
+
+DROP TYPE build_failure_kind_old;
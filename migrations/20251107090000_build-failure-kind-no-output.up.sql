@@ -0,0 +1 @@
+ALTER TYPE build_failure_kind ADD VALUE 'no_output';
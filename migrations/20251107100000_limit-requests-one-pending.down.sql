@@ -0,0 +1 @@
+DROP INDEX limit_requests_pending_crate_name_idx;
@@ -0,0 +1,10 @@
+-- a crate has at most one pending limit request, older duplicates are rejected.
+UPDATE limit_requests
+SET status = 'rejected'
+WHERE status = 'pending' AND id NOT IN (
+    SELECT MAX(id) FROM limit_requests WHERE status = 'pending' GROUP BY crate_name
+);
+
+CREATE UNIQUE INDEX limit_requests_pending_crate_name_idx
+    ON limit_requests (crate_name)
+    WHERE status = 'pending';
@@ -0,0 +1 @@
+DROP INDEX content_blob_references_archive_path_pattern_idx;
@@ -0,0 +1,3 @@
+-- Content blob references are removed by the prefix of their archive path.
+CREATE INDEX content_blob_references_archive_path_pattern_idx
+    ON content_blob_references (archive_path text_pattern_ops);
@@ -1,15 +1,20 @@
-use anyhow::{Context as _, Result, anyhow};
+use anyhow::{Context as _, Result, anyhow, bail};
 use clap::{Parser, Subcommand, ValueEnum};
 use docs_rs::{
-    Config, Context, Index, PackageKind, RustwideBuilder,
-    db::{self, CrateId, Overrides, ReleaseId, add_path_into_database, types::version::Version},
+    CompareCrates, Config, Context, Index, PackageKind, RustwideBuilder,
+    db::{
+        self, BuildId, CrateId, LimitRequest, Overrides, ReleaseId, add_path_into_database,
+        types::version::Version,
+    },
     start_background_metrics_webserver, start_web_server,
+    storage::{self, StorageKind},
     utils::{
         ConfigName, daemon::start_background_service_metric_collector, get_config,
         get_crate_pattern_and_priority, list_crate_priorities, queue_builder,
         remove_crate_priority, set_config, set_crate_priority,
     },
 };
+use docsrs_metadata::{Diagnostic, Metadata};
 use futures_util::StreamExt;
 use sentry::{
     TransactionContext, integrations::panic as sentry_panic,
@@ -174,6 +179,12 @@ enum CommandLine {
         #[command(subcommand)]
         subcommand: QueueSubcommand,
     },
+
+    /// Storage operations
+    Storage {
+        #[command(subcommand)]
+        subcommand: StorageSubcommand,
+    },
 }
 
 impl CommandLine {
@@ -228,6 +239,7 @@ impl CommandLine {
             }
             Self::Database { subcommand } => subcommand.handle_args(ctx)?,
             Self::Queue { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Storage { subcommand } => subcommand.handle_args(ctx)?,
         }
 
         Ok(())
@@ -255,6 +267,21 @@ enum QueueSubcommand {
         build_priority: i32,
     },
 
+    /// Remove a crate from the build queue that isn't being built yet
+    Remove {
+        /// Name of the queued crate
+        #[arg(name = "CRATE_NAME")]
+        crate_name: String,
+        /// Version of the queued crate
+        #[arg(name = "CRATE_VERSION")]
+        crate_version: Version,
+    },
+
+    /// Cancel all builds that are currently running.
+    ///
+    /// The build servers stop the builds when they renew their lease on the queue entry next time.
+    CancelRunning,
+
     /// Interactions with build queue priorities
     DefaultPriority {
         #[command(subcommand)]
@@ -291,6 +318,22 @@ impl QueueSubcommand {
                 ctx.config.registry_url.as_deref(),
             )?,
 
+            Self::Remove {
+                crate_name,
+                crate_version,
+            } => {
+                if ctx.build_queue.remove_crate(&crate_name, &crate_version)? {
+                    println!("Removed {crate_name} {crate_version} from the build queue");
+                } else {
+                    bail!("{crate_name} {crate_version} is not waiting in the build queue");
+                }
+            }
+
+            Self::CancelRunning => {
+                let count = ctx.build_queue.cancel_running_builds()?;
+                println!("Requested cancellation of {count} running builds");
+            }
+
             Self::GetLastSeenReference => {
                 if let Some(reference) = ctx.build_queue.last_seen_reference()? {
                     println!("Last seen reference: {reference}");
@@ -425,6 +468,70 @@ enum BuildSubcommand {
         toolchain_name: String,
     },
 
+    /// Try a new toolchain on the most popular crates, and switch to it
+    /// when only few of their builds regress
+    CanaryToolchain {
+        /// The toolchain to try, for example `nightly-2025-10-25`
+        toolchain_name: String,
+
+        /// Only report the regressions, never switch to the new toolchain
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Build crates with two toolchains, and write a report of how their builds differ
+    Compare {
+        /// The toolchain to compare against, for example `nightly-2025-10-25`
+        #[arg(long)]
+        toolchain_a: String,
+
+        /// The toolchain to compare, for example `nightly-2025-10-26`
+        #[arg(long)]
+        toolchain_b: String,
+
+        /// Comma separated crate names for their latest release, or `name@version`
+        #[arg(long, value_delimiter = ',', required_unless_present = "crates_query")]
+        crates: Vec<String>,
+
+        /// Compare the most downloaded crates with a name matching this SQL `LIKE` pattern
+        #[arg(long, conflicts_with = "crates")]
+        crates_query: Option<String>,
+
+        /// How many crates to compare with `--crates-query`
+        #[arg(long, default_value = "100", requires = "crates_query")]
+        limit: usize,
+
+        /// Directory to write `report.json` and `report.html` into
+        #[arg(long, default_value = "toolchain-compare")]
+        output: PathBuf,
+    },
+
+    /// Build a release again like one of its past builds, and compare the documentation
+    Reproduce {
+        /// Crate name
+        #[arg(name = "CRATE_NAME")]
+        crate_name: String,
+
+        /// Version of crate
+        #[arg(name = "CRATE_VERSION")]
+        crate_version: Version,
+
+        /// The build to reproduce
+        #[arg(long)]
+        build_id: i32,
+
+        /// Directory to write the documentation, build logs and `docs.diff` into
+        #[arg(long, default_value = "reproduce")]
+        output: PathBuf,
+    },
+
+    /// Check the docs.rs metadata in a `Cargo.toml` for problems
+    ValidateMetadata {
+        /// Path to the `Cargo.toml`, or the directory that contains it
+        #[arg(name = "MANIFEST")]
+        manifest: PathBuf,
+    },
+
     /// Locks the daemon, preventing it from building new crates
     Lock,
 
@@ -506,6 +613,85 @@ impl BuildSubcommand {
                 })?;
             }
 
+            Self::CanaryToolchain {
+                toolchain_name,
+                dry_run,
+            } => {
+                let report = rustwide_builder()?
+                    .run_toolchain_canary(&toolchain_name, !dry_run)
+                    .context("toolchain canary failed")?;
+                println!("{report}");
+            }
+
+            Self::Compare {
+                toolchain_a,
+                toolchain_b,
+                crates,
+                crates_query,
+                limit,
+                output,
+            } => {
+                let crates = match crates_query {
+                    Some(pattern) => CompareCrates::Query { pattern, limit },
+                    None => CompareCrates::List(crates),
+                };
+                let report = rustwide_builder()?
+                    .compare_toolchains(&toolchain_a, &toolchain_b, &crates)
+                    .context("toolchain comparison failed")?;
+                report
+                    .write_to(&output)
+                    .context("failed to write comparison report")?;
+                println!("{report}");
+                println!("report written to {}", output.display());
+            }
+
+            Self::Reproduce {
+                crate_name,
+                crate_version,
+                build_id,
+                output,
+            } => {
+                let report = rustwide_builder()?
+                    .reproduce_build(&crate_name, &crate_version, BuildId(build_id), &output)
+                    .context("reproducing the build failed")?;
+                println!("{report}");
+                println!("output written to {}", output.display());
+            }
+
+            Self::ValidateMetadata { manifest } => {
+                let manifest = if manifest.is_dir() {
+                    manifest.join("Cargo.toml")
+                } else {
+                    manifest
+                };
+                let manifest = std::fs::read_to_string(&manifest)
+                    .with_context(|| format!("failed to read {}", manifest.display()))?;
+
+                let available_targets = ctx.runtime.block_on(async {
+                    let mut conn = ctx.pool.get_async().await?;
+                    get_config::<Vec<String>>(&mut conn, ConfigName::AvailableTargets).await
+                })?;
+                if available_targets.is_none() {
+                    println!(
+                        "warning: targets are not checked, run `build update-toolchain` first"
+                    );
+                }
+
+                let diagnostics = Metadata::validate(&manifest, available_targets.as_deref())
+                    .context("failed to parse the manifest")?;
+                for diagnostic in &diagnostics {
+                    let severity = if diagnostic.is_error() {
+                        "error"
+                    } else {
+                        "warning"
+                    };
+                    println!("{severity}: {diagnostic}");
+                }
+                if diagnostics.iter().any(Diagnostic::is_error) {
+                    bail!("the docs.rs metadata is invalid");
+                }
+            }
+
             Self::Lock => ctx.build_queue.lock().context("Failed to lock")?,
             Self::Unlock => ctx.build_queue.unlock().context("Failed to unlock")?,
         }
@@ -694,6 +880,89 @@ impl DatabaseSubcommand {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum StorageSubcommand {
+    /// Copy every object from one storage backend to another, resuming a previous run
+    Migrate {
+        /// The backend to copy from: `database`, `s3` or `filesystem`
+        #[arg(long)]
+        from: StorageKind,
+        /// The backend to copy to: `database`, `s3` or `filesystem`
+        #[arg(long)]
+        to: StorageKind,
+        /// Only count the objects and bytes that would be copied
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(long)]
+        concurrency: Option<u8>,
+    },
+
+    /// Show how much storage the content blobs shared between archives save
+    ContentBlobReport,
+
+    /// Train a zstd dictionary on the HTML of random releases, for new uploads
+    TrainCompressionDictionary {
+        /// How many releases to sample files from
+        #[arg(long, default_value = "1000")]
+        releases: i64,
+        /// The maximum size of the dictionary, in bytes
+        #[arg(long, default_value = "112640")]
+        max_size: usize,
+    },
+}
+
+impl StorageSubcommand {
+    fn handle_args(self, ctx: Context) -> Result<()> {
+        match self {
+            Self::Migrate {
+                from,
+                to,
+                dry_run,
+                concurrency,
+            } => {
+                let summary = ctx.runtime.block_on(storage::migrate_storage(
+                    &ctx,
+                    from,
+                    to,
+                    dry_run,
+                    concurrency.map(Into::into),
+                ))?;
+
+                if dry_run {
+                    println!("objects that would be copied from {from} to {to}:");
+                } else {
+                    println!("objects copied from {from} to {to}:");
+                }
+                println!("objects: {}", summary.objects);
+                println!("bytes:   {}", summary.bytes);
+                println!("skipped: {} (copied by an earlier run)", summary.skipped);
+            }
+            Self::ContentBlobReport => {
+                let report = ctx
+                    .runtime
+                    .block_on(ctx.async_storage.content_blob_report())?;
+
+                println!("content blobs:  {}", report.blobs);
+                println!("files:          {}", report.files);
+                println!("stored bytes:   {}", report.stored_bytes);
+                println!("saved bytes:    {}", report.saved_bytes);
+            }
+            Self::TrainCompressionDictionary { releases, max_size } => {
+                let trained = ctx.runtime.block_on(
+                    ctx.async_storage
+                        .train_compression_dictionary(releases, max_size),
+                )?;
+
+                println!("dictionary id:  {}", trained.id);
+                println!("size:           {}", trained.size);
+                println!("samples:        {}", trained.samples);
+                println!("set DOCSRS_COMPRESSION_DICTIONARY=true to use it for new uploads");
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
 enum LimitsSubcommand {
     /// Get sandbox limit overrides for a crate
@@ -711,10 +980,40 @@ enum LimitsSubcommand {
         targets: Option<usize>,
         #[arg(long)]
         timeout: Option<usize>,
+        /// Who changes the overrides, for the audit log
+        #[arg(long)]
+        admin: String,
     },
 
     /// Remove sandbox limits overrides for a crate
-    Remove { crate_name: String },
+    Remove {
+        crate_name: String,
+        /// Who removes the overrides, for the audit log
+        #[arg(long)]
+        admin: String,
+    },
+
+    /// Show all changes of the sandbox limit overrides of a crate
+    History { crate_name: String },
+
+    /// List the limit requests of crate owners that wait for a review
+    Requests,
+
+    /// Approve a limit request and raise the overrides of its crate
+    Approve {
+        id: i32,
+        /// Who approves the request, for the audit log
+        #[arg(long)]
+        admin: String,
+    },
+
+    /// Reject a limit request
+    Reject {
+        id: i32,
+        /// Who rejects the request
+        #[arg(long)]
+        admin: String,
+    },
 }
 
 impl LimitsSubcommand {
@@ -739,6 +1038,7 @@ impl LimitsSubcommand {
                     memory,
                     targets,
                     timeout,
+                    admin,
                 } => {
                     let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
                     println!("previous sandbox limit overrides for {crate_name} = {overrides:?}");
@@ -748,15 +1048,51 @@ impl LimitsSubcommand {
                         timeout: timeout
                             .map(|timeout| std::time::Duration::from_secs(timeout as _)),
                     };
-                    Overrides::save(&mut conn, &crate_name, overrides).await?;
+                    Overrides::change(&mut conn, &crate_name, Some(overrides), &admin).await?;
                     let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
                     println!("new sandbox limit overrides for {crate_name} = {overrides:?}");
                 }
 
-                Self::Remove { crate_name } => {
+                Self::Remove { crate_name, admin } => {
                     let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
                     println!("previous overrides for {crate_name} = {overrides:?}");
-                    Overrides::remove(&mut conn, &crate_name).await?;
+                    Overrides::change(&mut conn, &crate_name, None, &admin).await?;
+                }
+
+                Self::History { crate_name } => {
+                    for change in Overrides::history(&mut conn, &crate_name).await? {
+                        print!("{} by {}", change.changed_at, change.changed_by);
+                        if let Some(id) = change.limit_request_id {
+                            print!(" (limit request {id})");
+                        }
+                        println!(": {:?} -> {:?}", change.old, change.new);
+                    }
+                }
+
+                Self::Requests => {
+                    for request in LimitRequest::pending(&mut conn).await? {
+                        println!(
+                            "#{} {} by {} at {}: {:?}\n    {}",
+                            request.id,
+                            request.crate_name,
+                            request.requested_by,
+                            request.created_at,
+                            request.limits,
+                            request.justification,
+                        );
+                    }
+                }
+
+                Self::Approve { id, admin } => {
+                    let overrides = LimitRequest::approve(&mut conn, id, &admin).await?;
+                    println!(
+                        "approved limit request {id}, new sandbox limit overrides = {overrides:?}"
+                    );
+                }
+
+                Self::Reject { id, admin } => {
+                    LimitRequest::reject(&mut conn, id, &admin).await?;
+                    println!("rejected limit request {id}");
                 }
             }
             Ok(())
@@ -2,22 +2,30 @@ use crate::{
     BuildPackageSummary, Config, Context, Index, InstanceMetrics, RustwideBuilder,
     cdn::{self, CdnMetrics},
     db::{
-        CrateId, Pool, delete_crate, delete_version,
+        CrateId, Pool, ReleaseId, delete_crate, delete_version,
         types::{krate_name::KrateName, version::Version},
-        update_latest_version_id,
+        update_build_status, update_latest_version_id,
     },
-    docbuilder::{BuilderMetrics, PackageKind},
+    docbuilder::{BuildCancellation, BuilderMetrics, PackageKind},
     error::Result,
     metrics::otel::AnyMeterProvider,
     storage::AsyncStorage,
     utils::{ConfigName, get_config, get_crate_priority, report_error, retry, set_config},
 };
 use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use crates_index_diff::{CrateVersion, DependencyKind};
 use fn_error_context::context;
 use futures_util::{StreamExt, stream::TryStreamExt};
 use opentelemetry::metrics::Counter;
+use semver::VersionReq;
 use sqlx::Connection as _;
-use std::{collections::HashMap, sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::runtime;
 use tracing::{debug, error, info, instrument, warn};
 
@@ -45,6 +53,11 @@ impl BuildQueueMetrics {
 /// For normal build priorities we use smaller values.
 pub(crate) const REBUILD_PRIORITY: i32 = 20;
 
+/// The static priority for background retries of failed releases.
+/// Separate from [`REBUILD_PRIORITY`] so retries have their own budget in the queue, only
+/// queued builds with exactly this priority count against it.
+pub(crate) const FAILED_REBUILD_PRIORITY: i32 = 25;
+
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
 pub(crate) struct QueuedCrate {
     #[serde(skip)]
@@ -56,6 +69,62 @@ pub(crate) struct QueuedCrate {
     pub(crate) attempt: i32,
 }
 
+/// Why a queued release isn't picked up by the next free build worker.
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
+pub(crate) enum QueueWaitReason {
+    /// The last attempt failed, the next one has to wait until its retry backoff passed.
+    RetryDelay,
+    /// The owner already has as many builds running as we allow for a single owner.
+    OwnerLimit { owner: String, running: i64 },
+    /// Fair scheduling builds releases of other owners first, until it's this owner's turn again.
+    OwnerTurn { owner: String, ahead: i64 },
+    /// A dependency that was published together with the release is still queued.
+    Dependency { name: String, version: Version },
+}
+
+impl fmt::Display for QueueWaitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RetryDelay => write!(f, "waiting to retry after a failed attempt"),
+            Self::OwnerLimit { owner, running } => write!(
+                f,
+                "{owner} already has {running} build{} running",
+                if *running == 1 { "" } else { "s" }
+            ),
+            Self::OwnerTurn { owner, ahead } => write!(
+                f,
+                "waiting for its turn after {ahead} other release{} of {owner}",
+                if *ahead == 1 { "" } else { "s" }
+            ),
+            Self::Dependency { name, version } => {
+                write!(f, "waiting for its dependency {name} {version} to be built")
+            }
+        }
+    }
+}
+
+/// What happened when a worker tried to renew its lease on a queue entry.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum LeaseState {
+    Renewed,
+    /// the lease was renewed, but the build should be cancelled.
+    CancelRequested,
+    /// the worker doesn't hold the lease any more.
+    Lost,
+}
+
+/// A build server that claimed releases from the queue at least once.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub(crate) struct BuildWorker {
+    pub(crate) name: String,
+    pub(crate) last_heartbeat: DateTime<Utc>,
+    pub(crate) total_builds: i32,
+    pub(crate) failed_builds: i32,
+    pub(crate) expired_leases: i32,
+    /// the release this worker currently holds a lease for.
+    pub(crate) current_build: Option<(String, Version)>,
+}
+
 #[derive(Debug)]
 pub struct AsyncBuildQueue {
     config: Arc<Config>,
@@ -136,7 +205,8 @@ impl AsyncBuildQueue {
                 SET priority = EXCLUDED.priority,
                     registry = EXCLUDED.registry,
                     attempt = 0,
-                    last_attempt = NULL
+                    last_attempt = NULL,
+                    next_attempt = NULL
             ;",
             name,
             version as _,
@@ -203,21 +273,98 @@ impl AsyncBuildQueue {
         Ok(sqlx::query_as!(
             QueuedCrate,
             r#"SELECT
-                id,
-                name,
-                version as "version: Version",
-                priority,
-                registry,
-                attempt
+                queue.id,
+                queue.name,
+                queue.version as "version: Version",
+                queue.priority,
+                queue.registry,
+                queue.attempt
              FROM queue
-             WHERE attempt < $1
-             ORDER BY priority ASC, attempt ASC, id ASC"#,
-            self.max_attempts
+             -- running builds have no place in the scheduling, they come first anyway.
+             LEFT JOIN queue_scheduling($1) AS queue_scheduling
+                ON queue_scheduling.id = queue.id
+             WHERE queue.attempt < $1
+             ORDER BY
+                queue.priority ASC,
+                CASE WHEN $2::BOOLEAN
+                    THEN COALESCE(
+                        queue_scheduling.owner_position + queue_scheduling.owner_running,
+                        0
+                    )
+                    ELSE 0
+                END ASC,
+                queue.attempt ASC,
+                queue.id ASC"#,
+            self.max_attempts,
+            self.config.build_queue_fair_scheduling,
         )
         .fetch_all(&mut *conn)
         .await?)
     }
 
+    /// Explain why queued releases are still waiting, when it's not just their place in the queue.
+    pub(crate) async fn wait_reasons(&self) -> Result<HashMap<(String, Version), QueueWaitReason>> {
+        let mut conn = self.db.get_async().await?;
+
+        let max_builds_per_owner = self.config.build_queue_max_builds_per_owner.map(i64::from);
+
+        Ok(sqlx::query!(
+            r#"SELECT
+                queue.name,
+                queue.version as "version: Version",
+                queue_scheduling.owner_key as "owner_key!",
+                queue_scheduling.owner_position as "owner_position!",
+                queue_scheduling.owner_running as "owner_running!",
+                COALESCE(queue.next_attempt > NOW(), FALSE) as "retry_delay!",
+                blocking.name as "dependency_name?",
+                blocking.version as "dependency_version?: Version"
+             FROM queue
+             INNER JOIN queue_scheduling($1) AS queue_scheduling
+                ON queue_scheduling.id = queue.id
+             LEFT JOIN LATERAL (
+                SELECT dependency.name, dependency.version
+                FROM queue_dependencies
+                INNER JOIN queue AS dependency ON dependency.id = queue_dependencies.dependency_id
+                WHERE
+                    queue_dependencies.queue_id = queue.id AND
+                    queue_dependencies.added > NOW() - make_interval(secs => $2) AND
+                    dependency.attempt < $1
+                ORDER BY dependency.id
+                LIMIT 1
+             ) AS blocking ON TRUE
+             WHERE
+                queue.worker IS NULL AND
+                queue.attempt < $1"#,
+            self.max_attempts,
+            self.config.build_queue_dependency_timeout.as_secs_f64(),
+        )
+        .fetch(&mut *conn)
+        .try_filter_map(|row| async move {
+            let reason = if row.retry_delay {
+                QueueWaitReason::RetryDelay
+            } else if let (Some(name), Some(version)) =
+                (row.dependency_name, row.dependency_version)
+            {
+                QueueWaitReason::Dependency { name, version }
+            } else if max_builds_per_owner.is_some_and(|max| row.owner_running >= max) {
+                QueueWaitReason::OwnerLimit {
+                    owner: row.owner_key,
+                    running: row.owner_running,
+                }
+            } else if self.config.build_queue_fair_scheduling && row.owner_position > 1 {
+                QueueWaitReason::OwnerTurn {
+                    owner: row.owner_key,
+                    ahead: row.owner_position - 1,
+                }
+            } else {
+                return Ok(None);
+            };
+            Ok(Some(((row.name, row.version), reason)))
+        })
+        .try_collect()
+        .await?)
+    }
+
     pub(crate) async fn has_build_queued(&self, name: &str, version: &Version) -> Result<bool> {
         let mut conn = self.db.get_async().await?;
         Ok(sqlx::query_scalar!(
@@ -262,8 +409,421 @@ impl AsyncBuildQueue {
     }
 }
 
+/// Lease functions.
+///
+/// Build workers claim a queue entry by taking a lease on it. The lease is refreshed through
+/// a heartbeat while the build runs. When a worker crashes, its lease expires and the release
+/// is put back into the queue for the next worker.
+impl AsyncBuildQueue {
+    /// Record a heartbeat for a build worker, registering it when we see it for the first time.
+    pub(crate) async fn worker_heartbeat(&self, worker: &str) -> Result<()> {
+        let mut conn = self.db.get_async().await?;
+        sqlx::query!(
+            "INSERT INTO build_workers (name)
+             VALUES ($1)
+             ON CONFLICT (name) DO UPDATE
+                SET last_heartbeat = NOW()",
+            worker,
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// How long to wait before the next attempt, after `attempt` attempts failed.
+    ///
+    /// The delay doubles with every failed attempt, and is randomly shortened by up to half
+    /// so retries of releases that failed at the same time, e.g. during a storage outage,
+    /// don't all come back at once.
+    fn retry_delay(&self, attempt: i32) -> Duration {
+        let exponent = attempt.saturating_sub(1).clamp(0, 31) as u32;
+        let delay = self
+            .config
+            .delay_between_build_attempts
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.config.max_delay_between_build_attempts);
+
+        delay.mul_f64(rand::random_range(0.5..=1.0))
+    }
+
+    /// Put queue entries with an expired lease back into the queue.
+    ///
+    /// The worker holding the lease most likely crashed during the build, so
+    /// this counts as a failed attempt.
+    ///
+    /// Returns the number of released queue entries.
+    pub(crate) async fn release_expired_leases(&self) -> Result<usize> {
+        let mut conn = self.db.get_async().await?;
+        let mut transaction = conn.begin().await?;
+
+        // the workers of expired leases won't finish their builds anymore.
+        let failed_releases = sqlx::query_scalar!(
+            r#"UPDATE builds
+               SET
+                  build_status = 'failure',
+                  failure_kind = 'infrastructure',
+                  build_finished = NOW(),
+                  errors = 'the lease of the build expired'
+               FROM queue, releases, crates
+               WHERE
+                  builds.build_status = 'in_progress' AND
+                  releases.id = builds.rid AND
+                  crates.id = releases.crate_id AND
+                  queue.name = crates.name AND
+                  queue.version = releases.version AND
+                  queue.lease_expires_at < NOW()
+               RETURNING builds.rid as "rid: ReleaseId""#,
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+        for release_id in failed_releases {
+            update_build_status(&mut transaction, release_id).await?;
+        }
+
+        // cancelled builds don't go back into the queue, even when the worker
+        // died before it could finish the cancellation.
+        sqlx::query!("DELETE FROM queue WHERE lease_expires_at < NOW() AND cancel_requested")
+            .execute(&mut *transaction)
+            .await?;
+
+        let expired = sqlx::query!(
+            r#"UPDATE queue
+               SET
+                  worker = NULL,
+                  lease_expires_at = NULL,
+                  attempt = queue.attempt + 1,
+                  last_attempt = NOW()
+               FROM (
+                  SELECT id, worker
+                  FROM queue
+                  WHERE lease_expires_at < NOW()
+                  FOR UPDATE SKIP LOCKED
+               ) AS expired
+               WHERE queue.id = expired.id
+               RETURNING
+                  queue.id,
+                  queue.attempt,
+                  queue.name,
+                  queue.version as "version: Version",
+                  expired.worker as "worker!""#,
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        for row in &expired {
+            warn!(
+                worker = row.worker,
+                "lease for {}-{} expired, putting it back into the queue", row.name, row.version,
+            );
+            sqlx::query!(
+                "UPDATE queue
+                 SET next_attempt = NOW() + make_interval(secs => $2)
+                 WHERE id = $1",
+                row.id,
+                self.retry_delay(row.attempt).as_secs_f64(),
+            )
+            .execute(&mut *transaction)
+            .await?;
+            sqlx::query!(
+                "UPDATE build_workers
+                 SET expired_leases = expired_leases + 1
+                 WHERE name = $1",
+                row.worker,
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(expired.len())
+    }
+
+    /// Claim the next release to build for the given worker.
+    ///
+    /// The queue entry stays reserved for the worker until its lease expires,
+    /// so the lease has to be renewed through [`Self::renew_lease`] while the build runs.
+    pub(crate) async fn claim_next_crate(&self, worker: &str) -> Result<Option<QueuedCrate>> {
+        self.release_expired_leases().await?;
+        self.worker_heartbeat(worker).await?;
+
+        let mut conn = self.db.get_async().await?;
+
+        // `SKIP LOCKED` here will enable another build-server to just
+        // skip over rows that are being claimed at the same time.
+        //
+        // The per-owner limit is only checked against the leases that exist when claiming,
+        // so two workers claiming at the same moment can exceed it by one build each.
+        //
+        // Releases waiting for a dependency are skipped until the dependency is built,
+        // failed too often, or the dependency timeout passed.
+        Ok(sqlx::query_as!(
+            QueuedCrate,
+            r#"UPDATE queue
+               SET
+                  worker = $1,
+                  lease_expires_at = NOW() + make_interval(secs => $2)
+               WHERE id = (
+                  SELECT queue.id
+                  FROM queue
+                  INNER JOIN queue_scheduling($3) AS queue_scheduling
+                     ON queue_scheduling.id = queue.id
+                  WHERE
+                     queue.worker IS NULL AND
+                     queue.attempt < $3 AND
+                     (queue.next_attempt IS NULL OR queue.next_attempt <= NOW()) AND
+                     ($5::INTEGER IS NULL OR queue_scheduling.owner_running < $5) AND
+                     NOT EXISTS (
+                        SELECT 1
+                        FROM queue_dependencies
+                        INNER JOIN queue AS dependency
+                           ON dependency.id = queue_dependencies.dependency_id
+                        WHERE
+                           queue_dependencies.queue_id = queue.id AND
+                           queue_dependencies.added > NOW() - make_interval(secs => $6) AND
+                           dependency.attempt < $3
+                     )
+                  ORDER BY
+                     queue.priority ASC,
+                     CASE WHEN $4::BOOLEAN
+                        THEN queue_scheduling.owner_position + queue_scheduling.owner_running
+                        ELSE 0
+                     END ASC,
+                     queue.attempt ASC,
+                     queue.id ASC
+                  LIMIT 1
+                  FOR UPDATE OF queue SKIP LOCKED
+               )
+               RETURNING
+                  id,
+                  name,
+                  version as "version: Version",
+                  priority,
+                  registry,
+                  attempt"#,
+            worker,
+            self.config.build_lease_duration.as_secs_f64(),
+            self.max_attempts,
+            self.config.build_queue_fair_scheduling,
+            self.config.build_queue_max_builds_per_owner.map(i32::from),
+            self.config.build_queue_dependency_timeout.as_secs_f64(),
+        )
+        .fetch_optional(&mut *conn)
+        .await?)
+    }
+
+    /// Extend the lease the worker holds on a queue entry.
+    pub(crate) async fn renew_lease(&self, id: i32, worker: &str) -> Result<LeaseState> {
+        self.worker_heartbeat(worker).await?;
+
+        let mut conn = self.db.get_async().await?;
+        let cancel_requested = sqlx::query_scalar!(
+            "UPDATE queue
+             SET lease_expires_at = NOW() + make_interval(secs => $3)
+             WHERE id = $1 AND worker = $2
+             RETURNING cancel_requested",
+            id,
+            worker,
+            self.config.build_lease_duration.as_secs_f64(),
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(match cancel_requested {
+            Some(false) => LeaseState::Renewed,
+            Some(true) => LeaseState::CancelRequested,
+            None => LeaseState::Lost,
+        })
+    }
+
+    /// Check the lease of a running build without renewing it, to notice cancellations
+    /// sooner than the next renewal.
+    pub(crate) async fn lease_state(&self, id: i32, worker: &str) -> Result<LeaseState> {
+        let mut conn = self.db.get_async().await?;
+        let cancel_requested = sqlx::query_scalar!(
+            "SELECT cancel_requested FROM queue WHERE id = $1 AND worker = $2",
+            id,
+            worker,
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        Ok(match cancel_requested {
+            Some(false) => LeaseState::Renewed,
+            Some(true) => LeaseState::CancelRequested,
+            None => LeaseState::Lost,
+        })
+    }
+
+    /// List the build workers that were active during the last day.
+    pub(crate) async fn workers(&self) -> Result<Vec<BuildWorker>> {
+        let mut conn = self.db.get_async().await?;
+
+        Ok(sqlx::query!(
+            r#"SELECT
+                build_workers.name,
+                build_workers.last_heartbeat,
+                build_workers.total_builds,
+                build_workers.failed_builds,
+                build_workers.expired_leases,
+                queue.name as "current_name?",
+                queue.version as "current_version?: Version"
+             FROM build_workers
+             LEFT OUTER JOIN queue ON
+                queue.worker = build_workers.name AND
+                queue.lease_expires_at >= NOW()
+             WHERE build_workers.last_heartbeat > NOW() - INTERVAL '1 day'
+             ORDER BY build_workers.name ASC"#,
+        )
+        .fetch(&mut *conn)
+        .map_ok(|row| BuildWorker {
+            name: row.name,
+            last_heartbeat: row.last_heartbeat,
+            total_builds: row.total_builds,
+            failed_builds: row.failed_builds,
+            expired_leases: row.expired_leases,
+            current_build: row.current_name.zip(row.current_version),
+        })
+        .try_collect()
+        .await?)
+    }
+}
+
+/// Cancellation functions.
+impl AsyncBuildQueue {
+    /// Remove a release from the queue that isn't being built yet.
+    ///
+    /// Returns `false` when the release isn't waiting in the queue.
+    pub(crate) async fn remove_crate(&self, name: &str, version: &Version) -> Result<bool> {
+        let mut conn = self.db.get_async().await?;
+        Ok(sqlx::query!(
+            "DELETE FROM queue
+             WHERE
+                name = $1 AND
+                version = $2 AND
+                worker IS NULL",
+            name,
+            version as _,
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Ask the worker building the given release to cancel the build.
+    ///
+    /// The worker notices the request with the next renewal of its lease.
+    /// Returns `false` when the release isn't being built.
+    pub(crate) async fn request_cancellation(&self, name: &str, version: &Version) -> Result<bool> {
+        let mut conn = self.db.get_async().await?;
+        Ok(sqlx::query!(
+            "UPDATE queue
+             SET cancel_requested = TRUE
+             WHERE
+                name = $1 AND
+                version = $2 AND
+                worker IS NOT NULL",
+            name,
+            version as _,
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Ask all workers to cancel their running builds.
+    ///
+    /// Returns the number of builds that will be cancelled.
+    pub(crate) async fn cancel_running_builds(&self) -> Result<usize> {
+        let mut conn = self.db.get_async().await?;
+        Ok(sqlx::query!(
+            "UPDATE queue
+             SET cancel_requested = TRUE
+             WHERE
+                worker IS NOT NULL AND
+                NOT cancel_requested",
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+}
+
 /// Index methods.
 impl AsyncBuildQueue {
+    /// Let a queued release wait for the queued releases of its dependencies.
+    ///
+    /// When multiple crates are published together, like the crates of a workspace,
+    /// a crate might otherwise be built before the new releases of its dependencies.
+    /// Like cargo, each dependency is resolved to its newest published version that matches
+    /// the requirement, out of its releases and its queued builds. The release only waits
+    /// when that version is queued, and not yet released.
+    ///
+    /// Returns the number of queued releases the release waits for.
+    pub(crate) async fn add_queued_dependencies(
+        &self,
+        conn: &mut sqlx::PgConnection,
+        name: &str,
+        version: &Version,
+        dependencies: &[(String, VersionReq)],
+    ) -> Result<usize> {
+        let mut dependency_ids = Vec::new();
+        for (dependency, req) in dependencies {
+            let newest_queued = sqlx::query!(
+                r#"SELECT id, version as "version: Version"
+                   FROM queue
+                   WHERE name = $1 AND attempt < $2"#,
+                dependency,
+                self.max_attempts,
+            )
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .filter(|row| req.matches(&row.version))
+            .max_by(|a, b| a.version.cmp(&b.version));
+            let Some(newest_queued) = newest_queued else {
+                continue;
+            };
+
+            let newest_released = sqlx::query_scalar!(
+                r#"SELECT releases.version as "version: Version"
+                   FROM releases
+                   INNER JOIN crates ON crates.id = releases.crate_id
+                   WHERE crates.name = $1 AND releases.yanked IS NOT TRUE"#,
+                dependency,
+            )
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .filter(|version| req.matches(version))
+            .max_by(|a, b| a.cmp(b));
+
+            // a rebuild of a release, or of an older release, doesn't change what cargo uses.
+            if newest_released.is_none_or(|released| *newest_queued.version > *released) {
+                dependency_ids.push(newest_queued.id);
+            }
+        }
+
+        if dependency_ids.is_empty() {
+            return Ok(0);
+        }
+
+        Ok(sqlx::query!(
+            "INSERT INTO queue_dependencies (queue_id, dependency_id)
+             SELECT queue.id, dependency_id
+             FROM queue, UNNEST($3::INTEGER[]) AS dependency_id
+             WHERE queue.name = $1 AND queue.version = $2
+             ON CONFLICT DO NOTHING",
+            name,
+            version as _,
+            &dependency_ids,
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected() as usize)
+    }
+
     async fn queue_crate_invalidation(&self, conn: &mut sqlx::PgConnection, krate: &str) {
         let krate = match krate
             .parse::<KrateName>()
@@ -357,17 +917,13 @@ impl AsyncBuildQueue {
 
             if let Some(release) = change.added() {
                 let priority = get_crate_priority(&mut conn, &release.name).await?;
+                let version: Version = release
+                    .version
+                    .parse()
+                    .context("couldn't parse release version as semver")?;
 
                 match self
-                    .add_crate(
-                        &release.name,
-                        &release
-                            .version
-                            .parse()
-                            .context("couldn't parse release version as semver")?,
-                        priority,
-                        index.repository_url(),
-                    )
+                    .add_crate(&release.name, &version, priority, index.repository_url())
                     .await
                     .with_context(|| {
                         format!(
@@ -383,6 +939,18 @@ impl AsyncBuildQueue {
                         self.metrics.queued_builds.inc();
                         self.queue_metrics.queued_builds.add(1, &[]);
                         crates_added += 1;
+
+                        if let Err(err) = self
+                            .add_queued_dependencies(
+                                &mut conn,
+                                &release.name,
+                                &version,
+                                &build_dependencies(release),
+                            )
+                            .await
+                        {
+                            report_error(&err);
+                        }
                     }
                     Err(err) => report_error(&err),
                 }
@@ -519,6 +1087,13 @@ impl BuildQueue {
         self.runtime
             .block_on(self.inner.set_last_seen_reference(oid))
     }
+    pub fn remove_crate(&self, name: &str, version: &Version) -> Result<bool> {
+        self.runtime
+            .block_on(self.inner.remove_crate(name, version))
+    }
+    pub fn cancel_running_builds(&self) -> Result<usize> {
+        self.runtime.block_on(self.inner.cancel_running_builds())
+    }
     #[cfg(test)]
     pub(crate) fn pending_count(&self) -> Result<usize> {
         self.runtime.block_on(self.inner.pending_count())
@@ -549,43 +1124,64 @@ impl BuildQueue {
 
     fn process_next_crate(
         &self,
+        cancellation: &BuildCancellation,
         f: impl FnOnce(&QueuedCrate) -> Result<BuildPackageSummary>,
     ) -> Result<()> {
-        let mut conn = self.runtime.block_on(self.inner.db.get_async())?;
-        let mut transaction = self.runtime.block_on(conn.begin())?;
-
-        // fetch the next available crate from the queue table.
-        // We are using `SELECT FOR UPDATE` inside a transaction so
-        // the QueuedCrate is locked until we are finished with it.
-        // `SKIP LOCKED` here will enable another build-server to just
-        // skip over taken (=locked) rows and start building the first
-        // available one.
-        let to_process = match self.runtime.block_on(
-            sqlx::query_as!(
-                QueuedCrate,
-                r#"SELECT
-                    id,
-                    name,
-                    version as "version: Version",
-                    priority,
-                    registry,
-                    attempt
-                 FROM queue
-                 WHERE
-                    attempt < $1 AND
-                    (last_attempt IS NULL OR last_attempt < NOW() - make_interval(secs => $2))
-                 ORDER BY priority ASC, attempt ASC, id ASC
-                 LIMIT 1
-                 FOR UPDATE SKIP LOCKED"#,
-                self.inner.max_attempts,
-                self.inner.config.delay_between_build_attempts.as_secs_f64(),
-            )
-            .fetch_optional(&mut *transaction),
-        )? {
+        let worker = self.inner.config.build_worker_name.clone();
+
+        // fetch the next available crate from the queue table, and take a lease on it.
+        // The lease is kept alive by a heartbeat while we're building, other build-servers
+        // will skip it until the lease expired.
+        let to_process = match self
+            .runtime
+            .block_on(self.inner.claim_next_crate(&worker))?
+        {
             Some(krate) => krate,
             None => return Ok(()),
         };
 
+        let heartbeat = self.runtime.spawn({
+            let inner = self.inner.clone();
+            let worker = worker.clone();
+            let cancellation = cancellation.clone();
+            let id = to_process.id;
+            async move {
+                let mut renewal = tokio::time::interval(inner.config.build_lease_duration / 3);
+                let mut cancellation_check =
+                    tokio::time::interval(inner.config.build_cancellation_check_interval);
+                // the first ticks complete immediately, the lease was just taken.
+                renewal.tick().await;
+                cancellation_check.tick().await;
+                loop {
+                    let state = tokio::select! {
+                        _ = renewal.tick() => inner
+                            .renew_lease(id, &worker)
+                            .await
+                            .context("failed to renew build lease"),
+                        _ = cancellation_check.tick() => inner
+                            .lease_state(id, &worker)
+                            .await
+                            .context("failed to check for a build cancellation"),
+                    };
+                    match state {
+                        Ok(LeaseState::Renewed) => {}
+                        Ok(LeaseState::CancelRequested) => {
+                            info!(worker, "cancelling the build for queue entry {id}");
+                            if let Err(err) = cancellation.cancel().await {
+                                report_error(&err.context("failed to cancel the build"));
+                            }
+                            break;
+                        }
+                        Ok(LeaseState::Lost) => {
+                            warn!(worker, "lost the build lease for queue entry {id}");
+                            break;
+                        }
+                        Err(err) => report_error(&err),
+                    }
+                }
+            }
+        });
+
         let res = {
             let instant = Instant::now();
             let res = f(&to_process);
@@ -595,43 +1191,93 @@ impl BuildQueue {
             res
         };
 
+        heartbeat.abort();
+
+        let cancelled = cancellation.is_requested();
+        let failed = !cancelled && !matches!(&res, Ok(summary) if summary.successful);
+
         self.inner.metrics.total_builds.inc();
         self.inner.builder_metrics.total_builds.add(1, &[]);
 
+        let mut conn = self.runtime.block_on(self.inner.db.get_async())?;
+        let mut transaction = self.runtime.block_on(conn.begin())?;
+
         self.runtime.block_on(
             self.inner
                 .queue_crate_invalidation(&mut transaction, &to_process.name),
         );
 
+        self.runtime.block_on(
+            sqlx::query!(
+                "UPDATE build_workers
+                 SET
+                    total_builds = total_builds + 1,
+                    failed_builds = failed_builds + $2
+                 WHERE name = $1",
+                worker,
+                i32::from(failed),
+            )
+            .execute(&mut *transaction),
+        )?;
+
         let mut increase_attempt_count = || -> Result<()> {
-            let attempt: i32 = self.runtime.block_on(
+            let attempt: Option<i32> = self.runtime.block_on(
                 sqlx::query_scalar!(
                     "UPDATE queue
                          SET
                             attempt = attempt + 1,
-                            last_attempt = NOW()
-                         WHERE id = $1
+                            last_attempt = NOW(),
+                            next_attempt = NOW() + make_interval(secs => $3),
+                            worker = NULL,
+                            lease_expires_at = NULL
+                         WHERE id = $1 AND worker = $2
                          RETURNING attempt;",
                     to_process.id,
+                    worker,
+                    self.inner.retry_delay(to_process.attempt + 1).as_secs_f64(),
                 )
-                .fetch_one(&mut *transaction),
+                .fetch_optional(&mut *transaction),
             )?;
 
-            if attempt >= self.inner.max_attempts {
-                self.inner.metrics.failed_builds.inc();
-                self.inner.builder_metrics.failed_builds.add(1, &[]);
+            match attempt {
+                Some(attempt) if attempt >= self.inner.max_attempts => {
+                    self.inner.metrics.failed_builds.inc();
+                    self.inner.builder_metrics.failed_builds.add(1, &[]);
+                }
+                Some(_) => {}
+                None => warn!(
+                    worker,
+                    "lease for {}-{} expired during the build, not updating the queue",
+                    to_process.name,
+                    to_process.version
+                ),
             }
             Ok(())
         };
 
         match res {
+            // a cancelled build is never retried, independent of how the builder ended.
+            _ if cancelled => {
+                self.runtime.block_on(
+                    sqlx::query!(
+                        "DELETE FROM queue WHERE id = $1 AND worker = $2;",
+                        to_process.id,
+                        worker,
+                    )
+                    .execute(&mut *transaction),
+                )?;
+            }
             Ok(BuildPackageSummary {
                 should_reattempt: false,
                 successful: _,
             }) => {
                 self.runtime.block_on(
-                    sqlx::query!("DELETE FROM queue WHERE id = $1;", to_process.id)
-                        .execute(&mut *transaction),
+                    sqlx::query!(
+                        "DELETE FROM queue WHERE id = $1 AND worker = $2;",
+                        to_process.id,
+                        worker,
+                    )
+                    .execute(&mut *transaction),
                 )?;
             }
             Ok(BuildPackageSummary {
@@ -663,7 +1309,8 @@ impl BuildQueue {
     ) -> Result<bool> {
         let mut processed = false;
 
-        self.process_next_crate(|krate| {
+        let cancellation = builder.cancellation();
+        self.process_next_crate(&cancellation, |krate| {
             processed = true;
 
             let kind = krate
@@ -701,41 +1348,88 @@ impl BuildQueue {
     }
 }
 
+/// The dependencies of a release from the index that are needed to build its documentation.
+fn build_dependencies(release: &CrateVersion) -> Vec<(String, VersionReq)> {
+    release
+        .dependencies
+        .iter()
+        .filter(|dep| dep.kind != Some(DependencyKind::Dev))
+        .filter_map(|dep| {
+            let req = VersionReq::parse(&dep.required_version).ok()?;
+            // renamed dependencies have the real crate name in `package`.
+            let name = dep.package.as_ref().unwrap_or(&dep.name);
+            Some((name.to_string(), req))
+        })
+        .collect()
+}
+
 /// Queue rebuilds as configured.
 ///
-/// The idea is to rebuild:
+/// There are two separate budgets in the queue.
+///
+/// `max_queued_rebuilds` is used to rebuild:
 /// * the latest release of each crate
 /// * when the nightly version is older than our configured threshold
 /// * and there was a successful build for that release, that included documentation.
 /// * starting with the oldest nightly versions.
 /// * also checking if there is already a build queued.
 ///
-/// This might exclude releases from rebuilds that
-/// * previously failed but would succeed with a newer nightly version
-/// * previously failed but would succeed just with a retry.
+/// `max_queued_failed_rebuilds` is used to retry the latest releases that failed to build,
+/// because they might succeed with a newer nightly version or just with a retry:
+/// * when their last build is older than `failed_rebuild_min_age`.
+/// * starting with the most downloaded crates, then the ones with the most stars.
+/// * skipping releases that failed the same way on their last two builds.
 #[instrument(skip_all)]
 pub async fn queue_rebuilds(
     conn: &mut sqlx::PgConnection,
     config: &Config,
     build_queue: &AsyncBuildQueue,
 ) -> Result<()> {
-    let already_queued_rebuilds: usize = build_queue
-        .pending_count_by_priority()
-        .await?
-        .iter()
-        .filter_map(|(priority, count)| (*priority >= REBUILD_PRIORITY).then_some(count))
-        .sum();
+    let pending_count_by_priority = build_queue.pending_count_by_priority().await?;
 
-    let rebuilds_to_queue = config
-        .max_queued_rebuilds
-        .expect("config.max_queued_rebuilds not set") as i64
-        - already_queued_rebuilds as i64;
+    if let Some(max_queued_rebuilds) = config.max_queued_rebuilds {
+        let already_queued_rebuilds: usize = pending_count_by_priority
+            .iter()
+            .filter_map(|(priority, count)| (*priority >= REBUILD_PRIORITY).then_some(count))
+            .sum();
+        let rebuilds_to_queue = max_queued_rebuilds as i64 - already_queued_rebuilds as i64;
+
+        if rebuilds_to_queue <= 0 {
+            info!("not queueing rebuilds; queue limit reached");
+        } else {
+            queue_outdated_rebuilds(&mut *conn, build_queue, rebuilds_to_queue).await?;
+        }
+    }
+
+    if let Some(max_queued_failed_rebuilds) = config.max_queued_failed_rebuilds {
+        let already_queued_failed_rebuilds = pending_count_by_priority
+            .get(&FAILED_REBUILD_PRIORITY)
+            .copied()
+            .unwrap_or_default();
+        let failed_rebuilds_to_queue =
+            max_queued_failed_rebuilds as i64 - already_queued_failed_rebuilds as i64;
 
-    if rebuilds_to_queue <= 0 {
-        info!("not queueing rebuilds; queue limit reached");
-        return Ok(());
+        if failed_rebuilds_to_queue <= 0 {
+            info!("not queueing retries of failed releases; queue limit reached");
+        } else {
+            queue_failed_rebuilds(
+                &mut *conn,
+                build_queue,
+                config.failed_rebuild_min_age,
+                failed_rebuilds_to_queue,
+            )
+            .await?;
+        }
     }
 
+    Ok(())
+}
+
+async fn queue_outdated_rebuilds(
+    conn: &mut sqlx::PgConnection,
+    build_queue: &AsyncBuildQueue,
+    limit: i64,
+) -> Result<()> {
     let mut results = sqlx::query!(
         r#"SELECT i.* FROM (
              SELECT
@@ -754,7 +1448,7 @@ pub async fn queue_rebuilds(
          ) as i
          ORDER BY i.last_build_attempt ASC
          LIMIT $1"#,
-        rebuilds_to_queue,
+        limit,
     )
     .fetch(&mut *conn);
 
@@ -775,9 +1469,78 @@ pub async fn queue_rebuilds(
     Ok(())
 }
 
+async fn queue_failed_rebuilds(
+    conn: &mut sqlx::PgConnection,
+    build_queue: &AsyncBuildQueue,
+    min_age: Duration,
+    limit: i64,
+) -> Result<()> {
+    // Failures of our own infrastructure don't tell anything about the release,
+    // so they never count as failing the same way twice.
+    let mut results = sqlx::query!(
+        r#"SELECT
+             c.name,
+             r.version as "version: Version"
+         FROM crates AS c
+         INNER JOIN releases AS r ON c.latest_version_id = r.id
+         INNER JOIN release_build_status AS rbs ON rbs.rid = r.id
+         LEFT JOIN repositories ON repositories.id = r.repository_id
+         WHERE
+             rbs.build_status = 'failure' AND
+             rbs.last_build_time < NOW() - make_interval(secs => $1) AND
+             NOT EXISTS (
+                 SELECT 1
+                 FROM (
+                     SELECT b.build_status, b.failure_kind
+                     FROM builds AS b
+                     WHERE b.rid = r.id
+                     ORDER BY b.id DESC
+                     LIMIT 2
+                 ) AS last_builds
+                 HAVING
+                     COUNT(*) = 2 AND
+                     BOOL_AND(
+                         last_builds.build_status = 'failure' AND
+                         last_builds.failure_kind IS NOT NULL AND
+                         last_builds.failure_kind != 'infrastructure'
+                     ) AND
+                     COUNT(DISTINCT last_builds.failure_kind) = 1
+             )
+         ORDER BY
+             c.downloads_total DESC NULLS LAST,
+             repositories.stars DESC NULLS LAST,
+             c.name ASC
+         LIMIT $2"#,
+        min_age.as_secs_f64(),
+        limit,
+    )
+    .fetch(&mut *conn);
+
+    while let Some(row) = results.next().await {
+        let row = row?;
+
+        if !build_queue
+            .has_build_queued(&row.name, &row.version)
+            .await?
+        {
+            info!(
+                "queueing retry of failed release {} {}...",
+                &row.name, &row.version
+            );
+            build_queue
+                .add_crate(&row.name, &row.version, FAILED_REBUILD_PRIORITY, None)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db::types::{BuildFailureKind, BuildStatus};
+    use crate::registry_api::{CrateOwner, OwnerKind};
     use crate::test::{FakeBuild, TestEnvironment, V1, V2};
     use chrono::Utc;
 
@@ -898,10 +1661,149 @@ mod tests {
     }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn test_add_duplicate_doesnt_fail_last_priority_wins() -> Result<()> {
-        let env = TestEnvironment::new().await?;
-
-        let queue = env.async_build_queue();
+    async fn test_dont_rebuild_when_full_with_lower_priorities() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .max_queued_rebuilds(Some(1))
+                .build()?,
+        )
+        .await?;
+
+        // every build with at least the rebuild priority counts against the budget.
+        let build_queue = env.async_build_queue();
+        build_queue
+            .add_crate("foo1", &V1, FAILED_REBUILD_PRIORITY, None)
+            .await?;
+
+        env.fake_release()
+            .await
+            .name("foo")
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default().rustc_version("rustc 1.84.0-nightly (e7c0d2750 2020-10-15)"),
+            ])
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        queue_rebuilds(&mut conn, env.config(), build_queue).await?;
+
+        assert!(!build_queue.has_build_queued("foo", &V1).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retry_old_failed_releases() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                // only retries of failed releases are configured.
+                .max_queued_rebuilds(None)
+                .max_queued_failed_rebuilds(Some(2))
+                .failed_rebuild_min_age(Duration::from_secs(7 * 24 * 60 * 60))
+                .build()?,
+        )
+        .await?;
+
+        for name in [
+            "unpopular",
+            "popular",
+            "recent",
+            "same-failure",
+            "succeeded",
+        ] {
+            let builds = match name {
+                "same-failure" => vec![
+                    FakeBuild::default().successful(false),
+                    FakeBuild::default().successful(false),
+                ],
+                "succeeded" => vec![FakeBuild::default()],
+                _ => vec![FakeBuild::default().successful(false)],
+            };
+            env.fake_release()
+                .await
+                .name(name)
+                .version(V1)
+                .builds(builds)
+                .create()
+                .await?;
+        }
+
+        let mut conn = env.async_db().async_conn().await;
+        sqlx::query!(
+            "UPDATE release_build_status
+             SET last_build_time = NOW() - INTERVAL '30 days'
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE releases.id = release_build_status.rid AND crates.name != 'recent'"
+        )
+        .execute(&mut *conn)
+        .await?;
+        sqlx::query!(
+            "UPDATE crates
+             SET downloads_total = CASE name WHEN 'popular' THEN 1000 ELSE 10 END"
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        let build_queue = env.async_build_queue();
+        queue_rebuilds(&mut conn, env.config(), build_queue).await?;
+
+        let queue = build_queue.queued_crates().await?;
+        assert_eq!(
+            queue
+                .iter()
+                .map(|c| (c.name.as_str(), c.priority))
+                .collect::<Vec<_>>(),
+            vec![
+                ("popular", FAILED_REBUILD_PRIORITY),
+                ("unpopular", FAILED_REBUILD_PRIORITY),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_rebuilds_have_their_own_budget() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .max_queued_rebuilds(Some(1))
+                .max_queued_failed_rebuilds(Some(1))
+                .failed_rebuild_min_age(Duration::ZERO)
+                .build()?,
+        )
+        .await?;
+
+        let build_queue = env.async_build_queue();
+        build_queue
+            .add_crate("foo1", &V1, REBUILD_PRIORITY, None)
+            .await?;
+        // a low priority build that isn't a retry doesn't use the budget for retries.
+        build_queue
+            .add_crate("foo2", &V1, FAILED_REBUILD_PRIORITY + 1, None)
+            .await?;
+
+        env.fake_release()
+            .await
+            .name("failed")
+            .version(V1)
+            .builds(vec![FakeBuild::default().successful(false)])
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        queue_rebuilds(&mut conn, env.config(), build_queue).await?;
+        assert!(build_queue.has_build_queued("failed", &V1).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_add_duplicate_doesnt_fail_last_priority_wins() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let queue = env.async_build_queue();
 
         queue.add_crate("some_crate", &V1, 0, None).await?;
         queue.add_crate("some_crate", &V1, 9, None).await?;
@@ -990,21 +1892,21 @@ mod tests {
         queue.add_crate("krate", &V1, 0, None)?;
 
         // first let it fail
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!(krate.name, "krate");
             anyhow::bail!("simulate a failure");
         })?;
 
-        queue.process_next_crate(|_| {
+        queue.process_next_crate(&BuildCancellation::default(), |_| {
             // this can't happen since we didn't wait between attempts
             unreachable!();
         })?;
 
         runtime.block_on(async {
-            // fake the build-attempt timestamp so it's older
+            // fake the retry timestamp so the backoff has passed
             let mut conn = env.async_db().async_conn().await;
             sqlx::query!(
-                "UPDATE queue SET last_attempt = $1",
+                "UPDATE queue SET next_attempt = $1",
                 Utc::now() - chrono::Duration::try_seconds(60).unwrap()
             )
             .execute(&mut *conn)
@@ -1013,7 +1915,7 @@ mod tests {
 
         let mut handled = false;
         // now we can process it again
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!(krate.name, "krate");
             handled = true;
             Ok(BuildPackageSummary::default())
@@ -1024,6 +1926,29 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retry_delay_backs_off_exponentially() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .delay_between_build_attempts(Duration::from_secs(60))
+                .max_delay_between_build_attempts(Duration::from_secs(600))
+                .build()?,
+        )
+        .await?;
+        let queue = env.async_build_queue();
+
+        for (attempt, max_delay) in [(1, 60), (2, 120), (3, 240), (4, 480), (5, 600), (40, 600)] {
+            let delay = queue.retry_delay(attempt);
+            assert!(
+                delay >= Duration::from_secs(max_delay) / 2
+                    && delay <= Duration::from_secs(max_delay),
+                "attempt {attempt}: {delay:?}"
+            );
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_add_and_process_crates() -> Result<()> {
         const MAX_ATTEMPTS: u16 = 3;
@@ -1049,14 +1974,14 @@ mod tests {
         }
 
         let assert_next = |name| -> Result<()> {
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(&BuildCancellation::default(), |krate| {
                 assert_eq!(name, krate.name);
                 Ok(BuildPackageSummary::default())
             })?;
             Ok(())
         };
         let assert_next_and_fail = |name| -> Result<()> {
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(&BuildCancellation::default(), |krate| {
                 assert_eq!(name, krate.name);
                 anyhow::bail!("simulate a failure");
             })?;
@@ -1089,7 +2014,7 @@ mod tests {
         // Since low-priority failed many times it will be removed from the queue. Because of
         // that the queue should now be empty.
         let mut called = false;
-        queue.process_next_crate(|_| {
+        queue.process_next_crate(&BuildCancellation::default(), |_| {
             called = true;
             Ok(BuildPackageSummary::default())
         })?;
@@ -1169,7 +2094,7 @@ mod tests {
 
         assert!(fetch_invalidations().is_empty());
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!("will_succeed", krate.name);
             Ok(BuildPackageSummary::default())
         })?;
@@ -1182,7 +2107,7 @@ mod tests {
                 .all(|i| i.krate == "will_succeed")
         );
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!("will_fail", krate.name);
             anyhow::bail!("simulate a failure");
         })?;
@@ -1211,7 +2136,7 @@ mod tests {
         queue.add_crate("bar", &V1, 0, None)?;
         assert_eq!(queue.pending_count()?, 2);
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!("foo", krate.name);
             Ok(BuildPackageSummary::default())
         })?;
@@ -1236,7 +2161,7 @@ mod tests {
         queue.add_crate("baz", &V1, 100, None)?;
         assert_eq!(queue.prioritized_count()?, 2);
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!("bar", krate.name);
             Ok(BuildPackageSummary::default())
         })?;
@@ -1263,7 +2188,9 @@ mod tests {
         );
 
         while queue.pending_count()? > 0 {
-            queue.process_next_crate(|_| Ok(BuildPackageSummary::default()))?;
+            queue.process_next_crate(&BuildCancellation::default(), |_| {
+                Ok(BuildPackageSummary::default())
+            })?;
         }
         assert!(queue.pending_count_by_priority()?.is_empty());
 
@@ -1290,7 +2217,7 @@ mod tests {
 
         for _ in 0..MAX_ATTEMPTS {
             assert_eq!(queue.failed_count()?, 0);
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(&BuildCancellation::default(), |krate| {
                 assert_eq!("foo", krate.name);
                 Ok(BuildPackageSummary {
                     should_reattempt: true,
@@ -1300,7 +2227,7 @@ mod tests {
         }
         assert_eq!(queue.failed_count()?, 1);
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!("bar", krate.name);
             Ok(BuildPackageSummary::default())
         })?;
@@ -1329,14 +2256,14 @@ mod tests {
 
         for _ in 0..MAX_ATTEMPTS {
             assert_eq!(queue.failed_count()?, 0);
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(&BuildCancellation::default(), |krate| {
                 assert_eq!("foo", krate.name);
                 anyhow::bail!("this failed");
             })?;
         }
         assert_eq!(queue.failed_count()?, 1);
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!("bar", krate.name);
             Ok(BuildPackageSummary::default())
         })?;
@@ -1428,6 +2355,662 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_claim_skips_leased_crates() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo", &V1, 0, None).await?;
+        queue.add_crate("bar", &V1, 0, None).await?;
+
+        let first = queue.claim_next_crate("worker-a").await?.unwrap();
+        assert_eq!(first.name, "foo");
+
+        let second = queue.claim_next_crate("worker-b").await?.unwrap();
+        assert_eq!(second.name, "bar");
+
+        assert!(queue.claim_next_crate("worker-c").await?.is_none());
+
+        // leased crates are still pending
+        assert_eq!(queue.pending_count().await?, 2);
+
+        let workers = queue.workers().await?;
+        assert_eq!(
+            workers
+                .iter()
+                .map(|w| (w.name.as_str(), w.current_build.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("worker-a", Some(("foo".into(), V1))),
+                ("worker-b", Some(("bar".into(), V1))),
+                ("worker-c", None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_renew_lease() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo", &V1, 0, None).await?;
+
+        let krate = queue.claim_next_crate("worker-a").await?.unwrap();
+
+        assert_eq!(
+            queue.renew_lease(krate.id, "worker-a").await?,
+            LeaseState::Renewed
+        );
+        assert_eq!(
+            queue.renew_lease(krate.id, "worker-b").await?,
+            LeaseState::Lost
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_remove_crate() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo", &V1, 0, None).await?;
+        queue.add_crate("bar", &V1, 0, None).await?;
+        queue.claim_next_crate("worker-a").await?;
+
+        // running builds have to be cancelled instead
+        assert!(!queue.remove_crate("foo", &V1).await?);
+        assert!(queue.remove_crate("bar", &V1).await?);
+        assert!(!queue.remove_crate("bar", &V1).await?);
+
+        assert!(queue.has_build_queued("foo", &V1).await?);
+        assert!(!queue.has_build_queued("bar", &V1).await?);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_request_cancellation() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo", &V1, 0, None).await?;
+        queue.add_crate("bar", &V1, 0, None).await?;
+        let krate = queue.claim_next_crate("worker-a").await?.unwrap();
+        assert_eq!(krate.name, "foo");
+
+        // only running builds can be cancelled
+        assert!(!queue.request_cancellation("bar", &V1).await?);
+        assert!(queue.request_cancellation("foo", &V1).await?);
+
+        assert_eq!(
+            queue.renew_lease(krate.id, "worker-a").await?,
+            LeaseState::CancelRequested
+        );
+
+        queue.claim_next_crate("worker-b").await?;
+        assert_eq!(queue.cancel_running_builds().await?, 1);
+        assert_eq!(queue.cancel_running_builds().await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_expired_cancelled_lease_is_removed() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo", &V1, 0, None).await?;
+        queue.claim_next_crate("worker-a").await?;
+        queue.cancel_running_builds().await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        sqlx::query!("UPDATE queue SET lease_expires_at = NOW() - INTERVAL '1 minute'")
+            .execute(&mut *conn)
+            .await?;
+
+        assert_eq!(queue.release_expired_leases().await?, 0);
+        assert_eq!(queue.pending_count().await?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancelled_build_is_removed_from_queue() -> Result<()> {
+        let env = TestEnvironment::with_config_and_runtime(
+            TestEnvironment::base_config()
+                .build_worker_name("worker-a".into())
+                .build()?,
+        )?;
+
+        let queue = env.build_queue();
+        queue.add_crate("foo", &V1, 0, None)?;
+
+        let cancellation = BuildCancellation::default();
+        queue.process_next_crate(&cancellation, |_| {
+            env.runtime().block_on(cancellation.cancel())?;
+            anyhow::bail!("the sandbox was killed");
+        })?;
+
+        // not retried, and not counted as a failure
+        assert_eq!(queue.pending_count()?, 0);
+        let workers = env.runtime().block_on(env.async_build_queue().workers())?;
+        assert_eq!(workers[0].total_builds, 1);
+        assert_eq!(workers[0].failed_builds, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancellation_is_noticed_before_lease_renewal() -> Result<()> {
+        let env = TestEnvironment::with_config_and_runtime(
+            TestEnvironment::base_config()
+                .build_worker_name("worker-a".into())
+                .build_lease_duration(Duration::from_secs(3600))
+                .build_cancellation_check_interval(Duration::from_millis(50))
+                .build()?,
+        )?;
+
+        let queue = env.build_queue();
+        queue.add_crate("foo", &V1, 0, None)?;
+
+        let cancellation = BuildCancellation::default();
+        queue.process_next_crate(&cancellation, |_| {
+            assert!(
+                env.runtime()
+                    .block_on(env.async_build_queue().request_cancellation("foo", &V1))?
+            );
+            let started = Instant::now();
+            while !cancellation.is_requested() {
+                assert!(started.elapsed() < Duration::from_secs(10));
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            anyhow::bail!("the sandbox was killed");
+        })?;
+
+        assert_eq!(queue.pending_count()?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_expired_lease_goes_back_to_queue() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .delay_between_build_attempts(Duration::ZERO)
+                .build()?,
+        )
+        .await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo", &V1, 0, None).await?;
+
+        let krate = queue.claim_next_crate("worker-a").await?.unwrap();
+        assert_eq!(krate.attempt, 0);
+        assert!(queue.claim_next_crate("worker-b").await?.is_none());
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V1)
+            .builds(vec![
+                FakeBuild::default().build_status(BuildStatus::InProgress),
+            ])
+            .create()
+            .await?;
+
+        // simulate a crashed worker that stopped renewing its lease.
+        let mut conn = env.async_db().async_conn().await;
+        sqlx::query!("UPDATE queue SET lease_expires_at = NOW() - INTERVAL '1 minute'")
+            .execute(&mut *conn)
+            .await?;
+
+        let krate = queue.claim_next_crate("worker-b").await?.unwrap();
+        assert_eq!(krate.name, "foo");
+        assert_eq!(krate.attempt, 1);
+
+        // the build of the old worker won't finish anymore.
+        let build = sqlx::query!(
+            r#"SELECT
+                builds.build_status as "build_status: BuildStatus",
+                builds.failure_kind as "failure_kind: BuildFailureKind",
+                release_build_status.build_status as "release_status: BuildStatus"
+             FROM builds
+             INNER JOIN release_build_status ON release_build_status.rid = builds.rid
+             WHERE builds.rid = $1"#,
+            rid.0,
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        assert_eq!(build.build_status, BuildStatus::Failure);
+        assert_eq!(build.failure_kind, Some(BuildFailureKind::Infrastructure));
+        assert_eq!(build.release_status, BuildStatus::Failure);
+
+        // the old worker lost its lease
+        assert_eq!(
+            queue.renew_lease(krate.id, "worker-a").await?,
+            LeaseState::Lost
+        );
+
+        let workers = queue.workers().await?;
+        assert_eq!(workers.len(), 2);
+        assert_eq!(workers[0].name, "worker-a");
+        assert_eq!(workers[0].expired_leases, 1);
+        assert_eq!(workers[1].name, "worker-b");
+        assert_eq!(workers[1].expired_leases, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lost_lease_keeps_queue_entry() -> Result<()> {
+        let env = TestEnvironment::new_with_runtime()?;
+
+        let queue = env.build_queue();
+        queue.add_crate("foo", &V1, 0, None)?;
+
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
+            // another worker took over after our lease expired.
+            env.runtime().block_on(async {
+                let mut conn = env.async_db().async_conn().await;
+                sqlx::query!(
+                    "UPDATE queue SET worker = 'other-worker' WHERE id = $1",
+                    krate.id
+                )
+                .execute(&mut *conn)
+                .await
+            })?;
+            Ok(BuildPackageSummary::default())
+        })?;
+
+        assert_eq!(queue.pending_count()?, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_worker_stats() -> Result<()> {
+        let env = TestEnvironment::with_config_and_runtime(
+            TestEnvironment::base_config()
+                .build_worker_name("worker-a".into())
+                .delay_between_build_attempts(Duration::ZERO)
+                .build()?,
+        )?;
+
+        let queue = env.build_queue();
+        queue.add_crate("foo", &V1, 0, None)?;
+        queue.add_crate("bar", &V1, 0, None)?;
+
+        queue.process_next_crate(&BuildCancellation::default(), |_| {
+            Ok(BuildPackageSummary::default())
+        })?;
+        queue.process_next_crate(&BuildCancellation::default(), |_| {
+            anyhow::bail!("simulate a failure")
+        })?;
+
+        let workers = env.runtime().block_on(env.async_build_queue().workers())?;
+        assert_eq!(workers.len(), 1);
+        assert_eq!(workers[0].name, "worker-a");
+        assert_eq!(workers[0].total_builds, 2);
+        assert_eq!(workers[0].failed_builds, 1);
+        assert_eq!(workers[0].current_build, None);
+
+        Ok(())
+    }
+
+    /// Release V1 of every crate owned by the given login, then queue V2 of them.
+    async fn queue_owned_crates(env: &TestEnvironment, owner: &str, names: &[&str]) -> Result<()> {
+        for name in names {
+            env.fake_release()
+                .await
+                .name(name)
+                .version(V1)
+                .add_owner(CrateOwner {
+                    login: owner.into(),
+                    avatar: format!("https://example.org/{owner}"),
+                    kind: OwnerKind::User,
+                })
+                .create()
+                .await?;
+            env.async_build_queue()
+                .add_crate(name, &V2, 0, None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fair_scheduling_takes_turns_between_owners() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_queue_fair_scheduling(true)
+                .build()?,
+        )
+        .await?;
+
+        queue_owned_crates(&env, "alice", &["a1", "a2", "a3"]).await?;
+        queue_owned_crates(&env, "bob", &["b1", "b2"]).await?;
+
+        let queue = env.async_build_queue();
+        assert_eq!(
+            queue
+                .queued_crates()
+                .await?
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a1", "b1", "a2", "b2", "a3"]
+        );
+
+        // running builds count towards the owner's turn
+        let first = queue.claim_next_crate("worker-a").await?.unwrap();
+        assert_eq!(first.name, "a1");
+        let second = queue.claim_next_crate("worker-b").await?.unwrap();
+        assert_eq!(second.name, "b1");
+        let third = queue.claim_next_crate("worker-c").await?.unwrap();
+        assert_eq!(third.name, "a2");
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_without_fair_scheduling_queue_order_is_kept() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        queue_owned_crates(&env, "alice", &["a1", "a2"]).await?;
+        queue_owned_crates(&env, "bob", &["b1"]).await?;
+
+        let queue = env.async_build_queue();
+        assert_eq!(
+            queue.claim_next_crate("worker-a").await?.unwrap().name,
+            "a1"
+        );
+        assert_eq!(
+            queue.claim_next_crate("worker-a").await?.unwrap().name,
+            "a2"
+        );
+        assert!(queue.wait_reasons().await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_max_builds_per_owner() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_queue_max_builds_per_owner(Some(1))
+                .build()?,
+        )
+        .await?;
+
+        queue_owned_crates(&env, "alice", &["a1", "a2"]).await?;
+        queue_owned_crates(&env, "bob", &["b1"]).await?;
+
+        let queue = env.async_build_queue();
+        assert_eq!(
+            queue.claim_next_crate("worker-a").await?.unwrap().name,
+            "a1"
+        );
+        assert_eq!(
+            queue.claim_next_crate("worker-b").await?.unwrap().name,
+            "b1"
+        );
+        assert!(queue.claim_next_crate("worker-c").await?.is_none());
+
+        assert_eq!(
+            queue.wait_reasons().await?,
+            HashMap::from([(
+                ("a2".into(), V2),
+                QueueWaitReason::OwnerLimit {
+                    owner: "alice".into(),
+                    running: 1
+                }
+            )])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_wait_reasons() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_queue_fair_scheduling(true)
+                .build()?,
+        )
+        .await?;
+
+        queue_owned_crates(&env, "alice", &["a1", "a2", "a3"]).await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("unknown", &V1, 0, None).await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        sqlx::query!(
+            "UPDATE queue
+             SET attempt = 1, last_attempt = NOW(), next_attempt = NOW() + INTERVAL '1 minute'
+             WHERE name = 'unknown'"
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        assert_eq!(
+            queue.wait_reasons().await?,
+            HashMap::from([
+                (
+                    ("a2".into(), V2),
+                    QueueWaitReason::OwnerTurn {
+                        owner: "alice".into(),
+                        ahead: 1
+                    }
+                ),
+                (
+                    ("a3".into(), V2),
+                    QueueWaitReason::OwnerTurn {
+                        owner: "alice".into(),
+                        ahead: 2
+                    }
+                ),
+                (("unknown".into(), V1), QueueWaitReason::RetryDelay),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_releases_dont_take_owner_turns() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_queue_fair_scheduling(true)
+                .build()?,
+        )
+        .await?;
+
+        queue_owned_crates(&env, "alice", &["a1", "a2"]).await?;
+
+        let queue = env.async_build_queue();
+        let mut conn = env.async_db().async_conn().await;
+        sqlx::query!(
+            "UPDATE queue SET attempt = $1 WHERE name = 'a1'",
+            queue.max_attempts
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        // `a1` stays in the queue, but isn't ahead of `a2` anymore.
+        assert!(queue.wait_reasons().await?.is_empty());
+        assert_eq!(
+            queue.claim_next_crate("worker-a").await?.unwrap().name,
+            "a2"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dependency_holds_back_release() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo-core", &V1, 0, None).await?;
+        queue.add_crate("foo-core", &V2, 0, None).await?;
+        // without waiting for its dependency, `foo` would be built first.
+        queue.add_crate("foo", &V1, -1, None).await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        assert_eq!(
+            queue
+                .add_queued_dependencies(
+                    &mut conn,
+                    "foo",
+                    &V1,
+                    &[
+                        ("foo-core".into(), VersionReq::parse("^1.0.0")?),
+                        ("unknown".into(), VersionReq::parse("^1.0.0")?),
+                    ],
+                )
+                .await?,
+            1
+        );
+
+        assert_eq!(
+            queue.wait_reasons().await?,
+            HashMap::from([(
+                ("foo".into(), V1),
+                QueueWaitReason::Dependency {
+                    name: "foo-core".into(),
+                    version: V1
+                }
+            )])
+        );
+
+        let krate = queue.claim_next_crate("worker").await?.unwrap();
+        assert_eq!((krate.name.as_str(), &krate.version), ("foo-core", &V1));
+        // the other release of the dependency doesn't match the requirement.
+        let krate = queue.claim_next_crate("worker").await?.unwrap();
+        assert_eq!((krate.name.as_str(), &krate.version), ("foo-core", &V2));
+        assert!(queue.claim_next_crate("worker").await?.is_none());
+
+        sqlx::query!("DELETE FROM queue WHERE name = 'foo-core'")
+            .execute(&mut *conn)
+            .await?;
+
+        let krate = queue.claim_next_crate("worker").await?.unwrap();
+        assert_eq!(krate.name, "foo");
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dependency_resolves_to_the_newest_matching_version() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let v1_1 = Version::new(1, 1, 0);
+        let v1_2 = Version::new(1, 2, 0);
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo-core", &V1, 0, None).await?;
+        queue.add_crate("foo-core", &v1_1, 0, None).await?;
+        queue.add_crate("foo", &V1, -1, None).await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        let dependencies = [("foo-core".into(), VersionReq::parse("^1.0.0")?)];
+        assert_eq!(
+            queue
+                .add_queued_dependencies(&mut conn, "foo", &V1, &dependencies)
+                .await?,
+            1
+        );
+        assert_eq!(
+            queue.wait_reasons().await?,
+            HashMap::from([(
+                ("foo".into(), V1),
+                QueueWaitReason::Dependency {
+                    name: "foo-core".into(),
+                    version: v1_1,
+                }
+            )])
+        );
+
+        // a newer matching release is already built, so the queued ones are never used.
+        env.fake_release()
+            .await
+            .name("foo-core")
+            .version(v1_2)
+            .create()
+            .await?;
+        queue.add_crate("bar", &V1, -1, None).await?;
+        assert_eq!(
+            queue
+                .add_queued_dependencies(&mut conn, "bar", &V1, &dependencies)
+                .await?,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dependency_timeout() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_queue_dependency_timeout(Duration::from_secs(60))
+                .build()?,
+        )
+        .await?;
+
+        let queue = env.async_build_queue();
+        queue.add_crate("foo-core", &V1, 0, None).await?;
+        queue.add_crate("foo", &V1, -1, None).await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        queue
+            .add_queued_dependencies(
+                &mut conn,
+                "foo",
+                &V1,
+                &[("foo-core".into(), VersionReq::parse("=1.0.0")?)],
+            )
+            .await?;
+
+        sqlx::query!("UPDATE queue_dependencies SET added = NOW() - INTERVAL '2 minutes'")
+            .execute(&mut *conn)
+            .await?;
+
+        assert!(queue.wait_reasons().await?.is_empty());
+        let krate = queue.claim_next_crate("worker").await?.unwrap();
+        assert_eq!(krate.name, "foo");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_dependencies() {
+        let release: CrateVersion = serde_json::from_value(serde_json::json!({
+            "name": "foo",
+            "vers": "1.0.0",
+            "yanked": false,
+            "cksum": "0000000000000000000000000000000000000000000000000000000000000000",
+            "features": {},
+            "deps": [
+                {"name": "foo-core", "req": "^1.0.0", "features": [], "optional": false, "default_features": true, "kind": "normal"},
+                {"name": "macros", "package": "foo-macros", "req": "=1.0.0", "features": [], "optional": false, "default_features": true},
+                {"name": "foo-test", "req": "^1.0.0", "features": [], "optional": false, "default_features": true, "kind": "dev"}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            build_dependencies(&release),
+            vec![
+                ("foo-core".into(), VersionReq::parse("^1.0.0").unwrap()),
+                ("foo-macros".into(), VersionReq::parse("=1.0.0").unwrap()),
+            ]
+        );
+    }
+
     #[test]
     fn test_add_long_name() -> Result<()> {
         let env = TestEnvironment::new_with_runtime()?;
@@ -1438,7 +3021,7 @@ mod tests {
 
         queue.add_crate(&name, &V1, 0, None)?;
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!(name, krate.name);
             Ok(BuildPackageSummary::default())
         })?;
@@ -1460,7 +3043,7 @@ mod tests {
 
         queue.add_crate("krate", &long_version, 0, None)?;
 
-        queue.process_next_crate(|krate| {
+        queue.process_next_crate(&BuildCancellation::default(), |krate| {
             assert_eq!(long_version, krate.version);
             Ok(BuildPackageSummary::default())
         })?;
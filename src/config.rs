@@ -1,4 +1,7 @@
-use crate::{cdn::cloudfront::CdnKind, storage::StorageKind};
+use crate::{
+    cdn::cloudfront::CdnKind,
+    storage::{CompressionAlgorithm, StorageKind},
+};
 use anyhow::{Context, Result, anyhow, bail};
 use std::{env::VarError, error::Error, io, path, path::PathBuf, str::FromStr, time::Duration};
 use tracing::trace;
@@ -85,6 +88,28 @@ pub struct Config {
     // for the remote archives?
     pub(crate) local_archive_cache_path: PathBuf,
 
+    // where the filesystem storage backend keeps the blobs
+    pub(crate) filesystem_storage_path: PathBuf,
+
+    // Size of the local disk cache for blobs from the storage backend, in bytes.
+    // When empty, blobs are always fetched from the backend.
+    pub(crate) storage_cache_size: Option<u64>,
+    // where the local disk cache keeps the blobs
+    pub(crate) storage_cache_path: PathBuf,
+    // Blobs bigger than this are never cached, in bytes.
+    pub(crate) storage_cache_max_entry_size: u64,
+
+    // Files in archives at least this big are stored once per content, as blobs shared
+    // between archives, in bytes. When empty, archives contain all their files.
+    pub(crate) content_addressed_min_size: Option<u64>,
+
+    // Compress uploaded files with the newest trained zstd dictionary, when there is one.
+    pub(crate) compression_dictionary: bool,
+
+    // How the files in archives are compressed, when they aren't compressed with a
+    // zstd dictionary.
+    pub(crate) archive_compression: CompressionAlgorithm,
+
     // Where to collect metrics for the metrics initiative.
     // When empty, we won't collect metrics.
     pub(crate) compiler_metrics_collection_path: Option<PathBuf>,
@@ -131,18 +156,66 @@ pub struct Config {
 
     // Build params
     pub(crate) build_attempts: u16,
+    /// Delay before retrying a build after an infrastructure failure.
+    /// Doubles with every failed attempt, up to `max_delay_between_build_attempts`.
     pub(crate) delay_between_build_attempts: Duration,
+    pub(crate) max_delay_between_build_attempts: Duration,
+    /// Name this build worker uses when claiming releases from the queue.
+    /// Has to be unique across all build servers, defaults to the hostname.
+    pub(crate) build_worker_name: String,
+    /// How long a claimed queue entry stays reserved for a worker without a heartbeat.
+    /// After the lease expired, another worker can pick up the release again.
+    pub(crate) build_lease_duration: Duration,
+    /// How often a running build checks whether it was cancelled.
+    pub(crate) build_cancellation_check_interval: Duration,
+    /// How often the log of a running build is copied to storage, and how often the live
+    /// build log on the build details page checks for new lines.
+    pub(crate) build_log_live_interval: Duration,
+    /// How long a live build log stays connected. Clients reconnect afterwards, and only get
+    /// the lines they don't have yet.
+    pub(crate) build_log_live_max_duration: Duration,
+    /// How often the resource usage of the sandbox is sampled during a build.
+    pub(crate) build_resource_sample_interval: Duration,
+    /// Take turns between crate owners when picking the next release with the same priority,
+    /// instead of building the queue strictly in order.
+    pub(crate) build_queue_fair_scheduling: bool,
+    /// Maximum number of releases of one owner that are built at the same time.
+    pub(crate) build_queue_max_builds_per_owner: Option<u16>,
+    /// How long a queued release waits for dependencies that were published together with it,
+    /// before it's built anyway.
+    pub(crate) build_queue_dependency_timeout: Duration,
+    /// How many of the most downloaded crates are built when trying a new toolchain.
+    pub(crate) toolchain_canary_sample_size: usize,
+    /// Share of previously successful builds that may fail with a new toolchain,
+    /// for the canary to still switch to it.
+    pub(crate) toolchain_canary_max_regression_rate: f64,
+    /// Try a newly configured toolchain with a canary before builds use it.
+    pub(crate) toolchain_canary_on_update: bool,
     pub(crate) rustwide_workspace: PathBuf,
     pub(crate) temp_dir: PathBuf,
     pub(crate) inside_docker: bool,
     pub(crate) docker_image: Option<String>,
     pub(crate) build_cpu_limit: Option<u32>,
     pub(crate) build_default_memory_limit: Option<usize>,
+    /// Maximum size in bytes of the cache of compiled dependencies that is shared between
+    /// builds. Builds don't use the cache when this is not set.
+    pub(crate) build_artifact_cache_size: Option<u64>,
+    /// How many non-default targets of a release are built at the same time.
+    pub(crate) build_max_parallel_targets: usize,
+    /// Builds that run out of memory or time are retried once with doubled limits,
+    /// up to these ceilings.
+    pub(crate) build_limit_ceiling_memory: usize,
+    pub(crate) build_limit_ceiling_timeout: Duration,
     pub(crate) include_default_targets: bool,
     pub(crate) disable_memory_limit: bool,
 
     // automatic rebuild configuration
     pub(crate) max_queued_rebuilds: Option<u16>,
+    /// How many retries of failed releases can be queued, on top of `max_queued_rebuilds`.
+    /// Failed releases are never retried when this is not set.
+    pub(crate) max_queued_failed_rebuilds: Option<u16>,
+    /// Failed releases are only retried when their last build is older than this.
+    pub(crate) failed_rebuild_min_age: Duration,
 
     // opentelemetry endpoint to send OTLP to
     pub(crate) opentelemetry_endpoint: Option<Url>,
@@ -172,12 +245,63 @@ impl Config {
         let prefix: PathBuf = require_env("DOCSRS_PREFIX")?;
         let temp_dir = prefix.join("tmp");
 
+        let archive_compression = env("DOCSRS_ARCHIVE_COMPRESSION", CompressionAlgorithm::Bzip2)?;
+        if archive_compression == CompressionAlgorithm::ZstdDictionary {
+            bail!(
+                "DOCSRS_ARCHIVE_COMPRESSION can't be {archive_compression}, \
+                 use DOCSRS_COMPRESSION_DICTIONARY instead"
+            );
+        }
+
+        // the lease is renewed every third of it, which needs a duration.
+        let build_lease_duration = env::<u64>("DOCSRS_BUILD_LEASE_DURATION", 300)?;
+        if build_lease_duration == 0 {
+            bail!("DOCSRS_BUILD_LEASE_DURATION must be at least one second");
+        }
+
         Ok(ConfigBuilder::default()
             .build_attempts(env("DOCSRS_BUILD_ATTEMPTS", 5u16)?)
             .delay_between_build_attempts(Duration::from_secs(env::<u64>(
                 "DOCSRS_DELAY_BETWEEN_BUILD_ATTEMPTS",
                 60,
             )?))
+            .max_delay_between_build_attempts(Duration::from_secs(env::<u64>(
+                "DOCSRS_MAX_DELAY_BETWEEN_BUILD_ATTEMPTS",
+                3600,
+            )?))
+            .build_worker_name(env(
+                "DOCSRS_BUILD_WORKER_NAME",
+                hostname::get()?.to_string_lossy().into_owned(),
+            )?)
+            .build_lease_duration(Duration::from_secs(build_lease_duration))
+            .build_cancellation_check_interval(Duration::from_secs(env::<u64>(
+                "DOCSRS_BUILD_CANCELLATION_CHECK_INTERVAL",
+                10,
+            )?))
+            .build_log_live_interval(Duration::from_secs(env::<u64>(
+                "DOCSRS_BUILD_LOG_LIVE_INTERVAL",
+                5,
+            )?))
+            .build_log_live_max_duration(Duration::from_secs(env::<u64>(
+                "DOCSRS_BUILD_LOG_LIVE_MAX_DURATION",
+                1800,
+            )?))
+            .build_resource_sample_interval(Duration::from_secs(env::<u64>(
+                "DOCSRS_BUILD_RESOURCE_SAMPLE_INTERVAL",
+                5,
+            )?))
+            .build_queue_fair_scheduling(env("DOCSRS_BUILD_QUEUE_FAIR_SCHEDULING", false)?)
+            .build_queue_max_builds_per_owner(maybe_env("DOCSRS_BUILD_QUEUE_MAX_BUILDS_PER_OWNER")?)
+            .build_queue_dependency_timeout(Duration::from_secs(env::<u64>(
+                "DOCSRS_BUILD_QUEUE_DEPENDENCY_TIMEOUT",
+                1800,
+            )?))
+            .toolchain_canary_sample_size(env("DOCSRS_TOOLCHAIN_CANARY_SAMPLE_SIZE", 100)?)
+            .toolchain_canary_max_regression_rate(env(
+                "DOCSRS_TOOLCHAIN_CANARY_MAX_REGRESSION_RATE",
+                0.02,
+            )?)
+            .toolchain_canary_on_update(env("DOCSRS_TOOLCHAIN_CANARY_ON_UPDATE", true)?)
             .delay_between_registry_fetches(Duration::from_secs(env::<u64>(
                 "DOCSRS_DELAY_BETWEEN_REGISTRY_FETCHES",
                 60,
@@ -233,6 +357,19 @@ impl Config {
                 "DOCSRS_ARCHIVE_INDEX_CACHE_PATH",
                 prefix.join("archive_cache"),
             )?)?)
+            .filesystem_storage_path(ensure_absolute_path(env(
+                "DOCSRS_FILESYSTEM_STORAGE_PATH",
+                prefix.join("storage"),
+            )?)?)
+            .storage_cache_size(maybe_env("DOCSRS_STORAGE_CACHE_SIZE")?)
+            .storage_cache_path(ensure_absolute_path(env(
+                "DOCSRS_STORAGE_CACHE_PATH",
+                prefix.join("storage_cache"),
+            )?)?)
+            .storage_cache_max_entry_size(env("DOCSRS_STORAGE_CACHE_MAX_ENTRY_SIZE", 1024 * 1024)?)
+            .content_addressed_min_size(maybe_env("DOCSRS_CONTENT_ADDRESSED_MIN_SIZE")?)
+            .compression_dictionary(env("DOCSRS_COMPRESSION_DICTIONARY", false)?)
+            .archive_compression(archive_compression)
             .compiler_metrics_collection_path(maybe_env("DOCSRS_COMPILER_METRICS_PATH")?)
             .temp_dir(temp_dir)
             .rustwide_workspace(env(
@@ -245,13 +382,27 @@ impl Config {
             )
             .build_cpu_limit(maybe_env("DOCSRS_BUILD_CPU_LIMIT")?)
             .build_default_memory_limit(maybe_env("DOCSRS_BUILD_DEFAULT_MEMORY_LIMIT")?)
+            .build_artifact_cache_size(maybe_env("DOCSRS_BUILD_ARTIFACT_CACHE_SIZE")?)
+            .build_max_parallel_targets(env("DOCSRS_BUILD_MAX_PARALLEL_TARGETS", 1)?)
+            .build_limit_ceiling_memory(env(
+                "DOCSRS_BUILD_LIMIT_CEILING_MEMORY",
+                8 * 1024 * 1024 * 1024,
+            )?)
+            .build_limit_ceiling_timeout(Duration::from_secs(env(
+                "DOCSRS_BUILD_LIMIT_CEILING_TIMEOUT",
+                60 * 60,
+            )?))
             .include_default_targets(env("DOCSRS_INCLUDE_DEFAULT_TARGETS", true)?)
             .disable_memory_limit(env("DOCSRS_DISABLE_MEMORY_LIMIT", false)?)
             .build_workspace_reinitialization_interval(Duration::from_secs(env(
                 "DOCSRS_BUILD_WORKSPACE_REINITIALIZATION_INTERVAL",
                 86400,
             )?))
-            .max_queued_rebuilds(maybe_env("DOCSRS_MAX_QUEUED_REBUILDS")?))
+            .max_queued_rebuilds(maybe_env("DOCSRS_MAX_QUEUED_REBUILDS")?)
+            .max_queued_failed_rebuilds(maybe_env("DOCSRS_MAX_QUEUED_FAILED_REBUILDS")?)
+            .failed_rebuild_min_age(Duration::from_secs(
+                env::<u64>("DOCSRS_FAILED_REBUILD_MIN_AGE_DAYS", 30)? * 24 * 60 * 60,
+            )))
     }
 }
 
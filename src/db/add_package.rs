@@ -1,9 +1,9 @@
 use crate::{
     db::types::{
-        BuildId, BuildStatus, CrateId, Feature, ReleaseId, dependencies::ReleaseDependencyList,
-        version::Version,
+        BuildFailureKind, BuildId, BuildStatus, CrateId, Feature, ReleaseId,
+        dependencies::ReleaseDependencyList, version::Version,
     },
-    docbuilder::DocCoverage,
+    docbuilder::{DocCoverage, Limits, ResourceUsage},
     error::Result,
     registry_api::{CrateData, CrateOwner, ReleaseData},
     storage::CompressionAlgorithm,
@@ -11,11 +11,13 @@ use crate::{
     web::crate_details::{latest_release, releases_for_crate},
 };
 use anyhow::{Context, anyhow};
+use chrono::{DateTime, Utc};
 use futures_util::stream::TryStreamExt;
 use serde_json::Value;
 use slug::slugify;
+use sqlx::Connection as _;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
     io::{BufRead, BufReader},
     path::Path,
@@ -43,6 +45,7 @@ pub(crate) async fn finish_release(
     has_docs: bool,
     has_examples: bool,
     compression_algorithms: impl IntoIterator<Item = CompressionAlgorithm>,
+    compression_dictionary: Option<u32>,
     repository_id: Option<i32>,
     archive_storage: bool,
     source_size: u64,
@@ -120,7 +123,13 @@ pub(crate) async fn finish_release(
     }
 
     add_keywords_into_database(conn, metadata_pkg, release_id).await?;
-    add_compression_into_database(conn, compression_algorithms.into_iter(), release_id).await?;
+    add_compression_into_database(
+        conn,
+        compression_algorithms.into_iter(),
+        compression_dictionary,
+        release_id,
+    )
+    .await?;
 
     update_latest_version_id(&mut *conn, crate_id)
         .await
@@ -162,6 +171,7 @@ pub async fn update_build_status(
          CASE
            WHEN summary.success_count > 0 THEN 'success'::build_status
            WHEN summary.failure_count > 0 THEN 'failure'::build_status
+           WHEN summary.cancelled_count > 0 THEN 'cancelled'::build_status
            ELSE 'in_progress'::build_status
          END as build_status
 
@@ -170,7 +180,8 @@ pub async fn update_build_status(
                r.id,
                MAX(b.build_finished) as last_build_time,
                SUM(CASE WHEN b.build_status = 'success' THEN 1 ELSE 0 END) as success_count,
-               SUM(CASE WHEN b.build_status = 'failure' THEN 1 ELSE 0 END) as failure_count
+               SUM(CASE WHEN b.build_status = 'failure' THEN 1 ELSE 0 END) as failure_count,
+               SUM(CASE WHEN b.build_status = 'cancelled' THEN 1 ELSE 0 END) as cancelled_count
              FROM
                releases as r
                LEFT OUTER JOIN builds AS b on b.rid = r.id
@@ -242,6 +253,7 @@ pub(crate) async fn add_doc_coverage(
 }
 
 /// Adds a build into database
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip(conn))]
 pub(crate) async fn finish_build(
     conn: &mut sqlx::PgConnection,
@@ -249,6 +261,7 @@ pub(crate) async fn finish_build(
     rustc_version: &str,
     docsrs_version: &str,
     build_status: BuildStatus,
+    failure_kind: Option<BuildFailureKind>,
     documentation_size: Option<u64>,
     errors: Option<&str>,
 ) -> Result<()> {
@@ -279,9 +292,10 @@ pub(crate) async fn finish_build(
              errors = $5,
              documentation_size = $6,
              rustc_nightly_date = $7,
+             failure_kind = $8,
              build_finished = NOW()
          WHERE
-            id = $8
+            id = $9
          RETURNING rid as "rid: ReleaseId" "#,
         rustc_version,
         docsrs_version,
@@ -290,6 +304,7 @@ pub(crate) async fn finish_build(
         errors,
         documentation_size.map(|v| v as i64),
         rustc_date,
+        failure_kind as Option<BuildFailureKind>,
         build_id.0,
     )
     .fetch_one(&mut *conn)
@@ -300,10 +315,126 @@ pub(crate) async fn finish_build(
     Ok(())
 }
 
+/// Record the limits and targets a build uses, so it can be reproduced later.
+#[instrument(skip(conn))]
+pub(crate) async fn record_build_configuration(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    limits: &Limits,
+    targets: &[&str],
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE builds SET limits = $2, targets = $3 WHERE id = $1",
+        build_id.0,
+        serde_json::to_value(limits)?,
+        targets as _,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Record the environment variables per target and the `cfg`s a build uses.
+#[instrument(skip(conn))]
+pub(crate) async fn record_build_environment(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    env: &BTreeMap<String, BTreeMap<String, String>>,
+    cfgs: &[String],
+) -> Result<()> {
+    sqlx::query!(
+        "UPDATE builds SET env = $2, cfgs = $3 WHERE id = $1",
+        build_id.0,
+        serde_json::to_value(env)?,
+        cfgs,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Record the outcome and resource usage of one target of a build, and update the
+/// resource usage of the whole build.
+///
+/// The default target can be built twice, the second attempt replaces the first one.
+#[instrument(skip(conn))]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn record_build_target(
+    conn: &mut sqlx::PgConnection,
+    build_id: BuildId,
+    target: &str,
+    build_status: BuildStatus,
+    failure_kind: Option<BuildFailureKind>,
+    build_started: DateTime<Utc>,
+    errors: Option<&str>,
+    resource_usage: &ResourceUsage,
+) -> Result<()> {
+    let mut transaction = conn.begin().await?;
+
+    sqlx::query!(
+        "INSERT INTO build_targets (
+             build_id, target, build_status, failure_kind, build_started, build_finished, errors,
+             peak_memory, cpu_time, disk_usage, network_bytes
+         )
+         VALUES ($1, $2, $3, $4, $5, NOW(), $6, $7, $8, $9, $10)
+         ON CONFLICT (build_id, target) DO UPDATE
+         SET
+             build_status = EXCLUDED.build_status,
+             failure_kind = EXCLUDED.failure_kind,
+             build_started = EXCLUDED.build_started,
+             build_finished = EXCLUDED.build_finished,
+             errors = EXCLUDED.errors,
+             peak_memory = EXCLUDED.peak_memory,
+             cpu_time = EXCLUDED.cpu_time,
+             disk_usage = EXCLUDED.disk_usage,
+             network_bytes = EXCLUDED.network_bytes",
+        build_id.0,
+        target,
+        build_status as BuildStatus,
+        failure_kind as Option<BuildFailureKind>,
+        build_started,
+        errors,
+        resource_usage.peak_memory.map(|bytes| bytes as i64),
+        resource_usage.cpu_time.map(|time| time.as_secs_f32()),
+        resource_usage.disk_usage.map(|bytes| bytes as i64),
+        resource_usage.network_bytes.map(|bytes| bytes as i64),
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    // targets that are built one after the other share their target directory, so the
+    // disk usage of the last target already contains the ones before.
+    sqlx::query!(
+        "UPDATE builds
+         SET
+             peak_memory = usage.peak_memory,
+             cpu_time = usage.cpu_time,
+             disk_usage = usage.disk_usage,
+             network_bytes = usage.network_bytes
+         FROM (
+             SELECT
+                 MAX(peak_memory) AS peak_memory,
+                 SUM(cpu_time) AS cpu_time,
+                 MAX(disk_usage) AS disk_usage,
+                 SUM(network_bytes) AS network_bytes
+             FROM build_targets
+             WHERE build_id = $1
+         ) AS usage
+         WHERE builds.id = $1",
+        build_id.0,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
 #[instrument(skip(conn))]
 pub(crate) async fn update_build_with_error(
     conn: &mut sqlx::PgConnection,
     build_id: BuildId,
+    failure_kind: BuildFailureKind,
     errors: Option<&str>,
 ) -> Result<BuildId> {
     debug!("updating build with error");
@@ -311,10 +442,12 @@ pub(crate) async fn update_build_with_error(
         r#"UPDATE builds
          SET
              build_status = $1,
-             errors = $2
-         WHERE id = $3
+             failure_kind = $2,
+             errors = $3
+         WHERE id = $4
          RETURNING rid as "rid: ReleaseId" "#,
         BuildStatus::Failure as BuildStatus,
+        failure_kind as BuildFailureKind,
         errors,
         build_id.0,
     )
@@ -326,6 +459,28 @@ pub(crate) async fn update_build_with_error(
     Ok(build_id)
 }
 
+/// Finish a build that was cancelled while it was running.
+pub(crate) async fn cancel_build(conn: &mut sqlx::PgConnection, build_id: BuildId) -> Result<()> {
+    debug!("updating build after cancellation");
+    let release_id = sqlx::query_scalar!(
+        r#"UPDATE builds
+         SET
+             build_status = $1,
+             build_finished = NOW(),
+             errors = 'the build was cancelled'
+         WHERE id = $2
+         RETURNING rid as "rid: ReleaseId" "#,
+        BuildStatus::Cancelled as BuildStatus,
+        build_id.0,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    update_build_status(conn, release_id).await?;
+
+    Ok(())
+}
+
 pub(crate) async fn initialize_crate(conn: &mut sqlx::PgConnection, name: &str) -> Result<CrateId> {
     sqlx::query_scalar!(
         "INSERT INTO crates (name)
@@ -597,22 +752,28 @@ async fn update_owners_in_database(
     Ok(())
 }
 
-/// Add the compression algorithms used for this crate to the database
+/// Add the compression algorithms used for this crate to the database, with the dictionary
+/// for `ZstdDictionary`.
 async fn add_compression_into_database<I>(
     conn: &mut sqlx::PgConnection,
     algorithms: I,
+    dictionary: Option<u32>,
     release_id: ReleaseId,
 ) -> Result<()>
 where
     I: Iterator<Item = CompressionAlgorithm>,
 {
     for alg in algorithms {
+        let dictionary_id = dictionary
+            .filter(|_| alg == CompressionAlgorithm::ZstdDictionary)
+            .map(i64::from);
         sqlx::query!(
-            "INSERT INTO compression_rels (release, algorithm)
-             VALUES ($1, $2)
+            "INSERT INTO compression_rels (release, algorithm, dictionary_id)
+             VALUES ($1, $2, $3)
              ON CONFLICT DO NOTHING;",
             release_id.0,
-            &(alg as i32)
+            &(alg as i32),
+            dictionary_id,
         )
         .execute(&mut *conn)
         .await?;
@@ -638,7 +799,13 @@ mod test {
             let release_id = initialize_release(&mut conn, crate_id, &V0_1).await?;
             let build_id = initialize_build(&mut conn, release_id).await?;
 
-            update_build_with_error(&mut conn, build_id, Some("error message")).await?;
+            update_build_with_error(
+                &mut conn,
+                build_id,
+                BuildFailureKind::Infrastructure,
+                Some("error message"),
+            )
+            .await?;
 
             let row = sqlx::query!(
                 r#"SELECT
@@ -646,6 +813,7 @@ mod test {
                 docsrs_version,
                 build_started,
                 build_status as "build_status: BuildStatus",
+                failure_kind as "failure_kind: BuildFailureKind",
                 errors
                 FROM builds
                 WHERE id = $1"#,
@@ -658,12 +826,51 @@ mod test {
             assert!(row.docsrs_version.is_none());
             assert!(row.build_started.is_some());
             assert_eq!(row.build_status, BuildStatus::Failure);
+            assert_eq!(row.failure_kind, Some(BuildFailureKind::Infrastructure));
             assert_eq!(row.errors, Some("error message".into()));
 
             Ok(())
         })
     }
 
+    #[test]
+    fn test_cancel_build() {
+        async_wrapper(|env| async move {
+            let mut conn = env.async_db().async_conn().await;
+            let crate_id = initialize_crate(&mut conn, "krate").await?;
+            let release_id = initialize_release(&mut conn, crate_id, &V0_1).await?;
+            let build_id = initialize_build(&mut conn, release_id).await?;
+
+            cancel_build(&mut conn, build_id).await?;
+
+            let row = sqlx::query!(
+                r#"SELECT
+                build_finished,
+                build_status as "build_status: BuildStatus"
+                FROM builds
+                WHERE id = $1"#,
+                build_id.0
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+
+            assert!(row.build_finished.is_some());
+            assert_eq!(row.build_status, BuildStatus::Cancelled);
+
+            let release_status = sqlx::query_scalar!(
+                r#"SELECT build_status as "build_status: BuildStatus"
+                FROM release_build_status
+                WHERE rid = $1"#,
+                release_id.0
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            assert_eq!(release_status, BuildStatus::Cancelled);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_finish_build_success_valid_rustc_date() {
         async_wrapper(|env| async move {
@@ -680,6 +887,7 @@ mod test {
                 BuildStatus::Success,
                 None,
                 None,
+                None,
             )
             .await?;
 
@@ -727,6 +935,7 @@ mod test {
                 "rustc_version",
                 "docsrs_version",
                 BuildStatus::Success,
+                None,
                 Some(42),
                 None,
             )
@@ -772,6 +981,7 @@ mod test {
                 "rustc_version",
                 "docsrs_version",
                 BuildStatus::Failure,
+                Some(BuildFailureKind::Rustdoc),
                 None,
                 Some("error message"),
             )
@@ -782,6 +992,7 @@ mod test {
                 rustc_version,
                 docsrs_version,
                 build_status as "build_status: BuildStatus",
+                failure_kind as "failure_kind: BuildFailureKind",
                 documentation_size,
                 errors
                 FROM builds
@@ -794,6 +1005,7 @@ mod test {
             assert_eq!(row.rustc_version, Some("rustc_version".into()));
             assert_eq!(row.docsrs_version, Some("docsrs_version".into()));
             assert_eq!(row.build_status, BuildStatus::Failure);
+            assert_eq!(row.failure_kind, Some(BuildFailureKind::Rustdoc));
             assert_eq!(row.errors, Some("error message".into()));
             assert!(row.documentation_size.is_none());
 
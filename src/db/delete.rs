@@ -12,7 +12,8 @@ use super::{CrateId, update_latest_version_id};
 
 /// List of directories in docs.rs's underlying storage (either the database or S3) containing a
 /// subdirectory named after the crate. Those subdirectories will be deleted.
-static LIBRARY_STORAGE_PATHS_TO_DELETE: &[&str] = &["rustdoc", "rustdoc-json", "sources"];
+static LIBRARY_STORAGE_PATHS_TO_DELETE: &[&str] =
+    &["rustdoc", "rustdoc-json", "coverage", "sources"];
 static OTHER_STORAGE_PATHS_TO_DELETE: &[&str] = &["sources"];
 
 #[derive(Debug, thiserror::Error)]
@@ -181,7 +182,6 @@ async fn delete_crate_from_database(
     sqlx::query!("DELETE FROM sandbox_overrides WHERE crate_name = $1", name,)
         .execute(&mut *transaction)
         .await?;
-
     for &(table, column) in METADATA {
         sqlx::query(
             format!(
@@ -221,10 +221,12 @@ async fn delete_crate_from_database(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::ReleaseId;
+    use crate::db::{BuildId, ReleaseId, record_build_target, types::BuildStatus};
+    use crate::docbuilder::ResourceUsage;
     use crate::registry_api::{CrateOwner, OwnerKind};
     use crate::storage::{CompressionAlgorithm, rustdoc_json_path};
     use crate::test::{V1, V2, async_wrapper, fake_release_that_failed_before_build};
+    use chrono::Utc;
     use test_case::test_case;
 
     async fn crate_exists(conn: &mut sqlx::PgConnection, name: &str) -> Result<bool> {
@@ -508,6 +510,47 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_delete_version_deletes_build_targets() {
+        async_wrapper(|env| async move {
+            let mut conn = env.async_db().async_conn().await;
+            let rid = env
+                .fake_release()
+                .await
+                .name("a")
+                .version(V1)
+                .create()
+                .await?;
+            let build_id = sqlx::query_scalar!(
+                r#"SELECT id as "id: BuildId" FROM builds WHERE rid = $1"#,
+                rid.0
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            record_build_target(
+                &mut conn,
+                build_id,
+                "x86_64-unknown-linux-gnu",
+                BuildStatus::Success,
+                None,
+                Utc::now(),
+                None,
+                &ResourceUsage::default(),
+            )
+            .await?;
+
+            delete_version(&mut conn, env.async_storage(), env.config(), "a", &V1).await?;
+
+            // the targets are deleted with their build.
+            let count = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM build_targets"#)
+                .fetch_one(&mut *conn)
+                .await?;
+            assert_eq!(count, 0);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_delete_incomplete_version() {
         async_wrapper(|env| async move {
@@ -0,0 +1,338 @@
+use crate::{db::Overrides, error::Result};
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use futures_util::stream::TryStreamExt;
+use sqlx::Connection as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "limit_request_status", rename_all = "snake_case")]
+pub enum LimitRequestStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// A request of a crate owner for higher sandbox limits.
+///
+/// Owners submit requests through crates.io, approving a request changes the
+/// [`Overrides`] of the crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitRequest {
+    pub id: i32,
+    pub crate_name: String,
+    /// crates.io login of the owner who requested the limits.
+    pub requested_by: String,
+    pub justification: String,
+    /// the requested limits, limits that are `None` keep their current value.
+    pub limits: Overrides,
+    pub status: LimitRequestStatus,
+    pub created_at: DateTime<Utc>,
+    pub reviewed_by: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+}
+
+macro_rules! row_to_limit_request {
+    ($row:expr) => {{
+        LimitRequest {
+            id: $row.id,
+            crate_name: $row.crate_name,
+            requested_by: $row.requested_by,
+            justification: $row.justification,
+            limits: Overrides::from_columns(
+                $row.max_memory_bytes,
+                $row.timeout_seconds,
+                $row.max_targets,
+            ),
+            status: $row.status,
+            created_at: $row.created_at,
+            reviewed_by: $row.reviewed_by,
+            reviewed_at: $row.reviewed_at,
+        }
+    }};
+}
+
+impl LimitRequest {
+    /// Returns `None` when the crate already has a pending request, a crate has at most one.
+    pub(crate) async fn create(
+        conn: &mut sqlx::PgConnection,
+        crate_name: &str,
+        requested_by: &str,
+        justification: &str,
+        limits: Overrides,
+    ) -> Result<Option<i32>> {
+        Ok(sqlx::query_scalar!(
+            "INSERT INTO limit_requests (
+                 crate_name, requested_by, justification,
+                 max_memory_bytes, timeout_seconds, max_targets
+             )
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (crate_name) WHERE status = 'pending' DO NOTHING
+             RETURNING id",
+            crate_name,
+            requested_by,
+            justification,
+            limits.memory.map(|i| i as i64),
+            limits.timeout.map(|d| d.as_secs() as i32),
+            limits.targets.map(|i| i as i32),
+        )
+        .fetch_optional(conn)
+        .await?)
+    }
+
+    pub async fn get(conn: &mut sqlx::PgConnection, id: i32) -> Result<Option<Self>> {
+        Ok(sqlx::query!(
+            r#"SELECT
+                 id, crate_name, requested_by, justification,
+                 max_memory_bytes, timeout_seconds, max_targets,
+                 status as "status: LimitRequestStatus",
+                 created_at, reviewed_by, reviewed_at
+             FROM limit_requests
+             WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(conn)
+        .await?
+        .map(|row| row_to_limit_request!(row)))
+    }
+
+    /// All requests that wait for a review, oldest first.
+    pub async fn pending(conn: &mut sqlx::PgConnection) -> Result<Vec<Self>> {
+        Ok(sqlx::query!(
+            r#"SELECT
+                 id, crate_name, requested_by, justification,
+                 max_memory_bytes, timeout_seconds, max_targets,
+                 status as "status: LimitRequestStatus",
+                 created_at, reviewed_by, reviewed_at
+             FROM limit_requests
+             WHERE status = 'pending'
+             ORDER BY id"#
+        )
+        .fetch(conn)
+        .map_ok(|row| row_to_limit_request!(row))
+        .try_collect()
+        .await?)
+    }
+
+    /// Approve a pending request, and raise the overrides of the crate to the requested limits.
+    ///
+    /// Returns the new overrides of the crate.
+    pub async fn approve(conn: &mut sqlx::PgConnection, id: i32, admin: &str) -> Result<Overrides> {
+        let mut transaction = conn.begin().await?;
+        let request =
+            Self::review(&mut transaction, id, admin, LimitRequestStatus::Approved).await?;
+
+        let current = Overrides::for_crate(&mut transaction, &request.crate_name)
+            .await?
+            .unwrap_or_default();
+        // limits below the current overrides don't lower them.
+        let overrides = Overrides {
+            memory: request.limits.memory.max(current.memory),
+            targets: request.limits.targets.max(current.targets),
+            timeout: request.limits.timeout.max(current.timeout),
+        };
+        Overrides::change_for_request(
+            &mut transaction,
+            &request.crate_name,
+            Some(overrides),
+            admin,
+            Some(id),
+        )
+        .await?;
+
+        transaction.commit().await?;
+        Ok(overrides)
+    }
+
+    /// Reject a pending request, the overrides of the crate stay the same.
+    pub async fn reject(conn: &mut sqlx::PgConnection, id: i32, admin: &str) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        Self::review(&mut transaction, id, admin, LimitRequestStatus::Rejected).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn review(
+        conn: &mut sqlx::PgConnection,
+        id: i32,
+        admin: &str,
+        status: LimitRequestStatus,
+    ) -> Result<Self> {
+        // lock the request, so it can only be reviewed once.
+        let current_status = sqlx::query_scalar!(
+            r#"SELECT status as "status: LimitRequestStatus"
+             FROM limit_requests
+             WHERE id = $1
+             FOR UPDATE"#,
+            id
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        match current_status {
+            None => bail!("limit request {id} doesn't exist"),
+            Some(LimitRequestStatus::Pending) => {}
+            Some(status) => bail!("limit request {id} was already reviewed: {status:?}"),
+        }
+
+        sqlx::query!(
+            "UPDATE limit_requests
+             SET status = $2, reviewed_by = $3, reviewed_at = NOW()
+             WHERE id = $1",
+            id,
+            status as LimitRequestStatus,
+            admin,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(Self::get(conn, id)
+            .await?
+            .expect("the request was locked above"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::async_wrapper;
+    use std::time::Duration;
+
+    #[test]
+    fn approve_raises_overrides() {
+        async_wrapper(|env| async move {
+            let mut conn = env.async_db().async_conn().await;
+
+            let existing = Overrides {
+                memory: Some(4_000_000_000),
+                targets: Some(1),
+                timeout: None,
+            };
+            Overrides::save(&mut conn, "foo", existing).await?;
+
+            let requested = Overrides {
+                memory: Some(8_000_000_000),
+                timeout: Some(Duration::from_secs(3600)),
+                targets: None,
+            };
+            let id = LimitRequest::create(&mut conn, "foo", "owner", "large build", requested)
+                .await?
+                .unwrap();
+            // only one pending request per crate.
+            assert_eq!(
+                LimitRequest::create(&mut conn, "foo", "owner", "again", requested).await?,
+                None
+            );
+            assert_eq!(
+                LimitRequest::pending(&mut conn)
+                    .await?
+                    .into_iter()
+                    .map(|request| request.id)
+                    .collect::<Vec<_>>(),
+                vec![id]
+            );
+
+            let expected = Overrides {
+                memory: Some(8_000_000_000),
+                targets: Some(1),
+                timeout: Some(Duration::from_secs(3600)),
+            };
+            assert_eq!(
+                LimitRequest::approve(&mut conn, id, "admin").await?,
+                expected
+            );
+            assert_eq!(
+                Overrides::for_crate(&mut conn, "foo").await?,
+                Some(expected)
+            );
+
+            let request = LimitRequest::get(&mut conn, id).await?.unwrap();
+            assert_eq!(request.status, LimitRequestStatus::Approved);
+            assert_eq!(request.reviewed_by.as_deref(), Some("admin"));
+            assert!(request.reviewed_at.is_some());
+            assert!(LimitRequest::pending(&mut conn).await?.is_empty());
+
+            let history = Overrides::history(&mut conn, "foo").await?;
+            assert_eq!(history.len(), 1);
+            assert_eq!(history[0].changed_by, "admin");
+            assert_eq!(history[0].limit_request_id, Some(id));
+            assert_eq!(history[0].old, Some(existing));
+            assert_eq!(history[0].new, Some(expected));
+
+            // a request can only be reviewed once.
+            assert!(LimitRequest::approve(&mut conn, id, "admin").await.is_err());
+            assert!(LimitRequest::reject(&mut conn, id, "admin").await.is_err());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn approve_doesnt_lower_overrides() {
+        async_wrapper(|env| async move {
+            let mut conn = env.async_db().async_conn().await;
+
+            let existing = Overrides {
+                memory: Some(8_000_000_000),
+                targets: Some(1),
+                timeout: None,
+            };
+            Overrides::save(&mut conn, "foo", existing).await?;
+
+            let requested = Overrides {
+                memory: Some(4_000_000_000),
+                targets: Some(10),
+                timeout: None,
+            };
+            let id = LimitRequest::create(&mut conn, "foo", "owner", "all targets", requested)
+                .await?
+                .unwrap();
+
+            let expected = Overrides {
+                memory: Some(8_000_000_000),
+                targets: Some(10),
+                timeout: None,
+            };
+            assert_eq!(
+                LimitRequest::approve(&mut conn, id, "admin").await?,
+                expected
+            );
+            assert_eq!(
+                Overrides::for_crate(&mut conn, "foo").await?,
+                Some(expected)
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn reject_keeps_overrides() {
+        async_wrapper(|env| async move {
+            let mut conn = env.async_db().async_conn().await;
+
+            let requested = Overrides {
+                targets: Some(10),
+                ..Overrides::default()
+            };
+            let id = LimitRequest::create(&mut conn, "foo", "owner", "all targets", requested)
+                .await?
+                .unwrap();
+            LimitRequest::reject(&mut conn, id, "admin").await?;
+
+            assert_eq!(Overrides::for_crate(&mut conn, "foo").await?, None);
+            assert!(Overrides::history(&mut conn, "foo").await?.is_empty());
+            assert_eq!(
+                LimitRequest::get(&mut conn, id).await?.unwrap().status,
+                LimitRequestStatus::Rejected
+            );
+            assert!(LimitRequest::pending(&mut conn).await?.is_empty());
+
+            assert!(
+                LimitRequest::approve(&mut conn, id + 1, "admin")
+                    .await
+                    .is_err()
+            );
+
+            Ok(())
+        })
+    }
+}
@@ -4,14 +4,16 @@ use sqlx::migrate::{Migrate, Migrator};
 
 pub use self::add_package::update_latest_version_id;
 pub(crate) use self::add_package::{
-    add_doc_coverage, finish_build, finish_release, initialize_build, initialize_crate,
-    initialize_release, update_build_with_error,
+    add_doc_coverage, cancel_build, finish_build, finish_release, initialize_build,
+    initialize_crate, initialize_release, record_build_configuration, record_build_environment,
+    record_build_target, update_build_with_error,
 };
 pub use self::{
     add_package::{update_build_status, update_crate_data_in_database},
     delete::{delete_crate, delete_version},
     file::{add_path_into_database, add_path_into_remote_archive},
-    overrides::Overrides,
+    limit_requests::{LimitRequest, LimitRequestStatus},
+    overrides::{Overrides, OverridesChange},
     pool::{AsyncPoolClient, Pool, PoolError},
     types::{BuildId, CrateId, ReleaseId},
 };
@@ -20,6 +22,7 @@ mod add_package;
 pub mod blacklist;
 pub mod delete;
 pub(crate) mod file;
+mod limit_requests;
 pub(crate) mod mimes;
 mod overrides;
 mod pool;
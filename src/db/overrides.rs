@@ -1,5 +1,7 @@
 use crate::error::Result;
+use chrono::{DateTime, Utc};
 use futures_util::stream::TryStreamExt;
+use sqlx::Connection as _;
 use std::time::Duration;
 
 #[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
@@ -9,17 +11,46 @@ pub struct Overrides {
     pub timeout: Option<Duration>,
 }
 
+/// A change of the overrides of a crate, from the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverridesChange {
+    pub changed_by: String,
+    pub changed_at: DateTime<Utc>,
+    /// the approved limit request that caused the change.
+    pub limit_request_id: Option<i32>,
+    /// `None` when the crate had no overrides.
+    pub old: Option<Overrides>,
+    /// `None` when the overrides were removed.
+    pub new: Option<Overrides>,
+}
+
 macro_rules! row_to_overrides {
     ($row:expr) => {{
-        Overrides {
-            memory: $row.max_memory_bytes.map(|i| i as usize),
-            targets: $row.max_targets.map(|i| i as usize),
-            timeout: $row.timeout_seconds.map(|i| Duration::from_secs(i as u64)),
-        }
+        Overrides::from_columns(
+            $row.max_memory_bytes,
+            $row.timeout_seconds,
+            $row.max_targets,
+        )
     }};
 }
 
 impl Overrides {
+    pub(crate) fn from_columns(
+        max_memory_bytes: Option<i64>,
+        timeout_seconds: Option<i32>,
+        max_targets: Option<i32>,
+    ) -> Self {
+        Overrides {
+            memory: max_memory_bytes.map(|i| i as usize),
+            targets: max_targets.map(|i| i as usize),
+            timeout: timeout_seconds.map(|i| Duration::from_secs(i as u64)),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
     pub async fn all(conn: &mut sqlx::PgConnection) -> Result<Vec<(String, Self)>> {
         Ok(sqlx::query!("SELECT * FROM sandbox_overrides")
             .fetch(conn)
@@ -81,6 +112,91 @@ impl Overrides {
             .await?;
         Ok(())
     }
+
+    /// Set the overrides of a crate, or remove them with `None`, and record the change
+    /// in the audit log.
+    pub async fn change(
+        conn: &mut sqlx::PgConnection,
+        krate: &str,
+        overrides: Option<Self>,
+        changed_by: &str,
+    ) -> Result<()> {
+        let mut transaction = conn.begin().await?;
+        Self::change_for_request(&mut transaction, krate, overrides, changed_by, None).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Like [`Overrides::change`], but doesn't start its own transaction.
+    pub(crate) async fn change_for_request(
+        conn: &mut sqlx::PgConnection,
+        krate: &str,
+        overrides: Option<Self>,
+        changed_by: &str,
+        limit_request_id: Option<i32>,
+    ) -> Result<()> {
+        let old = Self::for_crate(&mut *conn, krate).await?;
+        let new = overrides.filter(|overrides| !overrides.is_empty());
+        match new {
+            Some(overrides) => Self::save(&mut *conn, krate, overrides).await?,
+            None => Self::remove(&mut *conn, krate).await?,
+        }
+
+        sqlx::query!(
+            "INSERT INTO sandbox_override_changes (
+                 crate_name, changed_by, limit_request_id,
+                 old_max_memory_bytes, old_timeout_seconds, old_max_targets,
+                 new_max_memory_bytes, new_timeout_seconds, new_max_targets
+             )
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            krate,
+            changed_by,
+            limit_request_id,
+            old.and_then(|o| o.memory).map(|i| i as i64),
+            old.and_then(|o| o.timeout).map(|d| d.as_secs() as i32),
+            old.and_then(|o| o.targets).map(|i| i as i32),
+            new.and_then(|o| o.memory).map(|i| i as i64),
+            new.and_then(|o| o.timeout).map(|d| d.as_secs() as i32),
+            new.and_then(|o| o.targets).map(|i| i as i32),
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// All changes of the overrides of a crate, oldest first.
+    pub async fn history(
+        conn: &mut sqlx::PgConnection,
+        krate: &str,
+    ) -> Result<Vec<OverridesChange>> {
+        Ok(sqlx::query!(
+            "SELECT *
+             FROM sandbox_override_changes
+             WHERE crate_name = $1
+             ORDER BY id",
+            krate
+        )
+        .fetch(conn)
+        .map_ok(|row| OverridesChange {
+            changed_by: row.changed_by,
+            changed_at: row.changed_at,
+            limit_request_id: row.limit_request_id,
+            old: Some(Overrides::from_columns(
+                row.old_max_memory_bytes,
+                row.old_timeout_seconds,
+                row.old_max_targets,
+            ))
+            .filter(|overrides| !overrides.is_empty()),
+            new: Some(Overrides::from_columns(
+                row.new_max_memory_bytes,
+                row.new_timeout_seconds,
+                row.new_max_targets,
+            ))
+            .filter(|overrides| !overrides.is_empty()),
+        })
+        .try_collect()
+        .await?)
+    }
 }
 
 #[cfg(test)]
@@ -136,4 +252,44 @@ mod test {
             Ok(())
         })
     }
+
+    #[test]
+    fn changes_are_audited() {
+        async_wrapper(|env| async move {
+            let db = env.async_db();
+            let mut conn = db.async_conn().await;
+
+            let krate = "hexponent";
+            let overrides = Overrides {
+                memory: Some(100_000),
+                ..Overrides::default()
+            };
+            Overrides::change(&mut conn, krate, Some(overrides), "admin").await?;
+            assert_eq!(
+                Overrides::for_crate(&mut conn, krate).await?,
+                Some(overrides)
+            );
+            Overrides::change(&mut conn, krate, None, "other-admin").await?;
+            assert_eq!(Overrides::for_crate(&mut conn, krate).await?, None);
+
+            let history = Overrides::history(&mut conn, krate).await?;
+            assert_eq!(
+                history
+                    .iter()
+                    .map(|change| (change.changed_by.as_str(), change.old, change.new))
+                    .collect::<Vec<_>>(),
+                vec![
+                    ("admin", None, Some(overrides)),
+                    ("other-admin", Some(overrides), None),
+                ]
+            );
+            assert!(
+                history
+                    .iter()
+                    .all(|change| change.limit_request_id.is_none())
+            );
+
+            Ok(())
+        })
+    }
 }
@@ -41,6 +41,7 @@ pub(crate) enum BuildStatus {
     Success,
     Failure,
     InProgress,
+    Cancelled,
 }
 
 impl BuildStatus {
@@ -49,12 +50,42 @@ impl BuildStatus {
     }
 }
 
+/// Why a build failed, decides if the build is worth another attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "build_failure_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BuildFailureKind {
+    /// Our own infrastructure failed, like storage, the database, or docker.
+    Infrastructure,
+    /// The crate can't be built, for example because of an invalid manifest
+    /// or missing dependencies.
+    InvalidCrate,
+    /// rustdoc failed to document the crate.
+    Rustdoc,
+    /// The build ran out of memory in the sandbox.
+    OutOfMemory,
+    /// The build exceeded its time limit.
+    Timeout,
+    /// The build stopped printing output for too long. It's likely stuck rather than slow,
+    /// so a longer time limit wouldn't help.
+    NoOutput,
+}
+
+impl BuildFailureKind {
+    /// Only infrastructure failures might go away on their own,
+    /// all other failures would just happen again.
+    pub(crate) fn is_retryable(&self) -> bool {
+        matches!(self, BuildFailureKind::Infrastructure)
+    }
+}
+
 impl PartialEq<&str> for BuildStatus {
     fn eq(&self, other: &&str) -> bool {
         match self {
             Self::Success => *other == "success",
             Self::Failure => *other == "failure",
             Self::InProgress => *other == "in_progress",
+            Self::Cancelled => *other == "cancelled",
         }
     }
 }
@@ -0,0 +1,370 @@
+use crate::{
+    error::Result,
+    utils::{copy_dir_all, dir_size},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+const ENTRY_FILE: &str = "entry.json";
+const ARTIFACTS_DIR: &str = "artifacts";
+const TEMP_PREFIX: &str = ".tmp-";
+/// The parts of a profile directory with the artifacts of the dependencies. Cargo needs the
+/// fingerprints to know that the artifacts are up to date.
+const CACHED_DIRS: &[&str] = &["deps", "build", ".fingerprint"];
+
+/// Identifies a set of compiled dependencies of a crate.
+///
+/// Contains everything that changes what cargo compiles: the toolchain, the target,
+/// the cargo arguments (with the feature set) and the dependencies in the lockfile.
+///
+/// The artifacts come out of the sandbox of a build, so a build script of the crate could
+/// have changed the artifacts of its dependencies. They are only shared between the versions
+/// of the same crate, which is why the key contains its registry and name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ArtifactCacheKey(String);
+
+impl ArtifactCacheKey {
+    pub(crate) fn new(
+        registry: &str,
+        name: &str,
+        rustc_version: &str,
+        target: &str,
+        cargo_args: &[String],
+        lockfile: &str,
+    ) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Lockfile {
+            #[serde(default)]
+            package: Vec<Package>,
+        }
+
+        #[derive(Deserialize)]
+        struct Package {
+            name: String,
+            version: String,
+            source: Option<String>,
+            checksum: Option<String>,
+        }
+
+        // the crate that is built is the only package without source. Its entry contains
+        // its version, which changes with every release while the dependencies often don't.
+        let dependencies = toml::from_str::<Lockfile>(lockfile)?
+            .package
+            .into_iter()
+            .filter_map(|package| {
+                let source = package.source?;
+                Some([
+                    package.name,
+                    package.version,
+                    source,
+                    package.checksum.unwrap_or_default(),
+                ])
+            });
+
+        let mut context = md5::Context::new();
+        for part in [registry, name, rustc_version, target]
+            .map(str::to_owned)
+            .into_iter()
+            .chain(cargo_args.iter().cloned())
+            .chain(dependencies.flatten())
+        {
+            context.consume(part);
+            // separate the parts, so `["ab", "c"]` and `["a", "bc"]` have different keys.
+            context.consume([0]);
+        }
+        Ok(Self(format!("{:x}", context.finalize())))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    /// how long the build took that compiled the artifacts.
+    build_time: Duration,
+}
+
+/// Compiled dependencies of earlier builds, shared between the builds in a rustwide workspace.
+///
+/// A patch release of a crate with a large dependency tree can start with the artifacts
+/// of the previous release instead of compiling everything again. When the cache grows
+/// over its size limit, the least recently used entries are removed.
+#[derive(Debug)]
+pub(crate) struct ArtifactCache {
+    root: PathBuf,
+    max_size: u64,
+}
+
+impl ArtifactCache {
+    pub(crate) fn new(root: PathBuf, max_size: u64) -> Self {
+        Self { root, max_size }
+    }
+
+    /// Copy the cached artifacts into a profile directory, like `target/<target>/debug`.
+    ///
+    /// Returns how long the build took that compiled the artifacts, or `None` when
+    /// nothing is cached for the key.
+    pub(crate) fn restore(
+        &self,
+        key: &ArtifactCacheKey,
+        profile_dir: &Path,
+    ) -> Result<Option<Duration>> {
+        let entry_dir = self.root.join(&key.0);
+        let entry_file = entry_dir.join(ENTRY_FILE);
+        let entry: Entry = match fs::read(&entry_file) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        info!(key = key.0, "restoring cached build artifacts");
+        copy_dir_all(entry_dir.join(ARTIFACTS_DIR), profile_dir)?;
+        // the modification time of the entry file is when the entry was last used.
+        fs::File::options()
+            .write(true)
+            .open(&entry_file)?
+            .set_modified(SystemTime::now())?;
+
+        Ok(Some(entry.build_time))
+    }
+
+    /// Add the dependency artifacts in a profile directory to the cache, and evict the least
+    /// recently used entries if the cache got too large.
+    pub(crate) fn store(
+        &self,
+        key: &ArtifactCacheKey,
+        profile_dir: &Path,
+        build_time: Duration,
+    ) -> Result<()> {
+        let entry_dir = self.root.join(&key.0);
+        if entry_dir.exists() {
+            return Ok(());
+        }
+
+        let cached_dirs: Vec<_> = CACHED_DIRS
+            .iter()
+            .map(|dir| profile_dir.join(dir))
+            .filter(|dir| dir.is_dir())
+            .collect();
+        let mut size = 0;
+        for dir in &cached_dirs {
+            size += dir_size(dir)?;
+        }
+        if size > self.max_size {
+            debug!(size, "build artifacts are larger than the whole cache");
+            return Ok(());
+        }
+
+        // copy into a temporary directory first, so other builds never see a partial entry.
+        let temp_dir = self.root.join(format!("{TEMP_PREFIX}{}", Uuid::new_v4()));
+        let artifacts_dir = temp_dir.join(ARTIFACTS_DIR);
+        fs::create_dir_all(&artifacts_dir)?;
+        for dir in &cached_dirs {
+            copy_dir_all(dir, artifacts_dir.join(dir.file_name().unwrap()))?;
+        }
+        fs::write(
+            temp_dir.join(ENTRY_FILE),
+            serde_json::to_vec(&Entry { size, build_time })?,
+        )?;
+        if fs::rename(&temp_dir, &entry_dir).is_err() {
+            // another build stored the same artifacts in the meantime.
+            fs::remove_dir_all(&temp_dir)?;
+        } else {
+            info!(key = key.0, size, "stored build artifacts in cache");
+        }
+
+        self.evict()
+    }
+
+    fn evict(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        for dir in fs::read_dir(&self.root)? {
+            let dir = dir?;
+            if dir.file_name().to_string_lossy().starts_with(TEMP_PREFIX) {
+                continue;
+            }
+            let entry_file = dir.path().join(ENTRY_FILE);
+            let entry: Entry = serde_json::from_slice(&fs::read(&entry_file)?)?;
+            let last_used = fs::metadata(&entry_file)?.modified()?;
+            entries.push((last_used, entry.size, dir.path()));
+        }
+
+        let mut total_size: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort();
+        for (_, size, path) in entries {
+            if total_size <= self.max_size {
+                break;
+            }
+            info!(path = %path.display(), size, "evicting cached build artifacts");
+            fs::remove_dir_all(&path)?;
+            total_size -= size;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile(version: &str, dependency_version: &str) -> String {
+        format!(
+            r#"version = 4
+
+[[package]]
+name = "foo"
+version = "{version}"
+dependencies = ["bar"]
+
+[[package]]
+name = "bar"
+version = "{dependency_version}"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "0123456789abcdef"
+"#
+        )
+    }
+
+    fn key(dependency_version: &str) -> ArtifactCacheKey {
+        ArtifactCacheKey::new(
+            "crates.io",
+            "foo",
+            "rustc 1.92.0-nightly (6c699a372 2025-10-24)",
+            "x86_64-unknown-linux-gnu",
+            &["--features".into(), "foo".into()],
+            &lockfile("0.1.0", dependency_version),
+        )
+        .unwrap()
+    }
+
+    fn profile_dir(content: &str) -> Result<tempfile::TempDir> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("deps"))?;
+        fs::write(dir.path().join("deps/libfoo.rmeta"), content)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn keys_differ_by_every_part() -> Result<()> {
+        assert_eq!(key("1.0.0"), key("1.0.0"));
+        assert_ne!(key("1.0.0"), key("1.0.1"));
+        assert_ne!(
+            key("1.0.0"),
+            ArtifactCacheKey::new(
+                "crates.io",
+                "foo",
+                "rustc 1.92.0-nightly (6c699a372 2025-10-24)",
+                "x86_64-unknown-linux-gnu",
+                &["--features".into(), "bar".into()],
+                &lockfile("0.1.0", "1.0.0"),
+            )?
+        );
+        assert_ne!(
+            ArtifactCacheKey::new("crates.io", "foo", "a", "bc", &[], "")?,
+            ArtifactCacheKey::new("crates.io", "foo", "ab", "c", &[], "")?,
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn keys_differ_by_crate() -> Result<()> {
+        let key = |registry, name| {
+            ArtifactCacheKey::new(
+                registry,
+                name,
+                "rustc",
+                "x86_64-unknown-linux-gnu",
+                &[],
+                &lockfile("0.1.0", "1.0.0"),
+            )
+        };
+        assert_eq!(key("crates.io", "foo")?, key("crates.io", "foo")?);
+        assert_ne!(key("crates.io", "foo")?, key("crates.io", "bar")?);
+        assert_ne!(key("crates.io", "foo")?, key("other-registry", "foo")?);
+        Ok(())
+    }
+
+    #[test]
+    fn key_ignores_the_version_of_the_built_crate() -> Result<()> {
+        let key = |version| {
+            ArtifactCacheKey::new(
+                "crates.io",
+                "foo",
+                "rustc",
+                "x86_64-unknown-linux-gnu",
+                &[],
+                &lockfile(version, "1.0.0"),
+            )
+        };
+        assert_eq!(key("0.1.0")?, key("0.1.1")?);
+        Ok(())
+    }
+
+    #[test]
+    fn store_and_restore() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let cache = ArtifactCache::new(root.path().into(), 1024);
+
+        let restored = tempfile::tempdir()?;
+        assert_eq!(cache.restore(&key("1.0.0"), restored.path())?, None);
+
+        let built = profile_dir("foo")?;
+        fs::create_dir(built.path().join(".fingerprint"))?;
+        fs::write(built.path().join(".fingerprint/foo"), "fingerprint")?;
+        fs::create_dir(built.path().join("incremental"))?;
+        fs::write(built.path().join("incremental/foo"), "incremental")?;
+        fs::write(built.path().join("libfoo.rlib"), "the built crate")?;
+        cache.store(&key("1.0.0"), built.path(), Duration::from_secs(60))?;
+
+        assert_eq!(
+            cache.restore(&key("1.0.0"), restored.path())?,
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            fs::read_to_string(restored.path().join("deps/libfoo.rmeta"))?,
+            "foo"
+        );
+        assert_eq!(
+            fs::read_to_string(restored.path().join(".fingerprint/foo"))?,
+            "fingerprint"
+        );
+        // only the dependencies are cached.
+        assert!(!restored.path().join("incremental").exists());
+        assert!(!restored.path().join("libfoo.rlib").exists());
+        assert_eq!(cache.restore(&key("1.0.1"), restored.path())?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn evicts_least_recently_used() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        // room for two entries of 10 bytes.
+        let cache = ArtifactCache::new(root.path().into(), 25);
+        let built = profile_dir("0123456789")?;
+        let restored = tempfile::tempdir()?;
+
+        cache.store(&key("1.0.0"), built.path(), Duration::from_secs(1))?;
+        cache.store(&key("2.0.0"), built.path(), Duration::from_secs(1))?;
+        // mark `1.0.0` as used after `2.0.0`.
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(cache.restore(&key("1.0.0"), restored.path())?.is_some());
+
+        cache.store(&key("3.0.0"), built.path(), Duration::from_secs(1))?;
+        assert!(cache.restore(&key("1.0.0"), restored.path())?.is_some());
+        assert!(cache.restore(&key("2.0.0"), restored.path())?.is_none());
+        assert!(cache.restore(&key("3.0.0"), restored.path())?.is_some());
+
+        // larger than the whole cache.
+        let large = profile_dir(&"x".repeat(30))?;
+        cache.store(&key("4.0.0"), large.path(), Duration::from_secs(1))?;
+        assert!(cache.restore(&key("4.0.0"), restored.path())?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{
+    db::{
+        ReleaseId,
+        types::{BuildFailureKind, BuildStatus, version::Version},
+    },
+    error::Result,
+};
+use std::fmt;
+
+/// A release that is rebuilt with a new toolchain before we switch to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CanaryRelease {
+    pub(crate) id: ReleaseId,
+    pub(crate) name: String,
+    pub(crate) version: Version,
+}
+
+/// How the builds with a new toolchain compare to the builds with the configured one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanaryReport {
+    pub(crate) canary_id: i32,
+    pub(crate) toolchain: String,
+    pub(crate) rustc_version: String,
+    pub(crate) builds: usize,
+    /// releases that were built successfully before, but failed with the new toolchain.
+    pub(crate) regressed: Vec<(String, Version)>,
+    /// releases that failed before, but were built successfully with the new toolchain.
+    pub(crate) fixed: Vec<(String, Version)>,
+    pub(crate) regression_rate: f64,
+    pub(crate) passed: bool,
+    pub(crate) switched: bool,
+}
+
+impl fmt::Display for CanaryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "canary {} for {} ({}): {} builds, {} regressed ({:.1}%), {} fixed",
+            self.canary_id,
+            self.toolchain,
+            self.rustc_version,
+            self.builds,
+            self.regressed.len(),
+            self.regression_rate * 100.0,
+            self.fixed.len(),
+        )?;
+        for (name, version) in &self.regressed {
+            writeln!(f, "  regressed: {name} {version}")?;
+        }
+        for (name, version) in &self.fixed {
+            writeln!(f, "  fixed: {name} {version}")?;
+        }
+        if self.switched {
+            write!(f, "switched to {}", self.toolchain)
+        } else if self.passed {
+            write!(f, "passed, but kept the configured toolchain")
+        } else {
+            write!(f, "failed, kept the configured toolchain")
+        }
+    }
+}
+
+/// Start a new canary run for the given toolchain.
+pub(crate) async fn start_canary(conn: &mut sqlx::PgConnection, toolchain: &str) -> Result<i32> {
+    Ok(sqlx::query_scalar!(
+        "INSERT INTO toolchain_canaries (toolchain) VALUES ($1) RETURNING id",
+        toolchain,
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// The latest releases of the most downloaded library crates, that have a finished build
+/// we can compare against.
+pub(crate) async fn canary_sample(
+    conn: &mut sqlx::PgConnection,
+    sample_size: usize,
+) -> Result<Vec<CanaryRelease>> {
+    Ok(sqlx::query_as!(
+        CanaryRelease,
+        r#"SELECT
+            releases.id as "id: ReleaseId",
+            crates.name,
+            releases.version as "version: Version"
+         FROM crates
+         INNER JOIN releases ON releases.id = crates.latest_version_id
+         INNER JOIN release_build_status ON release_build_status.rid = releases.id
+         WHERE
+            releases.is_library AND
+            NOT COALESCE(releases.yanked, FALSE) AND
+            release_build_status.build_status IN ('success', 'failure')
+         ORDER BY
+            crates.downloads_total DESC NULLS LAST,
+            crates.name ASC
+         LIMIT $1"#,
+        sample_size as i64,
+    )
+    .fetch_all(&mut *conn)
+    .await?)
+}
+
+/// Store the result of building a release with the toolchain of the canary.
+///
+/// The canary only builds the default target, so it's compared with the default target of the
+/// latest build of the release. Builds from before the results of each target were recorded
+/// only have the status of the whole build.
+pub(crate) async fn record_canary_build(
+    conn: &mut sqlx::PgConnection,
+    canary_id: i32,
+    release_id: ReleaseId,
+    successful: bool,
+    failure_kind: Option<BuildFailureKind>,
+    documentation_size: Option<u64>,
+    errors: Option<&str>,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO toolchain_canary_builds (
+            canary_id, rid, previous_status, successful,
+            failure_kind, documentation_size, errors
+         )
+         SELECT
+            $1,
+            release_build_status.rid,
+            COALESCE(
+                (
+                    SELECT build_targets.build_status
+                    FROM builds
+                    INNER JOIN build_targets ON build_targets.build_id = builds.id
+                    WHERE
+                        builds.rid = releases.id AND
+                        build_targets.target = releases.default_target
+                    ORDER BY builds.id DESC
+                    LIMIT 1
+                ),
+                release_build_status.build_status
+            ),
+            $3, $4, $5, $6
+         FROM release_build_status
+         INNER JOIN releases ON releases.id = release_build_status.rid
+         WHERE release_build_status.rid = $2",
+        canary_id,
+        release_id.0,
+        successful,
+        failure_kind as Option<BuildFailureKind>,
+        documentation_size.map(|v| v as i64),
+        errors,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Whether the latest finished canary of the rustc version passed, `None` when it wasn't
+/// tried yet.
+pub(crate) async fn canary_passed(
+    conn: &mut sqlx::PgConnection,
+    rustc_version: &str,
+) -> Result<Option<bool>> {
+    Ok(sqlx::query_scalar!(
+        r#"SELECT passed AS "passed!"
+         FROM toolchain_canaries
+         WHERE rustc_version = $1 AND finished IS NOT NULL
+         ORDER BY id DESC
+         LIMIT 1"#,
+        rustc_version,
+    )
+    .fetch_optional(&mut *conn)
+    .await?)
+}
+
+/// Compare the builds of the canary with the builds of the configured toolchain,
+/// and decide if the new toolchain passed.
+///
+/// Builds that failed because of our infrastructure don't count either way.
+pub(crate) async fn finish_canary(
+    conn: &mut sqlx::PgConnection,
+    canary_id: i32,
+    rustc_version: &str,
+    max_regression_rate: f64,
+) -> Result<CanaryReport> {
+    let builds = sqlx::query!(
+        r#"SELECT
+            crates.name,
+            releases.version as "version: Version",
+            toolchain_canary_builds.previous_status as "previous_status: BuildStatus",
+            toolchain_canary_builds.successful,
+            toolchain_canary_builds.failure_kind as "failure_kind: BuildFailureKind"
+         FROM toolchain_canary_builds
+         INNER JOIN releases ON releases.id = toolchain_canary_builds.rid
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE toolchain_canary_builds.canary_id = $1
+         ORDER BY crates.name, releases.version"#,
+        canary_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut compared = 0;
+    let mut regressed = Vec::new();
+    let mut fixed = Vec::new();
+    for build in &builds {
+        if build.failure_kind == Some(BuildFailureKind::Infrastructure) {
+            continue;
+        }
+        match (build.previous_status, build.successful) {
+            (BuildStatus::Success, true) => compared += 1,
+            (BuildStatus::Success, false) => {
+                compared += 1;
+                regressed.push((build.name.clone(), build.version.clone()));
+            }
+            (BuildStatus::Failure, true) => fixed.push((build.name.clone(), build.version.clone())),
+            _ => {}
+        }
+    }
+
+    let regression_rate = if compared == 0 {
+        0.0
+    } else {
+        regressed.len() as f64 / compared as f64
+    };
+    // without any successful build to compare with, we can't tell if the toolchain works.
+    let passed = compared > 0 && regression_rate <= max_regression_rate;
+
+    let toolchain = sqlx::query_scalar!(
+        "UPDATE toolchain_canaries
+         SET
+            finished = NOW(),
+            rustc_version = $2,
+            regression_rate = $3,
+            passed = $4
+         WHERE id = $1
+         RETURNING toolchain",
+        canary_id,
+        rustc_version,
+        regression_rate,
+        passed,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(CanaryReport {
+        canary_id,
+        toolchain,
+        rustc_version: rustc_version.into(),
+        builds: builds.len(),
+        regressed,
+        fixed,
+        regression_rate,
+        passed,
+        switched: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{FakeBuild, V1, V2, async_wrapper};
+
+    #[test]
+    fn sample_prefers_popular_crates() {
+        async_wrapper(|env| async move {
+            let popular = env
+                .fake_release()
+                .await
+                .name("popular")
+                .version(V1)
+                .create()
+                .await?;
+            env.fake_release()
+                .await
+                .name("unpopular")
+                .version(V1)
+                .create()
+                .await?;
+            let failed = env
+                .fake_release()
+                .await
+                .name("failed")
+                .version(V2)
+                .builds(vec![
+                    FakeBuild::default().build_status(BuildStatus::Failure),
+                ])
+                .create()
+                .await?;
+
+            let mut conn = env.async_db().async_conn().await;
+            sqlx::query!(
+                "UPDATE crates
+                 SET downloads_total = CASE name WHEN 'popular' THEN 1000 ELSE 10 END"
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            let sample = canary_sample(&mut conn, 2).await?;
+            assert_eq!(
+                sample,
+                vec![
+                    CanaryRelease {
+                        id: popular,
+                        name: "popular".into(),
+                        version: V1,
+                    },
+                    CanaryRelease {
+                        id: failed,
+                        name: "failed".into(),
+                        version: V2,
+                    },
+                ]
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn compare_canary_builds() {
+        async_wrapper(|env| async move {
+            let mut releases = Vec::new();
+            for (name, status) in [
+                ("stays-ok", BuildStatus::Success),
+                ("regressed", BuildStatus::Success),
+                ("fixed", BuildStatus::Failure),
+                ("infra", BuildStatus::Success),
+            ] {
+                releases.push(
+                    env.fake_release()
+                        .await
+                        .name(name)
+                        .version(V1)
+                        .builds(vec![FakeBuild::default().build_status(status)])
+                        .create()
+                        .await?,
+                );
+            }
+
+            let mut conn = env.async_db().async_conn().await;
+            let canary_id = start_canary(&mut conn, "nightly-2025-10-25").await?;
+            for (release_id, successful, failure_kind) in [
+                (releases[0], true, None),
+                (releases[1], false, Some(BuildFailureKind::Rustdoc)),
+                (releases[2], true, None),
+                (releases[3], false, Some(BuildFailureKind::Infrastructure)),
+            ] {
+                record_canary_build(
+                    &mut conn,
+                    canary_id,
+                    release_id,
+                    successful,
+                    failure_kind,
+                    None,
+                    None,
+                )
+                .await?;
+            }
+
+            let report = finish_canary(&mut conn, canary_id, "rustc 1.92.0-nightly", 0.5).await?;
+            assert_eq!(report.builds, 4);
+            assert_eq!(report.regressed, vec![("regressed".into(), V1)]);
+            assert_eq!(report.fixed, vec![("fixed".into(), V1)]);
+            assert_eq!(report.regression_rate, 0.5);
+            assert!(report.passed);
+
+            let report = finish_canary(&mut conn, canary_id, "rustc 1.92.0-nightly", 0.1).await?;
+            assert!(!report.passed);
+
+            let passed = sqlx::query_scalar!(
+                "SELECT passed FROM toolchain_canaries WHERE id = $1",
+                canary_id
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            assert_eq!(passed, Some(false));
+            assert_eq!(
+                canary_passed(&mut conn, "rustc 1.92.0-nightly").await?,
+                Some(false)
+            );
+            assert_eq!(
+                canary_passed(&mut conn, "rustc 1.93.0-nightly").await?,
+                None
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn canary_build_is_compared_with_the_default_target() {
+        async_wrapper(|env| async move {
+            let release_id = env
+                .fake_release()
+                .await
+                .name("foo")
+                .version(V1)
+                .default_target("x86_64-unknown-linux-gnu")
+                .create()
+                .await?;
+
+            // the build succeeded, but not for the default target.
+            let mut conn = env.async_db().async_conn().await;
+            sqlx::query!(
+                "INSERT INTO build_targets (
+                    build_id, target, build_status, build_started, build_finished
+                 )
+                 SELECT id, 'x86_64-unknown-linux-gnu', 'failure', NOW(), NOW()
+                 FROM builds
+                 WHERE rid = $1",
+                release_id.0,
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            let canary_id = start_canary(&mut conn, "nightly-2025-10-25").await?;
+            record_canary_build(&mut conn, canary_id, release_id, true, None, None, None).await?;
+
+            let report = finish_canary(&mut conn, canary_id, "rustc 1.92.0-nightly", 0.0).await?;
+            assert_eq!(report.fixed, vec![("foo".into(), V1)]);
+            assert!(report.regressed.is_empty());
+
+            Ok(())
+        })
+    }
+}
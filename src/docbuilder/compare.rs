@@ -0,0 +1,407 @@
+use crate::{
+    db::{
+        ReleaseId,
+        types::{BuildFailureKind, BuildStatus, version::Version},
+    },
+    error::Result,
+};
+use anyhow::{Context as _, anyhow};
+use askama::Template;
+use serde::Serialize;
+use similar::TextDiff;
+use std::{fmt, fs, path::Path};
+
+/// Which releases to build for a toolchain comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompareCrates {
+    /// Crate names for their latest release, or `name@version` for a specific release.
+    List(Vec<String>),
+    /// The most downloaded crates with a name matching an SQL `LIKE` pattern.
+    Query { pattern: String, limit: usize },
+}
+
+/// A release that is built with both toolchains of a comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CompareRelease {
+    pub(crate) id: ReleaseId,
+    pub(crate) name: String,
+    pub(crate) version: Version,
+}
+
+/// Find the releases to build for a toolchain comparison.
+pub(crate) async fn compare_releases(
+    conn: &mut sqlx::PgConnection,
+    crates: &CompareCrates,
+) -> Result<Vec<CompareRelease>> {
+    match crates {
+        CompareCrates::List(specs) => {
+            let mut releases = Vec::with_capacity(specs.len());
+            for spec in specs {
+                let release = match spec.split_once('@') {
+                    Some((name, version)) => {
+                        let version: Version = version
+                            .parse()
+                            .with_context(|| format!("invalid version in {spec}"))?;
+                        sqlx::query_as!(
+                            CompareRelease,
+                            r#"SELECT
+                                releases.id as "id: ReleaseId",
+                                crates.name,
+                                releases.version as "version: Version"
+                             FROM crates
+                             INNER JOIN releases ON releases.crate_id = crates.id
+                             WHERE crates.name = $1 AND releases.version = $2"#,
+                            name,
+                            version as Version,
+                        )
+                        .fetch_optional(&mut *conn)
+                        .await?
+                    }
+                    None => {
+                        sqlx::query_as!(
+                            CompareRelease,
+                            r#"SELECT
+                                releases.id as "id: ReleaseId",
+                                crates.name,
+                                releases.version as "version: Version"
+                             FROM crates
+                             INNER JOIN releases ON releases.id = crates.latest_version_id
+                             WHERE crates.name = $1"#,
+                            spec,
+                        )
+                        .fetch_optional(&mut *conn)
+                        .await?
+                    }
+                };
+                releases.push(release.ok_or_else(|| anyhow!("release {spec} not found"))?);
+            }
+            Ok(releases)
+        }
+        CompareCrates::Query { pattern, limit } => Ok(sqlx::query_as!(
+            CompareRelease,
+            r#"SELECT
+                releases.id as "id: ReleaseId",
+                crates.name,
+                releases.version as "version: Version"
+             FROM crates
+             INNER JOIN releases ON releases.id = crates.latest_version_id
+             WHERE crates.name LIKE $1
+             ORDER BY
+                crates.downloads_total DESC NULLS LAST,
+                crates.name ASC
+             LIMIT $2"#,
+            pattern,
+            *limit as i64,
+        )
+        .fetch_all(&mut *conn)
+        .await?),
+    }
+}
+
+/// The latest finished build of a release, which is what we currently serve.
+pub(crate) async fn current_build(
+    conn: &mut sqlx::PgConnection,
+    release_id: ReleaseId,
+) -> Result<Option<ComparedBuild>> {
+    Ok(sqlx::query!(
+        r#"SELECT
+            build_status as "build_status: BuildStatus",
+            failure_kind as "failure_kind: BuildFailureKind",
+            documentation_size,
+            errors
+         FROM builds
+         WHERE
+            rid = $1 AND
+            build_status IN ('success', 'failure')
+         ORDER BY build_finished DESC NULLS LAST, id DESC
+         LIMIT 1"#,
+        release_id.0,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .map(|row| ComparedBuild {
+        status: row.build_status,
+        failure_kind: row.failure_kind,
+        documentation_size: row.documentation_size.map(|size| size as u64),
+        errors: row.errors,
+    }))
+}
+
+/// The outcome of building a release with one toolchain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ComparedBuild {
+    pub(crate) status: BuildStatus,
+    pub(crate) failure_kind: Option<BuildFailureKind>,
+    pub(crate) documentation_size: Option<u64>,
+    pub(crate) errors: Option<String>,
+}
+
+/// How the builds of one release differ between the two toolchains.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct ReleaseComparison {
+    pub(crate) name: String,
+    pub(crate) version: Version,
+    /// the build we currently serve, if there is one.
+    pub(crate) current: Option<ComparedBuild>,
+    pub(crate) a: ComparedBuild,
+    pub(crate) b: ComparedBuild,
+    pub(crate) status_changed: bool,
+    /// bytes the documentation grew (or shrank) from toolchain A to toolchain B.
+    pub(crate) documentation_size_change: Option<i64>,
+    /// unified diff of the build logs, empty when both logs are the same.
+    pub(crate) log_diff: String,
+}
+
+impl ReleaseComparison {
+    pub(crate) fn new(
+        name: String,
+        version: Version,
+        current: Option<ComparedBuild>,
+        (a, log_a): (ComparedBuild, &str),
+        (b, log_b): (ComparedBuild, &str),
+    ) -> Self {
+        let documentation_size_change = a
+            .documentation_size
+            .zip(b.documentation_size)
+            .map(|(a, b)| b as i64 - a as i64);
+        Self {
+            name,
+            version,
+            current,
+            status_changed: a.status != b.status,
+            documentation_size_change,
+            log_diff: diff_logs(log_a, log_b),
+            a,
+            b,
+        }
+    }
+
+    pub(crate) fn regressed(&self) -> bool {
+        self.a.status.is_success() && !self.b.status.is_success()
+    }
+
+    pub(crate) fn fixed(&self) -> bool {
+        !self.a.status.is_success() && self.b.status.is_success()
+    }
+}
+
+/// Unified diff between the build logs of two toolchains.
+fn diff_logs(a: &str, b: &str) -> String {
+    TextDiff::from_lines(a, b)
+        .unified_diff()
+        .context_radius(3)
+        .header("toolchain-a", "toolchain-b")
+        .to_string()
+}
+
+/// The result of building the same releases with two toolchains.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComparisonReport {
+    pub(crate) toolchain_a: String,
+    pub(crate) toolchain_b: String,
+    pub(crate) rustc_version_a: Option<String>,
+    pub(crate) rustc_version_b: Option<String>,
+    pub(crate) releases: Vec<ReleaseComparison>,
+}
+
+impl ComparisonReport {
+    /// Write the report as `report.json` and `report.html` into `dir`.
+    pub fn write_to(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("report.json"), serde_json::to_vec_pretty(self)?)?;
+        fs::write(
+            dir.join("report.html"),
+            ComparisonPage { report: self }.render()?,
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "toolchain-compare.html")]
+struct ComparisonPage<'a> {
+    report: &'a ComparisonReport,
+}
+
+impl fmt::Display for ComparisonReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "compared {} releases between {} and {}: {} regressed, {} fixed",
+            self.releases.len(),
+            self.toolchain_a,
+            self.toolchain_b,
+            self.releases.iter().filter(|r| r.regressed()).count(),
+            self.releases.iter().filter(|r| r.fixed()).count(),
+        )?;
+        for release in &self.releases {
+            if release.regressed() {
+                writeln!(f, "  regressed: {} {}", release.name, release.version)?;
+            } else if release.fixed() {
+                writeln!(f, "  fixed: {} {}", release.name, release.version)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{FakeBuild, V1, V2, async_wrapper};
+
+    fn build(status: BuildStatus, documentation_size: Option<u64>) -> ComparedBuild {
+        ComparedBuild {
+            status,
+            failure_kind: None,
+            documentation_size,
+            errors: None,
+        }
+    }
+
+    #[test]
+    fn resolve_compare_releases() {
+        async_wrapper(|env| async move {
+            let old = env
+                .fake_release()
+                .await
+                .name("foo")
+                .version(V1)
+                .create()
+                .await?;
+            let latest = env
+                .fake_release()
+                .await
+                .name("foo")
+                .version(V2)
+                .create()
+                .await?;
+            let bar = env
+                .fake_release()
+                .await
+                .name("bar")
+                .version(V1)
+                .create()
+                .await?;
+
+            let mut conn = env.async_db().async_conn().await;
+            let releases = compare_releases(
+                &mut conn,
+                &CompareCrates::List(vec!["foo".into(), format!("foo@{V1}")]),
+            )
+            .await?;
+            assert_eq!(
+                releases.iter().map(|r| r.id).collect::<Vec<_>>(),
+                vec![latest, old]
+            );
+
+            assert!(
+                compare_releases(&mut conn, &CompareCrates::List(vec!["baz".into()]))
+                    .await
+                    .is_err()
+            );
+
+            let releases = compare_releases(
+                &mut conn,
+                &CompareCrates::Query {
+                    pattern: "b%".into(),
+                    limit: 10,
+                },
+            )
+            .await?;
+            assert_eq!(releases.len(), 1);
+            assert_eq!(releases[0].id, bar);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn current_build_is_latest_finished_build() {
+        async_wrapper(|env| async move {
+            let release_id = env
+                .fake_release()
+                .await
+                .name("foo")
+                .version(V1)
+                .builds(vec![
+                    FakeBuild::default().build_status(BuildStatus::Failure),
+                    FakeBuild::default().build_status(BuildStatus::InProgress),
+                ])
+                .create()
+                .await?;
+
+            let mut conn = env.async_db().async_conn().await;
+            let current = current_build(&mut conn, release_id).await?.unwrap();
+            assert_eq!(current.status, BuildStatus::Failure);
+            assert_eq!(current.failure_kind, Some(BuildFailureKind::Rustdoc));
+            assert_eq!(current.documentation_size, Some(42));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn compare_two_builds() {
+        let comparison = ReleaseComparison::new(
+            "foo".into(),
+            V1,
+            None,
+            (
+                build(BuildStatus::Success, Some(100)),
+                "fetching\ndocumenting foo\nfinished\n",
+            ),
+            (
+                build(BuildStatus::Success, Some(80)),
+                "fetching\nwarning: unused import\ndocumenting foo\nfinished\n",
+            ),
+        );
+        assert!(!comparison.status_changed);
+        assert!(!comparison.regressed());
+        assert_eq!(comparison.documentation_size_change, Some(-20));
+        assert!(comparison.log_diff.contains("+warning: unused import\n"));
+
+        let comparison = ReleaseComparison::new(
+            "foo".into(),
+            V1,
+            None,
+            (build(BuildStatus::Success, Some(100)), "log"),
+            (build(BuildStatus::Failure, None), "log"),
+        );
+        assert!(comparison.status_changed);
+        assert!(comparison.regressed());
+        assert_eq!(comparison.documentation_size_change, None);
+        assert!(comparison.log_diff.is_empty());
+    }
+
+    #[test]
+    fn write_report() -> Result<()> {
+        let report = ComparisonReport {
+            toolchain_a: "nightly-2025-10-25".into(),
+            toolchain_b: "nightly-2025-10-26".into(),
+            rustc_version_a: None,
+            rustc_version_b: None,
+            releases: vec![ReleaseComparison::new(
+                "foo".into(),
+                V1,
+                Some(build(BuildStatus::Success, Some(100))),
+                (build(BuildStatus::Success, Some(100)), "ok\n"),
+                (build(BuildStatus::Failure, None), "error: <broken>\n"),
+            )],
+        };
+
+        let dir = tempfile::tempdir()?;
+        report.write_to(dir.path())?;
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.path().join("report.json"))?)?;
+        assert_eq!(json["releases"][0]["name"], "foo");
+        assert_eq!(json["releases"][0]["b"]["status"], "failure");
+
+        let html = fs::read_to_string(dir.path().join("report.html"))?;
+        assert!(html.contains("nightly-2025-10-26"));
+        assert!(html.contains("+error: &#60;broken&#62;"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,453 @@
+use crate::error::Result;
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Read,
+};
+
+/// Documentation coverage of a release, with every public item that is missing docs.
+///
+/// Generated from the rustdoc JSON output of the default target, and stored next to the
+/// other build artifacts.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct CoverageReport {
+    /// number of public items that were checked, including the documented ones.
+    pub(crate) total_items: usize,
+    /// undocumented items, sorted by their path.
+    pub(crate) undocumented: Vec<UndocumentedItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct UndocumentedItem {
+    /// full path of the item, like `foo::bar::Baz::new`.
+    pub(crate) path: String,
+    pub(crate) kind: String,
+    /// rustdoc page of the item relative to the documentation root, with an anchor
+    /// for members of types and traits, like `foo/bar/struct.Baz.html#method.new`.
+    pub(crate) page: String,
+    /// source file of the item, relative to the crate root.
+    pub(crate) file: Option<String>,
+    pub(crate) line: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct RustdocJson {
+    root: Value,
+    index: HashMap<String, Item>,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    name: Option<String>,
+    span: Option<Span>,
+    visibility: Value,
+    docs: Option<String>,
+    /// externally tagged, like `{"struct": {..}}`, or just `"extern_type"`.
+    inner: Value,
+}
+
+#[derive(Deserialize)]
+struct Span {
+    filename: String,
+    begin: (u32, u32),
+}
+
+impl Item {
+    fn kind(&self) -> (&str, &Value) {
+        match &self.inner {
+            Value::String(kind) => (kind, &Value::Null),
+            Value::Object(inner) => inner
+                .iter()
+                .next()
+                .map_or(("", &Value::Null), |(kind, inner)| (kind, inner)),
+            _ => ("", &Value::Null),
+        }
+    }
+
+    fn is_documented(&self) -> bool {
+        self.docs
+            .as_deref()
+            .is_some_and(|docs| !docs.trim().is_empty())
+    }
+
+    fn is_public(&self) -> bool {
+        self.visibility == "public"
+    }
+}
+
+/// ids are numbers in newer format versions, and strings in older ones.
+fn id_key(id: &Value) -> Option<String> {
+    match id {
+        Value::Number(id) => Some(id.to_string()),
+        Value::String(id) => Some(id.clone()),
+        _ => None,
+    }
+}
+
+fn ids<'a>(value: &'a Value, pointer: &str) -> impl Iterator<Item = String> + 'a {
+    value
+        .pointer(pointer)
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(id_key)
+}
+
+/// The name of the rustdoc page of an item, `None` for items without their own page.
+fn page_name(kind: &str, inner: &Value, name: &str) -> Option<String> {
+    let prefix = match kind {
+        "module" => return Some(format!("{name}/index.html")),
+        "function" => "fn",
+        "type_alias" => "type",
+        "trait_alias" => "traitalias",
+        "extern_type" => "foreigntype",
+        "proc_macro" => match inner.get("kind").and_then(Value::as_str) {
+            Some("attr") => "attr",
+            Some("derive") => "derive",
+            _ => "macro",
+        },
+        "struct" | "enum" | "union" | "trait" | "constant" | "static" | "macro" => kind,
+        _ => return None,
+    };
+    Some(format!("{prefix}.{name}.html"))
+}
+
+fn kind_label(kind: &str, inner: &Value) -> String {
+    match kind {
+        "proc_macro" => match inner.get("kind").and_then(Value::as_str) {
+            Some("attr") => "attribute macro",
+            Some("derive") => "derive macro",
+            _ => "macro",
+        },
+        "extern_type" => "foreign type",
+        "struct_field" => "field",
+        "assoc_const" => "associated constant",
+        "assoc_type" => "associated type",
+        kind => kind,
+    }
+    .replace('_', " ")
+}
+
+struct Location {
+    path: Vec<String>,
+    /// page of the item, relative to the documentation root.
+    page: String,
+}
+
+impl CoverageReport {
+    pub(crate) fn from_rustdoc_json(reader: impl Read) -> Result<Self> {
+        let json: RustdocJson = serde_json::from_reader(reader)?;
+        let root = id_key(&json.root).context("rustdoc JSON has no root module")?;
+        let root_name = json
+            .index
+            .get(&root)
+            .and_then(|item| item.name.clone())
+            .context("root module is missing in rustdoc JSON")?;
+
+        let locations = locate_items(&json.index, &root, &root_name);
+
+        let mut report = CoverageReport::default();
+        for (id, location) in &locations {
+            let item = &json.index[id];
+            let (kind, inner) = item.kind();
+            report.check(
+                item,
+                kind_label(kind, inner),
+                location.path.join("::"),
+                location.page.clone(),
+            );
+            report.check_members(&json.index, item, location);
+        }
+        report
+            .undocumented
+            .sort_by(|a, b| a.path.cmp(&b.path).then_with(|| a.kind.cmp(&b.kind)));
+        Ok(report)
+    }
+
+    fn check(&mut self, item: &Item, kind: String, path: String, page: String) {
+        self.total_items += 1;
+        if item.is_documented() {
+            return;
+        }
+        self.undocumented.push(UndocumentedItem {
+            path,
+            kind,
+            page,
+            file: item.span.as_ref().map(|span| span.filename.clone()),
+            line: item.span.as_ref().map(|span| span.begin.0),
+        });
+    }
+
+    fn check_members(&mut self, index: &HashMap<String, Item>, item: &Item, location: &Location) {
+        let (kind, inner) = item.kind();
+        let mut members = Vec::new();
+        match kind {
+            "struct" | "union" => {
+                // fields of tuple structs have no docs in rustdoc either.
+                let fields = if kind == "struct" {
+                    "/kind/plain/fields"
+                } else {
+                    "/fields"
+                };
+                members.extend(ids(inner, fields).map(|id| (id, false)));
+                members.extend(inherent_impl_items(index, inner).map(|id| (id, false)));
+            }
+            "enum" => {
+                members.extend(ids(inner, "/variants").map(|id| (id, true)));
+                members.extend(inherent_impl_items(index, inner).map(|id| (id, false)));
+            }
+            "trait" => members.extend(ids(inner, "/items").map(|id| (id, true))),
+            _ => {}
+        }
+
+        for (id, always_public) in members {
+            let Some(member) = index.get(&id) else {
+                continue;
+            };
+            let Some(name) = &member.name else {
+                continue;
+            };
+            if !always_public && !member.is_public() {
+                continue;
+            }
+            let (member_kind, member_inner) = member.kind();
+            let anchor = match member_kind {
+                "struct_field" => "structfield",
+                "variant" => "variant",
+                "function"
+                    if kind == "trait"
+                        && member_inner.get("has_body") == Some(&Value::Bool(false)) =>
+                {
+                    "tymethod"
+                }
+                "function" => "method",
+                "assoc_const" => "associatedconstant",
+                "assoc_type" => "associatedtype",
+                _ => continue,
+            };
+
+            let label = if member_kind == "function" {
+                "method".into()
+            } else {
+                kind_label(member_kind, member_inner)
+            };
+            self.check(
+                member,
+                label,
+                format!("{}::{name}", location.path.join("::")),
+                format!("{}#{anchor}.{name}", location.page),
+            );
+        }
+    }
+}
+
+fn inherent_impl_items<'a>(
+    index: &'a HashMap<String, Item>,
+    inner: &'a Value,
+) -> impl Iterator<Item = String> + 'a {
+    ids(inner, "/impls")
+        .filter_map(|id| index.get(&id))
+        .filter_map(|item| match item.kind() {
+            ("impl", inner) if inner.get("trait").is_none_or(Value::is_null) => Some(inner),
+            _ => None,
+        })
+        .flat_map(|inner| ids(inner, "/items"))
+}
+
+/// Find the rustdoc page of all public items, starting from the root module.
+///
+/// Items are shown where they are defined when their module is public, re-exports of
+/// items from private modules are inlined into the module with the re-export, like
+/// rustdoc does.
+fn locate_items(
+    index: &HashMap<String, Item>,
+    root: &str,
+    root_name: &str,
+) -> HashMap<String, Location> {
+    let mut locations = HashMap::new();
+    locations.insert(
+        root.to_owned(),
+        Location {
+            path: vec![root_name.to_owned()],
+            page: format!("{root_name}/index.html"),
+        },
+    );
+
+    let mut modules = VecDeque::from([(root.to_owned(), vec![root_name.to_owned()])]);
+    let mut reexports = Vec::new();
+    let mut globbed = HashSet::new();
+
+    let mut locate = |id: String,
+                      name: &str,
+                      module_path: &[String],
+                      modules: &mut VecDeque<(String, Vec<String>)>| {
+        let Some(item) = index.get(&id) else {
+            return;
+        };
+        if locations.contains_key(&id) {
+            return;
+        }
+        let (kind, inner) = item.kind();
+        let Some(page) = page_name(kind, inner, name) else {
+            return;
+        };
+        let mut path = module_path.to_vec();
+        path.push(name.to_owned());
+        if kind == "module" {
+            modules.push_back((id.clone(), path.clone()));
+        }
+        locations.insert(
+            id,
+            Location {
+                path,
+                page: format!("{}/{page}", module_path.join("/")),
+            },
+        );
+    };
+
+    loop {
+        while let Some((module, path)) = modules.pop_front() {
+            let Some(module) = index.get(&module) else {
+                continue;
+            };
+            for child_id in ids(module.kind().1, "/items") {
+                let Some(child) = index.get(&child_id) else {
+                    continue;
+                };
+                match child.kind() {
+                    ("use", inner) if child.is_public() => {
+                        reexports.push((inner.clone(), path.clone()))
+                    }
+                    ("use", _) => {}
+                    _ => {
+                        if let Some(name) = &child.name
+                            && child.is_public()
+                        {
+                            locate(child_id, name, &path, &mut modules);
+                        }
+                    }
+                }
+            }
+        }
+
+        if reexports.is_empty() {
+            break;
+        }
+        for (reexport, path) in reexports.drain(..) {
+            let Some(target) = reexport.get("id").and_then(id_key) else {
+                continue;
+            };
+            if reexport.get("is_glob") == Some(&Value::Bool(true)) {
+                if globbed.insert(target.clone()) {
+                    modules.push_back((target, path));
+                }
+            } else if let Some(name) = reexport.get("name").and_then(Value::as_str) {
+                locate(target, name, &path, &mut modules);
+            }
+        }
+    }
+
+    locations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn item(name: &str, docs: Option<&str>, inner: Value) -> Value {
+        json!({
+            "name": name,
+            "span": {"filename": "src/lib.rs", "begin": [1, 0], "end": [2, 0]},
+            "visibility": "public",
+            "docs": docs,
+            "inner": inner,
+        })
+    }
+
+    #[test]
+    fn finds_undocumented_items() -> Result<()> {
+        let mut index = json!({
+            "0": item("foo", Some("crate docs"), json!({"module": {"is_crate": true, "items": [1, 2, 5, 8, 11, 13]}})),
+            "1": item("Documented", Some("docs"), json!({"struct": {"kind": {"plain": {"fields": [3, 4]}}, "impls": [6, 7]}})),
+            "2": item("bar", None, json!({"module": {"items": [9]}})),
+            "3": item("field", None, json!({"struct_field": {}})),
+            "4": item("private_field", None, json!({"struct_field": {}})),
+            "5": item("Trait", Some("docs"), json!({"trait": {"items": [10, 12]}})),
+            "6": item("", None, json!({"impl": {"trait": null, "items": [14]}})),
+            "7": item("", None, json!({"impl": {"trait": {"path": "Clone"}, "items": [15]}})),
+            "8": item("private", None, json!({"module": {"items": [16]}})),
+            "9": item("bar_fn", None, json!({"function": {"has_body": true}})),
+            "10": item("required", None, json!({"function": {"has_body": false}})),
+            "11": item("Reexported", None, json!({"use": {"name": "Reexported", "id": 16, "is_glob": false}})),
+            "12": item("provided", Some("docs"), json!({"function": {"has_body": true}})),
+            "13": item("derive_it", None, json!({"proc_macro": {"kind": "derive"}})),
+            "14": item("new", None, json!({"function": {"has_body": true}})),
+            "15": item("clone", None, json!({"function": {"has_body": true}})),
+            "16": item("Hidden", None, json!({"enum": {"variants": [17], "impls": []}})),
+            "17": item("Variant", None, json!({"variant": {}})),
+        });
+        index["4"]["visibility"] = json!("crate");
+        index["8"]["visibility"] = json!("default");
+        index["9"]["span"]["filename"] = json!("src/bar.rs");
+        index["9"]["span"]["begin"] = json!([12, 4]);
+
+        let report = CoverageReport::from_rustdoc_json(
+            serde_json::to_vec(&json!({"root": 0, "format_version": 45, "index": index}))?
+                .as_slice(),
+        )?;
+
+        let undocumented = |path: &str, kind: &str, page: &str| UndocumentedItem {
+            path: path.into(),
+            kind: kind.into(),
+            page: page.into(),
+            file: Some("src/lib.rs".into()),
+            line: Some(1),
+        };
+        assert_eq!(
+            report,
+            CoverageReport {
+                // root, Documented + field + new, bar + bar_fn, Trait + 2 methods,
+                // derive_it, Hidden + Variant
+                total_items: 12,
+                undocumented: vec![
+                    undocumented(
+                        "foo::Documented::field",
+                        "field",
+                        "foo/struct.Documented.html#structfield.field"
+                    ),
+                    undocumented(
+                        "foo::Documented::new",
+                        "method",
+                        "foo/struct.Documented.html#method.new"
+                    ),
+                    undocumented("foo::Reexported", "enum", "foo/enum.Reexported.html"),
+                    undocumented(
+                        "foo::Reexported::Variant",
+                        "variant",
+                        "foo/enum.Reexported.html#variant.Variant"
+                    ),
+                    undocumented(
+                        "foo::Trait::required",
+                        "method",
+                        "foo/trait.Trait.html#tymethod.required"
+                    ),
+                    undocumented("foo::bar", "module", "foo/bar/index.html"),
+                    UndocumentedItem {
+                        file: Some("src/bar.rs".into()),
+                        line: Some(12),
+                        ..undocumented("foo::bar::bar_fn", "function", "foo/bar/fn.bar_fn.html")
+                    },
+                    undocumented(
+                        "foo::derive_it",
+                        "derive macro",
+                        "foo/derive.derive_it.html"
+                    ),
+                ],
+            }
+        );
+        Ok(())
+    }
+}
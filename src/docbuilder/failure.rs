@@ -0,0 +1,99 @@
+use crate::db::types::BuildFailureKind;
+use docsrs_metadata::MetadataError;
+use rustwide::{PrepareError, cmd::CommandError};
+
+/// Sort a build error into the kind of failure it represents.
+///
+/// Errors we don't know are treated as infrastructure failures, so they are retried
+/// like all build errors were before.
+pub(crate) fn classify_failure(err: &anyhow::Error) -> BuildFailureKind {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<PrepareError>() {
+            return match err {
+                PrepareError::PrivateGitRepository
+                | PrepareError::MissingCargoToml
+                | PrepareError::InvalidCargoTomlSyntax
+                | PrepareError::YankedDependencies(_)
+                | PrepareError::MissingDependencies(_) => BuildFailureKind::InvalidCrate,
+                _ => BuildFailureKind::Infrastructure,
+            };
+        }
+
+        if let Some(MetadataError::Parse(_)) = cause.downcast_ref::<MetadataError>() {
+            return BuildFailureKind::InvalidCrate;
+        }
+
+        if let Some(err) = cause.downcast_ref::<CommandError>() {
+            return classify_command_error(err);
+        }
+    }
+
+    BuildFailureKind::Infrastructure
+}
+
+/// Sort the error of a command we ran in the sandbox.
+///
+/// A failed command alone doesn't tell us if it was the crate or our infrastructure,
+/// so callers that know the command only fails for broken crates should handle
+/// [`CommandError::ExecutionFailed`] themselves.
+pub(crate) fn classify_command_error(err: &CommandError) -> BuildFailureKind {
+    match err {
+        CommandError::SandboxOOM => BuildFailureKind::OutOfMemory,
+        CommandError::Timeout(_) => BuildFailureKind::Timeout,
+        CommandError::NoOutputFor(_) => BuildFailureKind::NoOutput,
+        _ => BuildFailureKind::Infrastructure,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context as _, anyhow};
+
+    #[test]
+    fn unknown_errors_are_infrastructure() {
+        assert_eq!(
+            classify_failure(&anyhow!("connection reset by peer")),
+            BuildFailureKind::Infrastructure
+        );
+    }
+
+    #[test]
+    fn prepare_errors() {
+        let err = anyhow::Error::from(PrepareError::MissingDependencies("foo".into()))
+            .context("preparing the build failed");
+        assert_eq!(classify_failure(&err), BuildFailureKind::InvalidCrate);
+
+        let err = anyhow::Error::from(PrepareError::Uncategorized);
+        assert_eq!(classify_failure(&err), BuildFailureKind::Infrastructure);
+    }
+
+    #[test]
+    fn invalid_manifest() {
+        let err = toml::from_str::<toml::Value>("[package").unwrap_err();
+        let err = Err::<(), _>(MetadataError::from(err))
+            .context("reading the docs.rs metadata failed")
+            .unwrap_err();
+        assert_eq!(classify_failure(&err), BuildFailureKind::InvalidCrate);
+    }
+
+    #[test]
+    fn command_errors() {
+        assert_eq!(
+            classify_failure(&CommandError::SandboxOOM.into()),
+            BuildFailureKind::OutOfMemory
+        );
+        assert_eq!(
+            classify_failure(&CommandError::Timeout(900).into()),
+            BuildFailureKind::Timeout
+        );
+        assert_eq!(
+            classify_failure(&CommandError::NoOutputFor(300).into()),
+            BuildFailureKind::NoOutput
+        );
+        assert_eq!(
+            classify_failure(&CommandError::WorkspaceNotMountedCorrectly.into()),
+            BuildFailureKind::Infrastructure
+        );
+    }
+}
@@ -1,10 +1,14 @@
-use crate::{Config, db::Overrides, error::Result};
-use serde::Serialize;
+use crate::{
+    Config,
+    db::{Overrides, types::BuildFailureKind},
+    error::Result,
+};
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
 const GB: usize = 1024 * 1024 * 1024;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct Limits {
     pub memory: usize,
     pub targets: usize,
@@ -66,6 +70,46 @@ impl Limits {
     pub(crate) fn targets(&self) -> usize {
         self.targets
     }
+
+    /// Higher limits for another attempt of a build that ran out of memory or time.
+    ///
+    /// The exhausted limit is doubled, up to the given ceiling. Returns `None` for other
+    /// failures, or when the limit already is at the ceiling.
+    pub(crate) fn escalate(
+        &self,
+        failure: BuildFailureKind,
+        max_memory: usize,
+        max_timeout: Duration,
+    ) -> Option<Self> {
+        match failure {
+            BuildFailureKind::OutOfMemory => {
+                let memory = self.memory.saturating_mul(2).min(max_memory);
+                (memory > self.memory).then(|| Self {
+                    memory,
+                    ..self.clone()
+                })
+            }
+            BuildFailureKind::Timeout => {
+                let timeout = self.timeout.saturating_mul(2).min(max_timeout);
+                (timeout > self.timeout).then(|| Self {
+                    timeout,
+                    ..self.clone()
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The overrides that lead from `original` to these limits.
+    pub(crate) fn overrides_since(&self, original: &Self) -> Overrides {
+        let timeout = (self.timeout != original.timeout).then_some(self.timeout);
+        Overrides {
+            memory: (self.memory != original.memory).then_some(self.memory),
+            // a timeout override limits the build to one target, unless targets are set too.
+            targets: timeout.map(|_| self.targets),
+            timeout,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -171,6 +215,71 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn escalate_up_to_ceiling() {
+        let limits = Limits {
+            memory: 3 * GB,
+            targets: 10,
+            timeout: Duration::from_secs(15 * 60),
+            networking: false,
+            max_log_size: 1024,
+        };
+        let max_timeout = Duration::from_secs(45 * 60);
+
+        let escalated = limits
+            .escalate(BuildFailureKind::OutOfMemory, 8 * GB, max_timeout)
+            .unwrap();
+        assert_eq!(
+            escalated,
+            Limits {
+                memory: 6 * GB,
+                ..limits.clone()
+            }
+        );
+        assert_eq!(
+            escalated.overrides_since(&limits),
+            Overrides {
+                memory: Some(6 * GB),
+                ..Overrides::default()
+            }
+        );
+        // capped at the ceiling, and no escalation above it.
+        let escalated = escalated
+            .escalate(BuildFailureKind::OutOfMemory, 8 * GB, max_timeout)
+            .unwrap();
+        assert_eq!(escalated.memory, 8 * GB);
+        assert_eq!(
+            escalated.escalate(BuildFailureKind::OutOfMemory, 8 * GB, max_timeout),
+            None
+        );
+
+        let escalated = limits
+            .escalate(BuildFailureKind::Timeout, 8 * GB, max_timeout)
+            .unwrap();
+        assert_eq!(escalated.timeout, Duration::from_secs(30 * 60));
+        assert_eq!(
+            escalated.overrides_since(&limits),
+            Overrides {
+                timeout: Some(Duration::from_secs(30 * 60)),
+                targets: Some(10),
+                memory: None,
+            }
+        );
+
+        assert_eq!(
+            limits.escalate(BuildFailureKind::Rustdoc, 8 * GB, max_timeout),
+            None
+        );
+        assert_eq!(
+            limits.escalate(BuildFailureKind::NoOutput, 8 * GB, max_timeout),
+            None
+        );
+        assert_eq!(
+            limits.escalate(BuildFailureKind::Timeout, 8 * GB, limits.timeout),
+            None
+        );
+    }
+
     #[test]
     fn overrides_dont_lower_memory_limit() {
         async_wrapper(|env| async move {
@@ -0,0 +1,125 @@
+use crate::{Storage, db::BuildId, error::Result};
+use rustwide::logging::LogStorage;
+use std::{
+    sync::{Arc, mpsc},
+    thread,
+    time::Duration,
+};
+use tracing::warn;
+
+/// The path of the live log chunk that starts at byte `offset` of the build log `filename`.
+///
+/// The chunks of a build are deleted once its complete logs are stored.
+pub(crate) fn live_log_chunk_path(build_id: BuildId, filename: &str, offset: usize) -> String {
+    format!("{}{filename}/{offset}", live_log_prefix(build_id))
+}
+
+/// The prefix of all live log chunks of a build, inside the prefix of its build logs.
+pub(crate) fn live_log_prefix(build_id: BuildId) -> String {
+    format!("build-logs/{build_id}/live/")
+}
+
+/// Copies a build log to storage while rustwide is still capturing it, so the log
+/// can be followed while the build is running.
+///
+/// Every copy only stores the lines that were added since the last one, as a chunk named
+/// after its offset in the log, see [`live_log_chunk_path`]. The complete log is stored
+/// after the build.
+pub(crate) struct LiveBuildLog {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl LiveBuildLog {
+    pub(crate) fn start(
+        storage: Arc<Storage>,
+        build_id: BuildId,
+        filename: String,
+        log: LogStorage,
+        interval: Duration,
+    ) -> Result<Self> {
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("live-build-log".into())
+            .spawn(move || {
+                let mut stored_len = 0;
+                // runs until the sender is dropped in `stop`.
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let content = log.to_string();
+                    // the log only grows, so everything after the stored length is new.
+                    let Some(new_lines) = content.get(stored_len..).filter(|new| !new.is_empty())
+                    else {
+                        continue;
+                    };
+                    let path = live_log_chunk_path(build_id, &filename, stored_len);
+                    match storage.store_one(path.clone(), new_lines.to_owned()) {
+                        Ok(_) => stored_len = content.len(),
+                        // the next copy retries the same chunk with the lines added until then.
+                        Err(err) => warn!(?err, path, "could not store live build log"),
+                    }
+                }
+            })?;
+        Ok(Self { stop, handle })
+    }
+
+    /// Stop copying the log, waits for a running copy to finish.
+    pub(crate) fn stop(self) {
+        drop(self.stop);
+        if self.handle.join().is_err() {
+            warn!("live build log thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestEnvironment;
+    use futures_util::TryStreamExt as _;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copies_new_lines_while_capturing() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let storage = env.context.storage.clone();
+
+        let log = LogStorage::new(log::LevelFilter::Info);
+        let live_log = LiveBuildLog::start(
+            storage.clone(),
+            BuildId(1),
+            "x86_64-unknown-linux-gnu.txt".into(),
+            log.clone(),
+            Duration::from_millis(10),
+        )?;
+
+        tokio::task::spawn_blocking(move || {
+            rustwide::logging::capture(&log, || log::info!("documenting foo"));
+            thread::sleep(Duration::from_millis(100));
+            rustwide::logging::capture(&log, || log::info!("documenting bar"));
+            thread::sleep(Duration::from_millis(100));
+            live_log.stop();
+        })
+        .await?;
+
+        let storage = env.async_storage();
+        let chunk = |offset| async move {
+            let path = live_log_chunk_path(BuildId(1), "x86_64-unknown-linux-gnu.txt", offset);
+            storage
+                .get(&path, usize::MAX)
+                .await
+                .map(|blob| blob.content)
+        };
+        assert_eq!(chunk(0).await?, b"[INFO] documenting foo\n");
+        assert_eq!(chunk(23).await?, b"[INFO] documenting bar\n");
+        assert_eq!(
+            env.async_storage()
+                .list_prefix(&live_log_prefix(BuildId(1)))
+                .await
+                .try_collect::<Vec<_>>()
+                .await?
+                .len(),
+            2
+        );
+
+        Ok(())
+    }
+}
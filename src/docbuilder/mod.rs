@@ -1,8 +1,33 @@
+mod artifact_cache;
+mod canary;
+mod compare;
+mod coverage;
+mod failure;
 mod limits;
+mod live_log;
+mod reproduce;
+mod resource_usage;
 mod rustwide_builder;
 
+pub(crate) use self::artifact_cache::{ArtifactCache, ArtifactCacheKey};
+pub use self::canary::CanaryReport;
+pub(crate) use self::canary::{
+    CanaryRelease, canary_passed, canary_sample, finish_canary, record_canary_build, start_canary,
+};
+pub use self::compare::{CompareCrates, ComparisonReport};
+pub(crate) use self::compare::{
+    CompareRelease, ComparedBuild, ReleaseComparison, compare_releases, current_build,
+};
+pub(crate) use self::coverage::{CoverageReport, UndocumentedItem};
+pub(crate) use self::failure::{classify_command_error, classify_failure};
 pub(crate) use self::limits::Limits;
-pub(crate) use self::rustwide_builder::DocCoverage;
+pub(crate) use self::live_log::{LiveBuildLog, live_log_chunk_path, live_log_prefix};
+pub use self::reproduce::ReproductionReport;
+pub(crate) use self::reproduce::{diff_doc_trees, recorded_build};
+pub(crate) use self::resource_usage::{
+    ResourceSampler, ResourceUsage, build_containers, mount_suffix,
+};
+pub(crate) use self::rustwide_builder::{BuildCancellation, DocCoverage};
 pub use self::rustwide_builder::{
     BuildPackageSummary, BuilderMetrics, PackageKind, RustwideBuilder,
 };
@@ -0,0 +1,409 @@
+use crate::{
+    db::{BuildId, types::version::Version},
+    docbuilder::Limits,
+    error::Result,
+};
+use anyhow::{Context as _, anyhow};
+use chrono::{Days, NaiveDate};
+use similar::TextDiff;
+use sqlx::types::Json;
+use std::{
+    collections::BTreeSet,
+    fmt, fs,
+    io::{self, Read as _},
+    path::Path,
+};
+use walkdir::WalkDir;
+
+/// How a past build was run.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RecordedBuild {
+    pub(crate) rustc_version: Option<String>,
+    pub(crate) rustc_nightly_date: Option<NaiveDate>,
+    pub(crate) docsrs_version: Option<String>,
+    /// only recorded for newer builds.
+    pub(crate) limits: Option<Limits>,
+    /// only recorded for newer builds.
+    pub(crate) targets: Option<Vec<String>>,
+    pub(crate) default_target: Option<String>,
+    pub(crate) doc_targets: Vec<String>,
+}
+
+impl RecordedBuild {
+    /// The nightly toolchain of the build.
+    ///
+    /// The date in the rustc version is the date of the last commit,
+    /// the nightly with that commit is released the day after.
+    pub(crate) fn toolchain(&self) -> Result<String> {
+        let date = self
+            .rustc_nightly_date
+            .ok_or_else(|| anyhow!("the build has no nightly rustc version"))?;
+        Ok(format!(
+            "nightly-{}",
+            (date + Days::new(1)).format("%Y-%m-%d")
+        ))
+    }
+
+    /// The targets of the build, the default target first.
+    ///
+    /// For older builds that didn't record their targets, these are the targets
+    /// we currently have documentation for.
+    pub(crate) fn targets(&self) -> Result<Vec<String>> {
+        if let Some(targets) = &self.targets {
+            return Ok(targets.clone());
+        }
+
+        let default_target = self
+            .default_target
+            .clone()
+            .ok_or_else(|| anyhow!("the release has no default target"))?;
+        let mut targets = vec![default_target];
+        targets.extend(
+            self.doc_targets
+                .iter()
+                .filter(|&target| *target != targets[0])
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        Ok(targets)
+    }
+}
+
+pub(crate) async fn recorded_build(
+    conn: &mut sqlx::PgConnection,
+    name: &str,
+    version: &Version,
+    build_id: BuildId,
+) -> Result<RecordedBuild> {
+    let row = sqlx::query!(
+        r#"SELECT
+            builds.rustc_version,
+            builds.rustc_nightly_date,
+            builds.docsrs_version,
+            builds.limits as "limits: Json<Limits>",
+            builds.targets,
+            releases.default_target,
+            releases.doc_targets
+         FROM builds
+         INNER JOIN releases ON releases.id = builds.rid
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE builds.id = $1 AND crates.name = $2 AND releases.version = $3"#,
+        build_id.0,
+        name,
+        version as _,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| anyhow!("build {build_id} of {name} {version} not found"))?;
+
+    Ok(RecordedBuild {
+        rustc_version: row.rustc_version,
+        rustc_nightly_date: row.rustc_nightly_date,
+        docsrs_version: row.docsrs_version,
+        limits: row.limits.map(|limits| limits.0),
+        targets: row.targets,
+        default_target: row.default_target,
+        doc_targets: row
+            .doc_targets
+            .map(serde_json::from_value)
+            .transpose()
+            .context("invalid doc targets")?
+            .unwrap_or_default(),
+    })
+}
+
+/// How reproduced documentation differs from the documentation we serve.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct DocTreeDiff {
+    pub(crate) added: Vec<String>,
+    pub(crate) removed: Vec<String>,
+    pub(crate) changed: Vec<String>,
+    pub(crate) unchanged: usize,
+    /// unified diff of the changed text files.
+    pub(crate) diff: String,
+}
+
+/// Compare a local documentation directory with a rustdoc archive.
+pub(crate) fn diff_doc_trees<R: io::Read + io::Seek>(
+    local: &Path,
+    archive: R,
+) -> Result<DocTreeDiff> {
+    let mut archive = zip::ZipArchive::new(archive)?;
+    let archived_files: BTreeSet<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(String::from)
+        .collect();
+
+    let mut local_files = BTreeSet::new();
+    for entry in WalkDir::new(local) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let path = entry.path().strip_prefix(local)?;
+            local_files.insert(path.to_string_lossy().into_owned());
+        }
+    }
+
+    let mut diff = DocTreeDiff {
+        removed: archived_files.difference(&local_files).cloned().collect(),
+        ..Default::default()
+    };
+    for path in local_files {
+        if !archived_files.contains(&path) {
+            diff.added.push(path);
+            continue;
+        }
+
+        let reproduced = fs::read(local.join(&path))?;
+        let mut served = Vec::new();
+        archive.by_name(&path)?.read_to_end(&mut served)?;
+        if reproduced == served {
+            diff.unchanged += 1;
+            continue;
+        }
+
+        if let (Ok(served), Ok(reproduced)) = (
+            std::str::from_utf8(&served),
+            std::str::from_utf8(&reproduced),
+        ) {
+            diff.diff.push_str(
+                &TextDiff::from_lines(served, reproduced)
+                    .unified_diff()
+                    .header(&format!("served/{path}"), &format!("reproduced/{path}"))
+                    .to_string(),
+            );
+        }
+        diff.changed.push(path);
+    }
+
+    Ok(diff)
+}
+
+/// The result of building a release again like one of its past builds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReproductionReport {
+    pub(crate) build_id: BuildId,
+    pub(crate) toolchain: String,
+    pub(crate) recorded_rustc_version: Option<String>,
+    pub(crate) rustc_version: Option<String>,
+    pub(crate) recorded_docsrs_version: Option<String>,
+    pub(crate) docsrs_version: String,
+    /// if the build recorded its limits and targets.
+    pub(crate) recorded_configuration: bool,
+    pub(crate) limits: Limits,
+    pub(crate) targets: Vec<String>,
+    pub(crate) successful_targets: Vec<String>,
+    /// `None` when we don't serve documentation for the release.
+    pub(crate) diff: Option<DocTreeDiff>,
+}
+
+impl fmt::Display for ReproductionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "reproduced build {} with {} ({})",
+            self.build_id,
+            self.toolchain,
+            self.rustc_version
+                .as_deref()
+                .unwrap_or("unknown rustc version"),
+        )?;
+        if self.rustc_version != self.recorded_rustc_version {
+            writeln!(
+                f,
+                "warning: the build used {}",
+                self.recorded_rustc_version
+                    .as_deref()
+                    .unwrap_or("an unknown rustc version")
+            )?;
+        }
+        if self.recorded_docsrs_version.as_deref() != Some(&self.docsrs_version) {
+            writeln!(
+                f,
+                "warning: the build used {}, this is {}",
+                self.recorded_docsrs_version
+                    .as_deref()
+                    .unwrap_or("an unknown docs.rs version"),
+                self.docsrs_version,
+            )?;
+        }
+        if !self.recorded_configuration {
+            writeln!(
+                f,
+                "warning: the build didn't record its limits and targets, used the current ones"
+            )?;
+        }
+        writeln!(
+            f,
+            "limits: {} MiB memory, {}s timeout, {} targets, networking {}",
+            self.limits.memory / 1024 / 1024,
+            self.limits.timeout.as_secs(),
+            self.limits.targets,
+            if self.limits.networking {
+                "enabled"
+            } else {
+                "disabled"
+            },
+        )?;
+        writeln!(f, "targets: {}", self.targets.join(", "))?;
+        writeln!(
+            f,
+            "successful targets: {}",
+            self.successful_targets.join(", ")
+        )?;
+
+        let Some(diff) = &self.diff else {
+            return write!(f, "no documentation in storage to compare with");
+        };
+        writeln!(
+            f,
+            "documentation: {} unchanged, {} changed, {} added, {} removed files",
+            diff.unchanged,
+            diff.changed.len(),
+            diff.added.len(),
+            diff.removed.len(),
+        )?;
+        for path in &diff.changed {
+            writeln!(f, "  changed: {path}")?;
+        }
+        for path in &diff.added {
+            writeln!(f, "  added: {path}")?;
+        }
+        for path in &diff.removed {
+            writeln!(f, "  removed: {path}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::record_build_configuration,
+        test::{V1, async_wrapper},
+    };
+    use std::{io::Write as _, time::Duration};
+
+    fn recorded() -> RecordedBuild {
+        RecordedBuild {
+            rustc_version: Some("rustc 1.92.0-nightly (6c699a372 2025-10-24)".into()),
+            rustc_nightly_date: NaiveDate::from_ymd_opt(2025, 10, 24),
+            docsrs_version: None,
+            limits: None,
+            targets: None,
+            default_target: Some("x86_64-unknown-linux-gnu".into()),
+            doc_targets: vec![
+                "i686-pc-windows-msvc".into(),
+                "x86_64-unknown-linux-gnu".into(),
+            ],
+        }
+    }
+
+    #[test]
+    fn toolchain_is_nightly_after_commit_date() -> Result<()> {
+        assert_eq!(recorded().toolchain()?, "nightly-2025-10-25");
+
+        let build = RecordedBuild {
+            rustc_nightly_date: None,
+            ..recorded()
+        };
+        assert!(build.toolchain().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn targets_fall_back_to_doc_targets() -> Result<()> {
+        assert_eq!(
+            recorded().targets()?,
+            vec!["x86_64-unknown-linux-gnu", "i686-pc-windows-msvc"]
+        );
+
+        let build = RecordedBuild {
+            targets: Some(vec!["x86_64-unknown-linux-gnu".into()]),
+            ..recorded()
+        };
+        assert_eq!(build.targets()?, vec!["x86_64-unknown-linux-gnu"]);
+        Ok(())
+    }
+
+    #[test]
+    fn load_recorded_build() {
+        async_wrapper(|env| async move {
+            let release_id = env
+                .fake_release()
+                .await
+                .name("foo")
+                .version(V1)
+                .create()
+                .await?;
+
+            let mut conn = env.async_db().async_conn().await;
+            let build_id = sqlx::query_scalar!(
+                r#"SELECT id as "id: BuildId" FROM builds WHERE rid = $1"#,
+                release_id.0
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+
+            let build = recorded_build(&mut conn, "foo", &V1, build_id).await?;
+            assert_eq!(build.limits, None);
+            assert_eq!(build.targets, None);
+
+            let limits = Limits {
+                memory: 1024,
+                targets: 1,
+                timeout: Duration::from_secs(60),
+                networking: false,
+                max_log_size: 1024,
+            };
+            record_build_configuration(&mut conn, build_id, &limits, &["x86_64-unknown-linux-gnu"])
+                .await?;
+
+            let build = recorded_build(&mut conn, "foo", &V1, build_id).await?;
+            assert_eq!(build.limits, Some(limits));
+            assert_eq!(
+                build.targets,
+                Some(vec!["x86_64-unknown-linux-gnu".to_string()])
+            );
+            assert_eq!(build.toolchain()?, "nightly-1970-01-02");
+
+            assert!(
+                recorded_build(&mut conn, "bar", &V1, build_id)
+                    .await
+                    .is_err()
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn diff_local_docs_with_archive() -> Result<()> {
+        let mut archive = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (path, content) in [
+            ("foo/index.html", "<h1>foo</h1>\n"),
+            ("foo/struct.Bar.html", "<h1>Bar</h1>\n"),
+            ("foo/struct.Baz.html", "<h1>Baz</h1>\n"),
+        ] {
+            archive.start_file(path, zip::write::SimpleFileOptions::default())?;
+            archive.write_all(content.as_bytes())?;
+        }
+        let archive = archive.finish()?;
+
+        let local = tempfile::tempdir()?;
+        fs::create_dir(local.path().join("foo"))?;
+        fs::write(local.path().join("foo/index.html"), "<h1>foo</h1>\n")?;
+        fs::write(local.path().join("foo/struct.Bar.html"), "<h1>Bar!</h1>\n")?;
+        fs::write(local.path().join("foo/struct.Qux.html"), "<h1>Qux</h1>\n")?;
+
+        let diff = diff_doc_trees(local.path(), archive)?;
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.changed, vec!["foo/struct.Bar.html"]);
+        assert_eq!(diff.added, vec!["foo/struct.Qux.html"]);
+        assert_eq!(diff.removed, vec!["foo/struct.Baz.html"]);
+        assert!(diff.diff.contains("-<h1>Bar</h1>\n+<h1>Bar!</h1>\n"));
+
+        Ok(())
+    }
+}
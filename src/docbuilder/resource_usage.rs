@@ -0,0 +1,351 @@
+use crate::error::Result;
+use anyhow::{Context as _, bail};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+use tracing::warn;
+
+/// Resources a build used in its sandbox, `None` when they couldn't be measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct ResourceUsage {
+    /// highest memory usage of a sandbox container, in bytes.
+    pub(crate) peak_memory: Option<u64>,
+    /// CPU time of the sandbox containers.
+    pub(crate) cpu_time: Option<Duration>,
+    /// size of the target directory after the build, in bytes.
+    pub(crate) disk_usage: Option<u64>,
+    /// bytes sent and received by the sandbox containers.
+    pub(crate) network_bytes: Option<u64>,
+}
+
+/// The counters of a running sandbox container, totals since it was started.
+#[derive(Debug, Clone, PartialEq)]
+struct ContainerSample {
+    container: String,
+    cpu_time: Duration,
+    peak_memory: u64,
+    network_bytes: u64,
+}
+
+impl ContainerSample {
+    /// Read the counters of the container with the given init process.
+    ///
+    /// CPU time and peak memory come from the cgroup of the container, the network bytes
+    /// from its network namespace. This needs the cgroup v2 hierarchy and the processes of the
+    /// host, so it doesn't work when docs.rs itself runs in a container without them.
+    fn read(container: String, pid: u32) -> Result<Self> {
+        let proc = PathBuf::from(format!("/proc/{pid}"));
+        let cgroups = fs::read_to_string(proc.join("cgroup"))?;
+        let cgroup = Path::new(CGROUP_ROOT).join(cgroup_path(&cgroups)?.trim_start_matches('/'));
+
+        // `memory.peak` needs Linux 5.19, the current usage is the best we have before.
+        let peak_memory = fs::read_to_string(cgroup.join("memory.peak"))
+            .or_else(|_| fs::read_to_string(cgroup.join("memory.current")))?;
+
+        Ok(Self {
+            container,
+            cpu_time: parse_cpu_stat(&fs::read_to_string(cgroup.join("cpu.stat"))?)?,
+            peak_memory: peak_memory.trim().parse()?,
+            network_bytes: parse_net_dev(&fs::read_to_string(proc.join("net/dev"))?)?,
+        })
+    }
+}
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The cgroup v2 path in `/proc/<pid>/cgroup`, like `0::/system.slice/docker-4b3c.scope`.
+fn cgroup_path(content: &str) -> Result<&str> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .context("the process isn't in a cgroup v2 hierarchy")
+}
+
+/// The CPU time of a cgroup, from `usage_usec` in its `cpu.stat`.
+fn parse_cpu_stat(content: &str) -> Result<Duration> {
+    let usage = content
+        .lines()
+        .find_map(|line| line.strip_prefix("usage_usec "))
+        .context("cpu.stat has no usage_usec")?;
+    Ok(Duration::from_micros(usage.trim().parse()?))
+}
+
+/// The bytes received and sent by all interfaces in `/proc/<pid>/net/dev`, except loopback.
+fn parse_net_dev(content: &str) -> Result<u64> {
+    let mut bytes = 0;
+    // the first two lines are headers.
+    for line in content.lines().skip(2) {
+        let (interface, counters) = line
+            .split_once(':')
+            .with_context(|| format!("invalid net/dev line {line}"))?;
+        if interface.trim() == "lo" {
+            continue;
+        }
+        let counters: Vec<&str> = counters.split_whitespace().collect();
+        // 8 receive counters followed by 8 transmit counters, both starting with the bytes.
+        let (Some(received), Some(sent)) = (counters.first(), counters.get(8)) else {
+            bail!("invalid net/dev line {line}");
+        };
+        bytes += received.parse::<u64>()? + sent.parse::<u64>()?;
+    }
+    Ok(bytes)
+}
+
+/// The latest sample of every container of the build.
+///
+/// The counters are totals since the container was started, and the commands of a build run
+/// one after another, each in its own container. What a container used after its last sample
+/// isn't counted.
+#[derive(Debug, Default)]
+struct Samples(HashMap<String, ContainerSample>);
+
+impl Samples {
+    fn add(&mut self, samples: Vec<ContainerSample>) {
+        for sample in samples {
+            self.0.insert(sample.container.clone(), sample);
+        }
+    }
+
+    fn into_usage(self) -> ResourceUsage {
+        if self.0.is_empty() {
+            return ResourceUsage::default();
+        }
+        let samples = self.0.values();
+        ResourceUsage {
+            peak_memory: samples.clone().map(|sample| sample.peak_memory).max(),
+            cpu_time: Some(samples.clone().map(|sample| sample.cpu_time).sum()),
+            disk_usage: None,
+            network_bytes: Some(samples.map(|sample| sample.network_bytes).sum()),
+        }
+    }
+}
+
+/// Samples the resource usage of the sandbox containers of a build while it is running.
+///
+/// rustwide doesn't expose the containers it starts, so the sampler looks for containers of
+/// the sandbox image that mount the source directory of the build. Other builds of the
+/// same release running at the same time use other build directories, and are not counted.
+pub(crate) struct ResourceSampler {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<Samples>,
+}
+
+impl ResourceSampler {
+    pub(crate) fn start(
+        sandbox_image: String,
+        source_dir: &Path,
+        interval: Duration,
+    ) -> Result<Self> {
+        let mount_suffix = mount_suffix(source_dir);
+
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("build-resource-sampler".into())
+            .spawn(move || {
+                let mut samples = Samples::default();
+                let mut warned = false;
+                // runs until the sender is dropped in `stop`.
+                while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match sample_containers(&sandbox_image, &mount_suffix) {
+                        Ok(container_samples) => samples.add(container_samples),
+                        Err(err) if !warned => {
+                            warn!(?err, "could not sample the resource usage of the build");
+                            warned = true;
+                        }
+                        Err(_) => {}
+                    }
+                }
+                samples
+            })?;
+        Ok(Self { stop, handle })
+    }
+
+    /// Stop sampling, and return the resource usage of the build so far.
+    pub(crate) fn stop(self) -> ResourceUsage {
+        drop(self.stop);
+        match self.handle.join() {
+            Ok(samples) => samples.into_usage(),
+            Err(_) => {
+                warn!("build resource sampler thread panicked");
+                ResourceUsage::default()
+            }
+        }
+    }
+}
+
+fn docker(args: &[&str]) -> Result<String> {
+    let output = Command::new("docker").args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "docker {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// The part of the source directory of a build that identifies its sandbox containers in
+/// [`build_containers`].
+///
+/// When docs.rs itself runs in docker, the mounts of the sandbox contain the host path
+/// of the source directory, so we only compare the build directory and `source`.
+pub(crate) fn mount_suffix(source_dir: &Path) -> PathBuf {
+    source_dir
+        .iter()
+        .rev()
+        .take(2)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect()
+}
+
+/// The ids of the running containers of the sandbox image that mount one of the source
+/// directories identified by `mount_suffixes`.
+pub(crate) fn build_containers(
+    sandbox_image: &str,
+    mount_suffixes: &[PathBuf],
+) -> Result<Vec<String>> {
+    Ok(inspect_build_containers(sandbox_image, mount_suffixes)?
+        .into_iter()
+        .map(|(id, _pid)| id)
+        .collect())
+}
+
+/// The ids and the init processes of the containers found by [`build_containers`].
+fn inspect_build_containers(
+    sandbox_image: &str,
+    mount_suffixes: &[PathBuf],
+) -> Result<Vec<(String, u32)>> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Mount {
+        source: String,
+    }
+
+    let containers = docker(&[
+        "ps",
+        "--quiet",
+        "--filter",
+        &format!("ancestor={sandbox_image}"),
+    ])?;
+    let containers: Vec<_> = containers.lines().collect();
+    if containers.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut inspect = vec![
+        "inspect",
+        "--format",
+        "{{.Id}} {{.State.Pid}} {{json .Mounts}}",
+    ];
+    inspect.extend(&containers);
+    let mut build_containers = Vec::new();
+    // containers can stop between the two commands, `inspect` then fails for all of them.
+    for line in docker(&inspect).unwrap_or_default().lines() {
+        let mut fields = line.splitn(3, ' ');
+        let (Some(id), Some(pid), Some(mounts)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let mounts: Vec<Mount> = serde_json::from_str(mounts)?;
+        if mounts.iter().any(|mount| {
+            mount_suffixes
+                .iter()
+                .any(|suffix| Path::new(&mount.source).ends_with(suffix))
+        }) {
+            build_containers.push((id.to_owned(), pid.parse()?));
+        }
+    }
+    Ok(build_containers)
+}
+
+fn sample_containers(sandbox_image: &str, mount_suffix: &Path) -> Result<Vec<ContainerSample>> {
+    let mut samples = Vec::new();
+    for (id, pid) in inspect_build_containers(sandbox_image, &[mount_suffix.to_owned()])? {
+        match ContainerSample::read(id, pid) {
+            Ok(sample) => samples.push(sample),
+            // the container stopped since it was inspected.
+            Err(_) if !Path::new(&format!("/proc/{pid}")).exists() => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cgroup_path() -> Result<()> {
+        assert_eq!(
+            cgroup_path("0::/system.slice/docker-4b3c.scope\n")?,
+            "/system.slice/docker-4b3c.scope"
+        );
+        // cgroup v1
+        assert!(cgroup_path("12:memory:/docker/4b3c\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_cpu_stat() -> Result<()> {
+        assert_eq!(
+            parse_cpu_stat("usage_usec 1500000\nuser_usec 1000000\nsystem_usec 500000\n")?,
+            Duration::from_millis(1500)
+        );
+        assert!(parse_cpu_stat("user_usec 1000000\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_net_dev() -> Result<()> {
+        let content = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:     100       1    0    0    0     0          0         0      100       1    0    0    0     0       0          0
+  eth0:    1000      10    0    0    0     0          0         0      500       5    0    0    0     0       0          0
+";
+        assert_eq!(parse_net_dev(content)?, 1500);
+        assert!(parse_net_dev("header\nheader\n  eth0: 1000\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sums_samples() {
+        let sample = |container: &str, cpu_secs, peak_memory, network_bytes| ContainerSample {
+            container: container.into(),
+            cpu_time: Duration::from_secs(cpu_secs),
+            peak_memory,
+            network_bytes,
+        };
+
+        let samples = Samples::default();
+        assert_eq!(samples.into_usage(), ResourceUsage::default());
+
+        let mut samples = Samples::default();
+        samples.add(vec![sample("a", 10, 100, 10)]);
+        samples.add(vec![sample("a", 25, 120, 20), sample("b", 5, 70, 5)]);
+        // a finished container isn't sampled again, but its usage still counts.
+        samples.add(vec![sample("b", 7, 80, 7)]);
+        samples.add(Vec::new());
+
+        assert_eq!(
+            samples.into_usage(),
+            ResourceUsage {
+                peak_memory: Some(120),
+                cpu_time: Some(Duration::from_secs(25 + 7)),
+                disk_usage: None,
+                network_bytes: Some(27),
+            }
+        );
+    }
+}
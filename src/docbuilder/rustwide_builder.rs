@@ -2,27 +2,41 @@ use crate::{
     AsyncStorage, Config, Context, InstanceMetrics, RUSTDOC_STATIC_STORAGE_PREFIX, RegistryApi,
     Storage,
     db::{
-        BuildId, CrateId, Pool, ReleaseId, add_doc_coverage, add_path_into_remote_archive,
+        BuildId, CrateId, LimitRequest, Pool, ReleaseId, add_doc_coverage,
+        add_path_into_remote_archive,
         blacklist::is_blacklisted,
+        cancel_build,
         file::{add_path_into_database, file_list_to_json},
         finish_build, finish_release, initialize_build, initialize_crate, initialize_release,
-        types::{BuildStatus, version::Version},
+        record_build_configuration, record_build_environment, record_build_target,
+        types::{BuildFailureKind, BuildStatus, version::Version},
         update_build_with_error, update_crate_data_in_database,
     },
-    docbuilder::Limits,
+    docbuilder::{
+        ArtifactCache, ArtifactCacheKey, CanaryRelease, CanaryReport, CompareCrates,
+        CompareRelease, ComparedBuild, ComparisonReport, CoverageReport, Limits, LiveBuildLog,
+        ReleaseComparison, ReproductionReport, ResourceSampler, ResourceUsage, build_containers,
+        canary_passed, canary_sample, classify_command_error, classify_failure, compare_releases,
+        current_build, diff_doc_trees, finish_canary, live_log_prefix, mount_suffix,
+        record_canary_build, recorded_build, start_canary,
+    },
     error::Result,
-    metrics::{BUILD_TIME_HISTOGRAM_BUCKETS, DOCUMENTATION_SIZE_BUCKETS, otel::AnyMeterProvider},
+    metrics::{
+        BUILD_RESOURCE_SIZE_BUCKETS, BUILD_TIME_HISTOGRAM_BUCKETS, DOCUMENTATION_SIZE_BUCKETS,
+        otel::AnyMeterProvider,
+    },
     repositories::RepositoryStatsUpdater,
     storage::{
-        CompressionAlgorithm, RustdocJsonFormatVersion, compress, get_file_list,
-        rustdoc_archive_path, rustdoc_json_path, source_archive_path,
+        CompressionAlgorithm, RustdocJsonFormatVersion, compress, coverage_report_path,
+        get_file_list, rustdoc_archive_path, rustdoc_json_path, source_archive_path,
     },
     utils::{
-        CargoMetadata, ConfigName, MetadataPackage, copy_dir_all, get_config, parse_rustc_version,
-        report_error, retry, set_config,
+        CargoMetadata, ConfigName, MetadataPackage, copy_dir_all, dir_size, get_config,
+        parse_rustc_version, report_error, retry, set_config, spawn_blocking,
     },
 };
 use anyhow::{Context as _, Error, anyhow, bail};
+use chrono::Utc;
 use docsrs_metadata::{BuildTargets, DEFAULT_TARGETS, HOST_TARGET, Metadata};
 use itertools::Itertools as _;
 use opentelemetry::metrics::{Counter, Histogram};
@@ -35,24 +49,55 @@ use rustwide::{
 };
 use serde::Deserialize;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     fs::{self, File},
-    io::BufReader,
-    path::Path,
-    sync::Arc,
-    time::Instant,
+    io::{self, BufReader},
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use tokio::runtime;
 use tracing::{debug, error, info, info_span, instrument, warn};
+use uuid::Uuid;
 
 const USER_AGENT: &str = "docs.rs builder (https://github.com/rust-lang/docs.rs)";
 const COMPONENTS: &[&str] = &["llvm-tools-preview", "rustc-dev", "rustfmt"];
 const DUMMY_CRATE_NAME: &str = "empty-library";
+/// The sandbox image rustwide uses when we don't configure our own.
+const DEFAULT_SANDBOX_IMAGE: &str = "ghcr.io/rust-lang/crates-build-env/linux";
+/// Who requests the overrides for builds that only succeeded with escalated limits.
+const LIMIT_ESCALATION_REQUESTER: &str = "docs.rs";
 const DUMMY_CRATE_VERSION: Version = Version::new(1, 0, 0);
+/// The canary of a toolchain update blocks all builds until it's done, so it only builds
+/// this many releases, even when the configured sample size is larger.
+const UPDATE_CANARY_MAX_SAMPLE_SIZE: usize = 20;
 
 pub const RUSTDOC_JSON_COMPRESSION_ALGORITHMS: &[CompressionAlgorithm] =
     &[CompressionAlgorithm::Zstd, CompressionAlgorithm::Gzip];
 
+/// The directory cargo puts the compiled artifacts for a target in.
+fn profile_dir(build: &Build, target: &str, metadata: &Metadata) -> PathBuf {
+    // proc-macros are built without `--target`, see `prepare_command`.
+    if metadata.proc_macro {
+        build.host_target_dir().join("debug")
+    } else {
+        build.host_target_dir().join(target).join("debug")
+    }
+}
+
+fn available_memory() -> u64 {
+    sysinfo::System::new_with_specifics(
+        sysinfo::RefreshKind::nothing()
+            .with_memory(sysinfo::MemoryRefreshKind::nothing().with_ram()),
+    )
+    .available_memory()
+}
+
 /// read the format version from a rustdoc JSON file.
 fn read_format_version_from_rustdoc_json(
     reader: impl std::io::Read,
@@ -72,22 +117,101 @@ fn read_format_version_from_rustdoc_json(
 }
 
 async fn get_configured_toolchain(conn: &mut sqlx::PgConnection) -> Result<Toolchain> {
-    let name: String = get_config(conn, ConfigName::Toolchain)
-        .await?
-        .unwrap_or_else(|| "nightly".into());
+    let name: String = match get_config(conn, ConfigName::ActiveToolchain).await? {
+        Some(name) => name,
+        None => get_config(conn, ConfigName::Toolchain)
+            .await?
+            .unwrap_or_else(|| "nightly".into()),
+    };
+
+    Ok(toolchain_from_name(&name))
+}
 
+fn toolchain_from_name(name: &str) -> Toolchain {
     // If the toolchain is all hex, assume it references an artifact from
     // CI, for instance an `@bors try` build.
     let re = Regex::new(r"^[a-fA-F0-9]+$").unwrap();
-    if re.is_match(&name) {
-        debug!("using CI build {}", &name);
-        Ok(Toolchain::ci(&name, false))
+    if re.is_match(name) {
+        debug!("using CI build {}", name);
+        Toolchain::ci(name, false)
     } else {
-        debug!("using toolchain {}", &name);
-        Ok(Toolchain::dist(&name))
+        debug!("using toolchain {}", name);
+        Toolchain::dist(name)
     }
 }
 
+fn copy_docs(
+    target_dir: &Path,
+    local_storage: &Path,
+    target: &str,
+    is_default_target: bool,
+) -> Result<()> {
+    let source = target_dir.join(target).join("doc");
+
+    let mut dest = local_storage.to_path_buf();
+    // only add target name to destination directory when we are copying a non-default target.
+    // this is allowing us to host documents in the root of the crate documentation directory.
+    // for example winapi will be available in docs.rs/winapi/$version/winapi/ for it's
+    // default target: x86_64-pc-windows-msvc. But since it will be built under
+    // target/x86_64-pc-windows-msvc we still need target in this function.
+    if !is_default_target {
+        dest = dest.join(target);
+    }
+
+    info!("copy {} to {}", source.display(), dest.display());
+    copy_dir_all(source, dest).map_err(Into::into)
+}
+
+/// A target built by a worker of [`build_targets_concurrently`]: the target, its build log,
+/// and if its documentation was copied to the local storage.
+type TargetBuildResult<'t> = (&'t str, String, bool);
+
+/// Build `targets` with `parallelism` concurrent workers, returns the build log of each target.
+///
+/// `build_worker` is called once per worker, with the name of the worker's own build
+/// directory and a function returning the next target to build, until there are none left.
+/// Successful targets are added to `successful_targets` in the order of `targets`.
+fn build_targets_concurrently<'t>(
+    name: &str,
+    version: &Version,
+    targets: &[&'t str],
+    parallelism: usize,
+    successful_targets: &mut Vec<String>,
+    build_worker: impl Fn(&str, &dyn Fn() -> Option<&'t str>) -> Result<Vec<TargetBuildResult<'t>>>
+    + Sync,
+) -> Result<HashMap<&'t str, String>> {
+    let queue = Mutex::new(targets.iter().copied());
+    let next_target = || queue.lock().unwrap().next();
+
+    let mut results = thread::scope(|scope| -> Result<Vec<_>> {
+        let workers: Vec<_> = (0..parallelism)
+            .map(|worker| {
+                let build_worker = &build_worker;
+                let next_target = &next_target;
+                scope.spawn(move || {
+                    build_worker(&format!("{name}-{version}-target-{worker}"), next_target)
+                })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for worker in workers {
+            results.extend(worker.join().expect("target build worker panicked")?);
+        }
+        Ok(results)
+    })?;
+
+    // keep the order of the targets, like a sequential build.
+    results.sort_by_key(|(target, ..)| targets.iter().position(|t| t == target));
+    let mut build_logs = HashMap::new();
+    for (target, build_log, successful) in results {
+        if successful {
+            successful_targets.push(target.to_string());
+        }
+        build_logs.insert(target, build_log);
+    }
+    Ok(build_logs)
+}
+
 fn build_workspace(context: &Context) -> Result<Workspace> {
     let mut builder = WorkspaceBuilder::new(&context.config.rustwide_workspace, USER_AGENT)
         .running_inside_docker(context.config.inside_docker);
@@ -108,7 +232,7 @@ fn build_workspace(context: &Context) -> Result<Workspace> {
     Ok(workspace)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum PackageKind<'a> {
     Local(&'a Path),
     CratesIo,
@@ -123,6 +247,13 @@ pub struct BuilderMetrics {
     pub failed_builds: Counter<u64>,
     pub non_library_builds: Counter<u64>,
     pub documentation_size: Histogram<u64>,
+    pub artifact_cache_hits: Counter<u64>,
+    pub artifact_cache_misses: Counter<u64>,
+    pub artifact_cache_time_saved: Counter<f64>,
+    pub build_peak_memory: Histogram<u64>,
+    pub build_cpu_time: Histogram<f64>,
+    pub build_disk_usage: Histogram<u64>,
+    pub build_network_bytes: Histogram<u64>,
 }
 
 impl BuilderMetrics {
@@ -157,10 +288,147 @@ impl BuilderMetrics {
                 .with_unit("bytes")
                 .with_description("size of the generated documentation in bytes")
                 .build(),
+            artifact_cache_hits: meter
+                .u64_counter(format!("{PREFIX}.artifact_cache_hits"))
+                .with_unit("1")
+                .build(),
+            artifact_cache_misses: meter
+                .u64_counter(format!("{PREFIX}.artifact_cache_misses"))
+                .with_unit("1")
+                .build(),
+            artifact_cache_time_saved: meter
+                .f64_counter(format!("{PREFIX}.artifact_cache_time_saved"))
+                .with_unit("s")
+                .with_description(
+                    "build time saved with cached artifacts, compared to the build that cached them",
+                )
+                .build(),
+            build_peak_memory: meter
+                .u64_histogram(format!("{PREFIX}.build_peak_memory"))
+                .with_boundaries(BUILD_RESOURCE_SIZE_BUCKETS.to_vec())
+                .with_unit("bytes")
+                .with_description("peak memory usage of the sandbox while building a target")
+                .build(),
+            build_cpu_time: meter
+                .f64_histogram(format!("{PREFIX}.build_cpu_time"))
+                .with_boundaries(BUILD_TIME_HISTOGRAM_BUCKETS.to_vec())
+                .with_unit("s")
+                .with_description("CPU time of the sandbox while building a target")
+                .build(),
+            build_disk_usage: meter
+                .u64_histogram(format!("{PREFIX}.build_disk_usage"))
+                .with_boundaries(BUILD_RESOURCE_SIZE_BUCKETS.to_vec())
+                .with_unit("bytes")
+                .with_description("size of the target directory after building a target")
+                .build(),
+            build_network_bytes: meter
+                .u64_histogram(format!("{PREFIX}.build_network_bytes"))
+                .with_unit("bytes")
+                .with_description("bytes sent and received by the sandbox while building a target")
+                .build(),
+        }
+    }
+}
+
+/// Handle to abort the build that is currently running in a [`RustwideBuilder`].
+///
+/// Clones share their state, so the handle can be passed to another thread or task
+/// that decides when the build should be cancelled.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BuildCancellation {
+    requested: Arc<AtomicBool>,
+    /// image of the sandbox containers that have to be stopped, `None` when the build
+    /// doesn't run in a sandbox.
+    sandbox_image: Option<String>,
+    /// mount suffixes of the sandboxes the build is running right now, see [`mount_suffix`].
+    running: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl BuildCancellation {
+    fn for_sandbox_image(image: &str) -> Self {
+        Self {
+            sandbox_image: Some(image.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Register a sandbox of the build running in `source_dir`, until the guard is dropped.
+    fn running_in(&self, source_dir: &Path) -> RunningSandbox<'_> {
+        let mount_suffix = mount_suffix(source_dir);
+        self.running.lock().unwrap().push(mount_suffix.clone());
+        RunningSandbox {
+            cancellation: self,
+            mount_suffix,
+        }
+    }
+
+    /// Mark the build as cancelled and stop its sandboxes.
+    pub(crate) async fn cancel(&self) -> Result<()> {
+        self.requested.store(true, Ordering::SeqCst);
+
+        let Some(image) = self.sandbox_image.clone() else {
+            return Ok(());
+        };
+        let running = self.running.lock().unwrap().clone();
+        if running.is_empty() {
+            return Ok(());
+        }
+
+        // rustwide doesn't expose the container of a running command, so like the
+        // `ResourceSampler` we look for the containers that mount the build directories.
+        // Other builds on the same server use other build directories, and keep running.
+        let containers = spawn_blocking(move || build_containers(&image, &running)).await?;
+        if containers.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            ?containers,
+            "stopping sandbox containers of the cancelled build"
+        );
+        let status = tokio::process::Command::new("docker")
+            .arg("kill")
+            .args(&containers)
+            .status()
+            .await?;
+        if !status.success() {
+            bail!("stopping the sandbox containers failed with {status}");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    fn reset(&self) {
+        self.requested.store(false, Ordering::SeqCst);
+    }
+}
+
+/// A sandbox registered with [`BuildCancellation::running_in`].
+struct RunningSandbox<'a> {
+    cancellation: &'a BuildCancellation,
+    mount_suffix: PathBuf,
+}
+
+impl Drop for RunningSandbox<'_> {
+    fn drop(&mut self) {
+        let mut running = self.cancellation.running.lock().unwrap();
+        if let Some(idx) = running.iter().position(|dir| *dir == self.mount_suffix) {
+            running.swap_remove(idx);
         }
     }
 }
 
+/// The outcome of one run of the build closure in `build_package_inner`.
+enum BuildAttempt {
+    /// the build is done, and recorded in the database.
+    Finished(bool),
+    /// the default target ran out of memory or time, the build is retried with these limits.
+    Escalate(Limits),
+}
+
 pub struct RustwideBuilder {
     workspace: Workspace,
     toolchain: Toolchain,
@@ -174,6 +442,9 @@ pub struct RustwideBuilder {
     repository_stats_updater: Arc<RepositoryStatsUpdater>,
     workspace_initialize_time: Instant,
     builder_metrics: Arc<BuilderMetrics>,
+    cancellation: BuildCancellation,
+    artifact_cache: Option<ArtifactCache>,
+    sandbox_image: String,
 }
 
 impl RustwideBuilder {
@@ -182,6 +453,11 @@ impl RustwideBuilder {
             let mut conn = context.pool.get_async().await?;
             get_configured_toolchain(&mut conn).await
         })?;
+        let sandbox_image = context
+            .config
+            .docker_image
+            .as_deref()
+            .unwrap_or(DEFAULT_SANDBOX_IMAGE);
 
         Ok(RustwideBuilder {
             workspace: build_workspace(context)?,
@@ -196,9 +472,22 @@ impl RustwideBuilder {
             repository_stats_updater: context.repository_stats_updater.clone(),
             workspace_initialize_time: Instant::now(),
             builder_metrics: context.async_build_queue.builder_metrics(),
+            cancellation: BuildCancellation::for_sandbox_image(sandbox_image),
+            artifact_cache: context.config.build_artifact_cache_size.map(|max_size| {
+                ArtifactCache::new(
+                    context.config.rustwide_workspace.join("artifact-cache"),
+                    max_size,
+                )
+            }),
+            sandbox_image: sandbox_image.into(),
         })
     }
 
+    /// Handle to cancel the build that is running in this builder.
+    pub(crate) fn cancellation(&self) -> BuildCancellation {
+        self.cancellation.clone()
+    }
+
     pub fn reinitialize_workspace_if_interval_passed(&mut self, context: &Context) -> Result<()> {
         let interval = context.config.build_workspace_reinitialization_interval;
         if self.workspace_initialize_time.elapsed() >= interval {
@@ -210,6 +499,17 @@ impl RustwideBuilder {
         Ok(())
     }
 
+    /// Stop the current build when it was cancelled.
+    ///
+    /// A cancelled sandbox just looks like a failed rustdoc run, so this has to be checked
+    /// before we continue with the next step of the build.
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancellation.is_requested() {
+            bail!("the build was cancelled");
+        }
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     fn prepare_sandbox(&self, limits: &Limits) -> SandboxBuilder {
         SandboxBuilder::new()
@@ -253,12 +553,93 @@ impl RustwideBuilder {
         Ok(())
     }
 
+    /// Whether builds may use the configured toolchain, right after it was installed.
+    ///
+    /// With `toolchain_canary_on_update`, a new rustc version is tried with a canary before
+    /// builds use it. The canary is keyed on the rustc version instead of the name of the
+    /// toolchain, so a floating channel like `nightly` that rustup updates in place gets a canary
+    /// too. There's no older version of such a channel to fall back to when its canary fails.
+    ///
+    /// The canary runs inline and blocks all builds until it's done, so it builds at most
+    /// [`UPDATE_CANARY_MAX_SAMPLE_SIZE`] releases.
+    fn toolchain_passed_canary(&mut self, configured: &str) -> Result<bool> {
+        if !self.config.toolchain_canary_on_update {
+            return Ok(true);
+        }
+
+        let rustc_version = self.rustc_version()?;
+        let (built_with, passed) = self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            let built_with: Option<String> =
+                get_config(&mut conn, ConfigName::RustcVersion).await?;
+            let passed = canary_passed(&mut conn, &rustc_version).await?;
+            Ok::<_, Error>((built_with, passed))
+        })?;
+
+        // without earlier builds there's nothing to compare the canary builds with.
+        if built_with.is_none_or(|built_with| built_with == rustc_version) {
+            return Ok(true);
+        }
+        if let Some(passed) = passed {
+            return Ok(passed);
+        }
+
+        info!(
+            configured,
+            rustc_version, "trying the new rustc version before builds use it"
+        );
+        let toolchain = self.toolchain.clone();
+        let sample_size = self
+            .config
+            .toolchain_canary_sample_size
+            .min(UPDATE_CANARY_MAX_SAMPLE_SIZE);
+        let report = self.canary(&toolchain, configured, sample_size)?;
+        info!("{report}");
+        Ok(report.passed)
+    }
+
     #[instrument(skip_all)]
     fn update_toolchain(&mut self) -> Result<bool> {
-        self.toolchain = self.runtime.block_on(async {
+        let (configured, active) = self.runtime.block_on(async {
             let mut conn = self.db.get_async().await?;
-            get_configured_toolchain(&mut conn).await
+            let configured: String = get_config(&mut conn, ConfigName::Toolchain)
+                .await?
+                .unwrap_or_else(|| "nightly".into());
+            let active: Option<String> = get_config(&mut conn, ConfigName::ActiveToolchain).await?;
+            Ok::<_, Error>((configured, active))
         })?;
+
+        self.toolchain = toolchain_from_name(&configured);
+        let mut updated = self.update_toolchain_inner()?;
+        let mut toolchain_name = configured;
+
+        if !self.toolchain_passed_canary(&toolchain_name)? {
+            match active.filter(|active| *active != toolchain_name) {
+                Some(active) => {
+                    warn!(
+                        configured = toolchain_name,
+                        active,
+                        "the canary of the configured toolchain failed, not switching to it"
+                    );
+                    self.toolchain = toolchain_from_name(&active);
+                    updated = self.update_toolchain_inner()?;
+                    toolchain_name = active;
+                }
+                None => warn!(
+                    configured = toolchain_name,
+                    "the canary of the configured toolchain failed, but it was updated in place"
+                ),
+            }
+        }
+
+        self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            set_config(&mut conn, ConfigName::ActiveToolchain, toolchain_name).await
+        })?;
+        Ok(updated)
+    }
+
+    fn update_toolchain_inner(&mut self) -> Result<bool> {
         debug!(
             configured_toolchain = self.toolchain.to_string(),
             "configured toolchain"
@@ -315,20 +696,12 @@ impl RustwideBuilder {
             }
         }
 
-        self.toolchain.install(&self.workspace)?;
-
-        for target in &targets_to_install {
-            self.toolchain.add_target(&self.workspace, target)?;
-        }
-        // NOTE: rustup will automatically refuse to update the toolchain
-        // if `rustfmt` is not available in the newer version
-        // NOTE: this ignores the error so that you can still run a build without rustfmt.
-        // This should only happen if you run a build for the first time when rustfmt isn't available.
-        for component in COMPONENTS {
-            if let Err(err) = self.toolchain.add_component(&self.workspace, component) {
-                warn!("failed to install {component}: {err}");
-                info!("continuing anyway, since this must be the first build");
-            }
+        self.install_toolchain(&self.toolchain, &targets_to_install)?;
+        if let Err(err) = self.record_available_targets() {
+            warn!(
+                ?err,
+                "couldn't record the available targets of the toolchain"
+            );
         }
 
         let new_version = self.rustc_version()?;
@@ -400,6 +773,48 @@ impl RustwideBuilder {
         Ok(has_changed)
     }
 
+    /// Install a dist toolchain with the given targets and the components we need for builds.
+    fn install_toolchain(&self, toolchain: &Toolchain, targets: &HashSet<String>) -> Result<()> {
+        toolchain.install(&self.workspace)?;
+
+        for target in targets {
+            toolchain.add_target(&self.workspace, target)?;
+        }
+        // NOTE: rustup will automatically refuse to update the toolchain
+        // if `rustfmt` is not available in the newer version
+        // NOTE: this ignores the error so that you can still run a build without rustfmt.
+        // This should only happen if you run a build for the first time when rustfmt isn't available.
+        for component in COMPONENTS {
+            if let Err(err) = toolchain.add_component(&self.workspace, component) {
+                warn!("failed to install {component}: {err}");
+                info!("continuing anyway, since this must be the first build");
+            }
+        }
+        Ok(())
+    }
+
+    /// Save all targets rustup can install for the toolchain, not only the installed ones,
+    /// since non tier-one targets are installed when a crate needs them.
+    fn record_available_targets(&self) -> Result<()> {
+        let output = Command::new(&self.workspace, self.toolchain.rustup_binary("rustup"))
+            .args(&["target", "list"])
+            .log_output(false)
+            .run_capture()?;
+        let targets: Vec<String> = output
+            .stdout_lines()
+            .iter()
+            .filter_map(|line| {
+                let target = line.trim_end_matches(" (installed)").trim();
+                (!target.is_empty()).then(|| target.to_owned())
+            })
+            .collect();
+
+        self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            set_config(&mut conn, ConfigName::AvailableTargets, targets).await
+        })
+    }
+
     fn rustc_version(&self) -> Result<String> {
         let version = self
             .toolchain
@@ -476,9 +891,10 @@ impl RustwideBuilder {
                 let metadata = Metadata::from_crate_root(build.host_source_dir())?;
 
                 let res = self.execute_build(
-                    BuildId(0),
+                    None,
                     DUMMY_CRATE_NAME,
                     &DUMMY_CRATE_VERSION,
+                    PackageKind::CratesIo,
                     HOST_TARGET,
                     true,
                     build,
@@ -486,6 +902,7 @@ impl RustwideBuilder {
                     &metadata,
                     true,
                     false,
+                    false,
                 )?;
                 if !res.result.successful {
                     bail!("failed to build dummy crate for {}", rustc_version);
@@ -550,6 +967,8 @@ impl RustwideBuilder {
         kind: PackageKind<'_>,
         collect_metrics: bool,
     ) -> Result<BuildPackageSummary> {
+        self.cancellation.reset();
+
         let (crate_id, release_id, build_id) = self.runtime.block_on(async {
             let mut conn = self.db.get_async().await?;
             let crate_id = initialize_crate(&mut conn, name).await?;
@@ -571,6 +990,16 @@ impl RustwideBuilder {
                 successful,
                 should_reattempt: false,
             }),
+            Err(_) if self.cancellation.is_requested() => self.runtime.block_on(async {
+                info!("build of {} {} was cancelled", name, version);
+                let mut conn = self.db.get_async().await?;
+                cancel_build(&mut conn, build_id).await?;
+
+                Ok(BuildPackageSummary {
+                    successful: false,
+                    should_reattempt: false,
+                })
+            }),
             Err(err) => self.runtime.block_on(async {
                 // NOTE: this might hide some errors from us, while only surfacing them in the build
                 // result.
@@ -578,11 +1007,19 @@ impl RustwideBuilder {
                 // to sentry.
                 let mut conn = self.db.get_async().await?;
 
-                update_build_with_error(&mut conn, build_id, Some(&format!("{err:?}"))).await?;
+                let failure_kind = classify_failure(&err);
+                info!(?failure_kind, "build of {} {} failed", name, version);
+                update_build_with_error(
+                    &mut conn,
+                    build_id,
+                    failure_kind,
+                    Some(&format!("{err:?}")),
+                )
+                .await?;
 
                 Ok(BuildPackageSummary {
                     successful: false,
-                    should_reattempt: true,
+                    should_reattempt: failure_kind.is_retryable(),
                 })
             }),
         }
@@ -612,13 +1049,9 @@ impl RustwideBuilder {
             return Ok(false);
         }
 
-        let limits = self.get_limits(name)?;
+        let mut limits = self.get_limits(name)?;
         if !self.config.disable_memory_limit {
-            let info = sysinfo::System::new_with_specifics(
-                sysinfo::RefreshKind::nothing()
-                    .with_memory(sysinfo::MemoryRefreshKind::nothing().with_ram()),
-            );
-            let available = info.available_memory();
+            let available = available_memory();
             if limits.memory() as u64 > available {
                 bail!(
                     "not enough memory to build {} {}: needed {} MiB, have {} MiB\nhelp: set DOCSRS_DISABLE_MEMORY_LIMIT=true to force a build",
@@ -668,272 +1101,957 @@ impl RustwideBuilder {
         fs::create_dir_all(&self.config.temp_dir)?;
         let local_storage = tempfile::tempdir_in(&self.config.temp_dir)?;
 
-        let successful = build_dir
-            .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
-            .run(|build| {
-                let mut algs = HashSet::new();
-
-                debug!("adding sources into database");
-                let files_list = {
-                    let (files_list, new_alg) =
-                        self.runtime.block_on(add_path_into_remote_archive(
-                            &self.async_storage,
-                            &source_archive_path(name, version),
-                            build.host_source_dir(),
-                            false,
-                        ))?;
-                    algs.insert(new_alg);
-                    files_list
-                };
-                let source_size: u64 = files_list.iter().map(|info| info.size).sum();
-                let metadata = Metadata::from_crate_root(build.host_source_dir())?;
-                let BuildTargets {
-                    default_target,
-                    other_targets,
-                } = metadata.targets(self.config.include_default_targets);
-                let mut targets = vec![default_target];
-                targets.extend(&other_targets);
+        // the limits a build ran out of, when it was retried with higher limits.
+        let mut exhausted_limits = None;
+        let successful = loop {
+            let attempt = build_dir
+                .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
+                .run(|build| {
+                    let mut algs = HashSet::new();
+
+                    debug!("adding sources into database");
+                    let files_list = {
+                        let (files_list, new_alg) =
+                            self.runtime.block_on(add_path_into_remote_archive(
+                                &self.async_storage,
+                                &source_archive_path(name, version),
+                                build.host_source_dir(),
+                                false,
+                            ))?;
+                        algs.insert(new_alg);
+                        files_list
+                    };
+                    let source_size: u64 = files_list.iter().map(|info| info.size).sum();
+                    let metadata = Metadata::from_crate_root(build.host_source_dir())?;
+                    let BuildTargets {
+                        default_target,
+                        other_targets,
+                    } = metadata.targets(self.config.include_default_targets);
+                    let mut targets = vec![default_target];
+                    targets.extend(&other_targets);
+
+                    self.runtime.block_on(async {
+                        let mut conn = self.db.get_async().await?;
+                        // the default target, and as many other targets as the limits allow.
+                        let built_targets = &targets[..targets.len().min(1 + limits.targets())];
+                        record_build_configuration(&mut conn, build_id, &limits, built_targets)
+                            .await?;
+
+                        let env: BTreeMap<_, BTreeMap<_, _>> = built_targets
+                            .iter()
+                            .map(|&target| {
+                                let env = metadata.environment_variables(target);
+                                (target.to_owned(), env.into_iter().collect())
+                            })
+                            .collect();
+                        record_build_environment(&mut conn, build_id, &env, &metadata.cfgs())
+                            .await
+                    })?;
 
-                {
-                    let _span = info_span!("fetch_build_std_dependencies").entered();
-                    // Fetch this before we enter the sandbox, so networking isn't blocked.
-                    build.fetch_build_std_dependencies(&targets)?;
-                }
+                    {
+                        let _span = info_span!("fetch_build_std_dependencies").entered();
+                        // Fetch this before we enter the sandbox, so networking isn't blocked.
+                        build.fetch_build_std_dependencies(&targets)?;
+                    }
 
 
-                let mut has_docs = false;
-                let mut successful_targets = Vec::new();
+                    let mut has_docs = false;
+                    let mut successful_targets = Vec::new();
 
-                // Perform an initial build
-                let mut res =
-                    self.execute_build(build_id, name, version, default_target, true, build, &limits, &metadata, false, collect_metrics)?;
+                    // Perform an initial build
+                    let mut res =
+                        self.execute_build(Some(build_id), name, version, kind, default_target, true, build, &limits, &metadata, false, collect_metrics, true)?;
+                    self.check_cancelled()?;
 
-                // If the build fails with the lockfile given, try using only the dependencies listed in Cargo.toml.
-                let cargo_lock = build.host_source_dir().join("Cargo.lock");
-                if !res.result.successful && cargo_lock.exists() {
-                    info!("removing lockfile and reattempting build");
-                    std::fs::remove_file(cargo_lock)?;
-                    {
-                        let _span = info_span!("cargo_generate_lockfile").entered();
-                        Command::new(&self.workspace, self.toolchain.cargo())
-                            .cd(build.host_source_dir())
-                            .args(&["generate-lockfile"])
-                            .run_capture()?;
+                    // If the build fails with the lockfile given, try using only the dependencies listed in Cargo.toml.
+                    if !res.result.successful && self.regenerate_lockfile(build)? {
+                        res =
+                            self.execute_build(Some(build_id), name, version, kind, default_target, true, build, &limits, &metadata, false, collect_metrics, true)?;
+                        self.check_cancelled()?;
                     }
+
+                    // the sandbox limits are fixed for a run, so retrying with higher limits
+                    // needs a new run.
+                    if exhausted_limits.is_none()
+                        && let Some(failure) = res.result.failure_kind
+                        && let Some(escalated) = self.escalate_limits(&limits, failure)
                     {
-                        let _span = info_span!("cargo fetch --locked").entered();
-                        Command::new(&self.workspace, self.toolchain.cargo())
-                            .cd(build.host_source_dir())
-                            .args(&["fetch", "--locked"])
-                            .run_capture()?;
+                        info!(?failure, ?escalated, "retrying the build with higher limits");
+                        return Ok(BuildAttempt::Escalate(escalated));
                     }
-                    res =
-                        self.execute_build(build_id, name, version, default_target, true, build, &limits, &metadata, false, collect_metrics)?;
-                }
 
-                if res.result.successful
-                    && let Some(name) = res.cargo_metadata.root().library_name() {
-                        let host_target = build.host_target_dir();
-                        has_docs = host_target
-                            .join(default_target)
-                            .join("doc")
-                            .join(name)
-                            .is_dir();
-                    }
+                    if res.result.successful
+                        && let Some(name) = res.cargo_metadata.root().library_name() {
+                            let host_target = build.host_target_dir();
+                            has_docs = host_target
+                                .join(default_target)
+                                .join("doc")
+                                .join(name)
+                                .is_dir();
+                        }
 
-                let mut target_build_logs = HashMap::new();
-                let documentation_size = if has_docs {
-                    debug!("adding documentation for the default target to the database");
-                    self.copy_docs(
-                        &build.host_target_dir(),
-                        local_storage.path(),
-                        default_target,
-                        true,
-                    )?;
+                    let mut target_build_logs = HashMap::new();
+                    let documentation_size = if has_docs {
+                        debug!("adding documentation for the default target to the database");
+                        copy_docs(
+                            &build.host_target_dir(),
+                            local_storage.path(),
+                            default_target,
+                            true,
+                        )?;
 
-                    successful_targets.push(res.target.clone());
+                        successful_targets.push(res.target.clone());
 
-                    // Then build the documentation for all the targets
-                    // Limit the number of targets so that no one can try to build all 200000 possible targets
-                    for target in other_targets.into_iter().take(limits.targets()) {
-                        debug!("building package {} {} for {}", name, version, target);
-                        let target_res = self.build_target(
+                        // Then build the documentation for all the targets
+                        // Limit the number of targets so that no one can try to build all 200000 possible targets
+                        let other_targets: Vec<&str> =
+                            other_targets.into_iter().take(limits.targets()).collect();
+                        target_build_logs = self.build_other_targets(
                             build_id,
                             name,
                             version,
-                            target,
+                            kind,
+                            &krate,
                             build,
+                            &other_targets,
                             &limits,
                             local_storage.path(),
                             &mut successful_targets,
                             &metadata,
                             collect_metrics,
                         )?;
-                        target_build_logs.insert(target, target_res.build_log);
+                        self.check_cancelled()?;
+                        let (file_list, new_alg) =
+                            self.runtime.block_on(add_path_into_remote_archive(
+                                &self.async_storage,
+                                &rustdoc_archive_path(name, version),
+                                local_storage.path(),
+                                true,
+                            ))?;
+                        let documentation_size = file_list.iter().map(|info| info.size).sum::<u64>();
+                        self.metrics
+                            .documentation_size
+                            .observe(documentation_size as f64 / 1024.0 / 1024.0);
+                        self.builder_metrics.documentation_size.record(documentation_size, &[]);
+                        algs.insert(new_alg);
+                        Some(documentation_size)
+                    } else {
+                        None
+                    };
+
+                    let mut async_conn = self.runtime.block_on(self.db.get_async())?;
+
+                    self.runtime.block_on(finish_build(
+                        &mut async_conn,
+                        build_id,
+                        &res.result.rustc_version,
+                        &res.result.docsrs_version,
+                        if res.result.successful {
+                            BuildStatus::Success
+                        } else {
+                            BuildStatus::Failure
+                        },
+                        res.result.failure_kind,
+                        documentation_size,
+                        None,
+                    ))?;
+
+                    {
+                        let _span = info_span!("store_build_logs").entered();
+                        let build_log_path = format!("build-logs/{build_id}/{default_target}.txt");
+                        self.storage.store_one(build_log_path, res.build_log)?;
+                        for (target, log) in target_build_logs {
+                            let build_log_path = format!("build-logs/{build_id}/{target}.txt");
+                            self.storage.store_one(build_log_path, log)?;
+                        }
+                        // the complete logs replace the live logs.
+                        self.storage.delete_prefix(&live_log_prefix(build_id))?;
                     }
-                    let (file_list, new_alg) =
-                        self.runtime.block_on(add_path_into_remote_archive(
-                            &self.async_storage,
-                            &rustdoc_archive_path(name, version),
-                            local_storage.path(),
-                            true,
+
+                    if res.result.successful {
+                        self.metrics.successful_builds.inc();
+                        self.builder_metrics.successful_builds.add(1, &[]);
+                    } else if res.cargo_metadata.root().is_library() {
+                        self.metrics.failed_builds.inc();
+                        self.builder_metrics.failed_builds.add(1, &[]);
+                    } else {
+                        self.metrics.non_library_builds.inc();
+                        self.builder_metrics.non_library_builds.add(1, &[]);
+                    }
+
+                    let release_data = if !is_local {
+                        match self
+                            .runtime
+                            .block_on(self.registry_api.get_release_data(name, version))
+                            .with_context(|| {
+                                format!("could not fetch releases-data for {name}-{version}")
+                            }) {
+                            Ok(data) => Some(data),
+                            Err(err) => {
+                                report_error(&err);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    }
+                    .unwrap_or_default();
+
+                    let cargo_metadata = res.cargo_metadata.root();
+                    let repository = self.get_repo(cargo_metadata)?;
+
+                    // when we have an unsuccessful build, but the release was already successfullly
+                    // built in the past, don't touch the release record so the docs stay intact.
+                    // This mainly happens with manually triggered or automated rebuilds.
+                    // The `release_build_status` table is already updated with the information from
+                    // the current build via `finish_build`.
+                    let current_release_build_status = self.runtime.block_on(sqlx::query_scalar!(
+                        r#"
+                        SELECT build_status AS "build_status: BuildStatus"
+                        FROM release_build_status
+                        WHERE rid = $1
+                        "#,
+                        release_id.0,
+                    ).fetch_optional(&mut *async_conn))?;
+
+                    if !res.result.successful && current_release_build_status == Some(BuildStatus::Success) {
+                        info!("build was unsuccessful, but the release was already successfully built in the past. Skipping release record update.");
+                        return Ok(BuildAttempt::Finished(false));
+                    }
+
+                    let has_examples = build.host_source_dir().join("examples").is_dir();
+                    let compression_dictionary =
+                        self.runtime.block_on(self.async_storage.upload_dictionary_id(&algs))?;
+                    self.runtime.block_on(finish_release(
+                        &mut async_conn,
+                        crate_id,
+                        release_id,
+                        cargo_metadata,
+                        &build.host_source_dir(),
+                        &res.target,
+                        file_list_to_json(files_list),
+                        successful_targets,
+                        &release_data,
+                        has_docs,
+                        has_examples,
+                        algs,
+                        compression_dictionary,
+                        repository,
+                        true,
+                        source_size,
+                    ))?;
+
+                    if let Some(doc_coverage) = res.doc_coverage {
+                        self.runtime.block_on(add_doc_coverage(
+                            &mut async_conn,
+                            release_id,
+                            doc_coverage,
                         ))?;
-                    let documentation_size = file_list.iter().map(|info| info.size).sum::<u64>();
-                    self.metrics
-                        .documentation_size
-                        .observe(documentation_size as f64 / 1024.0 / 1024.0);
-                    self.builder_metrics.documentation_size.record(documentation_size, &[]);
-                    algs.insert(new_alg);
-                    Some(documentation_size)
-                } else {
-                    None
-                };
+                    }
 
-                let mut async_conn = self.runtime.block_on(self.db.get_async())?;
+                    // Some crates.io crate data is mutable, so we proactively update it during a release
+                    if !is_local {
+                        match self
+                            .runtime
+                            .block_on(self.registry_api.get_crate_data(name))
+                        {
+                            Ok(crate_data) => self.runtime.block_on(update_crate_data_in_database(
+                                &mut async_conn,
+                                name,
+                                &crate_data,
+                            ))?,
+                            Err(err) => warn!("{:#?}", err),
+                        }
+                    }
 
-                self.runtime.block_on(finish_build(
-                    &mut async_conn,
-                    build_id,
-                    &res.result.rustc_version,
-                    &res.result.docsrs_version,
                     if res.result.successful {
+                        // delete eventually existing files from pre-archive storage.
+                        // we're doing this in the end so eventual problems in the build
+                        // won't lead to non-existing docs.
+                        for prefix in &["rustdoc", "sources"] {
+                            let prefix = format!("{prefix}/{name}/{version}/");
+                            debug!("cleaning old storage folder {}", prefix);
+                            self.storage.delete_prefix(&prefix)?;
+                        }
+                    }
+
+                    self.runtime.block_on(async move {
+                        // we need to drop the async connection inside an async runtime context
+                        // so sqlx can use a runtime to handle the pool.
+                        drop(async_conn);
+                    });
+
+                    Ok(BuildAttempt::Finished(res.result.successful))
+                })?;
+
+            match attempt {
+                BuildAttempt::Finished(successful) => break successful,
+                BuildAttempt::Escalate(escalated) => {
+                    exhausted_limits = Some(mem::replace(&mut limits, escalated));
+                }
+            }
+        };
+
+        if successful
+            && let Some(exhausted_limits) = exhausted_limits
+            && let Err(err) =
+                self.suggest_overrides(name, version, build_id, &exhausted_limits, &limits)
+        {
+            // the build is already uploaded, it shouldn't fail because of the suggestion.
+            warn!(?err, "couldn't suggest the escalated limits as overrides");
+        }
+
+        {
+            let _span = info_span!("purge_from_cache").entered();
+            krate.purge_from_cache(&self.workspace)?;
+            local_storage.close()?;
+        }
+        Ok(successful)
+    }
+
+    /// Remove the lockfile of the crate and generate a new one from the dependencies
+    /// in `Cargo.toml`.
+    ///
+    /// Returns `false` when the crate doesn't have a lockfile.
+    fn regenerate_lockfile(&self, build: &Build) -> Result<bool> {
+        let cargo_lock = build.host_source_dir().join("Cargo.lock");
+        if !cargo_lock.exists() {
+            return Ok(false);
+        }
+
+        info!("removing lockfile and reattempting build");
+        std::fs::remove_file(cargo_lock)?;
+        {
+            let _span = info_span!("cargo_generate_lockfile").entered();
+            Command::new(&self.workspace, self.toolchain.cargo())
+                .cd(build.host_source_dir())
+                .args(&["generate-lockfile"])
+                .run_capture()?;
+        }
+        {
+            let _span = info_span!("cargo fetch --locked").entered();
+            Command::new(&self.workspace, self.toolchain.cargo())
+                .cd(build.host_source_dir())
+                .args(&["fetch", "--locked"])
+                .run_capture()?;
+        }
+        Ok(true)
+    }
+
+    /// Try a new toolchain on a sample of popular releases before switching to it.
+    ///
+    /// The releases are built for their default target into a shadow prefix in storage,
+    /// and compared with their current builds. When the regression rate stays below
+    /// the configured maximum and `switch` is set, the new toolchain becomes the
+    /// configured toolchain.
+    ///
+    /// [`Self::update_toolchain_and_add_essential_files`] runs it for a new rustc version
+    /// of the configured toolchain, unless `toolchain_canary_on_update` is disabled.
+    pub fn run_toolchain_canary(
+        &mut self,
+        toolchain_name: &str,
+        switch: bool,
+    ) -> Result<CanaryReport> {
+        let toolchain = self.install_shadow_toolchain(toolchain_name)?;
+        let sample_size = self.config.toolchain_canary_sample_size;
+        let mut report = self.canary(&toolchain, toolchain_name, sample_size)?;
+
+        if report.passed && switch {
+            self.runtime.block_on(async {
+                let mut conn = self.db.get_async().await?;
+                set_config(&mut conn, ConfigName::Toolchain, toolchain_name).await
+            })?;
+            self.update_toolchain_and_add_essential_files()?;
+            report.switched = true;
+        }
+
+        Ok(report)
+    }
+
+    /// Run a canary of an installed toolchain, and record its results.
+    fn canary(
+        &mut self,
+        toolchain: &Toolchain,
+        toolchain_name: &str,
+        sample_size: usize,
+    ) -> Result<CanaryReport> {
+        let configured = mem::replace(&mut self.toolchain, toolchain.clone());
+        let rustc_version = self.rustc_version();
+        self.toolchain = configured;
+        let rustc_version = rustc_version?;
+
+        let (canary_id, sample) = self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            let canary_id = start_canary(&mut conn, toolchain_name).await?;
+            let sample = canary_sample(&mut conn, sample_size).await?;
+            Ok::<_, Error>((canary_id, sample))
+        })?;
+
+        let storage_prefix = format!("canary/{canary_id}/");
+        let built = self.build_canary_sample(toolchain, canary_id, sample, &storage_prefix);
+        // the shadow builds are only needed for the recorded results.
+        let deleted = self.storage.delete_prefix(&storage_prefix);
+        built?;
+        deleted?;
+
+        self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            finish_canary(
+                &mut conn,
+                canary_id,
+                &rustc_version,
+                self.config.toolchain_canary_max_regression_rate,
+            )
+            .await
+        })
+    }
+
+    /// Shadow build the sample of a canary below `storage_prefix`, and record the results.
+    fn build_canary_sample(
+        &mut self,
+        toolchain: &Toolchain,
+        canary_id: i32,
+        sample: Vec<CanaryRelease>,
+        storage_prefix: &str,
+    ) -> Result<()> {
+        for release in sample {
+            let (successful, failure_kind, documentation_size, errors) =
+                match self.build_shadow(toolchain, &release.name, &release.version, storage_prefix)
+                {
+                    Ok(res) => (
+                        res.successful,
+                        res.failure_kind,
+                        res.documentation_size,
+                        None,
+                    ),
+                    Err(err) => {
+                        warn!(
+                            ?err,
+                            "canary build of {} {} failed", release.name, release.version
+                        );
+                        (
+                            false,
+                            Some(classify_failure(&err)),
+                            None,
+                            Some(format!("{err:?}")),
+                        )
+                    }
+                };
+
+            self.runtime.block_on(async {
+                let mut conn = self.db.get_async().await?;
+                record_canary_build(
+                    &mut conn,
+                    canary_id,
+                    release.id,
+                    successful,
+                    failure_kind,
+                    documentation_size,
+                    errors.as_deref(),
+                )
+                .await
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Build the releases with two toolchains, and compare the builds.
+    ///
+    /// The builds don't touch the database or the documentation we serve, their
+    /// documentation and build logs only live in a temporary prefix in storage.
+    pub fn compare_toolchains(
+        &mut self,
+        toolchain_a_name: &str,
+        toolchain_b_name: &str,
+        crates: &CompareCrates,
+    ) -> Result<ComparisonReport> {
+        let toolchain_a = self.install_shadow_toolchain(toolchain_a_name)?;
+        let toolchain_b = self.install_shadow_toolchain(toolchain_b_name)?;
+
+        let releases = self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            compare_releases(&mut conn, crates).await
+        })?;
+
+        let mut report = ComparisonReport {
+            toolchain_a: toolchain_a_name.into(),
+            toolchain_b: toolchain_b_name.into(),
+            rustc_version_a: None,
+            rustc_version_b: None,
+            releases: Vec::with_capacity(releases.len()),
+        };
+        let storage_prefix = format!("toolchain-compare/{}/", Uuid::new_v4());
+        let compared = self.build_comparisons(
+            &toolchain_a,
+            &toolchain_b,
+            releases,
+            &storage_prefix,
+            &mut report,
+        );
+        // the builds are only needed for the report, also when the comparison failed.
+        let deleted = self.storage.delete_prefix(&storage_prefix);
+        compared?;
+        deleted?;
+
+        Ok(report)
+    }
+
+    /// Build the releases for [`Self::compare_toolchains`] below `storage_prefix`, and add
+    /// their comparisons to the report.
+    fn build_comparisons(
+        &mut self,
+        toolchain_a: &Toolchain,
+        toolchain_b: &Toolchain,
+        releases: Vec<CompareRelease>,
+        storage_prefix: &str,
+        report: &mut ComparisonReport,
+    ) -> Result<()> {
+        for release in releases {
+            let (a, log_a) = self.build_for_comparison(
+                toolchain_a,
+                &release,
+                &format!("{storage_prefix}a/"),
+                &mut report.rustc_version_a,
+            );
+            let (b, log_b) = self.build_for_comparison(
+                toolchain_b,
+                &release,
+                &format!("{storage_prefix}b/"),
+                &mut report.rustc_version_b,
+            );
+
+            let current = self.runtime.block_on(async {
+                let mut conn = self.db.get_async().await?;
+                current_build(&mut conn, release.id).await
+            })?;
+
+            report.releases.push(ReleaseComparison::new(
+                release.name,
+                release.version,
+                current,
+                (a, &log_a),
+                (b, &log_b),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Shadow build of a release for a toolchain comparison, returns the build and its log.
+    ///
+    /// Errors are part of the comparison, so they are recorded in the build instead of
+    /// aborting the whole comparison.
+    fn build_for_comparison(
+        &mut self,
+        toolchain: &Toolchain,
+        release: &CompareRelease,
+        storage_prefix: &str,
+        rustc_version: &mut Option<String>,
+    ) -> (ComparedBuild, String) {
+        match self.build_shadow(toolchain, &release.name, &release.version, storage_prefix) {
+            Ok(res) => {
+                *rustc_version = Some(res.rustc_version);
+                let build = ComparedBuild {
+                    status: if res.successful {
                         BuildStatus::Success
                     } else {
                         BuildStatus::Failure
                     },
-                    documentation_size,
-                    None,
-                ))?;
+                    failure_kind: res.failure_kind,
+                    documentation_size: res.documentation_size,
+                    errors: None,
+                };
+                (build, res.build_log)
+            }
+            Err(err) => {
+                warn!(
+                    ?err,
+                    "build of {} {} with {} failed", release.name, release.version, toolchain
+                );
+                let build = ComparedBuild {
+                    status: BuildStatus::Failure,
+                    failure_kind: Some(classify_failure(&err)),
+                    documentation_size: None,
+                    errors: Some(format!("{err:?}")),
+                };
+                (build, String::new())
+            }
+        }
+    }
 
-                {
-                    let _span = info_span!("store_build_logs").entered();
-                    let build_log_path = format!("build-logs/{build_id}/{default_target}.txt");
-                    self.storage.store_one(build_log_path, res.build_log)?;
-                    for (target, log) in target_build_logs {
-                        let build_log_path = format!("build-logs/{build_id}/{target}.txt");
-                        self.storage.store_one(build_log_path, log)?;
-                    }
-                }
+    /// Install a toolchain we only use for shadow builds, without switching to it.
+    fn install_shadow_toolchain(&self, toolchain_name: &str) -> Result<Toolchain> {
+        let toolchain = toolchain_from_name(toolchain_name);
+        info!(%toolchain, "installing toolchain for shadow builds");
+        if toolchain.as_ci().is_some() {
+            toolchain.install(&self.workspace)?;
+        } else {
+            let targets = DEFAULT_TARGETS.iter().map(|&t| t.to_string()).collect();
+            self.install_toolchain(&toolchain, &targets)?;
+        }
+        Ok(toolchain)
+    }
 
-                if res.result.successful {
-                    self.metrics.successful_builds.inc();
-                    self.builder_metrics.successful_builds.add(1, &[]);
-                } else if res.cargo_metadata.root().is_library() {
-                    self.metrics.failed_builds.inc();
-                    self.builder_metrics.failed_builds.add(1, &[]);
-                } else {
-                    self.metrics.non_library_builds.inc();
-                    self.builder_metrics.non_library_builds.add(1, &[]);
+    /// Build the documentation of a crates.io release for its default target with another
+    /// toolchain.
+    ///
+    /// Nothing is written to the database, and the documentation and build log are stored
+    /// below `storage_prefix` instead of next to the documentation we serve.
+    pub(crate) fn build_shadow(
+        &mut self,
+        toolchain: &Toolchain,
+        name: &str,
+        version: &Version,
+        storage_prefix: &str,
+    ) -> Result<ShadowBuildResult> {
+        let configured = std::mem::replace(&mut self.toolchain, toolchain.clone());
+        let res = self.build_shadow_inner(name, version, storage_prefix);
+        self.toolchain = configured;
+        res
+    }
+
+    fn build_shadow_inner(
+        &mut self,
+        name: &str,
+        version: &Version,
+        storage_prefix: &str,
+    ) -> Result<ShadowBuildResult> {
+        info!(
+            "shadow build of {} {} with {}",
+            name, version, self.toolchain
+        );
+
+        let limits = self.get_limits(name)?;
+        self.workspace.purge_all_build_dirs()?;
+        let mut build_dir = self
+            .workspace
+            .build_dir(&format!("shadow-{name}-{version}"));
+
+        let krate = Crate::crates_io(name, &version.to_string());
+        krate.fetch(&self.workspace)?;
+
+        fs::create_dir_all(&self.config.temp_dir)?;
+        let local_storage = tempfile::tempdir_in(&self.config.temp_dir)?;
+
+        let result = build_dir
+            .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
+            .run(|build| {
+                let metadata = Metadata::from_crate_root(build.host_source_dir())?;
+                let BuildTargets { default_target, .. } =
+                    metadata.targets(self.config.include_default_targets);
+                build.fetch_build_std_dependencies(&[default_target])?;
+
+                let execute_build = || {
+                    self.execute_build(
+                        None,
+                        name,
+                        version,
+                        PackageKind::CratesIo,
+                        default_target,
+                        true,
+                        build,
+                        &limits,
+                        &metadata,
+                        false,
+                        false,
+                        false,
+                    )
+                };
+                let mut res = execute_build()?;
+                if !res.result.successful && self.regenerate_lockfile(build)? {
+                    res = execute_build()?;
                 }
 
-                let release_data = if !is_local {
-                    match self
-                        .runtime
-                        .block_on(self.registry_api.get_release_data(name, version))
-                        .with_context(|| {
-                            format!("could not fetch releases-data for {name}-{version}")
-                        }) {
-                        Ok(data) => Some(data),
-                        Err(err) => {
-                            report_error(&err);
-                            None
-                        }
-                    }
+                let doc_dir = build.host_target_dir().join(default_target).join("doc");
+                let documentation_size = if res.result.successful && doc_dir.is_dir() {
+                    copy_docs(
+                        &build.host_target_dir(),
+                        local_storage.path(),
+                        default_target,
+                        true,
+                    )?;
+                    let (file_list, _) = self.runtime.block_on(add_path_into_remote_archive(
+                        &self.async_storage,
+                        &format!("{storage_prefix}{}", rustdoc_archive_path(name, version)),
+                        local_storage.path(),
+                        false,
+                    ))?;
+                    Some(file_list.iter().map(|info| info.size).sum())
                 } else {
                     None
-                }
-                .unwrap_or_default();
-
-                let cargo_metadata = res.cargo_metadata.root();
-                let repository = self.get_repo(cargo_metadata)?;
-
-                // when we have an unsuccessful build, but the release was already successfullly
-                // built in the past, don't touch the release record so the docs stay intact.
-                // This mainly happens with manually triggered or automated rebuilds.
-                // The `release_build_status` table is already updated with the information from
-                // the current build via `finish_build`.
-                let current_release_build_status = self.runtime.block_on(sqlx::query_scalar!(
-                    r#"
-                    SELECT build_status AS "build_status: BuildStatus"
-                    FROM release_build_status
-                    WHERE rid = $1
-                    "#,
-                    release_id.0,
-                ).fetch_optional(&mut *async_conn))?;
-
-                if !res.result.successful && current_release_build_status == Some(BuildStatus::Success) {
-                    info!("build was unsuccessful, but the release was already successfully built in the past. Skipping release record update.");
-                    return Ok(false);
-                }
+                };
 
-                let has_examples = build.host_source_dir().join("examples").is_dir();
-                self.runtime.block_on(finish_release(
-                    &mut async_conn,
-                    crate_id,
-                    release_id,
-                    cargo_metadata,
-                    &build.host_source_dir(),
-                    &res.target,
-                    file_list_to_json(files_list),
-                    successful_targets,
-                    &release_data,
-                    has_docs,
-                    has_examples,
-                    algs,
-                    repository,
-                    true,
-                    source_size,
-                ))?;
+                self.storage.store_one(
+                    format!("{storage_prefix}build-logs/{name}/{version}.txt"),
+                    res.build_log.clone(),
+                )?;
 
-                if let Some(doc_coverage) = res.doc_coverage {
-                    self.runtime.block_on(add_doc_coverage(
-                        &mut async_conn,
-                        release_id,
-                        doc_coverage,
-                    ))?;
-                }
+                Ok(ShadowBuildResult {
+                    rustc_version: res.result.rustc_version,
+                    successful: res.result.successful,
+                    failure_kind: res.result.failure_kind,
+                    documentation_size,
+                    build_log: res.build_log,
+                })
+            })?;
 
-                // Some crates.io crate data is mutable, so we proactively update it during a release
-                if !is_local {
-                    match self
-                        .runtime
-                        .block_on(self.registry_api.get_crate_data(name))
+        krate.purge_from_cache(&self.workspace)?;
+        local_storage.close()?;
+        Ok(result)
+    }
+
+    /// Build a release again with the toolchain, limits and targets of one of its past
+    /// builds, and compare the documentation with the documentation we currently serve.
+    ///
+    /// Nothing is written to the database or storage. The documentation, the build logs
+    /// and a diff of the documentation are written to `output`.
+    pub fn reproduce_build(
+        &mut self,
+        name: &str,
+        version: &Version,
+        build_id: BuildId,
+        output: &Path,
+    ) -> Result<ReproductionReport> {
+        let recorded = self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            recorded_build(&mut conn, name, version, build_id).await
+        })?;
+
+        let toolchain_name = recorded.toolchain()?;
+        let targets = recorded.targets()?;
+        let limits = match &recorded.limits {
+            Some(limits) => limits.clone(),
+            None => {
+                warn!("build {build_id} didn't record its limits, using the current limits");
+                self.get_limits(name)?
+            }
+        };
+
+        let toolchain = toolchain_from_name(&toolchain_name);
+        info!(%toolchain, "installing toolchain to reproduce build {build_id}");
+        self.install_toolchain(&toolchain, &targets.iter().cloned().collect())?;
+
+        // start with empty directories, so nothing from an earlier run ends up in the diff.
+        let docs_dir = output.join("docs");
+        let logs_dir = output.join("logs");
+        for dir in [&docs_dir, &logs_dir] {
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+            fs::create_dir_all(dir)?;
+        }
+
+        let configured = std::mem::replace(&mut self.toolchain, toolchain);
+        let res =
+            self.reproduce_build_inner(name, version, &limits, &targets, &docs_dir, &logs_dir);
+        self.toolchain = configured;
+        let (rustc_version, successful_targets) = res?;
+
+        let archive_path = rustdoc_archive_path(name, version);
+        let diff = if self.storage.exists(&archive_path)? {
+            let archive = self.storage.get_full_archive(&archive_path)?;
+            let diff = diff_doc_trees(&docs_dir, io::Cursor::new(archive.content))?;
+            fs::write(output.join("docs.diff"), &diff.diff)?;
+            Some(diff)
+        } else {
+            None
+        };
+
+        Ok(ReproductionReport {
+            build_id,
+            toolchain: toolchain_name,
+            recorded_rustc_version: recorded.rustc_version,
+            rustc_version,
+            recorded_docsrs_version: recorded.docsrs_version,
+            docsrs_version: format!("docsrs {}", crate::BUILD_VERSION),
+            recorded_configuration: recorded.limits.is_some() && recorded.targets.is_some(),
+            limits,
+            targets,
+            successful_targets,
+            diff,
+        })
+    }
+
+    /// Returns the rustc version and the successful targets.
+    fn reproduce_build_inner(
+        &mut self,
+        name: &str,
+        version: &Version,
+        limits: &Limits,
+        targets: &[String],
+        docs_dir: &Path,
+        logs_dir: &Path,
+    ) -> Result<(Option<String>, Vec<String>)> {
+        info!(
+            "reproducing build of {} {} with {}",
+            name, version, self.toolchain
+        );
+
+        self.workspace.purge_all_build_dirs()?;
+        let mut build_dir = self
+            .workspace
+            .build_dir(&format!("reproduce-{name}-{version}"));
+
+        let krate = Crate::crates_io(name, &version.to_string());
+        krate.fetch(&self.workspace)?;
+
+        let result = build_dir
+            .build(&self.toolchain, &krate, self.prepare_sandbox(limits))
+            .run(|build| {
+                let metadata = Metadata::from_crate_root(build.host_source_dir())?;
+                let targets: Vec<&str> = targets.iter().map(String::as_str).collect();
+                build.fetch_build_std_dependencies(&targets)?;
+
+                let mut rustc_version = None;
+                let mut successful_targets = Vec::new();
+                for (i, &target) in targets.iter().enumerate() {
+                    let is_default_target = i == 0;
+                    let execute_build = || {
+                        self.execute_build(
+                            None,
+                            name,
+                            version,
+                            PackageKind::CratesIo,
+                            target,
+                            is_default_target,
+                            build,
+                            limits,
+                            &metadata,
+                            false,
+                            false,
+                            false,
+                        )
+                    };
+                    let mut res = execute_build()?;
+                    // like the original build, retry the default target with a fresh lockfile.
+                    if is_default_target
+                        && !res.result.successful
+                        && self.regenerate_lockfile(build)?
                     {
-                        Ok(crate_data) => self.runtime.block_on(update_crate_data_in_database(
-                            &mut async_conn,
-                            name,
-                            &crate_data,
-                        ))?,
-                        Err(err) => warn!("{:#?}", err),
+                        res = execute_build()?;
                     }
-                }
+                    fs::write(logs_dir.join(format!("{target}.txt")), &res.build_log)?;
+                    rustc_version = Some(res.result.rustc_version);
 
-                if res.result.successful {
-                    // delete eventually existing files from pre-archive storage.
-                    // we're doing this in the end so eventual problems in the build
-                    // won't lead to non-existing docs.
-                    for prefix in &["rustdoc", "sources"] {
-                        let prefix = format!("{prefix}/{name}/{version}/");
-                        debug!("cleaning old storage folder {}", prefix);
-                        self.storage.delete_prefix(&prefix)?;
+                    if res.result.successful
+                        && build.host_target_dir().join(target).join("doc").is_dir()
+                    {
+                        copy_docs(
+                            &build.host_target_dir(),
+                            docs_dir,
+                            target,
+                            is_default_target,
+                        )?;
+                        successful_targets.push(target.to_string());
+                    } else if is_default_target {
+                        // the original build doesn't build other targets either.
+                        break;
                     }
                 }
 
-                self.runtime.block_on(async move {
-                    // we need to drop the async connection inside an async runtime context
-                    // so sqlx can use a runtime to handle the pool.
-                    drop(async_conn);
-                });
-
-                Ok(res.result.successful)
+                Ok((rustc_version, successful_targets))
             })?;
 
-        {
-            let _span = info_span!("purge_from_cache").entered();
-            krate.purge_from_cache(&self.workspace)?;
-            local_storage.close()?;
+        krate.purge_from_cache(&self.workspace)?;
+        Ok(result)
+    }
+
+    /// Build the documentation for the non-default targets, returns the build log of
+    /// each target.
+    ///
+    /// With `build_max_parallel_targets` set, several targets are built at the same time.
+    /// Each concurrent worker has its own build directory and sandboxes, and uses the
+    /// lockfile the default target was built with.
+    #[instrument(skip(self, krate, build, metadata))]
+    #[allow(clippy::too_many_arguments)]
+    fn build_other_targets<'t>(
+        &self,
+        build_id: BuildId,
+        name: &str,
+        version: &Version,
+        kind: PackageKind<'_>,
+        krate: &Crate,
+        build: &Build,
+        targets: &[&'t str],
+        limits: &Limits,
+        local_storage: &Path,
+        successful_targets: &mut Vec<String>,
+        metadata: &Metadata,
+        collect_metrics: bool,
+    ) -> Result<HashMap<&'t str, String>> {
+        let mut parallelism = self.config.build_max_parallel_targets.min(targets.len());
+        if !self.config.disable_memory_limit {
+            // every sandbox can use the whole memory limit.
+            parallelism = parallelism.min((available_memory() / limits.memory() as u64) as usize);
         }
-        Ok(successful)
+
+        let mut build_logs = HashMap::new();
+        if parallelism <= 1 {
+            for &target in targets {
+                self.check_cancelled()?;
+                debug!("building package {} {} for {}", name, version, target);
+                let target_res = self.build_target(
+                    build_id,
+                    name,
+                    version,
+                    kind,
+                    target,
+                    build,
+                    limits,
+                    local_storage,
+                    successful_targets,
+                    metadata,
+                    collect_metrics,
+                )?;
+                build_logs.insert(target, target_res.build_log);
+            }
+            return Ok(build_logs);
+        }
+
+        info!(
+            parallelism,
+            "building {} targets concurrently",
+            targets.len()
+        );
+        let lockfile = fs::read(build.host_source_dir().join("Cargo.lock")).ok();
+        build_targets_concurrently(
+            name,
+            version,
+            targets,
+            parallelism,
+            successful_targets,
+            |build_dir, next_target| {
+                self.workspace
+                    .build_dir(build_dir)
+                    .build(&self.toolchain, krate, self.prepare_sandbox(limits))
+                    .run(|worker_build| {
+                        if let Some(lockfile) = &lockfile {
+                            fs::write(worker_build.host_source_dir().join("Cargo.lock"), lockfile)?;
+                        }
+
+                        let mut results = Vec::new();
+                        while let Some(target) = next_target() {
+                            self.check_cancelled()?;
+                            debug!("building package {} {} for {}", name, version, target);
+                            let mut successful = Vec::new();
+                            let target_res = self.build_target(
+                                build_id,
+                                name,
+                                version,
+                                kind,
+                                target,
+                                worker_build,
+                                limits,
+                                local_storage,
+                                &mut successful,
+                                metadata,
+                                collect_metrics,
+                            )?;
+                            results.push((target, target_res.build_log, !successful.is_empty()));
+                        }
+                        Ok(results)
+                    })
+            },
+        )
     }
 
     #[instrument(skip(self, build))]
@@ -943,6 +2061,7 @@ impl RustwideBuilder {
         build_id: BuildId,
         name: &str,
         version: &Version,
+        kind: PackageKind<'_>,
         target: &str,
         build: &Build,
         limits: &Limits,
@@ -952,9 +2071,10 @@ impl RustwideBuilder {
         collect_metrics: bool,
     ) -> Result<FullBuildResult> {
         let target_res = self.execute_build(
-            build_id,
+            Some(build_id),
             name,
             version,
+            kind,
             target,
             false,
             build,
@@ -962,6 +2082,7 @@ impl RustwideBuilder {
             metadata,
             false,
             collect_metrics,
+            true,
         )?;
         if target_res.result.successful {
             // Cargo is not giving any error and not generating documentation of some crates
@@ -969,7 +2090,7 @@ impl RustwideBuilder {
             // adding target to successfully_targets.
             if build.host_target_dir().join(target).join("doc").is_dir() {
                 debug!("adding documentation for target {} to the database", target,);
-                self.copy_docs(&build.host_target_dir(), local_storage, target, false)?;
+                copy_docs(&build.host_target_dir(), local_storage, target, false)?;
                 successful_targets.push(target.to_string());
             }
         }
@@ -1060,6 +2181,23 @@ impl RustwideBuilder {
                 .context("couldn't parse rustdoc json to find format version")?
         };
 
+        if is_default_target {
+            let _span = info_span!("store_coverage_report").entered();
+            match File::open(&json_filename)
+                .map_err(Error::from)
+                .and_then(|file| CoverageReport::from_rustdoc_json(BufReader::new(file)))
+            {
+                Ok(report) => {
+                    self.storage.store_one(
+                        coverage_report_path(name, version),
+                        serde_json::to_vec(&report)?,
+                    )?;
+                }
+                // the report is optional, the build is still fine without it.
+                Err(err) => warn!(?err, "couldn't generate the documentation coverage report"),
+            }
+        }
+
         for alg in RUSTDOC_JSON_COMPRESSION_ALGORITHMS {
             let compressed_json: Vec<u8> = {
                 let _span =
@@ -1140,13 +2278,157 @@ impl RustwideBuilder {
         )
     }
 
+    /// Copy cached dependency artifacts into the build directory, before the first build
+    /// for the target.
+    ///
+    /// Returns the cache key when the cache is enabled, with the build time of the cached
+    /// artifacts when they were restored.
+    fn restore_cached_artifacts(
+        &self,
+        build: &Build,
+        name: &str,
+        kind: PackageKind<'_>,
+        target: &str,
+        metadata: &Metadata,
+    ) -> Option<(ArtifactCacheKey, Option<Duration>)> {
+        let cache = self.artifact_cache.as_ref()?;
+        let registry = match kind {
+            // local crates can change without a new version.
+            PackageKind::Local(_) => return None,
+            PackageKind::CratesIo => "crates.io",
+            PackageKind::Registry(registry) => registry,
+        };
+        let profile_dir = profile_dir(build, target, metadata);
+        // builds for the same target in this build directory, like the retry with a new
+        // lockfile, already have their artifacts.
+        if profile_dir.exists() {
+            return None;
+        }
+
+        let restore = || -> Result<_> {
+            let lockfile = fs::read_to_string(build.host_source_dir().join("Cargo.lock"))?;
+            // the per-target environment changes the artifacts as much as the arguments do.
+            let mut args = metadata.cargo_args(target, &[], &[]);
+            let mut env: Vec<_> = metadata.environment_variables(target).into_iter().collect();
+            env.sort();
+            args.extend(env.into_iter().map(|(key, val)| format!("{key}={val}")));
+            let key = ArtifactCacheKey::new(
+                registry,
+                name,
+                &self.rustc_version()?,
+                target,
+                &args,
+                &lockfile,
+            )?;
+            let cached_build_time = cache.restore(&key, &profile_dir)?;
+            Ok((key, cached_build_time))
+        };
+        match restore() {
+            Ok((key, cached_build_time)) => {
+                if cached_build_time.is_some() {
+                    self.builder_metrics.artifact_cache_hits.add(1, &[]);
+                } else {
+                    self.builder_metrics.artifact_cache_misses.add(1, &[]);
+                }
+                Some((key, cached_build_time))
+            }
+            Err(err) => {
+                // the cache only makes builds faster, so a build can continue without it.
+                warn!(?err, "could not restore cached build artifacts");
+                None
+            }
+        }
+    }
+
+    /// Add the artifacts of a successful build to the cache, or record how much time
+    /// the cached artifacts saved.
+    fn store_cached_artifacts(
+        &self,
+        key: &ArtifactCacheKey,
+        cached_build_time: Option<Duration>,
+        profile_dir: &Path,
+        build_time: Duration,
+    ) {
+        let Some(cache) = &self.artifact_cache else {
+            return;
+        };
+        if let Some(cached_build_time) = cached_build_time {
+            self.builder_metrics.artifact_cache_time_saved.add(
+                cached_build_time.saturating_sub(build_time).as_secs_f64(),
+                &[],
+            );
+        } else if let Err(err) = cache.store(key, profile_dir, build_time) {
+            warn!(?err, "could not store build artifacts in cache");
+        }
+    }
+
+    /// Higher limits for a build that ran out of memory or time, within the configured ceiling.
+    fn escalate_limits(&self, limits: &Limits, failure: BuildFailureKind) -> Option<Limits> {
+        let mut max_memory = self.config.build_limit_ceiling_memory;
+        if !self.config.disable_memory_limit {
+            max_memory = max_memory.min(available_memory() as usize);
+        }
+        limits.escalate(failure, max_memory, self.config.build_limit_ceiling_timeout)
+    }
+
+    /// After a build only succeeded with escalated limits, ask the admins to make them
+    /// the overrides of the crate.
+    fn suggest_overrides(
+        &self,
+        name: &str,
+        version: &Version,
+        build_id: BuildId,
+        exhausted_limits: &Limits,
+        limits: &Limits,
+    ) -> Result<()> {
+        let justification = format!(
+            "build {build_id} of {name} {version} failed with {exhausted_limits:?}, \
+             and succeeded with {limits:?}"
+        );
+        self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            // when the crate already has a pending request, the admins will see that one.
+            if let Some(id) = LimitRequest::create(
+                &mut conn,
+                name,
+                LIMIT_ESCALATION_REQUESTER,
+                &justification,
+                limits.overrides_since(exhausted_limits),
+            )
+            .await?
+            {
+                info!(id, "suggested escalated limits as overrides");
+            }
+            Ok(())
+        })
+    }
+
+    fn record_resource_usage_metrics(&self, usage: &ResourceUsage) {
+        let metrics = &self.builder_metrics;
+        if let Some(peak_memory) = usage.peak_memory {
+            metrics.build_peak_memory.record(peak_memory, &[]);
+        }
+        if let Some(cpu_time) = usage.cpu_time {
+            metrics.build_cpu_time.record(cpu_time.as_secs_f64(), &[]);
+        }
+        if let Some(disk_usage) = usage.disk_usage {
+            metrics.build_disk_usage.record(disk_usage, &[]);
+        }
+        if let Some(network_bytes) = usage.network_bytes {
+            metrics.build_network_bytes.record(network_bytes, &[]);
+        }
+    }
+
     #[instrument(skip(self, build))]
     #[allow(clippy::too_many_arguments)]
+    /// `build_id` is the build in the database this target is part of. Essential files and
+    /// shadow builds have none, so they don't get a live log or a row in `build_targets`.
     fn execute_build(
         &self,
-        build_id: BuildId,
+        build_id: Option<BuildId>,
         name: &str,
         version: &Version,
+        kind: PackageKind<'_>,
         target: &str,
         is_default_target: bool,
         build: &Build,
@@ -1154,6 +2436,7 @@ impl RustwideBuilder {
         metadata: &Metadata,
         create_essential_files: bool,
         collect_metrics: bool,
+        build_json: bool,
     ) -> Result<FullBuildResult> {
         let cargo_metadata = CargoMetadata::load_from_rustwide(
             &self.workspace,
@@ -1177,6 +2460,14 @@ impl RustwideBuilder {
         let mut storage = LogStorage::new(log::LevelFilter::Info);
         storage.set_max_size(limits.max_log_size());
 
+        let build_start = Instant::now();
+        let build_started = Utc::now();
+        let cached_artifacts = if create_essential_files {
+            None
+        } else {
+            self.restore_cached_artifacts(build, name, kind, target, metadata)
+        };
+
         // we have to run coverage before the doc-build because currently it
         // deletes the doc-target folder.
         // https://github.com/rust-lang/cargo/issues/9447
@@ -1189,16 +2480,19 @@ impl RustwideBuilder {
             }
         };
 
-        if let Err(err) = self.execute_json_build(
-            build_id,
-            name,
-            version,
-            target,
-            is_default_target,
-            build,
-            metadata,
-            limits,
-        ) {
+        if build_json
+            && let Some(build_id) = build_id
+            && let Err(err) = self.execute_json_build(
+                build_id,
+                name,
+                version,
+                target,
+                is_default_target,
+                build,
+                metadata,
+                limits,
+            )
+        {
             // FIXME: this is temporary. Theoretically all `Err` things coming out
             // of the method should be retryable, so we could juse use `?` here.
             // But since this is new, I want to be carful and first see what kind of
@@ -1209,7 +2503,29 @@ impl RustwideBuilder {
             );
         }
 
-        let successful = {
+        let live_log = build_id
+            .map(|build_id| {
+                LiveBuildLog::start(
+                    self.storage.clone(),
+                    build_id,
+                    format!("{target}.txt"),
+                    storage.clone(),
+                    self.config.build_log_live_interval,
+                )
+            })
+            .transpose()?;
+
+        let _running = self.cancellation.running_in(&build.host_source_dir());
+        // the build could have been cancelled before its sandbox was registered.
+        self.check_cancelled()?;
+
+        let resource_sampler = ResourceSampler::start(
+            self.sandbox_image.clone(),
+            &build.host_source_dir(),
+            self.config.build_resource_sample_interval,
+        )?;
+
+        let build_result = {
             let _span = info_span!("cargo_build", target = %target, is_default_target).entered();
             logging::capture(&storage, || {
                 self.prepare_command(
@@ -1221,9 +2537,65 @@ impl RustwideBuilder {
                     collect_metrics,
                 )
                 .and_then(|command| command.run().map_err(Error::from))
-                .is_ok()
             })
         };
+        if let Some(live_log) = live_log {
+            live_log.stop();
+        }
+        let resource_usage = ResourceUsage {
+            disk_usage: dir_size(build.host_target_dir())
+                .inspect_err(|err| warn!(?err, "could not measure the size of the target dir"))
+                .ok(),
+            ..resource_sampler.stop()
+        };
+        self.record_resource_usage_metrics(&resource_usage);
+
+        let failure_kind = match &build_result {
+            Ok(()) => None,
+            Err(err) => Some(match err.downcast_ref::<CommandError>() {
+                // the command itself ran, so rustdoc (or cargo) rejected the crate.
+                Some(CommandError::ExecutionFailed { .. }) => BuildFailureKind::Rustdoc,
+                Some(err) => classify_command_error(err),
+                None => classify_failure(err),
+            }),
+        };
+        if failure_kind.is_some_and(|kind| kind.is_retryable()) {
+            return Err(build_result
+                .unwrap_err()
+                .context("running the documentation build failed"));
+        }
+        let successful = build_result.is_ok();
+
+        if let Some(build_id) = build_id {
+            let errors = build_result.as_ref().err().map(|err| format!("{err:#}"));
+            self.runtime.block_on(async {
+                let mut conn = self.db.get_async().await?;
+                record_build_target(
+                    &mut conn,
+                    build_id,
+                    target,
+                    if successful {
+                        BuildStatus::Success
+                    } else {
+                        BuildStatus::Failure
+                    },
+                    failure_kind,
+                    build_started,
+                    errors.as_deref(),
+                    &resource_usage,
+                )
+                .await
+            })?;
+        }
+
+        if successful && let Some((key, cached_build_time)) = cached_artifacts {
+            self.store_cached_artifacts(
+                &key,
+                cached_build_time,
+                &profile_dir(build, target, metadata),
+                build_start.elapsed(),
+            );
+        }
 
         if collect_metrics
             && let Some(compiler_metric_target_dir) = &self.config.compiler_metrics_collection_path
@@ -1261,6 +2633,7 @@ impl RustwideBuilder {
                 rustc_version: self.rustc_version()?,
                 docsrs_version: format!("docsrs {}", crate::BUILD_VERSION),
                 successful,
+                failure_kind,
             },
             doc_coverage,
             cargo_metadata,
@@ -1323,7 +2696,7 @@ impl RustwideBuilder {
         ];
 
         rustdoc_flags_extras.extend(UNCONDITIONAL_ARGS.iter().map(|&s| s.to_owned()));
-        let mut cargo_args = metadata.cargo_args(&cargo_args, &rustdoc_flags_extras);
+        let mut cargo_args = metadata.cargo_args(target, &cargo_args, &rustdoc_flags_extras);
 
         // If the explicit target is not a tier one target, we need to install it.
         let has_build_std = cargo_args.windows(2).any(|args| {
@@ -1340,7 +2713,7 @@ impl RustwideBuilder {
             .timeout(Some(limits.timeout()))
             .no_output_timeout(None);
 
-        for (key, val) in metadata.environment_variables() {
+        for (key, val) in metadata.environment_variables(target) {
             command = command.env(key, val);
         }
 
@@ -1363,29 +2736,6 @@ impl RustwideBuilder {
     }
 
     #[instrument(skip(self))]
-    fn copy_docs(
-        &self,
-        target_dir: &Path,
-        local_storage: &Path,
-        target: &str,
-        is_default_target: bool,
-    ) -> Result<()> {
-        let source = target_dir.join(target).join("doc");
-
-        let mut dest = local_storage.to_path_buf();
-        // only add target name to destination directory when we are copying a non-default target.
-        // this is allowing us to host documents in the root of the crate documentation directory.
-        // for example winapi will be available in docs.rs/winapi/$version/winapi/ for it's
-        // default target: x86_64-pc-windows-msvc. But since it will be built under
-        // target/x86_64-pc-windows-msvc we still need target in this function.
-        if !is_default_target {
-            dest = dest.join(target);
-        }
-
-        info!("copy {} to {}", source.display(), dest.display());
-        copy_dir_all(source, dest).map_err(Into::into)
-    }
-
     fn get_repo(&self, metadata: &MetadataPackage) -> Result<Option<i32>> {
         self.runtime
             .block_on(self.repository_stats_updater.load_repository(metadata))
@@ -1414,11 +2764,22 @@ pub(crate) struct DocCoverage {
     pub(crate) items_with_examples: i32,
 }
 
+/// The outcome of a build that didn't touch the database or the documentation we serve.
+#[derive(Debug)]
+pub(crate) struct ShadowBuildResult {
+    pub(crate) rustc_version: String,
+    pub(crate) successful: bool,
+    pub(crate) failure_kind: Option<BuildFailureKind>,
+    pub(crate) documentation_size: Option<u64>,
+    pub(crate) build_log: String,
+}
+
 #[derive(Debug)]
 pub(crate) struct BuildResult {
     pub(crate) rustc_version: String,
     pub(crate) docsrs_version: String,
     pub(crate) successful: bool,
+    pub(crate) failure_kind: Option<BuildFailureKind>,
 }
 
 #[derive(Debug)]
@@ -1443,7 +2804,7 @@ mod tests {
     use crate::db::types::Feature;
     use crate::registry_api::ReleaseData;
     use crate::storage::{CompressionAlgorithm, compression};
-    use crate::test::{AxumRouterTestExt, TestEnvironment};
+    use crate::test::{AxumRouterTestExt, TestEnvironment, V1};
     use pretty_assertions::assert_eq;
     use std::{io, iter};
     use test_case::test_case;
@@ -1720,6 +3081,41 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[ignore]
+    fn test_shadow_build() -> Result<()> {
+        let env = TestEnvironment::new_with_runtime()?;
+
+        let crate_ = DUMMY_CRATE_NAME;
+        let version = DUMMY_CRATE_VERSION;
+        let mut builder = RustwideBuilder::init(&env.context).unwrap();
+        builder.update_toolchain()?;
+
+        let toolchain = builder.toolchain.clone();
+        let res = builder.build_shadow(&toolchain, crate_, &version, "canary/1/")?;
+        assert!(res.successful);
+        assert!(res.documentation_size.is_some());
+
+        let storage = env.storage();
+        assert!(storage.exists(&format!(
+            "canary/1/{}",
+            rustdoc_archive_path(crate_, &version)
+        ))?);
+        assert!(storage.exists(&format!("canary/1/build-logs/{crate_}/{version}.txt"))?);
+        assert!(!storage.exists(&rustdoc_archive_path(crate_, &version))?);
+
+        // nothing is written to the database
+        let releases = env.runtime().block_on(async {
+            let mut conn = env.async_db().async_conn().await;
+            sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM releases"#)
+                .fetch_one(&mut *conn)
+                .await
+        })?;
+        assert_eq!(releases, 0);
+
+        Ok(())
+    }
+
     #[test]
     #[ignore]
     fn test_build_binary_crate() -> Result<()> {
@@ -1806,6 +3202,7 @@ mod tests {
                 BuildStatus::Success,
                 None,
                 None,
+                None,
             )
             .await?;
             finish_release(
@@ -1842,6 +3239,7 @@ mod tests {
                 false,
                 iter::once(CompressionAlgorithm::Bzip2),
                 None,
+                None,
                 true,
                 42,
             )
@@ -2074,7 +3472,8 @@ mod tests {
         let summary = builder.build_package(crate_, &version, PackageKind::CratesIo, false)?;
 
         assert!(!summary.successful);
-        assert!(summary.should_reattempt);
+        // a missing `Cargo.toml` won't go away with another attempt.
+        assert!(!summary.should_reattempt);
 
         let row = env.runtime().block_on(async {
             let mut conn = env.async_db().async_conn().await;
@@ -2315,4 +3714,122 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    #[ignore]
+    fn test_build_targets_in_parallel() -> Result<()> {
+        let env = TestEnvironment::with_config_and_runtime(
+            TestEnvironment::base_config()
+                .build_max_parallel_targets(3)
+                .disable_memory_limit(true)
+                .build()?,
+        )?;
+
+        let mut builder = RustwideBuilder::init(&env.context)?;
+        builder.update_toolchain()?;
+
+        assert!(
+            builder
+                .build_local_package(Path::new("tests/crates/additional-targets"))?
+                .successful
+        );
+
+        let (build_id, doc_targets) = env.runtime().block_on(async {
+            let mut conn = env.async_db().async_conn().await;
+            sqlx::query!(
+                r#"SELECT
+                        b.id as "id: BuildId",
+                        r.doc_targets
+                    FROM
+                        crates as c
+                        INNER JOIN releases AS r ON c.id = r.crate_id
+                        INNER JOIN builds AS b ON r.id = b.rid
+                    WHERE
+                        c.name = $1 AND
+                        r.version = $2"#,
+                "additional-targets",
+                "0.1.0",
+            )
+            .fetch_one(&mut *conn)
+            .await
+            .map(|row| (row.id, row.doc_targets.unwrap()))
+        })?;
+
+        let targets: Vec<String> = serde_json::from_value(doc_targets)?;
+        // the default targets, and the additional target.
+        assert_eq!(targets.len(), DEFAULT_TARGETS.len() + 1);
+        for target in &targets {
+            assert!(
+                env.storage()
+                    .exists(&format!("build-logs/{build_id}/{target}.txt"))?,
+                "no build log for {target}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_targets_concurrently_copies_docs_of_all_workers() -> Result<()> {
+        let builds = tempfile::tempdir()?;
+        let local_storage = tempfile::tempdir()?;
+        let targets = ["a-target", "b-target", "c-target", "d-target", "e-target"];
+
+        let build_dirs = Mutex::new(HashSet::new());
+        let mut successful_targets = Vec::new();
+        let build_logs = build_targets_concurrently(
+            "foo",
+            &V1,
+            &targets,
+            2,
+            &mut successful_targets,
+            |build_dir, next_target| {
+                build_dirs.lock().unwrap().insert(build_dir.to_string());
+                let target_dir = builds.path().join(build_dir).join("target");
+
+                let mut results = Vec::new();
+                while let Some(target) = next_target() {
+                    let successful = target != "c-target";
+                    if successful {
+                        let doc_dir = target_dir.join(target).join("doc").join("foo");
+                        fs::create_dir_all(&doc_dir)?;
+                        fs::write(doc_dir.join("index.html"), target)?;
+                        copy_docs(&target_dir, local_storage.path(), target, false)?;
+                    }
+                    results.push((target, format!("log of {target}"), successful));
+                    // let the other worker pick up targets too.
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Ok(results)
+            },
+        )?;
+
+        assert_eq!(
+            build_dirs.into_inner().unwrap(),
+            HashSet::from([
+                "foo-1.0.0-target-0".to_string(),
+                "foo-1.0.0-target-1".to_string()
+            ])
+        );
+        // in the order of the targets, no matter which worker finished first.
+        assert_eq!(
+            successful_targets,
+            ["a-target", "b-target", "d-target", "e-target"]
+        );
+        assert_eq!(
+            build_logs,
+            targets
+                .iter()
+                .map(|&target| (target, format!("log of {target}")))
+                .collect()
+        );
+
+        for target in successful_targets {
+            let index = local_storage.path().join(&target).join("foo/index.html");
+            assert_eq!(fs::read_to_string(index)?, target);
+        }
+        assert!(!local_storage.path().join("c-target").exists());
+
+        Ok(())
+    }
 }
@@ -7,6 +7,7 @@ pub use self::config::Config;
 pub use self::context::Context;
 pub use self::docbuilder::PackageKind;
 pub use self::docbuilder::{BuildPackageSummary, RustwideBuilder};
+pub use self::docbuilder::{CompareCrates, ComparisonReport};
 pub use self::index::Index;
 pub use self::metrics::{InstanceMetrics, ServiceMetrics};
 pub use self::registry_api::RegistryApi;
@@ -58,6 +58,21 @@ pub const DOCUMENTATION_SIZE_BUCKETS: &[f64; 16] = &[
     16384.0, 32768.0,
 ];
 
+/// Peak memory and disk usage of builds, in bytes, from 64 MiB to 64 GiB.
+pub const BUILD_RESOURCE_SIZE_BUCKETS: &[f64] = &[
+    67108864.0,    // 64 MiB
+    134217728.0,   // 128 MiB
+    268435456.0,   // 256 MiB
+    536870912.0,   // 512 MiB
+    1073741824.0,  // 1 GiB
+    2147483648.0,  // 2 GiB
+    4294967296.0,  // 4 GiB
+    8589934592.0,  // 8 GiB
+    17179869184.0, // 16 GiB
+    34359738368.0, // 32 GiB
+    68719476736.0, // 64 GiB
+];
+
 /// the measured times of building crates will be put into these buckets
 pub const BUILD_TIME_HISTOGRAM_BUCKETS: &[f64] = &[
     30.0,   // 0.5
@@ -1,7 +1,10 @@
 use crate::{APP_USER_AGENT, db::types::version::Version, error::Result, utils::retry_async};
 use anyhow::{Context, anyhow, bail};
 use chrono::{DateTime, Utc};
-use reqwest::header::{ACCEPT, HeaderValue, USER_AGENT};
+use reqwest::{
+    StatusCode,
+    header::{ACCEPT, AUTHORIZATION, HeaderValue, USER_AGENT},
+};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use tracing::instrument;
@@ -243,6 +246,44 @@ impl RegistryApi {
         Ok(result)
     }
 
+    /// The login of the user a registry API token belongs to, `None` when the token
+    /// isn't valid.
+    #[instrument(skip_all)]
+    pub(crate) async fn token_login(&self, token: &str) -> Result<Option<String>> {
+        let url = {
+            let mut url = self.api_base.clone();
+            url.path_segments_mut()
+                .map_err(|()| anyhow!("Invalid API url"))?
+                .extend(&["api", "v1", "me"]);
+            url
+        };
+
+        #[derive(Deserialize)]
+        struct Response {
+            user: User,
+        }
+
+        #[derive(Deserialize)]
+        struct User {
+            login: String,
+        }
+
+        let response = self
+            .client
+            .get(url)
+            .header(AUTHORIZATION, token)
+            .send()
+            .await?;
+        if matches!(
+            response.status(),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+        ) {
+            return Ok(None);
+        }
+        let response: Response = response.error_for_status()?.json().await?;
+        Ok(Some(response.user.login))
+    }
+
     /// Fetch crates from the registry's API
     pub(crate) async fn search(&self, query_params: &str) -> Result<Search> {
         #[derive(Deserialize, Debug)]
@@ -12,6 +12,7 @@ use tracing::instrument;
 pub(crate) struct FileInfo {
     range: FileRange,
     compression: CompressionAlgorithm,
+    blob: Option<String>,
 }
 
 impl FileInfo {
@@ -21,6 +22,18 @@ impl FileInfo {
     pub(crate) fn compression(&self) -> CompressionAlgorithm {
         self.compression
     }
+    /// the hash of the content blob with the file, when it's not stored in the archive.
+    pub(crate) fn blob(&self) -> Option<&str> {
+        self.blob.as_deref()
+    }
+}
+
+/// A file of an archive that is stored as a shared content blob instead of inside the archive.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) struct ContentBlobFile {
+    pub(crate) path: String,
+    pub(crate) hash: String,
+    pub(crate) compression: CompressionAlgorithm,
 }
 
 /// crates a new empty SQLite database, and returns a configured connection
@@ -59,12 +72,18 @@ async fn sqlite_open<P: AsRef<Path>>(path: P) -> Result<sqlx::SqlitePool> {
     .map_err(Into::into)
 }
 
-/// create an archive index based on a zipfile.
+/// create an archive index based on a zipfile, and the files of the archive that are stored
+/// as content blobs.
+///
+/// Files the zipfile stores without compression were compressed by us with
+/// `stored_compression`, for algorithms the ZIP format doesn't support.
 ///
 /// Will delete the destination file if it already exists.
-#[instrument(skip(zipfile))]
+#[instrument(skip(zipfile, content_blobs))]
 pub(crate) async fn create<R: io::Read + io::Seek, P: AsRef<Path> + std::fmt::Debug>(
     zipfile: &mut R,
+    stored_compression: Option<CompressionAlgorithm>,
+    content_blobs: &[ContentBlobFile],
     destination: P,
 ) -> Result<()> {
     let pool = sqlite_create(destination).await?;
@@ -78,7 +97,8 @@ pub(crate) async fn create<R: io::Read + io::Seek, P: AsRef<Path> + std::fmt::De
                 path TEXT UNIQUE,
                 start INTEGER,
                 end INTEGER,
-                compression INTEGER
+                compression INTEGER,
+                blob TEXT
             );
         "#,
     )
@@ -98,9 +118,10 @@ pub(crate) async fn create<R: io::Read + io::Seek, P: AsRef<Path> + std::fmt::De
 
             let start = entry.data_start() as i64;
             let end = (entry.data_start() + entry.compressed_size() - 1) as i64;
-            let compression_raw = match entry.compression() {
-                zip::CompressionMethod::Bzip2 => compression_bzip,
-                c => bail!("unsupported compression algorithm {} in zip-file", c),
+            let compression_raw = match (entry.compression(), stored_compression) {
+                (zip::CompressionMethod::Bzip2, _) => compression_bzip,
+                (zip::CompressionMethod::Stored, Some(alg)) => alg as i32,
+                (c, _) => bail!("unsupported compression algorithm {} in zip-file", c),
             };
 
             insert_stmt.push_values([()], |mut b, _| {
@@ -117,6 +138,23 @@ pub(crate) async fn create<R: io::Read + io::Seek, P: AsRef<Path> + std::fmt::De
         }
     }
 
+    for chunk in &content_blobs.iter().chunks(CHUNKS) {
+        let mut insert_stmt =
+            QueryBuilder::<Sqlite>::new("INSERT INTO files (path, start, end, compression, blob) ");
+        insert_stmt.push_values(chunk, |mut b, file| {
+            b.push_bind(&file.path)
+                .push_bind(0)
+                .push_bind(0)
+                .push_bind(file.compression as i32)
+                .push_bind(&file.hash);
+        });
+        insert_stmt
+            .build()
+            .persistent(false)
+            .execute(&mut *tx)
+            .await?;
+    }
+
     sqlx::query("CREATE INDEX idx_files_path ON files (path);")
         .execute(&mut *tx)
         .await?;
@@ -134,9 +172,10 @@ async fn find_in_sqlite_index<'e, E>(executor: E, search_for: &str) -> Result<Op
 where
     E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
 {
+    // indexes created before content blobs don't have the `blob` column.
     let row = sqlx::query(
         "
-        SELECT start, end, compression
+        SELECT *
         FROM files
         WHERE path = ?
         ",
@@ -147,9 +186,14 @@ where
     .context("error fetching SQLite data")?;
 
     if let Some(row) = row {
-        let start: u64 = row.try_get(0)?;
-        let end: u64 = row.try_get(1)?;
-        let compression_raw: i32 = row.try_get(2)?;
+        let start: u64 = row.try_get("start")?;
+        let end: u64 = row.try_get("end")?;
+        let compression_raw: i32 = row.try_get("compression")?;
+        let blob = match row.try_get("blob") {
+            Ok(blob) => blob,
+            Err(sqlx::Error::ColumnNotFound(_)) => None,
+            Err(err) => return Err(err.into()),
+        };
 
         Ok(Some(FileInfo {
             range: start..=end,
@@ -158,6 +202,7 @@ where
                     "invalid compression algorithm '{value}' in database"
                 ))
             })?,
+            blob,
         }))
     } else {
         Ok(None)
@@ -205,23 +250,79 @@ mod tests {
         let mut tf = create_test_archive(1);
 
         let tempfile = tempfile::NamedTempFile::new().unwrap().into_temp_path();
-        create(&mut tf, &tempfile).await?;
+        create(&mut tf, None, &[], &tempfile).await?;
 
         let fi = find_in_file(&tempfile, "testfile0").await?.unwrap();
 
         assert_eq!(fi.range, FileRange::new(39, 459));
         assert_eq!(fi.compression, CompressionAlgorithm::Bzip2);
+        assert_eq!(fi.blob(), None);
 
         assert!(find_in_file(&tempfile, "some_other_file",).await?.is_none());
         Ok(())
     }
 
+    #[tokio::test]
+    async fn index_with_content_blobs() -> Result<()> {
+        let mut tf = create_test_archive(1);
+
+        let tempfile = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        create(
+            &mut tf,
+            None,
+            &[ContentBlobFile {
+                path: "shared.js".into(),
+                hash: "abcdef".into(),
+                compression: CompressionAlgorithm::Zstd,
+            }],
+            &tempfile,
+        )
+        .await?;
+
+        let fi = find_in_file(&tempfile, "shared.js").await?.unwrap();
+        assert_eq!(fi.blob(), Some("abcdef"));
+        assert_eq!(fi.compression, CompressionAlgorithm::Zstd);
+        assert_eq!(
+            find_in_file(&tempfile, "testfile0").await?.unwrap().blob(),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn index_with_stored_compression() -> Result<()> {
+        let mut archive = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        archive.start_file(
+            "index.html",
+            SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored),
+        )?;
+        archive.write_all(b"compressed by us")?;
+        let mut zipfile = archive.finish()?;
+
+        let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
+        assert!(create(&mut zipfile, None, &[], &tempfile).await.is_err());
+
+        create(
+            &mut zipfile,
+            Some(CompressionAlgorithm::ZstdDictionary),
+            &[],
+            &tempfile,
+        )
+        .await?;
+        let fi = find_in_file(&tempfile, "index.html").await?.unwrap();
+        assert_eq!(fi.compression, CompressionAlgorithm::ZstdDictionary);
+        assert_eq!(fi.range.end() - fi.range.start() + 1, 16);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn archive_with_more_than_65k_files() -> Result<()> {
         let mut tf = create_test_archive(100_000);
 
         let tempfile = tempfile::NamedTempFile::new()?.into_temp_path();
-        create(&mut tf, &tempfile).await?;
+        create(&mut tf, None, &[], &tempfile).await?;
 
         let pool = sqlite_open(&tempfile).await?;
         let mut conn = pool.acquire().await?;
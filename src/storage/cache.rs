@@ -0,0 +1,432 @@
+use super::{CompressionAlgorithm, FileRange, StreamingBlob};
+use crate::{db::BuildId, error::Result, metrics::otel::AnyMeterProvider};
+use anyhow::Context as _;
+use chrono::{DateTime, Utc};
+use mime::Mime;
+use opentelemetry::metrics::{Counter, Gauge};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tokio::io::AsyncReadExt as _;
+use tracing::warn;
+
+/// What a cache entry contains.
+///
+/// Archives are replaced when a release is rebuilt, so their ranges are only the same for the
+/// same build.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct CacheKey {
+    pub(super) path: String,
+    pub(super) range: Option<FileRange>,
+    pub(super) build_id: Option<BuildId>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    file: PathBuf,
+    size: u64,
+    /// the position in [`CacheState::lru`].
+    last_used: u64,
+    mime: Mime,
+    date_updated: DateTime<Utc>,
+    compression: Option<CompressionAlgorithm>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// the keys of the entries, least recently used first.
+    lru: BTreeMap<u64, CacheKey>,
+    /// incremented on every use of an entry, also used for unique file names.
+    counter: u64,
+    /// incremented on every invalidation, see [`StorageCache::generation`].
+    generation: u64,
+    size: u64,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: &CacheKey) {
+        self.counter += 1;
+        let counter = self.counter;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.lru.remove(&entry.last_used);
+            entry.last_used = counter;
+            self.lru.insert(counter, key.clone());
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.last_used);
+        self.size -= entry.size;
+        Some(entry)
+    }
+}
+
+#[derive(Debug)]
+struct CacheMetrics {
+    hits: Counter<u64>,
+    misses: Counter<u64>,
+    evictions: Counter<u64>,
+    invalidations: Counter<u64>,
+    size: Gauge<u64>,
+}
+
+impl CacheMetrics {
+    fn new(meter_provider: &AnyMeterProvider) -> Self {
+        let meter = meter_provider.meter("storage");
+        const PREFIX: &str = "docsrs.storage.cache";
+        Self {
+            hits: meter
+                .u64_counter(format!("{PREFIX}.hits"))
+                .with_unit("1")
+                .build(),
+            misses: meter
+                .u64_counter(format!("{PREFIX}.misses"))
+                .with_unit("1")
+                .build(),
+            evictions: meter
+                .u64_counter(format!("{PREFIX}.evictions"))
+                .with_unit("1")
+                .build(),
+            invalidations: meter
+                .u64_counter(format!("{PREFIX}.invalidations"))
+                .with_unit("1")
+                .build(),
+            size: meter
+                .u64_gauge(format!("{PREFIX}.size"))
+                .with_unit("By")
+                .build(),
+        }
+    }
+}
+
+/// A size-bounded LRU cache on the local disk for blobs and ranges of blobs that are read
+/// often, so they don't have to be fetched from the storage backend every time.
+///
+/// Only the content is on disk, the index is in memory. Every process uses its own temporary
+/// directory, which is removed when the cache is dropped.
+pub(super) struct StorageCache {
+    dir: tempfile::TempDir,
+    max_size: u64,
+    max_entry_size: u64,
+    state: Mutex<CacheState>,
+    metrics: CacheMetrics,
+}
+
+impl StorageCache {
+    pub(super) fn new(
+        path: &Path,
+        max_size: u64,
+        max_entry_size: u64,
+        meter_provider: &AnyMeterProvider,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(path)
+            .with_context(|| format!("could not create {}", path.display()))?;
+        Ok(Self {
+            dir: tempfile::Builder::new()
+                .prefix("storage-cache-")
+                .tempdir_in(path)?,
+            max_size,
+            max_entry_size: max_entry_size.min(max_size),
+            state: Mutex::new(CacheState::default()),
+            metrics: CacheMetrics::new(meter_provider),
+        })
+    }
+
+    /// Whether a blob of `content_length` bytes fits into the cache.
+    pub(super) fn accepts(&self, content_length: usize) -> bool {
+        content_length as u64 <= self.max_entry_size
+    }
+
+    /// Pass this to [`StorageCache::insert`] to not cache blobs that were fetched before an
+    /// invalidation, their content might be outdated.
+    pub(super) fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    /// The cached blob for `key`, with its content in memory.
+    pub(super) async fn get(&self, key: &CacheKey) -> Option<StreamingBlob> {
+        let (file, mime, date_updated, compression) = {
+            let mut state = self.state.lock().unwrap();
+            let Some(entry) = state.entries.get(key) else {
+                self.metrics.misses.add(1, &[]);
+                return None;
+            };
+            let cached = (
+                entry.file.clone(),
+                entry.mime.clone(),
+                entry.date_updated,
+                entry.compression,
+            );
+            state.touch(key);
+            cached
+        };
+
+        match tokio::fs::read(&file).await {
+            Ok(content) => {
+                self.metrics.hits.add(1, &[]);
+                Some(StreamingBlob {
+                    path: key.path.clone(),
+                    mime,
+                    date_updated,
+                    compression,
+                    content_length: content.len(),
+                    content: Box::new(io::Cursor::new(content)),
+                })
+            }
+            Err(err) => {
+                // the entry was evicted while we read it.
+                if err.kind() != io::ErrorKind::NotFound {
+                    warn!(?err, ?file, "could not read storage cache entry");
+                }
+                self.metrics.misses.add(1, &[]);
+                None
+            }
+        }
+    }
+
+    /// Read `blob` into memory and add it to the cache, returns a blob with the same content.
+    ///
+    /// Blobs that turn out to be bigger than [`StorageCache::accepts`] are not cached, neither
+    /// are blobs that were fetched before the last invalidation.
+    pub(super) async fn insert(
+        &self,
+        key: CacheKey,
+        generation: u64,
+        mut blob: StreamingBlob,
+    ) -> Result<StreamingBlob> {
+        let mut content = Vec::with_capacity(blob.content_length);
+        (&mut blob.content)
+            .take(self.max_entry_size + 1)
+            .read_to_end(&mut content)
+            .await?;
+        let size = content.len() as u64;
+        if size > self.max_entry_size {
+            blob.content = Box::new(io::Cursor::new(content).chain(blob.content));
+            return Ok(blob);
+        }
+
+        let file = {
+            let mut state = self.state.lock().unwrap();
+            state.counter += 1;
+            self.dir.path().join(state.counter.to_string())
+        };
+        let cached = match tokio::fs::write(&file, &content).await {
+            Ok(()) => true,
+            Err(err) => {
+                warn!(?err, ?file, "could not write storage cache entry");
+                false
+            }
+        };
+
+        if cached {
+            let removed = {
+                let mut state = self.state.lock().unwrap();
+                if state.generation == generation {
+                    let mut removed: Vec<_> = state
+                        .remove(&key)
+                        .map(|entry| entry.file)
+                        .into_iter()
+                        .collect();
+                    while state.size + size > self.max_size {
+                        let Some((_, oldest)) = state.lru.pop_first() else {
+                            break;
+                        };
+                        // `pop_first` already removed it from the LRU order.
+                        if let Some(entry) = state.entries.remove(&oldest) {
+                            state.size -= entry.size;
+                            self.metrics.evictions.add(1, &[]);
+                            removed.push(entry.file);
+                        }
+                    }
+
+                    state.counter += 1;
+                    let last_used = state.counter;
+                    state.lru.insert(last_used, key.clone());
+                    state.entries.insert(
+                        key,
+                        CacheEntry {
+                            file,
+                            size,
+                            last_used,
+                            mime: blob.mime.clone(),
+                            date_updated: blob.date_updated,
+                            compression: blob.compression,
+                        },
+                    );
+                    state.size += size;
+                    self.metrics.size.record(state.size, &[]);
+                    removed
+                } else {
+                    vec![file]
+                }
+            };
+            remove_files(removed).await;
+        }
+
+        Ok(StreamingBlob {
+            path: blob.path,
+            mime: blob.mime,
+            date_updated: blob.date_updated,
+            compression: blob.compression,
+            content_length: content.len(),
+            content: Box::new(io::Cursor::new(content)),
+        })
+    }
+
+    /// Remove all entries of blobs whose path starts with `prefix`.
+    pub(super) async fn invalidate_prefix(&self, prefix: &str) {
+        self.invalidate_matching(|path| path.starts_with(prefix))
+            .await
+    }
+
+    /// Remove all entries of the blob at `path`.
+    pub(super) async fn invalidate(&self, path: &str) {
+        self.invalidate_matching(|cached_path| cached_path == path)
+            .await
+    }
+
+    async fn invalidate_matching(&self, matches: impl Fn(&str) -> bool + Send) {
+        let removed: Vec<_> = {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            let keys: Vec<_> = state
+                .entries
+                .keys()
+                .filter(|key| matches(&key.path))
+                .cloned()
+                .collect();
+            let removed = keys
+                .iter()
+                .filter_map(|key| state.remove(key))
+                .map(|entry| entry.file)
+                .collect();
+            self.metrics.size.record(state.size, &[]);
+            removed
+        };
+        self.metrics.invalidations.add(removed.len() as u64, &[]);
+        remove_files(removed).await;
+    }
+
+    #[cfg(test)]
+    fn size(&self) -> u64 {
+        self.state.lock().unwrap().size
+    }
+}
+
+async fn remove_files(files: Vec<PathBuf>) {
+    for file in files {
+        if let Err(err) = tokio::fs::remove_file(&file).await {
+            warn!(?err, ?file, "could not remove storage cache entry");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::otel::NoopMeterProvider;
+    use std::sync::Arc;
+
+    fn meter_provider() -> AnyMeterProvider {
+        Arc::new(NoopMeterProvider::new())
+    }
+
+    fn key(path: &str) -> CacheKey {
+        CacheKey {
+            path: path.into(),
+            range: None,
+            build_id: None,
+        }
+    }
+
+    fn blob(content: &[u8]) -> StreamingBlob {
+        StreamingBlob {
+            path: "some/path".into(),
+            mime: mime::TEXT_PLAIN,
+            date_updated: Utc::now(),
+            compression: None,
+            content_length: content.len(),
+            content: Box::new(io::Cursor::new(content.to_vec())),
+        }
+    }
+
+    async fn content(blob: Option<StreamingBlob>) -> Option<Vec<u8>> {
+        Some(blob?.materialize(usize::MAX).await.unwrap().content)
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = StorageCache::new(dir.path(), 10, 4, &meter_provider())?;
+
+        assert!(cache.accepts(4));
+        assert!(!cache.accepts(5));
+
+        cache.insert(key("a"), 0, blob(b"aaaa")).await?;
+        cache.insert(key("b"), 0, blob(b"bbbb")).await?;
+        // using `a` makes `b` the least recently used entry.
+        assert_eq!(
+            content(cache.get(&key("a")).await).await,
+            Some(b"aaaa".to_vec())
+        );
+
+        let returned = cache.insert(key("c"), 0, blob(b"cccc")).await?;
+        assert_eq!(content(Some(returned)).await, Some(b"cccc".to_vec()));
+        assert_eq!(cache.size(), 8);
+        assert!(cache.get(&key("b")).await.is_none());
+        assert!(cache.get(&key("a")).await.is_some());
+        assert!(cache.get(&key("c")).await.is_some());
+
+        // the same path with a different range or build is a different entry.
+        let range_key = CacheKey {
+            range: Some(0..=1),
+            ..key("a")
+        };
+        assert!(cache.get(&range_key).await.is_none());
+
+        // bigger blobs than their length said are returned, but not cached.
+        let mut too_big = blob(b"ddddd");
+        too_big.content_length = 1;
+        let returned = cache.insert(key("d"), 0, too_big).await?;
+        assert_eq!(content(Some(returned)).await, Some(b"ddddd".to_vec()));
+        assert!(cache.get(&key("d")).await.is_none());
+        assert_eq!(cache.size(), 8);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalidates_prefix() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache = StorageCache::new(dir.path(), 100, 10, &meter_provider())?;
+
+        let generation = cache.generation();
+        cache
+            .insert(key("rustdoc/foo/1.0.0.zip"), generation, blob(b"foo"))
+            .await?;
+        cache
+            .insert(key("rustdoc/bar/1.0.0.zip"), generation, blob(b"bar"))
+            .await?;
+
+        cache.invalidate_prefix("rustdoc/foo/").await;
+        assert!(cache.get(&key("rustdoc/foo/1.0.0.zip")).await.is_none());
+        assert!(cache.get(&key("rustdoc/bar/1.0.0.zip")).await.is_some());
+
+        cache.invalidate("rustdoc/bar/1.0.0.zip").await;
+        assert!(cache.get(&key("rustdoc/bar/1.0.0.zip")).await.is_none());
+        assert_eq!(cache.size(), 0);
+
+        // blobs fetched before the invalidation might be outdated.
+        cache
+            .insert(key("rustdoc/foo/1.0.0.zip"), generation, blob(b"foo"))
+            .await?;
+        assert!(cache.get(&key("rustdoc/foo/1.0.0.zip")).await.is_none());
+
+        Ok(())
+    }
+}
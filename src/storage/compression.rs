@@ -1,10 +1,11 @@
-use anyhow::Error;
+use anyhow::{Error, bail};
 use bzip2::read::{BzDecoder, BzEncoder};
 use flate2::read::{GzDecoder, GzEncoder};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
-    io::{self, Read},
+    fmt,
+    io::{self, BufReader, Read},
 };
 use strum::{Display, EnumIter, EnumString, FromRepr};
 use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite};
@@ -31,8 +32,16 @@ pub enum CompressionAlgorithm {
     Zstd = 0,
     Bzip2 = 1,
     Gzip = 2,
+    Brotli = 3,
+    /// zstd with a [`CompressionDictionary`]. The id of the dictionary is in the frame header.
+    ZstdDictionary = 4,
 }
 
+const ZSTD_LEVEL: i32 = 9;
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
 impl CompressionAlgorithm {
     pub fn file_extension(&self) -> &'static str {
         file_extension_for(*self)
@@ -58,6 +67,10 @@ pub(crate) fn file_extension_for(algorithm: CompressionAlgorithm) -> &'static st
         CompressionAlgorithm::Zstd => "zst",
         CompressionAlgorithm::Bzip2 => "bz2",
         CompressionAlgorithm::Gzip => "gz",
+        CompressionAlgorithm::Brotli => "br",
+        // nothing can decompress these without our dictionary, so there is no mapping back in
+        // `compression_from_file_extension`.
+        CompressionAlgorithm::ZstdDictionary => "dict.zst",
     }
 }
 
@@ -66,14 +79,67 @@ pub(crate) fn compression_from_file_extension(ext: &str) -> Option<CompressionAl
         "zst" => Some(CompressionAlgorithm::Zstd),
         "bz2" => Some(CompressionAlgorithm::Bzip2),
         "gz" => Some(CompressionAlgorithm::Gzip),
+        "br" => Some(CompressionAlgorithm::Brotli),
         _ => None,
     }
 }
 
+/// A zstd dictionary for [`CompressionAlgorithm::ZstdDictionary`], trained on rustdoc HTML.
+///
+/// Rustdoc pages share most of their markup, so with the dictionary even small pages compress
+/// well.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CompressionDictionary {
+    id: u32,
+    content: Vec<u8>,
+}
+
+impl fmt::Debug for CompressionDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionDictionary")
+            .field("id", &self.id)
+            .field("size", &self.content.len())
+            .finish()
+    }
+}
+
+impl CompressionDictionary {
+    pub fn new(content: Vec<u8>) -> Result<Self, Error> {
+        let Some(id) = zstd::zstd_safe::get_dict_id_from_dict(&content) else {
+            bail!("not a zstd dictionary, or one without id");
+        };
+        Ok(Self {
+            id: id.get(),
+            content,
+        })
+    }
+
+    /// Train a dictionary of at most `max_size` bytes on `samples`.
+    pub fn train(samples: &[impl AsRef<[u8]>], max_size: usize) -> Result<Self, Error> {
+        Self::new(zstd::dict::from_samples(samples, max_size)?)
+    }
+
+    /// the id zstd writes into the header of each frame compressed with this dictionary.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+}
+
+/// The id of the dictionary the zstd frame starting with `frame` was compressed with.
+///
+/// `frame` has to contain the whole frame header, which is at most 18 bytes.
+pub(crate) fn dictionary_id_from_frame(frame: &[u8]) -> Option<u32> {
+    zstd::zstd_safe::get_dict_id_from_frame(frame).map(|id| id.get())
+}
+
 // public for benchmarking
 pub fn compress(content: impl Read, algorithm: CompressionAlgorithm) -> Result<Vec<u8>, Error> {
     match algorithm {
-        CompressionAlgorithm::Zstd => Ok(zstd::encode_all(content, 9)?),
+        CompressionAlgorithm::Zstd => Ok(zstd::encode_all(content, ZSTD_LEVEL)?),
         CompressionAlgorithm::Bzip2 => {
             let mut compressor = BzEncoder::new(content, bzip2::Compression::best());
 
@@ -87,9 +153,38 @@ pub fn compress(content: impl Read, algorithm: CompressionAlgorithm) -> Result<V
             compressor.read_to_end(&mut data)?;
             Ok(data)
         }
+        CompressionAlgorithm::Brotli => {
+            let mut compressor = brotli::CompressorReader::new(
+                content,
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_SIZE,
+            );
+            let mut data = vec![];
+            compressor.read_to_end(&mut data)?;
+            Ok(data)
+        }
+        CompressionAlgorithm::ZstdDictionary => {
+            bail!("{algorithm} needs a dictionary, use `compress_with_dictionary`")
+        }
     }
 }
 
+// public for benchmarking
+pub fn compress_with_dictionary(
+    content: impl Read,
+    dictionary: &CompressionDictionary,
+) -> Result<Vec<u8>, Error> {
+    let mut compressor = zstd::stream::read::Encoder::with_dictionary(
+        BufReader::new(content),
+        ZSTD_LEVEL,
+        dictionary.content(),
+    )?;
+    let mut data = vec![];
+    compressor.read_to_end(&mut data)?;
+    Ok(data)
+}
+
 /// async compression, reads from an AsyncRead, writes to an AsyncWrite.
 pub async fn compress_async<'a, R, W>(
     mut reader: R,
@@ -119,6 +214,20 @@ where
             io::copy(&mut reader, &mut enc).await?;
             enc.shutdown().await?;
         }
+        CompressionAlgorithm::Brotli => {
+            let mut enc = write::BrotliEncoder::with_quality(
+                writer,
+                async_compression::Level::Precise(BROTLI_QUALITY as i32),
+            );
+            io::copy(&mut reader, &mut enc).await?;
+            enc.shutdown().await?;
+        }
+        CompressionAlgorithm::ZstdDictionary => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "async compression with a dictionary is not supported",
+            ));
+        }
     }
 
     Ok(())
@@ -128,22 +237,37 @@ where
 ///
 /// You provide an AsyncRead that gives us the compressed data. With the
 /// wrapper we return you can then read decompressed data from the wrapper.
+///
+/// [`CompressionAlgorithm::ZstdDictionary`] needs the dictionary the data was compressed with,
+/// see [`dictionary_id_from_frame`].
 pub fn wrap_reader_for_decompression<'a>(
     input: impl AsyncBufRead + Unpin + Send + 'a,
     algorithm: CompressionAlgorithm,
-) -> Box<dyn AsyncBufRead + Unpin + Send + 'a> {
+    dictionary: Option<&CompressionDictionary>,
+) -> io::Result<Box<dyn AsyncBufRead + Unpin + Send + 'a>> {
     use async_compression::tokio::bufread;
-    use tokio::io;
+    use tokio::io::BufReader;
 
-    match algorithm {
-        CompressionAlgorithm::Zstd => {
-            Box::new(io::BufReader::new(bufread::ZstdDecoder::new(input)))
+    Ok(match algorithm {
+        CompressionAlgorithm::Zstd => Box::new(BufReader::new(bufread::ZstdDecoder::new(input))),
+        CompressionAlgorithm::Bzip2 => Box::new(BufReader::new(bufread::BzDecoder::new(input))),
+        CompressionAlgorithm::Gzip => Box::new(BufReader::new(bufread::GzipDecoder::new(input))),
+        CompressionAlgorithm::Brotli => {
+            Box::new(BufReader::new(bufread::BrotliDecoder::new(input)))
         }
-        CompressionAlgorithm::Bzip2 => Box::new(io::BufReader::new(bufread::BzDecoder::new(input))),
-        CompressionAlgorithm::Gzip => {
-            Box::new(io::BufReader::new(bufread::GzipDecoder::new(input)))
+        CompressionAlgorithm::ZstdDictionary => {
+            let dictionary = dictionary.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "decompressing needs the dictionary",
+                )
+            })?;
+            Box::new(BufReader::new(bufread::ZstdDecoder::with_dict(
+                input,
+                dictionary.content(),
+            )?))
         }
-    }
+    })
 }
 
 pub fn decompress(
@@ -162,31 +286,119 @@ pub fn decompress(
         CompressionAlgorithm::Gzip => {
             io::copy(&mut GzDecoder::new(content), &mut buffer)?;
         }
+        CompressionAlgorithm::Brotli => {
+            io::copy(
+                &mut brotli::Decompressor::new(content, BROTLI_BUFFER_SIZE),
+                &mut buffer,
+            )?;
+        }
+        CompressionAlgorithm::ZstdDictionary => {
+            bail!("{algorithm} needs a dictionary, use `decompress_with_dictionary`")
+        }
     }
 
     Ok(buffer.into_inner())
 }
 
+// public for benchmarking
+pub fn decompress_with_dictionary(
+    content: impl Read,
+    dictionary: &CompressionDictionary,
+    max_size: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut buffer = crate::utils::sized_buffer::SizedBuffer::new(max_size);
+    let mut decoder = zstd::stream::read::Decoder::with_dictionary(
+        BufReader::new(content),
+        dictionary.content(),
+    )?;
+    io::copy(&mut decoder, &mut buffer)?;
+    Ok(buffer.into_inner())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use strum::IntoEnumIterator;
     use test_case::test_case;
 
+    pub(crate) fn test_dictionary() -> CompressionDictionary {
+        let samples: Vec<String> = (0..500)
+            .map(|i| {
+                format!(
+                    r#"<!DOCTYPE html><html lang="en"><head><title>Struct{i} in krate - Rust</title></head><body class="rustdoc struct"><main><div class="docblock"><p>The {i}th struct, {}.</p></div></main></body></html>"#,
+                    i * 7919 % 1000
+                )
+            })
+            .collect();
+        CompressionDictionary::train(&samples, 4096).unwrap()
+    }
+
+    fn compress_any(
+        content: &[u8],
+        alg: CompressionAlgorithm,
+        dictionary: &CompressionDictionary,
+    ) -> Result<Vec<u8>, Error> {
+        match alg {
+            CompressionAlgorithm::ZstdDictionary => compress_with_dictionary(content, dictionary),
+            _ => compress(content, alg),
+        }
+    }
+
+    fn decompress_any(
+        content: &[u8],
+        alg: CompressionAlgorithm,
+        dictionary: &CompressionDictionary,
+        max_size: usize,
+    ) -> Result<Vec<u8>, Error> {
+        match alg {
+            CompressionAlgorithm::ZstdDictionary => {
+                decompress_with_dictionary(content, dictionary, max_size)
+            }
+            _ => decompress(content, alg, max_size),
+        }
+    }
+
     #[test]
     fn test_compression() {
+        let dictionary = test_dictionary();
         let orig = "fn main() {}";
         for alg in CompressionAlgorithm::iter() {
             println!("testing algorithm {alg}");
 
-            let data = compress(orig.as_bytes(), alg).unwrap();
+            let data = compress_any(orig.as_bytes(), alg, &dictionary).unwrap();
             assert_eq!(
-                decompress(data.as_slice(), alg, usize::MAX).unwrap(),
+                decompress_any(data.as_slice(), alg, &dictionary, usize::MAX).unwrap(),
                 orig.as_bytes()
             );
         }
     }
 
+    #[test]
+    fn test_dictionary_needed() {
+        let dictionary = test_dictionary();
+        let alg = CompressionAlgorithm::ZstdDictionary;
+        assert!(compress(&b"fn main() {}"[..], alg).is_err());
+
+        let data = compress_with_dictionary(&b"fn main() {}"[..], &dictionary).unwrap();
+        assert_eq!(dictionary_id_from_frame(&data), Some(dictionary.id()));
+        assert!(decompress(data.as_slice(), alg, usize::MAX).is_err());
+        assert!(wrap_reader_for_decompression(data.as_slice(), alg, None).is_err());
+
+        // plain zstd frames don't reference a dictionary.
+        let data = compress(&b"fn main() {}"[..], CompressionAlgorithm::Zstd).unwrap();
+        assert_eq!(dictionary_id_from_frame(&data), None);
+    }
+
+    #[test]
+    fn test_dictionary_from_bytes() {
+        let dictionary = test_dictionary();
+        assert_eq!(
+            CompressionDictionary::new(dictionary.content().to_vec()).unwrap(),
+            dictionary
+        );
+        assert!(CompressionDictionary::new(b"not a dictionary".to_vec()).is_err());
+    }
+
     #[test]
     fn test_decompression_too_big() {
         const MAX_SIZE: usize = 1024;
@@ -195,27 +407,29 @@ mod tests {
         let exact = &[b'A'; MAX_SIZE] as &[u8];
         let big = &[b'A'; MAX_SIZE * 2] as &[u8];
 
+        let dictionary = test_dictionary();
         for alg in CompressionAlgorithm::iter() {
-            let compressed_small = compress(small, alg).unwrap();
-            let compressed_exact = compress(exact, alg).unwrap();
-            let compressed_big = compress(big, alg).unwrap();
+            let compressed_small = compress_any(small, alg, &dictionary).unwrap();
+            let compressed_exact = compress_any(exact, alg, &dictionary).unwrap();
+            let compressed_big = compress_any(big, alg, &dictionary).unwrap();
 
             // Ensure decompressing within the limit works.
             assert_eq!(
                 small.len(),
-                decompress(compressed_small.as_slice(), alg, MAX_SIZE)
+                decompress_any(compressed_small.as_slice(), alg, &dictionary, MAX_SIZE)
                     .unwrap()
                     .len()
             );
             assert_eq!(
                 exact.len(),
-                decompress(compressed_exact.as_slice(), alg, MAX_SIZE)
+                decompress_any(compressed_exact.as_slice(), alg, &dictionary, MAX_SIZE)
                     .unwrap()
                     .len()
             );
 
             // Ensure decompressing a file over the limit returns a SizeLimitReached error.
-            let err = decompress(compressed_big.as_slice(), alg, MAX_SIZE).unwrap_err();
+            let err =
+                decompress_any(compressed_big.as_slice(), alg, &dictionary, MAX_SIZE).unwrap_err();
             assert!(
                 err.downcast_ref::<std::io::Error>()
                     .and_then(|io| io.get_ref())
@@ -228,6 +442,8 @@ mod tests {
     #[test_case(CompressionAlgorithm::Zstd, "Zstd")]
     #[test_case(CompressionAlgorithm::Bzip2, "Bzip2")]
     #[test_case(CompressionAlgorithm::Gzip, "Gzip")]
+    #[test_case(CompressionAlgorithm::Brotli, "Brotli")]
+    #[test_case(CompressionAlgorithm::ZstdDictionary, "ZstdDictionary")]
     fn test_enum_display(alg: CompressionAlgorithm, expected: &str) {
         assert_eq!(alg.to_string(), expected);
     }
@@ -235,6 +451,7 @@ mod tests {
     #[test_case(CompressionAlgorithm::Zstd, "zst")]
     #[test_case(CompressionAlgorithm::Bzip2, "bz2")]
     #[test_case(CompressionAlgorithm::Gzip, "gz")]
+    #[test_case(CompressionAlgorithm::Brotli, "br")]
     fn test_file_extensions(alg: CompressionAlgorithm, expected: &str) {
         assert_eq!(file_extension_for(alg), expected);
         assert_eq!(compression_from_file_extension(expected), Some(alg));
@@ -0,0 +1,207 @@
+//! Trained zstd dictionaries for [`CompressionAlgorithm::ZstdDictionary`].
+//!
+//! The dictionaries are stored in `compression_dictionaries`. Each zstd frame references its
+//! dictionary by id in the frame header, so objects compressed with an older dictionary can
+//! still be decompressed after a new one is trained.
+
+use super::{
+    AsyncStorage, CompressionAlgorithm, CompressionAlgorithms, StreamingBlob,
+    compression::{CompressionDictionary, dictionary_id_from_frame},
+    rustdoc_archive_path,
+};
+use crate::{db::types::version::Version, error::Result, utils::spawn_blocking};
+use anyhow::{Context as _, anyhow};
+use std::{
+    io::{self, Read as _},
+    sync::Arc,
+};
+use tokio::io::AsyncBufReadExt as _;
+use tracing::{info, instrument, warn};
+
+/// HTML files per release used for training, so big crates don't dominate the dictionary.
+const SAMPLES_PER_RELEASE: usize = 50;
+/// Bigger files are skipped, the dictionary matters most for small files.
+const MAX_SAMPLE_SIZE: u64 = 256 * 1024;
+
+/// A dictionary trained by [`AsyncStorage::train_compression_dictionary`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct TrainedDictionary {
+    pub id: u32,
+    /// the size of the dictionary, in bytes.
+    pub size: usize,
+    /// how many files it was trained on.
+    pub samples: usize,
+}
+
+impl AsyncStorage {
+    /// The dictionary new uploads are compressed with, when `compression_dictionary` is
+    /// configured.
+    ///
+    /// This is the newest dictionary when it's first needed, so everything one process uploads
+    /// uses the same dictionary. Until the first dictionary is trained, every upload checks
+    /// for it again. Dictionaries trained later are used after a restart.
+    pub(crate) async fn upload_dictionary(&self) -> Result<Option<Arc<CompressionDictionary>>> {
+        if !self.config.compression_dictionary {
+            return Ok(None);
+        }
+        if let Some(dictionary) = self.upload_dictionary.get() {
+            return Ok(Some(dictionary.clone()));
+        }
+
+        let mut conn = self.pool.get_async().await?;
+        let Some(id) = sqlx::query_scalar!(
+            "SELECT id FROM compression_dictionaries ORDER BY created_at DESC LIMIT 1"
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        else {
+            return Ok(None);
+        };
+        drop(conn);
+
+        let dictionary = self.compression_dictionary(id as u32).await?;
+        Ok(Some(
+            self.upload_dictionary
+                .get_or_init(|| async { dictionary })
+                .await
+                .clone(),
+        ))
+    }
+
+    /// The id of the dictionary of uploads compressed with one of `algorithms`, which is stored
+    /// next to them in `compression_rels`.
+    pub(crate) async fn upload_dictionary_id(
+        &self,
+        algorithms: &CompressionAlgorithms,
+    ) -> Result<Option<u32>> {
+        if !algorithms.contains(&CompressionAlgorithm::ZstdDictionary) {
+            return Ok(None);
+        }
+        Ok(self
+            .upload_dictionary()
+            .await?
+            .map(|dictionary| dictionary.id()))
+    }
+
+    /// The dictionary with this id. Dictionaries never change, so they are cached forever.
+    async fn compression_dictionary(&self, id: u32) -> Result<Arc<CompressionDictionary>> {
+        if let Some(dictionary) = self.dictionaries.get(&id) {
+            return Ok(dictionary.clone());
+        }
+
+        let mut conn = self.pool.get_async().await?;
+        let content = sqlx::query_scalar!(
+            "SELECT content FROM compression_dictionaries WHERE id = $1",
+            id as i64
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| anyhow!("compression dictionary {id} not found"))?;
+
+        let dictionary = Arc::new(CompressionDictionary::new(content)?);
+        self.dictionaries.insert(id, dictionary.clone());
+        Ok(dictionary)
+    }
+
+    /// Like [`StreamingBlob::decompress`], but also fetches the dictionary for
+    /// [`CompressionAlgorithm::ZstdDictionary`] from the frame header.
+    pub(super) async fn decompress(&self, mut stream: StreamingBlob) -> Result<StreamingBlob> {
+        if stream.compression != Some(CompressionAlgorithm::ZstdDictionary) {
+            return Ok(stream.decompress(None).await?);
+        }
+
+        let header = stream.content.fill_buf().await?;
+        let id = dictionary_id_from_frame(header)
+            .ok_or_else(|| anyhow!("no dictionary id in the zstd frame of {}", stream.path))?;
+        let dictionary = self.compression_dictionary(id).await?;
+        Ok(stream.decompress(Some(&dictionary)).await?)
+    }
+
+    /// Train a new dictionary, of at most `max_size` bytes, on the HTML files of up to
+    /// `releases` random releases with documentation, and store it.
+    ///
+    /// Uploads only use it with `compression_dictionary` configured, after a restart.
+    #[instrument(skip(self))]
+    pub async fn train_compression_dictionary(
+        &self,
+        releases: i64,
+        max_size: usize,
+    ) -> Result<TrainedDictionary> {
+        let mut conn = self.pool.get_async().await?;
+        let sampled = sqlx::query!(
+            r#"SELECT
+                 crates.name,
+                 releases.version AS "version: Version"
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE releases.rustdoc_status = TRUE AND releases.archive_storage = TRUE
+             ORDER BY random()
+             LIMIT $1"#,
+            releases,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        drop(conn);
+
+        let mut samples: Vec<Vec<u8>> = Vec::new();
+        for release in sampled {
+            let archive_path = rustdoc_archive_path(&release.name, &release.version);
+            let archive = match self.get_full_archive(&archive_path).await {
+                Ok(archive) => archive,
+                Err(err) => {
+                    warn!(?err, archive_path, "could not fetch archive, skipping");
+                    continue;
+                }
+            };
+
+            samples.extend(
+                spawn_blocking(move || {
+                    let mut zip = zip::ZipArchive::new(io::Cursor::new(archive.content))?;
+                    let mut files = Vec::new();
+                    for i in 0..zip.len() {
+                        if files.len() >= SAMPLES_PER_RELEASE {
+                            break;
+                        }
+                        let mut file = zip.by_index(i)?;
+                        if !file.name().ends_with(".html") || file.size() > MAX_SAMPLE_SIZE {
+                            continue;
+                        }
+                        let mut content = Vec::with_capacity(file.size() as usize);
+                        file.read_to_end(&mut content)?;
+                        files.push(content);
+                    }
+                    Ok(files)
+                })
+                .await?,
+            );
+        }
+
+        let sample_count = samples.len();
+        let dictionary = spawn_blocking(move || CompressionDictionary::train(&samples, max_size))
+            .await
+            .with_context(|| format!("could not train a dictionary on {sample_count} files"))?;
+
+        let mut conn = self.pool.get_async().await?;
+        sqlx::query!(
+            "INSERT INTO compression_dictionaries (id, content, samples)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (id) DO NOTHING",
+            dictionary.id() as i64,
+            dictionary.content(),
+            sample_count as i32,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        info!(
+            id = dictionary.id(),
+            samples = sample_count,
+            "trained compression dictionary"
+        );
+        Ok(TrainedDictionary {
+            id: dictionary.id(),
+            size: dictionary.content().len(),
+            samples: sample_count,
+        })
+    }
+}
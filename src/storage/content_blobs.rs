@@ -0,0 +1,379 @@
+//! Files stored once per content and shared between the archives that contain them.
+//!
+//! When `content_addressed_min_size` is configured, the files of an archive that are at least
+//! that big are not added to the ZIP file. They are stored as blobs keyed by the SHA-256 of
+//! their content, and the archive index points to the blob instead of a range in the archive.
+//!
+//! `content_blob_references` counts the files of each archive using a blob. When an archive is
+//! replaced or deleted its references are removed, and blobs without references are deleted.
+
+use super::{AsyncStorage, Blob, BlobUpload, CompressionAlgorithm, compress};
+use crate::{db::file::detect_mime, error::Result, utils::spawn_blocking};
+use sha2::{Digest as _, Sha256};
+use sqlx::Connection as _;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{info, instrument};
+
+const CONTENT_BLOBS_PREFIX: &str = "content-blobs/";
+
+/// the storage path of the content blob with this hash.
+pub(super) fn content_blob_path(hash: &str) -> String {
+    format!("{CONTENT_BLOBS_PREFIX}{}/{hash}", &hash[..2])
+}
+
+/// the storage path of the archive with all files, when some of them are stored as
+/// content blobs or compressed with an algorithm the ZIP format doesn't support.
+///
+/// It starts with the path of the archive, so it's deleted together with the archive.
+pub(super) fn full_archive_path(archive_path: &str) -> String {
+    format!("{archive_path}.full.zip")
+}
+
+/// the hex-encoded SHA-256 of a file.
+pub(super) fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// A file of an archive that is stored as content blob.
+#[derive(Debug, Clone)]
+pub(super) struct ContentBlobSource {
+    pub(super) path_in_archive: String,
+    pub(super) local_path: PathBuf,
+    pub(super) hash: String,
+    pub(super) size: u64,
+}
+
+/// How much storage the content blobs save.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ContentBlobReport {
+    /// content blobs in use.
+    pub blobs: u64,
+    /// files in archives that are stored as one of these blobs.
+    pub files: u64,
+    /// the compressed size of the blobs.
+    pub stored_bytes: u64,
+    /// the compressed size of the files that would have been stored again without the blobs.
+    pub saved_bytes: u64,
+}
+
+impl AsyncStorage {
+    /// Upload the content blobs of an archive that are not stored yet, and replace the
+    /// references of the archive with these files.
+    ///
+    /// Returns the hashes the archive referenced before, but not anymore. Pass these to
+    /// [`Self::collect_content_blobs`] after the new archive index is stored.
+    #[instrument(skip(self, files))]
+    pub(super) async fn register_content_blobs(
+        &self,
+        archive_path: &str,
+        files: &[ContentBlobSource],
+        alg: CompressionAlgorithm,
+    ) -> Result<Vec<String>> {
+        let mut counts: BTreeMap<&str, (&ContentBlobSource, i32)> = BTreeMap::new();
+        for file in files {
+            counts.entry(&file.hash).or_insert((file, 0)).1 += 1;
+        }
+        let hashes: Vec<String> = counts.keys().map(|hash| hash.to_string()).collect();
+
+        let mut conn = self.pool.get_async().await?;
+        loop {
+            let blobs = sqlx::query!(
+                r#"SELECT
+                     hash,
+                     deleting_since IS NOT NULL AS "deleting!",
+                     COALESCE(
+                         deleting_since < NOW() - INTERVAL '5 minutes',
+                         FALSE
+                     ) AS "stale_deletion!"
+                 FROM content_blobs
+                 WHERE hash = ANY($1)"#,
+                &hashes,
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+
+            // a blob that is being deleted can only be uploaded again after its object is
+            // deleted. When the garbage collection didn't finish in time, it was interrupted
+            // and we finish the deletion.
+            let stale: Vec<String> = blobs
+                .iter()
+                .filter(|blob| blob.stale_deletion)
+                .map(|blob| blob.hash.clone())
+                .collect();
+            if !stale.is_empty() {
+                self.delete_content_blobs(&stale).await?;
+                continue;
+            }
+            if blobs.iter().any(|blob| blob.deleting) {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+
+            let existing: HashSet<&str> = blobs.iter().map(|blob| blob.hash.as_str()).collect();
+            let missing: Vec<ContentBlobSource> = counts
+                .values()
+                .filter(|(file, _)| !existing.contains(file.hash.as_str()))
+                .map(|(file, _)| (*file).clone())
+                .collect();
+            if !missing.is_empty() {
+                let uploads = spawn_blocking(move || {
+                    missing
+                        .into_iter()
+                        .map(|file| {
+                            let content = compress(File::open(&file.local_path)?, alg)?;
+                            Ok((
+                                file.hash.clone(),
+                                file.size,
+                                BlobUpload {
+                                    path: content_blob_path(&file.hash),
+                                    mime: detect_mime(&file.path_in_archive),
+                                    content,
+                                    compression: Some(alg),
+                                },
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .await?;
+
+                let sizes: Vec<_> = uploads
+                    .iter()
+                    .map(|(hash, size, upload)| (hash.clone(), *size, upload.content.len()))
+                    .collect();
+                // the objects are stored before their rows, so there is no row without object.
+                self.store_inner(uploads.into_iter().map(|(_, _, upload)| upload).collect())
+                    .await?;
+                for (hash, size, compressed_size) in sizes {
+                    sqlx::query!(
+                        "INSERT INTO content_blobs (hash, size, compressed_size)
+                         VALUES ($1, $2, $3)
+                         ON CONFLICT (hash) DO NOTHING",
+                        hash,
+                        size as i64,
+                        compressed_size as i64,
+                    )
+                    .execute(&mut *conn)
+                    .await?;
+                }
+            }
+
+            let mut tx = conn.begin().await?;
+
+            // the lock keeps the garbage collection from deleting the blobs until our references
+            // are committed. When it started deleting one of them in the meantime, we start over
+            // and upload it again after its deletion.
+            let usable = sqlx::query_scalar!(
+                "SELECT hash FROM content_blobs
+                 WHERE hash = ANY($1) AND deleting_since IS NULL
+                 FOR SHARE",
+                &hashes,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            if usable.len() < hashes.len() {
+                tx.rollback().await?;
+                continue;
+            }
+
+            let removed = sqlx::query_scalar!(
+                "DELETE FROM content_blob_references WHERE archive_path = $1 RETURNING hash",
+                archive_path,
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            for (hash, (_, count)) in &counts {
+                sqlx::query!(
+                    "INSERT INTO content_blob_references (archive_path, hash, count)
+                     VALUES ($1, $2, $3)",
+                    archive_path,
+                    hash,
+                    count,
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+
+            tx.commit().await?;
+
+            return Ok(removed
+                .into_iter()
+                .filter(|hash| !counts.contains_key(hash.as_str()))
+                .collect());
+        }
+    }
+
+    /// Delete the content blobs with these hashes that no archive references anymore.
+    #[instrument(skip(self))]
+    pub(super) async fn collect_content_blobs(&self, hashes: Vec<String>) -> Result<()> {
+        if hashes.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.pool.get_async().await?;
+        let mut tx = conn.begin().await?;
+
+        // lock the blobs first, so no new references can be added, then look for references
+        // again. They could have been added while we waited for the lock.
+        let locked = sqlx::query_scalar!(
+            "SELECT hash FROM content_blobs
+             WHERE hash = ANY($1) AND deleting_since IS NULL
+             FOR UPDATE",
+            &hashes,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let referenced: HashSet<String> = sqlx::query_scalar!(
+            "SELECT DISTINCT hash FROM content_blob_references WHERE hash = ANY($1)",
+            &locked,
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .collect();
+        let unreferenced: Vec<String> = locked
+            .into_iter()
+            .filter(|hash| !referenced.contains(hash))
+            .collect();
+
+        // from now on, the blobs can't be referenced or uploaded again.
+        sqlx::query!(
+            "UPDATE content_blobs SET deleting_since = NOW() WHERE hash = ANY($1)",
+            &unreferenced
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        drop(conn);
+
+        self.delete_content_blobs(&unreferenced).await?;
+
+        if !unreferenced.is_empty() {
+            info!(
+                count = unreferenced.len(),
+                "deleted unreferenced content blobs"
+            );
+        }
+        Ok(())
+    }
+
+    /// Delete the objects of content blobs that are marked for deletion, and then their rows.
+    async fn delete_content_blobs(&self, hashes: &[String]) -> Result<()> {
+        for hash in hashes {
+            let path = content_blob_path(hash);
+            self.backend.delete_prefix(&path).await?;
+            if let Some(cache) = &self.cache {
+                cache.invalidate(&path).await;
+            }
+        }
+
+        let mut conn = self.pool.get_async().await?;
+        sqlx::query!(
+            "DELETE FROM content_blobs WHERE hash = ANY($1) AND deleting_since IS NOT NULL",
+            hashes,
+        )
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
+    }
+
+    /// Remove the references of all archives starting with `prefix`, and delete the content
+    /// blobs only they referenced.
+    pub(super) async fn release_content_blobs(&self, prefix: &str) -> Result<()> {
+        let mut conn = self.pool.get_async().await?;
+        // the prefix is matched literally, `_` and `%` are wildcards in `LIKE`.
+        let pattern = prefix
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let hashes: Vec<String> = sqlx::query_scalar!(
+            "DELETE FROM content_blob_references
+             WHERE archive_path LIKE $1 || '%'
+             RETURNING hash",
+            pattern,
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+        drop(conn);
+
+        self.collect_content_blobs(hashes).await
+    }
+
+    /// `true` when some files of the archive are stored as content blobs, so the ZIP file
+    /// itself is incomplete.
+    #[cfg(test)]
+    pub(crate) async fn archive_has_content_blobs(&self, archive_path: &str) -> Result<bool> {
+        let mut conn = self.pool.get_async().await?;
+        Ok(sqlx::query_scalar!(
+            r#"SELECT EXISTS (
+                 SELECT 1 FROM content_blob_references WHERE archive_path = $1
+             ) AS "exists!""#,
+            archive_path,
+        )
+        .fetch_one(&mut *conn)
+        .await?)
+    }
+
+    /// The path of the archive with all files, for downloads.
+    ///
+    /// When some files are stored as content blobs, or compressed with an algorithm the ZIP
+    /// format doesn't support, a full archive is stored next to the archive when it's uploaded.
+    pub(crate) async fn full_archive_path(&self, archive_path: &str) -> Result<String> {
+        let full_archive_path = full_archive_path(archive_path);
+        Ok(if self.exists(&full_archive_path).await? {
+            full_archive_path
+        } else {
+            archive_path.to_owned()
+        })
+    }
+
+    /// Fetch an archive with all files, including the files that are stored as content blobs.
+    #[instrument(skip(self))]
+    pub(crate) async fn get_full_archive(&self, archive_path: &str) -> Result<Blob> {
+        self.get(&self.full_archive_path(archive_path).await?, usize::MAX)
+            .await
+    }
+
+    /// How many content blobs there are, and how much storage they save.
+    pub async fn content_blob_report(&self) -> Result<ContentBlobReport> {
+        let mut conn = self.pool.get_async().await?;
+        let row = sqlx::query!(
+            r#"SELECT
+                 COUNT(*) AS "blobs!",
+                 COALESCE(SUM(refs.files), 0)::BIGINT AS "files!",
+                 COALESCE(SUM(content_blobs.compressed_size), 0)::BIGINT AS "stored_bytes!",
+                 COALESCE(
+                     SUM(content_blobs.compressed_size * (refs.files - 1)), 0
+                 )::BIGINT AS "saved_bytes!"
+             FROM content_blobs
+             INNER JOIN (
+                 SELECT hash, SUM(count) AS files
+                 FROM content_blob_references
+                 GROUP BY hash
+             ) AS refs ON refs.hash = content_blobs.hash"#
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(ContentBlobReport {
+            blobs: row.blobs as u64,
+            files: row.files as u64,
+            stored_bytes: row.stored_bytes as u64,
+            saved_bytes: row.saved_bytes as u64,
+        })
+    }
+}
+
+// The tests for this module are in src/storage/mod.rs, next to the other archive tests.
@@ -1,9 +1,11 @@
-use super::{BlobUpload, FileRange, StorageMetrics, StreamingBlob};
+use super::{BlobUpload, CompressionAlgorithm, FileRange, StorageMetrics, StreamingBlob};
 use crate::{InstanceMetrics, db::Pool, error::Result};
 use chrono::{DateTime, Utc};
 use futures_util::stream::{Stream, TryStreamExt};
+use mime::Mime;
 use sqlx::Acquire;
 use std::{io, sync::Arc};
+use tokio::io::{AsyncRead, AsyncReadExt as _};
 
 pub(crate) struct DatabaseBackend {
     pool: Pool,
@@ -136,6 +138,36 @@ impl DatabaseBackend {
         })
     }
 
+    pub(super) async fn size(&self, path: &str) -> Result<u64> {
+        let size = sqlx::query_scalar!(
+            r#"SELECT octet_length(content) AS "size!" FROM files WHERE path = $1"#,
+            path
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(super::PathNotFoundError)?;
+        Ok(size.try_into()?)
+    }
+
+    /// The content is a column, so it's read completely before it's stored.
+    pub(super) async fn store_stream(
+        &self,
+        path: &str,
+        mime: &Mime,
+        compression: Option<CompressionAlgorithm>,
+        content: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<()> {
+        let mut buffer = Vec::new();
+        content.read_to_end(&mut buffer).await?;
+        self.store_batch(vec![BlobUpload {
+            path: path.into(),
+            mime: mime.clone(),
+            content: buffer,
+            compression,
+        }])
+        .await
+    }
+
     pub(super) async fn store_batch(&self, batch: Vec<BlobUpload>) -> Result<()> {
         let mut conn = self.pool.get_async().await?;
         let mut trans = conn.begin().await?;
@@ -0,0 +1,401 @@
+use super::{BlobUpload, CompressionAlgorithm, FileRange, StorageMetrics, StreamingBlob};
+use crate::{InstanceMetrics, error::Result, utils::spawn_blocking};
+use anyhow::{Context as _, bail};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncRead, AsyncReadExt as _, AsyncSeekExt as _, BufReader};
+use walkdir::WalkDir;
+
+/// Directory with the content of the blobs, in the same hierarchy as their paths.
+const BLOBS_DIR: &str = "blobs";
+/// Directory with a JSON sidecar file per blob, see [`BlobMetadata`].
+const METADATA_DIR: &str = "metadata";
+/// Directory for files that are being written, they are moved into place when complete.
+const TEMP_DIR: &str = "tmp";
+
+/// Everything we have to know about a blob that is not its content.
+#[derive(Debug, Serialize, Deserialize)]
+struct BlobMetadata {
+    mime: String,
+    compression: Option<i32>,
+    #[serde(default)]
+    public: bool,
+}
+
+/// Errors opening a path that mean there is no blob at that path.
+fn is_not_found(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory | io::ErrorKind::InvalidFilename
+    )
+}
+
+fn convert_not_found(err: io::Error) -> anyhow::Error {
+    if is_not_found(&err) {
+        super::PathNotFoundError.into()
+    } else {
+        err.into()
+    }
+}
+
+/// How often moving a file into place is tried, when its directory is removed concurrently.
+const PERSIST_ATTEMPTS: usize = 3;
+
+/// Write `content` to `target` so readers either see the old or the new file.
+fn write_atomic(temp_dir: &Path, target: &Path, content: &[u8]) -> Result<()> {
+    use std::io::Write as _;
+
+    let mut file = NamedTempFile::new_in(temp_dir)?;
+    file.write_all(content)?;
+    file.as_file().sync_data()?;
+    persist(file, target)
+}
+
+/// Move a complete temporary file to `target`, creating its directory.
+///
+/// `delete_prefix` removes directories that are empty, which can happen between creating
+/// the directory and moving the file into it. Both are retried then.
+fn persist(mut file: NamedTempFile, target: &Path) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let created = match target.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        };
+        let err = match created {
+            Ok(()) => match file.persist(target) {
+                Ok(_) => return Ok(()),
+                Err(err) => {
+                    file = err.file;
+                    err.error
+                }
+            },
+            Err(err) => err,
+        };
+        if err.kind() != io::ErrorKind::NotFound || attempt >= PERSIST_ATTEMPTS {
+            return Err(err.into());
+        }
+        attempt += 1;
+    }
+}
+
+/// Stores blobs as files below a local directory, for mirrors and development setups that
+/// don't want to run S3 or keep the blobs in the database.
+///
+/// Unlike on S3, a path can't be both a blob and the prefix of other blobs: `a` and `a/b`
+/// can't both exist, since `a` would have to be a file and a directory. Storing the second
+/// one fails.
+pub(super) struct FilesystemBackend {
+    root: PathBuf,
+    metrics: Arc<InstanceMetrics>,
+    otel_metrics: StorageMetrics,
+}
+
+impl FilesystemBackend {
+    pub(super) fn new(
+        root: PathBuf,
+        metrics: Arc<InstanceMetrics>,
+        otel_metrics: StorageMetrics,
+    ) -> Result<Self> {
+        for dir in [BLOBS_DIR, METADATA_DIR, TEMP_DIR] {
+            fs::create_dir_all(root.join(dir))
+                .with_context(|| format!("could not create {}", root.join(dir).display()))?;
+        }
+        Ok(Self {
+            root,
+            metrics,
+            otel_metrics,
+        })
+    }
+
+    /// The relative filesystem path of a storage path, which can't leave the directory
+    /// it's joined to.
+    fn relative_path(path: &str) -> Result<PathBuf> {
+        // `Path::components` would normalize `a//b` and `a/./b`, so they would be
+        // the same file as `a/b`.
+        if path
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        {
+            bail!("invalid storage path {path:?}");
+        }
+        Ok(path.split('/').collect())
+    }
+
+    /// The relative directory that contains all storage paths starting with `prefix`,
+    /// `None` when no valid path starts with it.
+    fn prefix_dir(prefix: &str) -> Option<PathBuf> {
+        match prefix.rsplit_once('/') {
+            Some((dir, _)) => Self::relative_path(dir).ok(),
+            None => Some(PathBuf::new()),
+        }
+    }
+
+    fn blob_path(&self, path: &str) -> Result<PathBuf> {
+        Ok(self.root.join(BLOBS_DIR).join(Self::relative_path(path)?))
+    }
+
+    fn metadata_path(&self, path: &str) -> Result<PathBuf> {
+        Ok(self
+            .root
+            .join(METADATA_DIR)
+            .join(Self::relative_path(path)?))
+    }
+
+    async fn read_metadata(&self, path: &str) -> Result<BlobMetadata> {
+        let blob_path = self.blob_path(path).map_err(|_| super::PathNotFoundError)?;
+        // the metadata is written before the blob, so a missing blob is checked first.
+        tokio::fs::metadata(blob_path)
+            .await
+            .map_err(convert_not_found)?;
+        let metadata = tokio::fs::read(self.metadata_path(path)?)
+            .await
+            .map_err(convert_not_found)?;
+        Ok(serde_json::from_slice(&metadata)?)
+    }
+
+    pub(super) async fn exists(&self, path: &str) -> Result<bool> {
+        let Ok(blob_path) = self.blob_path(path) else {
+            return Ok(false);
+        };
+        match tokio::fs::metadata(blob_path).await {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(err) if is_not_found(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub(super) async fn get_public_access(&self, path: &str) -> Result<bool> {
+        Ok(self.read_metadata(path).await?.public)
+    }
+
+    pub(super) async fn set_public_access(&self, path: &str, public: bool) -> Result<()> {
+        let mut metadata = self.read_metadata(path).await?;
+        metadata.public = public;
+
+        let temp_dir = self.root.join(TEMP_DIR);
+        let metadata_path = self.metadata_path(path)?;
+        spawn_blocking(move || {
+            write_atomic(&temp_dir, &metadata_path, &serde_json::to_vec(&metadata)?)
+        })
+        .await
+    }
+
+    pub(super) async fn get_stream(
+        &self,
+        path: &str,
+        range: Option<FileRange>,
+    ) -> Result<StreamingBlob> {
+        let blob_path = self.blob_path(path).map_err(|_| super::PathNotFoundError)?;
+        let mut file = tokio::fs::File::open(&blob_path)
+            .await
+            .map_err(convert_not_found)?;
+        let file_metadata = file.metadata().await?;
+        let metadata = self.read_metadata(path).await?;
+
+        let date_updated: DateTime<Utc> = file_metadata.modified()?.into();
+        let (content, content_length) = if let Some(range) = range {
+            file.seek(io::SeekFrom::Start(*range.start())).await?;
+            let length = range.end() - range.start() + 1;
+            let available = file_metadata.len().saturating_sub(*range.start());
+            (file.take(length), length.min(available))
+        } else {
+            let length = file_metadata.len();
+            (file.take(length), length)
+        };
+
+        Ok(StreamingBlob {
+            path: path.into(),
+            mime: metadata
+                .mime
+                .parse()
+                .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            date_updated,
+            compression: metadata.compression.and_then(|i| i.try_into().ok()),
+            content_length: content_length.try_into()?,
+            content: Box::new(BufReader::new(content)),
+        })
+    }
+
+    pub(super) async fn size(&self, path: &str) -> Result<u64> {
+        let blob_path = self.blob_path(path).map_err(|_| super::PathNotFoundError)?;
+        Ok(tokio::fs::metadata(blob_path)
+            .await
+            .map_err(convert_not_found)?
+            .len())
+    }
+
+    pub(super) async fn store_stream(
+        &self,
+        path: &str,
+        mime: &Mime,
+        compression: Option<CompressionAlgorithm>,
+        content: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<()> {
+        let metadata = BlobMetadata {
+            mime: mime.to_string(),
+            compression: compression.map(|alg| alg as i32),
+            public: false,
+        };
+        let temp_dir = self.root.join(TEMP_DIR);
+        let metadata_path = self.metadata_path(path)?;
+        let blob_path = self.blob_path(path)?;
+
+        let temp_file = {
+            let temp_dir = temp_dir.clone();
+            spawn_blocking(move || Ok(NamedTempFile::new_in(temp_dir)?)).await?
+        };
+        let mut file = tokio::fs::File::from_std(temp_file.reopen()?);
+        tokio::io::copy(content, &mut file).await?;
+        file.sync_data().await?;
+        drop(file);
+
+        spawn_blocking(move || {
+            // like in `store_batch`, the metadata is written first.
+            write_atomic(&temp_dir, &metadata_path, &serde_json::to_vec(&metadata)?)?;
+            persist(temp_file, &blob_path)
+        })
+        .await?;
+
+        self.metrics.uploaded_files_total.inc();
+        self.otel_metrics.uploaded_files.add(1, &[]);
+        Ok(())
+    }
+
+    pub(super) async fn store_batch(&self, batch: Vec<BlobUpload>) -> Result<()> {
+        let files = batch
+            .into_iter()
+            .map(|blob| {
+                let metadata = BlobMetadata {
+                    mime: blob.mime.to_string(),
+                    compression: blob.compression.map(|alg| alg as i32),
+                    // like S3, storing a blob again resets its visibility.
+                    public: false,
+                };
+                Ok((
+                    self.metadata_path(&blob.path)?,
+                    serde_json::to_vec(&metadata)?,
+                    self.blob_path(&blob.path)?,
+                    blob.content,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let count = files.len() as u64;
+
+        let temp_dir = self.root.join(TEMP_DIR);
+        spawn_blocking(move || {
+            for (metadata_path, metadata, blob_path, content) in files {
+                // readers find the blob through its content, so the metadata is written first.
+                write_atomic(&temp_dir, &metadata_path, &metadata)?;
+                write_atomic(&temp_dir, &blob_path, &content)?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        self.metrics.uploaded_files_total.inc_by(count);
+        self.otel_metrics.uploaded_files.add(count, &[]);
+        Ok(())
+    }
+
+    /// All storage paths starting with `prefix`, sorted.
+    async fn paths_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let blobs_dir = self.root.join(BLOBS_DIR);
+        let prefix = prefix.to_owned();
+        spawn_blocking(move || {
+            // only walk the directory that contains everything with the prefix.
+            let Some(prefix_dir) = FilesystemBackend::prefix_dir(&prefix) else {
+                return Ok(Vec::new());
+            };
+
+            let mut paths = Vec::new();
+            for entry in WalkDir::new(blobs_dir.join(prefix_dir)) {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) if err.io_error().is_some_and(is_not_found) => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let path = entry
+                    .path()
+                    .strip_prefix(&blobs_dir)?
+                    .to_str()
+                    .context("non-utf8 path in storage")?
+                    .replace(std::path::MAIN_SEPARATOR, "/");
+                if path.starts_with(&prefix) {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+            Ok(paths)
+        })
+        .await
+    }
+
+    pub(super) async fn list_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Stream<Item = Result<String>> + 'a {
+        match self.paths_with_prefix(prefix).await {
+            Ok(paths) => stream::iter(paths.into_iter().map(Ok).collect::<Vec<_>>()),
+            Err(err) => stream::iter(vec![Err(err)]),
+        }
+    }
+
+    pub(super) async fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let files = self
+            .paths_with_prefix(prefix)
+            .await?
+            .iter()
+            .map(|path| Ok([self.blob_path(path)?, self.metadata_path(path)?]))
+            .collect::<Result<Vec<_>>>()?;
+        let roots = Self::prefix_dir(prefix).map(|prefix_dir| {
+            [BLOBS_DIR, METADATA_DIR].map(|root| (self.root.join(root), prefix_dir.clone()))
+        });
+
+        spawn_blocking(move || {
+            for file in files.iter().flatten() {
+                match fs::remove_file(file) {
+                    Err(err) if !is_not_found(&err) => return Err(err.into()),
+                    _ => {}
+                }
+            }
+
+            // remove the directories that are empty now, up to the roots. Removing a
+            // directory that still has files fails, and we keep it. A concurrent upload into
+            // a removed directory creates it again, see `persist`.
+            for (root, prefix_dir) in roots.into_iter().flatten() {
+                let dir = root.join(prefix_dir);
+                for entry in WalkDir::new(&dir).contents_first(true) {
+                    let Ok(entry) = entry else { continue };
+                    if entry.file_type().is_dir() && entry.path() != root {
+                        let _ = fs::remove_dir(entry.path());
+                    }
+                }
+                for parent in dir.ancestors().skip(1) {
+                    if parent == root
+                        || !parent.starts_with(&root)
+                        || fs::remove_dir(parent).is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+// The tests for this module are in src/storage/mod.rs, as part of the backend tests. Please add
+// any test checking the public interface there.
@@ -0,0 +1,320 @@
+use super::{StorageBackend, StorageKind, StorageMetrics};
+use crate::{Context, error::Result};
+use anyhow::{Context as _, bail};
+use futures_util::{StreamExt as _, TryStreamExt as _, ready, stream};
+use std::{
+    collections::HashSet,
+    io,
+    pin::Pin,
+    task::{self, Poll},
+};
+use tokio::io::{AsyncRead, ReadBuf};
+use tracing::{info, instrument};
+
+/// How many paths are listed before they are checked against the checkpoints and copied.
+const CHUNK_SIZE: usize = 1000;
+
+/// What a storage migration did, or would do in a dry run.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MigrationSummary {
+    /// objects copied, or that would be copied.
+    pub objects: u64,
+    /// the size of these objects as they are stored, so mostly compressed.
+    pub bytes: u64,
+    /// objects an earlier run already copied, and that were skipped.
+    pub skipped: u64,
+}
+
+/// Computes the md5 checksum and the size of everything that is read through it.
+struct ChecksumReader<R> {
+    inner: R,
+    md5: md5::Context,
+    size: u64,
+}
+
+impl<R> ChecksumReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            md5: md5::Context::new(),
+            size: 0,
+        }
+    }
+
+    fn finish(self) -> (u64, String) {
+        (self.size, format!("{:x}", self.md5.finalize()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChecksumReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        let read = &buf.filled()[filled..];
+        this.md5.consume(read);
+        this.size += read.len() as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Copy one object with its compression and `public` flag, and check that the target returns
+/// the same content. Returns the size and checksum of the object.
+///
+/// The content is streamed, and the checksums are computed while it is copied and read back.
+#[instrument(skip(source, target))]
+async fn migrate_object(
+    source: &StorageBackend,
+    target: &StorageBackend,
+    path: &str,
+) -> Result<(u64, String)> {
+    let stream = source.get_stream(path, None).await?;
+    let public = source.get_public_access(path).await?;
+    let compression = stream.compression;
+
+    let mut content = ChecksumReader::new(stream.content);
+    target
+        .store_stream(path, &stream.mime, compression, &mut content)
+        .await?;
+    let (size, checksum) = content.finish();
+    // storing an object resets its visibility on some backends, so it's always set.
+    target.set_public_access(path, public).await?;
+
+    let copy = target.get_stream(path, None).await?;
+    let mut copy_content = ChecksumReader::new(copy.content);
+    tokio::io::copy(&mut copy_content, &mut tokio::io::sink()).await?;
+    let (_, copy_checksum) = copy_content.finish();
+    if copy_checksum != checksum {
+        bail!("checksum mismatch for {path}: expected {checksum}, got {copy_checksum}");
+    }
+    if copy.compression != compression {
+        bail!(
+            "compression mismatch for {path}: expected {compression:?}, got {:?}",
+            copy.compression
+        );
+    }
+    if target.get_public_access(path).await? != public {
+        bail!("public access mismatch for {path}");
+    }
+
+    Ok((size, checksum))
+}
+
+/// Copy every object from the `from` storage backend to the `to` backend, with the storage
+/// configuration of `ctx`.
+///
+/// Each copied object is recorded in `storage_migration_checkpoints`, so running the migration
+/// again continues where it stopped. Objects that change in the source after they were copied
+/// are not copied again, so builds should be paused while migrating.
+///
+/// With `dry_run`, nothing is copied, and the summary contains the objects that would be.
+pub async fn migrate_storage(
+    ctx: &Context,
+    from: StorageKind,
+    to: StorageKind,
+    dry_run: bool,
+    concurrency: Option<usize>,
+) -> Result<MigrationSummary> {
+    if from == to {
+        bail!("can't migrate the {from} storage to itself");
+    }
+
+    let backend = |kind| {
+        StorageBackend::new(
+            kind,
+            ctx.pool.clone(),
+            ctx.instance_metrics.clone(),
+            &ctx.config,
+            StorageMetrics::new(&ctx.meter_provider),
+        )
+    };
+    let source = backend(from)
+        .await
+        .with_context(|| format!("could not open the {from} storage"))?;
+    let target = backend(to)
+        .await
+        .with_context(|| format!("could not open the {to} storage"))?;
+
+    let mut conn = ctx.pool.get_async().await?;
+    let mut summary = MigrationSummary::default();
+
+    let mut chunks = source.list_prefix("").await.chunks(CHUNK_SIZE);
+    while let Some(chunk) = chunks.next().await {
+        let paths = chunk.into_iter().collect::<Result<Vec<_>>>()?;
+
+        let migrated: HashSet<String> = sqlx::query_scalar!(
+            "SELECT path
+             FROM storage_migration_checkpoints
+             WHERE source = $1 AND target = $2 AND path = ANY($3)",
+            from.to_string(),
+            to.to_string(),
+            &paths,
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .collect();
+
+        let pending: Vec<_> = paths
+            .into_iter()
+            .filter(|path| !migrated.contains(path))
+            .collect();
+        summary.skipped += migrated.len() as u64;
+
+        if dry_run {
+            for path in &pending {
+                summary.objects += 1;
+                summary.bytes += source.size(path).await?;
+            }
+            continue;
+        }
+
+        // checkpoints are written as objects complete, so a failure only loses the objects
+        // that were in flight.
+        let mut results = stream::iter(&pending)
+            .map(|path| {
+                let (source, target) = (&source, &target);
+                async move {
+                    let (size, checksum) = migrate_object(source, target, path).await?;
+                    Ok::<_, anyhow::Error>((path, size, checksum))
+                }
+            })
+            .buffer_unordered(concurrency.unwrap_or_else(num_cpus::get));
+
+        while let Some((path, size, checksum)) = results.try_next().await? {
+            sqlx::query!(
+                "INSERT INTO storage_migration_checkpoints (source, target, path, size, checksum)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (source, target, path) DO UPDATE
+                 SET size = EXCLUDED.size,
+                     checksum = EXCLUDED.checksum,
+                     migrated_at = NOW()",
+                from.to_string(),
+                to.to_string(),
+                path,
+                size as i64,
+                checksum,
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            summary.objects += 1;
+            summary.bytes += size;
+        }
+
+        info!(
+            objects = summary.objects,
+            bytes = summary.bytes,
+            skipped = summary.skipped,
+            "migrated chunk of objects from {from} to {to}"
+        );
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::{Blob, CompressionAlgorithm},
+        test::TestEnvironment,
+    };
+
+    async fn fetch_raw(backend: &StorageBackend, path: &str) -> Result<Blob> {
+        backend
+            .get_stream(path, None)
+            .await?
+            .materialize(usize::MAX)
+            .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn migrates_to_filesystem_and_resumes() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        let storage = env.async_storage();
+
+        storage.store_one_uncompressed("a.txt", "content a").await?;
+        storage.store_one("dir/b.html", "content b").await?;
+        storage.set_public_access("dir/b.html", true).await?;
+
+        let summary = migrate_storage(
+            &env.context,
+            StorageKind::Database,
+            StorageKind::Filesystem,
+            true,
+            None,
+        )
+        .await?;
+        assert_eq!(summary.objects, 2);
+        assert_eq!(summary.skipped, 0);
+
+        let target = StorageBackend::new(
+            StorageKind::Filesystem,
+            env.context.pool.clone(),
+            env.context.instance_metrics.clone(),
+            env.config(),
+            StorageMetrics::new(env.meter_provider()),
+        )
+        .await?;
+        // the dry run didn't copy anything.
+        assert!(fetch_raw(&target, "a.txt").await.is_err());
+
+        let migrated = migrate_storage(
+            &env.context,
+            StorageKind::Database,
+            StorageKind::Filesystem,
+            false,
+            None,
+        )
+        .await?;
+        assert_eq!(migrated, summary);
+
+        let a = fetch_raw(&target, "a.txt").await?;
+        assert_eq!(a.content, b"content a");
+        assert_eq!(a.compression, None);
+        assert!(!target.get_public_access("a.txt").await?);
+
+        let b = fetch_raw(&target, "dir/b.html").await?;
+        assert_eq!(b.compression, Some(CompressionAlgorithm::default()));
+        assert_eq!(b.mime, mime::TEXT_HTML);
+        assert!(target.get_public_access("dir/b.html").await?);
+
+        // a second run only copies the new object.
+        storage.store_one_uncompressed("c.txt", "content c").await?;
+        let resumed = migrate_storage(
+            &env.context,
+            StorageKind::Database,
+            StorageKind::Filesystem,
+            false,
+            None,
+        )
+        .await?;
+        assert_eq!(resumed.objects, 1);
+        assert_eq!(resumed.skipped, 2);
+        assert_eq!(fetch_raw(&target, "c.txt").await?.content, b"content c");
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refuses_migrating_to_the_same_backend() -> Result<()> {
+        let env = TestEnvironment::new().await?;
+        assert!(
+            migrate_storage(
+                &env.context,
+                StorageKind::Database,
+                StorageKind::Database,
+                true,
+                None
+            )
+            .await
+            .is_err()
+        );
+        Ok(())
+    }
+}
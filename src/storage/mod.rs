@@ -1,12 +1,26 @@
 mod archive_index;
+mod cache;
 pub(crate) mod compression;
+mod compression_dictionaries;
+mod content_blobs;
 mod database;
+mod filesystem;
+mod migrate;
 mod s3;
 
-pub use self::compression::{CompressionAlgorithm, CompressionAlgorithms, compress, decompress};
+pub use self::compression::{
+    CompressionAlgorithm, CompressionAlgorithms, CompressionDictionary, compress,
+    compress_with_dictionary, decompress, decompress_with_dictionary,
+};
+pub use self::compression_dictionaries::TrainedDictionary;
+pub use self::content_blobs::ContentBlobReport;
+pub use self::migrate::{MigrationSummary, migrate_storage};
 use self::{
+    cache::{CacheKey, StorageCache},
     compression::{compress_async, wrap_reader_for_decompression},
+    content_blobs::{ContentBlobSource, content_blob_path, full_archive_path, hash_file},
     database::DatabaseBackend,
+    filesystem::FilesystemBackend,
     s3::S3Backend,
 };
 use crate::{
@@ -32,7 +46,7 @@ use path_slash::PathExt;
 use std::{
     fmt,
     fs::{self, File},
-    io::{self, BufReader},
+    io::{self, BufReader, Write as _},
     iter,
     num::ParseIntError,
     ops::RangeInclusive,
@@ -44,9 +58,9 @@ use std::{
     },
 };
 use tokio::{
-    io::{AsyncBufRead, AsyncBufReadExt},
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncRead},
     runtime,
-    sync::RwLock,
+    sync::{OnceCell, RwLock},
 };
 use tracing::{error, info, info_span, instrument, trace, warn};
 use tracing_futures::Instrument as _;
@@ -118,12 +132,18 @@ impl std::fmt::Debug for StreamingBlob {
 impl StreamingBlob {
     /// wrap the content stream in a streaming decompressor according to the
     /// algorithm found in `compression` attribute.
-    pub(crate) async fn decompress(mut self) -> Result<Self, io::Error> {
+    ///
+    /// `dictionary` is only used for [`CompressionAlgorithm::ZstdDictionary`], see
+    /// [`AsyncStorage::decompress`] to fetch it.
+    pub(crate) async fn decompress(
+        mut self,
+        dictionary: Option<&CompressionDictionary>,
+    ) -> Result<Self, io::Error> {
         let Some(alg) = self.compression else {
             return Ok(self);
         };
 
-        self.content = wrap_reader_for_decompression(self.content, alg);
+        self.content = wrap_reader_for_decompression(self.content, alg, dictionary)?;
 
         // We fill the first bytes here to force the compressor to start decompressing.
         // This is because we want a failure here in this method when the data is corrupted,
@@ -204,10 +224,12 @@ pub fn get_file_list<P: AsRef<Path>>(path: P) -> Box<dyn Iterator<Item = Result<
 #[error("invalid storage backend")]
 pub struct InvalidStorageBackendError;
 
-#[derive(Debug)]
+#[derive(strum::Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case")]
 pub enum StorageKind {
     Database,
     S3,
+    Filesystem,
 }
 
 impl std::str::FromStr for StorageKind {
@@ -217,6 +239,7 @@ impl std::str::FromStr for StorageKind {
         match input {
             "database" => Ok(StorageKind::Database),
             "s3" => Ok(StorageKind::S3),
+            "filesystem" => Ok(StorageKind::Filesystem),
             _ => Err(InvalidStorageBackendError),
         }
     }
@@ -243,13 +266,127 @@ impl StorageMetrics {
 enum StorageBackend {
     Database(DatabaseBackend),
     S3(Box<S3Backend>),
+    Filesystem(FilesystemBackend),
+}
+
+impl StorageBackend {
+    async fn new(
+        kind: StorageKind,
+        pool: Pool,
+        metrics: Arc<InstanceMetrics>,
+        config: &Config,
+        otel_metrics: StorageMetrics,
+    ) -> Result<Self> {
+        Ok(match kind {
+            StorageKind::Database => {
+                StorageBackend::Database(DatabaseBackend::new(pool, metrics, otel_metrics))
+            }
+            StorageKind::S3 => StorageBackend::S3(Box::new(
+                S3Backend::new(metrics, config, otel_metrics).await?,
+            )),
+            StorageKind::Filesystem => StorageBackend::Filesystem(FilesystemBackend::new(
+                config.filesystem_storage_path.clone(),
+                metrics,
+                otel_metrics,
+            )?),
+        })
+    }
+
+    async fn get_public_access(&self, path: &str) -> Result<bool> {
+        match self {
+            StorageBackend::Database(db) => db.get_public_access(path).await,
+            StorageBackend::S3(s3) => s3.get_public_access(path).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.get_public_access(path).await,
+        }
+    }
+
+    async fn set_public_access(&self, path: &str, public: bool) -> Result<()> {
+        match self {
+            StorageBackend::Database(db) => db.set_public_access(path, public).await,
+            StorageBackend::S3(s3) => s3.set_public_access(path, public).await,
+            StorageBackend::Filesystem(filesystem) => {
+                filesystem.set_public_access(path, public).await
+            }
+        }
+    }
+
+    async fn get_stream(&self, path: &str, range: Option<FileRange>) -> Result<StreamingBlob> {
+        match self {
+            StorageBackend::Database(db) => db.get_stream(path, range).await,
+            StorageBackend::S3(s3) => s3.get_stream(path, range).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.get_stream(path, range).await,
+        }
+    }
+
+    /// The size of an object as it is stored, without fetching its content.
+    async fn size(&self, path: &str) -> Result<u64> {
+        match self {
+            StorageBackend::Database(db) => db.size(path).await,
+            StorageBackend::S3(s3) => s3.size(path).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.size(path).await,
+        }
+    }
+
+    /// Store one object with the content read from `content`, as it is, without buffering the
+    /// whole object in memory where the backend allows it.
+    async fn store_stream(
+        &self,
+        path: &str,
+        mime: &Mime,
+        compression: Option<CompressionAlgorithm>,
+        content: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<()> {
+        match self {
+            StorageBackend::Database(db) => db.store_stream(path, mime, compression, content).await,
+            StorageBackend::S3(s3) => s3.store_stream(path, mime, compression, content).await,
+            StorageBackend::Filesystem(filesystem) => {
+                filesystem
+                    .store_stream(path, mime, compression, content)
+                    .await
+            }
+        }
+    }
+
+    async fn store_batch(&self, batch: Vec<BlobUpload>) -> Result<()> {
+        match self {
+            StorageBackend::Database(db) => db.store_batch(batch).await,
+            StorageBackend::S3(s3) => s3.store_batch(batch).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.store_batch(batch).await,
+        }
+    }
+
+    async fn list_prefix<'a>(&'a self, prefix: &'a str) -> BoxStream<'a, Result<String>> {
+        match self {
+            StorageBackend::Database(db) => Box::pin(db.list_prefix(prefix).await),
+            StorageBackend::S3(s3) => Box::pin(s3.list_prefix(prefix).await),
+            StorageBackend::Filesystem(filesystem) => {
+                Box::pin(filesystem.list_prefix(prefix).await)
+            }
+        }
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        match self {
+            StorageBackend::Database(db) => db.delete_prefix(prefix).await,
+            StorageBackend::S3(s3) => s3.delete_prefix(prefix).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.delete_prefix(prefix).await,
+        }
+    }
 }
 
 pub struct AsyncStorage {
     backend: StorageBackend,
+    /// for the references to content blobs.
+    pool: Pool,
+    /// Local cache for the blobs of the backend, when `storage_cache_size` is configured.
+    cache: Option<StorageCache>,
     config: Arc<Config>,
     /// Locks to synchronize access to the locally cached archive index files.
     locks: DashMap<PathBuf, Arc<RwLock<()>>>,
+    /// The zstd dictionaries used so far, by id.
+    dictionaries: DashMap<u32, Arc<CompressionDictionary>>,
+    /// The dictionary new uploads are compressed with, see [`Self::upload_dictionary`].
+    upload_dictionary: OnceCell<Arc<CompressionDictionary>>,
 }
 
 impl AsyncStorage {
@@ -261,17 +398,33 @@ impl AsyncStorage {
     ) -> Result<Self> {
         let otel_metrics = StorageMetrics::new(otel_meter_provider);
 
+        let cache = config
+            .storage_cache_size
+            .map(|size| {
+                StorageCache::new(
+                    &config.storage_cache_path,
+                    size,
+                    config.storage_cache_max_entry_size,
+                    otel_meter_provider,
+                )
+            })
+            .transpose()?;
+
         Ok(Self {
-            backend: match config.storage_backend {
-                StorageKind::Database => {
-                    StorageBackend::Database(DatabaseBackend::new(pool, metrics, otel_metrics))
-                }
-                StorageKind::S3 => StorageBackend::S3(Box::new(
-                    S3Backend::new(metrics, &config, otel_metrics).await?,
-                )),
-            },
+            backend: StorageBackend::new(
+                config.storage_backend,
+                pool.clone(),
+                metrics,
+                &config,
+                otel_metrics,
+            )
+            .await?,
+            pool,
+            cache,
             config,
             locks: DashMap::new(),
+            dictionaries: DashMap::new(),
+            upload_dictionary: OnceCell::new(),
         })
     }
 
@@ -280,23 +433,18 @@ impl AsyncStorage {
         match &self.backend {
             StorageBackend::Database(db) => db.exists(path).await,
             StorageBackend::S3(s3) => s3.exists(path).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.exists(path).await,
         }
     }
 
     #[instrument]
     pub(crate) async fn get_public_access(&self, path: &str) -> Result<bool> {
-        match &self.backend {
-            StorageBackend::Database(db) => db.get_public_access(path).await,
-            StorageBackend::S3(s3) => s3.get_public_access(path).await,
-        }
+        self.backend.get_public_access(path).await
     }
 
     #[instrument]
     pub(crate) async fn set_public_access(&self, path: &str, public: bool) -> Result<()> {
-        match &self.backend {
-            StorageBackend::Database(db) => db.set_public_access(path, public).await,
-            StorageBackend::S3(s3) => s3.set_public_access(path, public).await,
-        }
+        self.backend.set_public_access(path, public).await
     }
 
     fn max_file_size_for(&self, path: &str) -> usize {
@@ -332,7 +480,11 @@ impl AsyncStorage {
         } else {
             // Add rustdoc prefix, name and version to the path for accessing the file stored in the database
             let remote_path = format!("rustdoc/{name}/{version}/{path}");
-            self.get_stream(&remote_path).await?
+            self.decompress(
+                self.get_cached_raw_stream(&remote_path, None, latest_build_id)
+                    .await?,
+            )
+            .await?
         })
     }
 
@@ -355,7 +507,13 @@ impl AsyncStorage {
             .await?
         } else {
             let remote_path = format!("sources/{name}/{version}/{path}");
-            self.get(&remote_path, self.max_file_size_for(path)).await?
+            self.decompress(
+                self.get_cached_raw_stream(&remote_path, None, latest_build_id)
+                    .await?,
+            )
+            .await?
+            .materialize(self.max_file_size_for(path))
+            .await?
         })
     }
 
@@ -409,11 +567,47 @@ impl AsyncStorage {
     /// get a decompressing stream to an object in storage
     #[instrument]
     pub(crate) async fn get_stream(&self, path: &str) -> Result<StreamingBlob> {
-        let blob = match &self.backend {
-            StorageBackend::Database(db) => db.get_stream(path, None).await,
-            StorageBackend::S3(s3) => s3.get_stream(path, None).await,
-        }?;
-        Ok(blob.decompress().await?)
+        self.decompress(self.get_raw_stream(path, None).await?)
+            .await
+    }
+
+    /// get a stream to an object or a range inside an object in storage, without decompressing
+    /// it.
+    async fn get_raw_stream(&self, path: &str, range: Option<FileRange>) -> Result<StreamingBlob> {
+        self.backend.get_stream(path, range).await
+    }
+
+    /// like `get_raw_stream`, but through the local cache when it's enabled and the object is
+    /// small enough.
+    ///
+    /// Only use this for objects that are replaced by a new build, with the id of the latest
+    /// build, or not at all, like content blobs.
+    async fn get_cached_raw_stream(
+        &self,
+        path: &str,
+        range: Option<FileRange>,
+        latest_build_id: Option<BuildId>,
+    ) -> Result<StreamingBlob> {
+        let Some(cache) = &self.cache else {
+            return self.get_raw_stream(path, range).await;
+        };
+
+        let key = CacheKey {
+            path: path.to_owned(),
+            range: range.clone(),
+            build_id: latest_build_id,
+        };
+        if let Some(blob) = cache.get(&key).await {
+            return Ok(blob);
+        }
+
+        let generation = cache.generation();
+        let blob = self.get_raw_stream(path, range).await?;
+        if cache.accepts(blob.content_length) {
+            cache.insert(key, generation, blob).await
+        } else {
+            Ok(blob)
+        }
     }
 
     /// get, decompress and materialize part of an object from store
@@ -439,15 +633,12 @@ impl AsyncStorage {
         range: FileRange,
         compression: Option<CompressionAlgorithm>,
     ) -> Result<StreamingBlob> {
-        let mut raw_stream = match &self.backend {
-            StorageBackend::Database(db) => db.get_stream(path, Some(range)).await,
-            StorageBackend::S3(s3) => s3.get_stream(path, Some(range)).await,
-        }?;
+        let mut raw_stream = self.get_raw_stream(path, Some(range)).await?;
         // `compression` represents the compression of the file-stream inside the archive.
         // We don't compress the whole archive, so the encoding of the archive's blob is irrelevant
         // here.
         raw_stream.compression = compression;
-        Ok(raw_stream.decompress().await?)
+        self.decompress(raw_stream).await
     }
 
     fn local_index_cache_lock(&self, local_index_path: impl AsRef<Path>) -> Arc<RwLock<()>> {
@@ -580,10 +771,25 @@ impl AsyncStorage {
                 .await?
                 .ok_or(PathNotFoundError)?;
 
-            match self
-                .get_range_stream(archive_path, info.range(), Some(info.compression()))
-                .await
-            {
+            let stream = async {
+                let mut raw_stream = match info.blob() {
+                    Some(hash) => {
+                        self.get_cached_raw_stream(&content_blob_path(hash), None, None)
+                            .await?
+                    }
+                    None => {
+                        self.get_cached_raw_stream(
+                            archive_path,
+                            Some(info.range()),
+                            latest_build_id,
+                        )
+                        .await?
+                    }
+                };
+                raw_stream.compression = Some(info.compression());
+                self.decompress(raw_stream).await
+            };
+            match stream.await {
                 Ok(stream) => {
                     debug_assert_eq!(stream.compression, None);
                     return Ok(StreamingBlob {
@@ -617,6 +823,9 @@ impl AsyncStorage {
                     );
                     self.purge_archive_index_cache(archive_path, latest_build_id)
                         .await?;
+                    if let Some(cache) = &self.cache {
+                        cache.invalidate(archive_path).await;
+                    }
 
                     continue;
                 }
@@ -633,13 +842,25 @@ impl AsyncStorage {
         archive_path: &str,
         root_dir: &Path,
     ) -> Result<(Vec<FileEntry>, CompressionAlgorithm)> {
-        let (mut zip_content,    file_paths) =
+        let dictionary = self.upload_dictionary().await?;
+        let entry_alg = if dictionary.is_some() {
+            CompressionAlgorithm::ZstdDictionary
+        } else {
+            self.config.archive_compression
+        };
+        // the ZIP format only supports bzip2 of our algorithms, files compressed with other
+        // algorithms are compressed by us and stored without ZIP compression.
+        let precompressed = entry_alg != CompressionAlgorithm::Bzip2;
+
+        let content_addressed_min_size = self.config.content_addressed_min_size;
+        let (mut zip_content, full_zip_content, file_paths, content_blobs) =
             spawn_blocking({
                 let archive_path = archive_path.to_owned();
                 let root_dir = root_dir.to_owned();
 
                 move || {
                     let mut file_paths = Vec::new();
+                    let mut content_blobs = Vec::new();
 
                     // We are only using the `zip` library to create the archives and the matching
                     // index-file. The ZIP format allows more compression formats, and these can even be mixed
@@ -650,37 +871,101 @@ impl AsyncStorage {
                     // For decompression we are sharing the compression algorithms defined in
                     // `storage::compression`. So every new algorithm to be used inside ZIP archives
                     // also has to be added as supported algorithm for storage compression, together
-                    // with a mapping in `storage::archive_index::Index::new_from_zip`.
+                    // with a mapping in `storage::archive_index::create`.
+
+                    let bzip2_options = zip::write::SimpleFileOptions::default()
+                        .compression_method(zip::CompressionMethod::Bzip2);
 
                     let zip_content = {
                         let _span =
                             info_span!("create_zip_archive", %archive_path, root_dir=%root_dir.display()).entered();
 
-                        let options = zip::write::SimpleFileOptions::default()
-                            .compression_method(zip::CompressionMethod::Bzip2);
+                        let options = if precompressed {
+                            zip::write::SimpleFileOptions::default()
+                                .compression_method(zip::CompressionMethod::Stored)
+                        } else {
+                            bzip2_options
+                        };
 
                         let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
                         for file_path in get_file_list(&root_dir) {
                             let file_path = file_path?;
-
-                            let mut file = fs::File::open(root_dir.join(&file_path))?;
-                            zip.start_file(file_path.to_str().unwrap(), options)?;
-                            io::copy(&mut file, &mut zip)?;
-                            file_paths.push(FileEntry{path: file_path, size: file.metadata()?.len()});
+                            let local_path = root_dir.join(&file_path);
+
+                            let mut file = fs::File::open(&local_path)?;
+                            let size = file.metadata()?.len();
+                            if content_addressed_min_size.is_some_and(|min_size| size >= min_size) {
+                                content_blobs.push(ContentBlobSource {
+                                    path_in_archive: file_path.to_str().unwrap().to_owned(),
+                                    hash: hash_file(&local_path)?,
+                                    local_path,
+                                    size,
+                                });
+                            } else {
+                                zip.start_file(file_path.to_str().unwrap(), options)?;
+                                if !precompressed {
+                                    io::copy(&mut file, &mut zip)?;
+                                } else if let Some(dictionary) = &dictionary {
+                                    zip.write_all(&compress_with_dictionary(file, dictionary)?)?;
+                                } else {
+                                    zip.write_all(&compress(file, entry_alg)?)?;
+                                }
+                            }
+                            file_paths.push(FileEntry{path: file_path, size});
                         }
 
                         zip.finish()?.into_inner()
                     };
 
+                    // the archive in the bucket misses the content blobs, or only we can
+                    // decompress its files, so downloads use an archive with all files.
+                    let full_zip_content = if precompressed {
+                        let _span = info_span!("create_full_zip_archive", %archive_path).entered();
+
+                        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+                        for file in &file_paths {
+                            zip.start_file(file.path.to_str().unwrap(), bzip2_options)?;
+                            io::copy(&mut fs::File::open(root_dir.join(&file.path))?, &mut zip)?;
+                        }
+                        Some(zip.finish()?.into_inner())
+                    } else if content_blobs.is_empty() {
+                        None
+                    } else {
+                        let _span = info_span!("create_full_zip_archive", %archive_path).entered();
+
+                        let mut zip = zip::ZipWriter::new_append(io::Cursor::new(zip_content.clone()))?;
+                        for file in &content_blobs {
+                            zip.start_file(&file.path_in_archive, bzip2_options)?;
+                            io::copy(&mut fs::File::open(&file.local_path)?, &mut zip)?;
+                        }
+                        Some(zip.finish()?.into_inner())
+                    };
+
                     Ok((
                         zip_content,
-                        file_paths
+                        full_zip_content,
+                        file_paths,
+                        content_blobs,
                     ))
                 }
             })
             .await?;
 
         let alg = CompressionAlgorithm::default();
+
+        // before the new index is stored, so its content blobs can't be deleted.
+        let unreferenced_content_blobs = self
+            .register_content_blobs(archive_path, &content_blobs, alg)
+            .await?;
+        let content_blobs: Vec<_> = content_blobs
+            .into_iter()
+            .map(|file| archive_index::ContentBlobFile {
+                path: file.path_in_archive,
+                hash: file.hash,
+                compression: alg,
+            })
+            .collect();
+
         let remote_index_path = format!("{}.{ARCHIVE_INDEX_FILE_EXTENSION}", &archive_path);
         let compressed_index_content = {
             let _span = info_span!("create_archive_index", %remote_index_path).entered();
@@ -689,8 +974,13 @@ impl AsyncStorage {
             let local_index_path =
                 tempfile::NamedTempFile::new_in(&self.config.temp_dir)?.into_temp_path();
 
-            archive_index::create(&mut io::Cursor::new(&mut zip_content), &local_index_path)
-                .await?;
+            archive_index::create(
+                &mut io::Cursor::new(&mut zip_content),
+                precompressed.then_some(entry_alg),
+                &content_blobs,
+                &local_index_path,
+            )
+            .await?;
 
             let mut buf: Vec<u8> = Vec::new();
             compress_async(
@@ -702,7 +992,9 @@ impl AsyncStorage {
             buf
         };
 
-        self.store_inner(vec![
+        let full_archive_path = full_archive_path(archive_path);
+        let has_full_archive = full_zip_content.is_some();
+        let mut uploads = vec![
             BlobUpload {
                 path: archive_path.to_string(),
                 mime: mimes::APPLICATION_ZIP.clone(),
@@ -715,10 +1007,30 @@ impl AsyncStorage {
                 content: compressed_index_content,
                 compression: Some(alg),
             },
-        ])
-        .await?;
+        ];
+        if let Some(content) = full_zip_content {
+            uploads.push(BlobUpload {
+                path: full_archive_path.clone(),
+                mime: mimes::APPLICATION_ZIP.clone(),
+                content,
+                compression: None,
+            });
+        }
+        self.store_inner(uploads).await?;
+
+        // the archive had a full archive before, but doesn't need it anymore.
+        if !has_full_archive && self.exists(&full_archive_path).await? {
+            self.backend.delete_prefix(&full_archive_path).await?;
+            if let Some(cache) = &self.cache {
+                cache.invalidate(&full_archive_path).await;
+            }
+        }
 
-        Ok((file_paths, CompressionAlgorithm::Bzip2))
+        // only after the new index is stored, the old one could still reference them.
+        self.collect_content_blobs(unreferenced_content_blobs)
+            .await?;
+
+        Ok((file_paths, entry_alg))
     }
 
     /// Store all files in `root_dir` into the backend under `prefix`.
@@ -728,7 +1040,12 @@ impl AsyncStorage {
         prefix: &Path,
         root_dir: &Path,
     ) -> Result<(Vec<FileEntry>, CompressionAlgorithm)> {
-        let alg = CompressionAlgorithm::default();
+        let dictionary = self.upload_dictionary().await?;
+        let alg = if dictionary.is_some() {
+            CompressionAlgorithm::ZstdDictionary
+        } else {
+            CompressionAlgorithm::default()
+        };
 
         let (blobs, file_paths_and_mimes) = spawn_blocking({
             let prefix = prefix.to_owned();
@@ -748,7 +1065,10 @@ impl AsyncStorage {
 
                     let file_size = file.metadata()?.len();
 
-                    let content = compress(file, alg)?;
+                    let content = match &dictionary {
+                        Some(dictionary) => compress_with_dictionary(file, dictionary)?,
+                        None => compress(file, alg)?,
+                    };
                     let bucket_path = prefix.join(&file_path).to_slash().unwrap().to_string();
 
                     let file_info = FileEntry {
@@ -854,27 +1174,41 @@ impl AsyncStorage {
     }
 
     async fn store_inner(&self, batch: Vec<BlobUpload>) -> Result<()> {
-        match &self.backend {
-            StorageBackend::Database(db) => db.store_batch(batch).await,
-            StorageBackend::S3(s3) => s3.store_batch(batch).await,
+        let paths: Vec<_> = self
+            .cache
+            .as_ref()
+            .map(|_| batch.iter().map(|blob| blob.path.clone()).collect())
+            .unwrap_or_default();
+
+        let result = self.backend.store_batch(batch).await;
+        if let Some(cache) = &self.cache {
+            for path in paths {
+                cache.invalidate(&path).await;
+            }
         }
+        result
     }
 
     pub(super) async fn list_prefix<'a>(
         &'a self,
         prefix: &'a str,
     ) -> BoxStream<'a, Result<String>> {
-        match &self.backend {
-            StorageBackend::Database(db) => Box::pin(db.list_prefix(prefix).await),
-            StorageBackend::S3(s3) => Box::pin(s3.list_prefix(prefix).await),
-        }
+        self.backend.list_prefix(prefix).await
     }
 
     pub(crate) async fn delete_prefix(&self, prefix: &str) -> Result<()> {
-        match &self.backend {
-            StorageBackend::Database(db) => db.delete_prefix(prefix).await,
-            StorageBackend::S3(s3) => s3.delete_prefix(prefix).await,
+        let result = self.backend.delete_prefix(prefix).await;
+        // after deleting, so the cache can't be filled again with the deleted objects.
+        if let Some(cache) = &self.cache {
+            cache.invalidate_prefix(prefix).await;
+        }
+        result?;
+
+        // the content blobs of deleted archives, they only exist when they're configured.
+        if self.config.content_addressed_min_size.is_some() {
+            self.release_content_blobs(prefix).await?;
         }
+        Ok(())
     }
 
     // We're using `&self` instead of consuming `self` or creating a Drop impl because during tests
@@ -995,8 +1329,11 @@ impl AsyncStorage {
                     let mut decompressed = Vec::new();
                     {
                         // old async-compression can read the broken zstd stream
-                        let mut reader =
-                            wrap_reader_for_decompression(compressed_blob.content.as_slice(), alg);
+                        let mut reader = wrap_reader_for_decompression(
+                            compressed_blob.content.as_slice(),
+                            alg,
+                            None,
+                        )?;
 
                         tokio::io::copy(&mut reader, &mut decompressed).await?;
                     }
@@ -1027,6 +1364,7 @@ impl std::fmt::Debug for AsyncStorage {
         match &self.backend {
             StorageBackend::Database(_) => write!(f, "database-backed storage"),
             StorageBackend::S3(_) => write!(f, "S3-backed storage"),
+            StorageBackend::Filesystem(_) => write!(f, "filesystem-backed storage"),
         }
     }
 }
@@ -1106,6 +1444,11 @@ impl Storage {
         self.runtime.block_on(self.inner.get(path, max_size))
     }
 
+    pub(crate) fn get_full_archive(&self, archive_path: &str) -> Result<Blob> {
+        self.runtime
+            .block_on(self.inner.get_full_archive(archive_path))
+    }
+
     pub(super) fn get_range(
         &self,
         path: &str,
@@ -1268,6 +1611,11 @@ pub(crate) fn rustdoc_json_path(
     path
 }
 
+/// Per-item documentation coverage of a release, see `CoverageReport`.
+pub(crate) fn coverage_report_path(name: &str, version: &Version) -> String {
+    format!("coverage/{name}/{version}/report.json")
+}
+
 pub(crate) fn source_archive_path(name: &str, version: &Version) -> String {
     format!("sources/{name}/{version}.zip")
 }
@@ -1311,7 +1659,11 @@ mod test {
         // with decompression, does nothing
         {
             let stream = streaming_blob(CONTENT, None);
-            let blob = stream.decompress().await?.materialize(usize::MAX).await?;
+            let blob = stream
+                .decompress(None)
+                .await?
+                .materialize(usize::MAX)
+                .await?;
             assert_eq!(blob.content, CONTENT);
             assert!(blob.compression.is_none());
         }
@@ -1338,7 +1690,7 @@ mod test {
         // not later when materializing / streaming.
         {
             let err = streaming_blob(NOT_ZSTD, Some(alg))
-                .decompress()
+                .decompress(None)
                 .await
                 .unwrap_err();
 
@@ -1379,7 +1731,7 @@ mod test {
         // with decompression
         {
             let blob = streaming_blob(compressed_content.clone(), Some(alg))
-                .decompress()
+                .decompress(None)
                 .await?
                 .materialize(usize::MAX)
                 .await?;
@@ -1394,6 +1746,7 @@ mod test {
     #[test_case(CompressionAlgorithm::Zstd)]
     #[test_case(CompressionAlgorithm::Bzip2)]
     #[test_case(CompressionAlgorithm::Gzip)]
+    #[test_case(CompressionAlgorithm::Brotli)]
     async fn test_async_compression(alg: CompressionAlgorithm) -> Result<()> {
         const CONTENT: &[u8] = b"Hello, world! Hello, world! Hello, world! Hello, world!";
 
@@ -1409,7 +1762,8 @@ mod test {
             let mut reader = wrap_reader_for_decompression(
                 io::Cursor::new(compressed_index_content.clone()),
                 alg,
-            );
+                None,
+            )?;
 
             tokio::io::copy(&mut reader, &mut io::Cursor::new(&mut decompressed_buf)).await?;
 
@@ -1436,7 +1790,7 @@ mod test {
             content_length: compressed_index_content.len(),
             content: Box::new(io::Cursor::new(compressed_index_content)),
         }
-        .decompress()
+        .decompress(None)
         .await?
         .materialize(usize::MAX)
         .await?;
@@ -1490,6 +1844,358 @@ mod test {
         assert_eq!(detected_mime, expected_mime);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_content_addressed_archives() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .content_addressed_min_size(Some(16))
+                .build()?,
+        )
+        .await?;
+        let storage = env.async_storage();
+
+        const SHARED: &str = "shared between the releases";
+        let shared_hash = {
+            let dir = tempfile::tempdir()?;
+            std::fs::write(dir.path().join("shared"), SHARED)?;
+            hash_file(&dir.path().join("shared"))?
+        };
+
+        let store = |archive_path: &'static str, unique: &'static str| async move {
+            let dir = tempfile::tempdir()?;
+            std::fs::create_dir(dir.path().join("static"))?;
+            std::fs::write(dir.path().join("static/main.js"), SHARED)?;
+            std::fs::write(dir.path().join("small.html"), unique)?;
+            let (files, _) = storage
+                .store_all_in_archive(archive_path, dir.path())
+                .await?;
+            assert_eq!(files.len(), 2);
+            Ok::<_, anyhow::Error>(())
+        };
+        store("a.zip", "a").await?;
+        store("b.zip", "b").await?;
+
+        for archive in ["a.zip", "b.zip"] {
+            let blob = storage
+                .get_from_archive(archive, None, "static/main.js", usize::MAX)
+                .await?;
+            assert_eq!(blob.content, SHARED.as_bytes());
+            assert_eq!(blob.mime, "text/javascript");
+            assert!(storage.archive_has_content_blobs(archive).await?);
+        }
+        assert_eq!(
+            storage
+                .get_from_archive("b.zip", None, "small.html", usize::MAX)
+                .await?
+                .content,
+            b"b"
+        );
+
+        // the shared file is stored once, outside of the archives.
+        let report = storage.content_blob_report().await?;
+        assert_eq!(report.blobs, 1);
+        assert_eq!(report.files, 2);
+        assert_eq!(report.saved_bytes, report.stored_bytes);
+        assert!(storage.exists(&content_blob_path(&shared_hash)).await?);
+
+        // the full archive contains all files again.
+        let full = storage.get_full_archive("a.zip").await?;
+        let mut zip = zip::ZipArchive::new(io::Cursor::new(full.content))?;
+        let mut names: Vec<_> = zip.file_names().map(ToOwned::to_owned).collect();
+        names.sort();
+        assert_eq!(names, ["small.html", "static/main.js"]);
+        let mut content = String::new();
+        io::Read::read_to_string(&mut zip.by_name("static/main.js")?, &mut content)?;
+        assert_eq!(content, SHARED);
+
+        // the blob is deleted with the last archive referencing it.
+        storage.delete_prefix("a.zip").await?;
+        assert!(storage.exists(&content_blob_path(&shared_hash)).await?);
+        assert_eq!(
+            storage
+                .get_from_archive("b.zip", None, "static/main.js", usize::MAX)
+                .await?
+                .content,
+            SHARED.as_bytes()
+        );
+        storage.delete_prefix("b.zip").await?;
+        assert!(!storage.exists(&content_blob_path(&shared_hash)).await?);
+        assert_eq!(storage.content_blob_report().await?.blobs, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_interrupted_content_blob_deletion_is_finished() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .content_addressed_min_size(Some(1))
+                .build()?,
+        )
+        .await?;
+        let storage = env.async_storage();
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("file.txt"), "content")?;
+        let hash = hash_file(&dir.path().join("file.txt"))?;
+        storage.store_all_in_archive("a.zip", dir.path()).await?;
+
+        // the garbage collection marked the blob, deleted the object, and then died.
+        let mut conn = env.async_db().async_conn().await;
+        sqlx::query!("DELETE FROM content_blob_references")
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query!(
+            "UPDATE content_blobs SET deleting_since = NOW() - INTERVAL '1 hour' WHERE hash = $1",
+            hash,
+        )
+        .execute(&mut *conn)
+        .await?;
+        storage.delete_prefix(&content_blob_path(&hash)).await?;
+
+        storage.store_all_in_archive("b.zip", dir.path()).await?;
+        assert_eq!(
+            storage
+                .get_from_archive("b.zip", None, "file.txt", usize::MAX)
+                .await?
+                .content,
+            b"content"
+        );
+        let deleting_since = sqlx::query_scalar!(
+            "SELECT deleting_since FROM content_blobs WHERE hash = $1",
+            hash
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+        assert!(deleting_since.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replaced_archive_releases_content_blobs() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .content_addressed_min_size(Some(1))
+                .build()?,
+        )
+        .await?;
+        let storage = env.async_storage();
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("file.txt"), "old content")?;
+        let old_hash = hash_file(&dir.path().join("file.txt"))?;
+        storage.store_all_in_archive("test.zip", dir.path()).await?;
+
+        std::fs::write(dir.path().join("file.txt"), "new content")?;
+        storage.store_all_in_archive("test.zip", dir.path()).await?;
+
+        assert!(!storage.exists(&content_blob_path(&old_hash)).await?);
+        assert_eq!(
+            storage
+                .get_from_archive("test.zip", Some(BuildId(2)), "file.txt", usize::MAX)
+                .await?
+                .content,
+            b"new content"
+        );
+        assert_eq!(storage.content_blob_report().await?.blobs, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_delete_prefix_matches_content_blob_references_literally() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .content_addressed_min_size(Some(1))
+                .build()?,
+        )
+        .await?;
+        let storage = env.async_storage();
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("file.txt"), "content")?;
+        let hash = hash_file(&dir.path().join("file.txt"))?;
+        storage
+            .store_all_in_archive("foo-bar.zip", dir.path())
+            .await?;
+
+        // `_` would match any character in a `LIKE` pattern.
+        storage.delete_prefix("foo_bar").await?;
+        assert!(storage.exists(&content_blob_path(&hash)).await?);
+        assert_eq!(storage.content_blob_report().await?.blobs, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_archive_compression() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .archive_compression(CompressionAlgorithm::Brotli)
+                .build()?,
+        )
+        .await?;
+        let storage = env.async_storage();
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("index.html"), "the index")?;
+        let (_, alg) = storage.store_all_in_archive("test.zip", dir.path()).await?;
+        assert_eq!(alg, CompressionAlgorithm::Brotli);
+
+        let blob = storage
+            .get_from_archive("test.zip", None, "index.html", usize::MAX)
+            .await?;
+        assert_eq!(blob.content, b"the index");
+        assert_eq!(
+            storage.full_archive_path("test.zip").await?,
+            "test.zip.full.zip"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compression_dictionary() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .compression_dictionary(true)
+                .build()?,
+        )
+        .await?;
+        let storage = env.async_storage();
+
+        let pages: Vec<(String, String)> = (0..100)
+            .map(|i| {
+                (
+                    format!("struct.Item{i}.html"),
+                    format!(
+                        r#"<!DOCTYPE html><html lang="en"><head><meta charset="utf-8"><title>Item{i} in dummy - Rust</title></head><body class="rustdoc struct"><nav class="sidebar"><a href="index.html">dummy</a></nav><main><h1>Struct <span class="struct">Item{i}</span></h1><div class="docblock"><p>Item number {}.</p></div></main></body></html>"#,
+                        i * 7919 % 1000
+                    ),
+                )
+            })
+            .collect();
+        let mut release = env
+            .fake_release()
+            .await
+            .name("dummy")
+            .version("0.1.0")
+            .archive_storage(true);
+        for (path, content) in &pages {
+            release = release.rustdoc_file_with(path, content.as_bytes());
+        }
+        release.create().await?;
+
+        // without a dictionary, uploads use plain zstd.
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("page.html"), &pages[0].1)?;
+        let (_, alg) = storage.store_all(Path::new("before"), dir.path()).await?;
+        assert_eq!(alg, CompressionAlgorithm::Zstd);
+
+        let trained = storage.train_compression_dictionary(10, 4096).await?;
+        assert_eq!(trained.samples, 50);
+
+        // the first dictionary is picked up without a restart.
+        let (_, alg) = storage.store_all(Path::new("after"), dir.path()).await?;
+        assert_eq!(alg, CompressionAlgorithm::ZstdDictionary);
+
+        let mut raw = storage.get_raw_stream("after/page.html", None).await?;
+        assert_eq!(raw.compression, Some(CompressionAlgorithm::ZstdDictionary));
+        assert_eq!(
+            compression::dictionary_id_from_frame(raw.content.fill_buf().await?),
+            Some(trained.id)
+        );
+
+        let blob = storage.get("after/page.html", usize::MAX).await?;
+        assert_eq!(blob.content, pages[0].1.as_bytes());
+        assert_eq!(blob.compression, None);
+        assert_eq!(
+            storage.get("before/page.html", usize::MAX).await?.content,
+            pages[0].1.as_bytes()
+        );
+
+        // files in archives use the dictionary too, downloads use a full archive with bzip2.
+        let (_, alg) = storage
+            .store_all_in_archive("after.zip", dir.path())
+            .await?;
+        assert_eq!(alg, CompressionAlgorithm::ZstdDictionary);
+        assert_eq!(
+            storage
+                .get_from_archive("after.zip", None, "page.html", usize::MAX)
+                .await?
+                .content,
+            pages[0].1.as_bytes()
+        );
+        assert_eq!(
+            storage.full_archive_path("after.zip").await?,
+            "after.zip.full.zip"
+        );
+        let full = storage.get_full_archive("after.zip").await?;
+        let mut zip = zip::ZipArchive::new(io::Cursor::new(full.content))?;
+        let mut page = String::new();
+        io::Read::read_to_string(&mut zip.by_name("page.html")?, &mut page)?;
+        assert_eq!(page, pages[0].1);
+
+        // later dictionaries are only picked up by a new storage.
+        let newer = storage.train_compression_dictionary(10, 2048).await?;
+        assert_ne!(newer.id, trained.id);
+        assert_eq!(
+            storage
+                .upload_dictionary()
+                .await?
+                .map(|dictionary| dictionary.id()),
+            Some(trained.id)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cached_archive_ranges() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .storage_cache_size(Some(1024 * 1024))
+                .build()?,
+        )
+        .await?;
+        let storage = env.async_storage();
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("file.txt"), "content")?;
+        storage.store_all_in_archive("test.zip", dir.path()).await?;
+
+        const LATEST_BUILD_ID: Option<BuildId> = Some(BuildId(42));
+        let get = || storage.get_from_archive("test.zip", LATEST_BUILD_ID, "file.txt", usize::MAX);
+        assert_eq!(get().await?.content, b"content");
+
+        // remove the archive behind the back of the cache, the range is still cached.
+        let StorageBackend::Database(db) = &storage.backend else {
+            unreachable!("the tests use the database backend");
+        };
+        db.delete_prefix("test.zip").await?;
+        assert_eq!(get().await?.content, b"content");
+
+        // but not for another build of the release.
+        assert!(
+            storage
+                .get_from_archive("test.zip", Some(BuildId(43)), "file.txt", usize::MAX)
+                .await
+                .is_err()
+        );
+
+        storage.delete_prefix("test.zip").await?;
+        assert!(
+            get()
+                .await
+                .unwrap_err()
+                .downcast_ref::<PathNotFoundError>()
+                .is_some()
+        );
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_outdated_local_archive_index_gets_redownloaded() -> Result<()> {
         use tokio::fs;
@@ -2103,6 +2809,41 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_store_stream(storage: &Storage) -> Result<()> {
+        let backend = &storage.inner.backend;
+        let path = "stream/file.js";
+        storage.runtime.block_on(async {
+            assert!(
+                backend
+                    .size(path)
+                    .await
+                    .unwrap_err()
+                    .is::<PathNotFoundError>()
+            );
+
+            let mut content: &[u8] = b"compressed content";
+            backend
+                .store_stream(
+                    path,
+                    &mime::TEXT_JAVASCRIPT,
+                    Some(CompressionAlgorithm::Zstd),
+                    &mut content,
+                )
+                .await?;
+            assert_eq!(backend.size(path).await?, 18);
+
+            let blob = backend
+                .get_stream(path, None)
+                .await?
+                .materialize(usize::MAX)
+                .await?;
+            assert_eq!(blob.content, b"compressed content");
+            assert_eq!(blob.mime, mime::TEXT_JAVASCRIPT);
+            assert_eq!(blob.compression, Some(CompressionAlgorithm::Zstd));
+            Ok(())
+        })
+    }
+
     // Remember to add the test name to the macro below when adding a new one.
 
     macro_rules! backend_tests {
@@ -2153,6 +2894,7 @@ mod backend_tests {
         backends {
             s3 => StorageKind::S3,
             database => StorageKind::Database,
+            filesystem => StorageKind::Filesystem,
         }
 
         tests {
@@ -2168,6 +2910,7 @@ mod backend_tests {
             test_delete_percent,
             test_exists_without_remote_archive,
             test_set_public,
+            test_store_stream,
         }
 
         tests_with_metrics {
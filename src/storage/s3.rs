@@ -1,5 +1,5 @@
-use super::{BlobUpload, FileRange, StorageMetrics, StreamingBlob};
-use crate::{Config, InstanceMetrics};
+use super::{BlobUpload, CompressionAlgorithm, FileRange, StorageMetrics, StreamingBlob};
+use crate::{Config, InstanceMetrics, utils::spawn_blocking};
 use anyhow::{Context as _, Error};
 use async_stream::try_stream;
 use aws_config::BehaviorVersion;
@@ -7,6 +7,7 @@ use aws_sdk_s3::{
     Client,
     config::{Region, retry::RetryConfig},
     error::{ProvideErrorMetadata, SdkError},
+    primitives::ByteStream,
     types::{Delete, ObjectIdentifier, Tag, Tagging},
 };
 use aws_smithy_types_convert::date_time::DateTimeExt;
@@ -16,7 +17,9 @@ use futures_util::{
     pin_mut,
     stream::{FuturesUnordered, Stream, StreamExt},
 };
+use mime::Mime;
 use std::sync::Arc;
+use tokio::io::AsyncRead;
 use tracing::{error, warn};
 
 const PUBLIC_ACCESS_TAG: &str = "static-cloudfront-access";
@@ -222,6 +225,48 @@ impl S3Backend {
         })
     }
 
+    pub(super) async fn size(&self, path: &str) -> Result<u64, Error> {
+        let head = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(path)
+            .send()
+            .await
+            .convert_errors()?;
+        Ok(head.content_length.unwrap_or(0).try_into()?)
+    }
+
+    /// The content is spooled to a temporary file first, since S3 needs its length before the
+    /// upload, and the SDK re-reads the body when it retries.
+    pub(super) async fn store_stream(
+        &self,
+        path: &str,
+        mime: &Mime,
+        compression: Option<CompressionAlgorithm>,
+        content: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<(), Error> {
+        let (file, temp_path) = spawn_blocking(|| Ok(tempfile::NamedTempFile::new()?))
+            .await?
+            .into_parts();
+        let mut file = tokio::fs::File::from_std(file);
+        tokio::io::copy(content, &mut file).await?;
+        drop(file);
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(path)
+            .body(ByteStream::from_path(&temp_path).await?)
+            .content_type(mime.to_string())
+            .set_content_encoding(compression.map(|alg| alg.to_string()))
+            .send()
+            .await?;
+        self.metrics.uploaded_files_total.inc();
+        self.otel_metrics.uploaded_files.add(1, &[]);
+        Ok(())
+    }
+
     pub(super) async fn store_batch(&self, mut batch: Vec<BlobUpload>) -> Result<(), Error> {
         // Attempt to upload the batch 3 times
         for _ in 0..3 {
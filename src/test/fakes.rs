@@ -4,7 +4,7 @@ use crate::{
         BuildId, ReleaseId,
         file::{FileEntry, file_list_to_json},
         initialize_build, initialize_crate, initialize_release,
-        types::{BuildStatus, version::Version},
+        types::{BuildFailureKind, BuildStatus, version::Version},
         update_build_status,
     },
     docbuilder::{DocCoverage, RUSTDOC_JSON_COMPRESSION_ALGORITHMS},
@@ -576,6 +576,9 @@ impl<'a> FakeRelease<'a> {
             self.has_docs,
             self.has_examples,
             iter::once(algs),
+            storage
+                .upload_dictionary_id(&iter::once(algs).collect())
+                .await?,
             repository,
             archive_storage,
             24,
@@ -702,6 +705,7 @@ impl FakeBuild {
             &self.rustc_version,
             &self.docsrs_version,
             self.build_status,
+            (self.build_status == BuildStatus::Failure).then_some(BuildFailureKind::Rustdoc),
             Some(42),
             None,
         )
@@ -417,6 +417,9 @@ impl TestEnvironment {
             .local_archive_cache_path(
                 std::env::temp_dir().join(format!("docsrs-test-index-{}", rand::random::<u64>())),
             )
+            .filesystem_storage_path(
+                std::env::temp_dir().join(format!("docsrs-test-storage-{}", rand::random::<u64>())),
+            )
             // set stale content serving so Cache::ForeverInCdn and Cache::ForeverInCdnAndStaleInBrowser
             // are actually different.
             .cache_control_stale_while_revalidate(Some(86400))
@@ -501,6 +504,10 @@ impl Drop for TestEnvironment {
         if self.context.config.local_archive_cache_path.exists() {
             fs::remove_dir_all(&self.context.config.local_archive_cache_path).unwrap();
         }
+
+        if self.context.config.filesystem_storage_path.exists() {
+            fs::remove_dir_all(&self.context.config.filesystem_storage_path).unwrap();
+        }
     }
 }
 
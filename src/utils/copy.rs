@@ -1,6 +1,7 @@
 use std::fs;
 use std::io;
 use std::path::Path;
+use walkdir::WalkDir;
 
 /// cp -r src dst
 pub(crate) fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::Result<()> {
@@ -18,6 +19,18 @@ pub(crate) fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> io::
     Ok(())
 }
 
+/// du -sb path
+pub(crate) fn dir_size(path: impl AsRef<Path>) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in WalkDir::new(path) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
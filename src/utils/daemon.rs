@@ -93,7 +93,7 @@ pub fn start_background_queue_rebuild(context: &Context) -> Result<(), Error> {
     let config = context.config.clone();
     let build_queue = context.async_build_queue.clone();
 
-    if config.max_queued_rebuilds.is_none() {
+    if config.max_queued_rebuilds.is_none() && config.max_queued_failed_rebuilds.is_none() {
         info!("rebuild config incomplete, skipping rebuild queueing");
         return Ok(());
     }
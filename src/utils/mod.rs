@@ -2,7 +2,7 @@
 
 pub(crate) use self::{
     cargo_metadata::{CargoMetadata, Dependency, Package as MetadataPackage},
-    copy::copy_dir_all,
+    copy::{copy_dir_all, dir_size},
     html::rewrite_rustdoc_html_stream,
     rustc_version::{get_correct_docsrs_style_file, parse_rustc_version},
 };
@@ -47,6 +47,11 @@ pub enum ConfigName {
     LastSeenIndexReference,
     QueueLocked,
     Toolchain,
+    /// The toolchain builds use. With the toolchain canary, `Toolchain` is only used after it
+    /// passed its canary.
+    ActiveToolchain,
+    /// `rustup target list` of the build toolchain, to validate the targets in metadata.
+    AvailableTargets,
 }
 
 pub async fn set_config(
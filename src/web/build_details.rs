@@ -1,11 +1,16 @@
 use crate::{
     AsyncStorage, Config,
-    db::{BuildId, types::BuildStatus},
+    db::{BuildId, Pool, types::BuildStatus},
+    docbuilder::live_log_chunk_path,
+    error::Result,
     impl_axum_webpage,
+    storage::PathNotFoundError,
+    utils::report_error,
     web::{
         MetaData,
         cache::CachePolicy,
         error::{AxumNope, AxumResult},
+        escaped_uri::EscapedURI,
         extractors::{DbConnection, Path, rustdoc::RustdocParams},
         file::File,
         filters, match_version,
@@ -14,11 +19,25 @@ use crate::{
 };
 use anyhow::Context as _;
 use askama::Template;
-use axum::{extract::Extension, response::IntoResponse};
+use async_stream::stream;
+use axum::{
+    extract::Extension,
+    http::{HeaderMap, header::CACHE_CONTROL},
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
 use chrono::{DateTime, Utc};
-use futures_util::TryStreamExt;
+use futures_util::{Stream, TryStreamExt, future};
 use serde::Deserialize;
-use std::sync::Arc;
+use sqlx::types::Json;
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct BuildDetails {
@@ -31,6 +50,20 @@ pub(crate) struct BuildDetails {
     errors: Option<String>,
 }
 
+/// Resources the sandbox used for one target of a build, or for the whole build.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ResourceUsageRow {
+    /// `None` for the totals of the build.
+    target: Option<String>,
+    /// in seconds.
+    build_time: Option<f32>,
+    peak_memory: Option<i64>,
+    /// in seconds.
+    cpu_time: Option<f32>,
+    disk_usage: Option<i64>,
+    network_bytes: Option<i64>,
+}
+
 #[derive(Template)]
 #[template(path = "crate/build_details.html")]
 #[derive(Debug, Clone, PartialEq)]
@@ -39,6 +72,14 @@ struct BuildDetailsPage {
     build_details: BuildDetails,
     all_log_filenames: Vec<String>,
     current_filename: Option<String>,
+    /// Server-Sent Events with the log of the current file, while the build is running.
+    live_log_url: Option<EscapedURI>,
+    /// usage per target followed by the totals, empty for builds without recorded targets.
+    resource_usage: Vec<ResourceUsageRow>,
+    /// The `cfg`s the build used, empty for builds that didn't record them.
+    cfgs: Vec<String>,
+    /// The environment variables of every target, empty for builds that didn't record them.
+    env: BTreeMap<String, BTreeMap<String, String>>,
     params: RustdocParams,
 }
 
@@ -92,6 +133,13 @@ pub(crate) async fn build_details_handler(
              COALESCE(builds.build_finished, builds.build_started) as build_time,
              builds.output,
              builds.errors,
+             EXTRACT(EPOCH FROM builds.build_finished - builds.build_started)::REAL AS build_duration,
+             builds.peak_memory,
+             builds.cpu_time,
+             builds.disk_usage,
+             builds.network_bytes,
+             builds.env as "env: Json<BTreeMap<String, BTreeMap<String, String>>>",
+             builds.cfgs,
              releases.default_target
          FROM builds
          INNER JOIN releases ON releases.id = builds.rid
@@ -114,16 +162,28 @@ pub(crate) async fn build_details_handler(
         // toFor a long time only for one target, then we started storing the logs for other
         // targets. In any case, all the logfiles are put into a folder we can just query.
         let prefix = format!("build-logs/{id}/");
-        let all_log_filenames: Vec<_> = storage
-            .list_prefix(&prefix) // the result from S3 is ordered by key
+        let in_progress = row.build_status == BuildStatus::InProgress;
+        let mut all_log_filenames: Vec<_> = storage
+            .list_prefix(&prefix)
             .await
-            .map_ok(|path| {
-                path.strip_prefix(&prefix)
-                    .expect("since we query for the prefix, it has to be always there")
-                    .to_owned()
+            .try_filter_map(|path| {
+                let filename = path
+                    .strip_prefix(&prefix)
+                    .expect("since we query for the prefix, it has to be always there");
+                // a running target only has the chunks of its live log.
+                let filename = match filename.strip_prefix("live/") {
+                    Some(chunk) => chunk
+                        .split_once('/')
+                        .filter(|_| in_progress)
+                        .map(|(filename, _)| filename),
+                    None => Some(filename),
+                };
+                future::ready(Ok(filename.map(str::to_owned)))
             })
             .try_collect()
             .await?;
+        all_log_filenames.sort();
+        all_log_filenames.dedup();
 
         let current_filename = if let Some(filename) = build_params.filename {
             // if we have a given filename in the URL, we use that one.
@@ -137,6 +197,10 @@ pub(crate) async fn build_details_handler(
             } else {
                 None
             }
+        } else if row.build_status == BuildStatus::InProgress {
+            // a running build only has the logs of the targets it started to build,
+            // the default target is always built first.
+            all_log_filenames.first().cloned()
         } else {
             // this can only happen when `releases.default_target` is NULL,
             // which is the case for in-progress builds or builds which errored
@@ -146,8 +210,14 @@ pub(crate) async fn build_details_handler(
         };
 
         let file_content = if let Some(ref filename) = current_filename {
-            let file = File::from_path(&storage, &format!("{prefix}{filename}"), &config).await?;
-            String::from_utf8(file.0.content).context("non utf8")?
+            let path = format!("{prefix}{filename}");
+            if in_progress && !storage.exists(&path).await? {
+                // the live log is sent by `build_log_live_handler`.
+                String::new()
+            } else {
+                let file = File::from_path(&storage, &path, &config).await?;
+                String::from_utf8(file.0.content).context("non utf8")?
+            }
         } else {
             "".to_string()
         };
@@ -155,6 +225,41 @@ pub(crate) async fn build_details_handler(
         (file_content, all_log_filenames, current_filename)
     };
 
+    let mut resource_usage: Vec<_> = sqlx::query!(
+        r#"SELECT
+             target,
+             EXTRACT(EPOCH FROM build_finished - build_started)::REAL AS "build_time!",
+             peak_memory,
+             cpu_time,
+             disk_usage,
+             network_bytes
+         FROM build_targets
+         WHERE build_id = $1
+         ORDER BY build_started"#,
+        id.0,
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| ResourceUsageRow {
+        target: Some(row.target),
+        build_time: Some(row.build_time),
+        peak_memory: row.peak_memory,
+        cpu_time: row.cpu_time,
+        disk_usage: row.disk_usage,
+        network_bytes: row.network_bytes,
+    })
+    .try_collect()
+    .await?;
+    if !resource_usage.is_empty() {
+        resource_usage.push(ResourceUsageRow {
+            target: None,
+            build_time: row.build_duration,
+            peak_memory: row.peak_memory,
+            cpu_time: row.cpu_time,
+            disk_usage: row.disk_usage,
+            network_bytes: row.network_bytes,
+        });
+    }
+
     let metadata = MetaData::from_crate(
         &mut conn,
         params.name(),
@@ -164,6 +269,11 @@ pub(crate) async fn build_details_handler(
     .await?;
     let params = params.apply_metadata(&metadata);
 
+    let live_log_url = current_filename
+        .as_deref()
+        .filter(|_| row.build_status == BuildStatus::InProgress)
+        .map(|filename| params.build_log_live_url(id, filename));
+
     Ok(BuildDetailsPage {
         metadata,
         build_details: BuildDetails {
@@ -177,22 +287,232 @@ pub(crate) async fn build_details_handler(
         },
         all_log_filenames,
         current_filename,
+        live_log_url,
+        resource_usage,
+        cfgs: row.cfgs.unwrap_or_default(),
+        env: row.env.map(|env| env.0).unwrap_or_default(),
         params,
     }
     .into_response())
 }
 
+/// How long a live log may stay unchanged before we check whether its build ended without
+/// storing its complete log.
+const BUILD_STATUS_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Follow a build log with Server-Sent Events.
+///
+/// Every event has the length of the log the client has as its id, a reconnecting client
+/// sends it back in `Last-Event-ID`, and only gets the lines after it. Without it, the first
+/// `reset` event contains the log so far. `log` events contain the lines that were added
+/// since. A `finished` event with the build status is sent once the complete log is stored.
+/// Streams of builds that don't finish end after `build_log_live_max_duration`.
+pub(crate) async fn build_log_live_handler(
+    params: RustdocParams,
+    Path(build_params): Path<BuildDetailsParams>,
+    mut conn: DbConnection,
+    headers: HeaderMap,
+    Extension(pool): Extension<Pool>,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+) -> AxumResult<impl IntoResponse> {
+    let id = build_params
+        .id
+        .parse()
+        .map(BuildId)
+        .map_err(|_| AxumNope::BuildNotFound)?;
+    let filename = build_params.filename.ok_or(AxumNope::BuildNotFound)?;
+    let offset = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse().ok());
+
+    let version = match_version(&mut conn, params.name(), params.req_version())
+        .await?
+        .assume_exact_name()?
+        .into_version();
+
+    sqlx::query_scalar!(
+        "SELECT builds.id
+         FROM builds
+         INNER JOIN releases ON releases.id = builds.rid
+         INNER JOIN crates ON releases.crate_id = crates.id
+         WHERE builds.id = $1 AND crates.name = $2 AND releases.version = $3",
+        id.0,
+        params.name(),
+        version as _
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AxumNope::BuildNotFound)?;
+
+    let events = build_log_events(pool, storage, id, filename, offset, config);
+
+    let mut response = Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response();
+    // `Sse` sets its own caching header, but we control caching through `CachePolicy`.
+    response.headers_mut().remove(CACHE_CONTROL);
+    response.extensions_mut().insert(CachePolicy::NoCaching);
+    Ok(response)
+}
+
+async fn get_log(storage: &AsyncStorage, path: &str, max_size: usize) -> Result<Option<String>> {
+    match storage.get(path, max_size).await {
+        Ok(blob) => Ok(Some(String::from_utf8(blob.content)?)),
+        Err(err) if err.is::<PathNotFoundError>() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+async fn build_status(pool: &Pool, id: BuildId) -> Result<BuildStatus> {
+    let mut conn = pool.get_async().await?;
+    Ok(sqlx::query_scalar!(
+        r#"SELECT build_status as "build_status: BuildStatus" FROM builds WHERE id = $1"#,
+        id.0
+    )
+    .fetch_one(&mut *conn)
+    .await?)
+}
+
+/// What changed in a followed build log since the last poll.
+enum LogUpdate {
+    Unchanged,
+    /// the lines from the live log chunks, and the length of the log with them.
+    Lines(String, usize),
+    /// the complete log was stored, or the build ended without it.
+    Finished(Option<String>, BuildStatus),
+}
+
+fn build_log_events(
+    pool: Pool,
+    storage: Arc<AsyncStorage>,
+    id: BuildId,
+    filename: String,
+    offset: Option<usize>,
+    config: Arc<Config>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    async fn poll(
+        pool: &Pool,
+        storage: &AsyncStorage,
+        id: BuildId,
+        filename: &str,
+        offset: usize,
+        max_size: usize,
+        check_status: bool,
+    ) -> Result<LogUpdate> {
+        // every chunk is named after its offset, so the next one starts where the client is.
+        let mut lines = String::new();
+        let mut len = offset;
+        while let Some(chunk) =
+            get_log(storage, &live_log_chunk_path(id, filename, len), max_size).await?
+        {
+            len += chunk.len();
+            lines.push_str(&chunk);
+        }
+        if !lines.is_empty() {
+            return Ok(LogUpdate::Lines(lines, len));
+        }
+
+        let path = format!("build-logs/{id}/{filename}");
+        if let Some(log) = get_log(storage, &path, max_size).await? {
+            // the complete logs are only stored when the build is finished.
+            return Ok(LogUpdate::Finished(
+                Some(log),
+                build_status(pool, id).await?,
+            ));
+        }
+        if check_status {
+            let status = build_status(pool, id).await?;
+            if status != BuildStatus::InProgress {
+                return Ok(LogUpdate::Finished(None, status));
+            }
+        }
+        Ok(LogUpdate::Unchanged)
+    }
+
+    stream! {
+        let started = Instant::now();
+        let mut sent = offset;
+        let mut changed = Instant::now();
+        // the stream also ends when the build never finishes, the client reconnects after it.
+        while started.elapsed() < config.build_log_live_max_duration {
+            let check_status = changed.elapsed() >= BUILD_STATUS_CHECK_INTERVAL;
+            if check_status {
+                changed = Instant::now();
+            }
+            let update = poll(
+                &pool,
+                &storage,
+                id,
+                &filename,
+                sent.unwrap_or(0),
+                config.max_file_size,
+                check_status,
+            )
+            .await;
+            match update {
+                Ok(LogUpdate::Unchanged) => {}
+                Ok(LogUpdate::Lines(lines, len)) => {
+                    let event = if sent.is_some() { "log" } else { "reset" };
+                    yield Ok(Event::default().event(event).data(lines).id(len.to_string()));
+                    sent = Some(len);
+                    changed = Instant::now();
+                }
+                Ok(LogUpdate::Finished(log, status)) => {
+                    if let Some(log) = log {
+                        match sent.and_then(|sent| log.get(sent..)) {
+                            Some("") => {}
+                            Some(new_lines) => {
+                                yield Ok(Event::default()
+                                    .event("log")
+                                    .data(new_lines)
+                                    .id(log.len().to_string()))
+                            }
+                            // the client has no log yet, or one that doesn't match.
+                            None => {
+                                yield Ok(Event::default()
+                                    .event("reset")
+                                    .data(&log)
+                                    .id(log.len().to_string()))
+                            }
+                        }
+                    }
+                    // browsers ignore events without data.
+                    yield Ok(Event::default()
+                        .event("finished")
+                        .json_data(status)
+                        .expect("a build status can always be serialized"));
+                    break;
+                }
+                Err(err) => {
+                    report_error(&err.context("could not follow build log"));
+                    break;
+                }
+            }
+            tokio::time::sleep(config.build_log_live_interval).await;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
-        db::types::{BuildId, ReleaseId},
+        db::{
+            record_build_environment, record_build_target,
+            types::{BuildId, BuildStatus, ReleaseId},
+        },
+        docbuilder::{ResourceUsage, live_log_chunk_path},
         test::{
             AxumResponseTestExt, AxumRouterTestExt, FakeBuild, TestEnvironment, V0_1,
             async_wrapper, fake_release_that_failed_before_build,
         },
     };
+    use axum::{body::Body, http::Request};
     use kuchikiki::traits::TendrilSink;
+    use std::{collections::BTreeMap, time::Duration};
     use test_case::test_case;
+    use tower::ServiceExt as _;
 
     fn get_all_log_links(page: &kuchikiki::NodeRef) -> Vec<(String, String)> {
         page.select("ul > li a.release")
@@ -537,4 +857,346 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resource_usage_per_target() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+
+        let web = env.web_app().await;
+        let url = format!("/crate/foo/0.1.0/builds/{build_id}");
+        let page = kuchikiki::parse_html().one(web.get(&url).await?.text().await?);
+        assert!(page.select_first("table").is_err());
+
+        for (target, usage) in [
+            (
+                "x86_64-unknown-linux-gnu",
+                ResourceUsage {
+                    peak_memory: Some(2_000_000_000),
+                    cpu_time: Some(Duration::from_secs(90)),
+                    disk_usage: Some(500_000_000),
+                    network_bytes: Some(0),
+                },
+            ),
+            (
+                "i686-pc-windows-msvc",
+                ResourceUsage {
+                    peak_memory: Some(1_000_000_000),
+                    cpu_time: Some(Duration::from_secs(30)),
+                    disk_usage: None,
+                    network_bytes: None,
+                },
+            ),
+        ] {
+            record_build_target(
+                &mut conn,
+                build_id,
+                target,
+                BuildStatus::Success,
+                None,
+                chrono::Utc::now(),
+                None,
+                &usage,
+            )
+            .await?;
+        }
+
+        let page = kuchikiki::parse_html().one(web.get(&url).await?.text().await?);
+        let rows: Vec<Vec<String>> = page
+            .select("table tbody tr")
+            .unwrap()
+            .map(|row| {
+                row.as_node()
+                    .select("td")
+                    .unwrap()
+                    .skip(2)
+                    .map(|cell| cell.text_contents())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["2 GB", "1.5 minutes", "500 MB", "0 B"],
+                vec!["1 GB", "30 seconds", "-", "-"],
+                // the most memory of a target, and the CPU time of all targets.
+                vec!["2 GB", "2 minutes", "500 MB", "0 B"],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_environment() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+
+        let web = env.web_app().await;
+        let url = format!("/crate/foo/0.1.0/builds/{build_id}");
+        let page = kuchikiki::parse_html().one(web.get(&url).await?.text().await?);
+        assert!(page.select_first("#build-environment").is_err());
+
+        let variables = BTreeMap::from([
+            ("DOCS_RS".to_owned(), "1".to_owned()),
+            ("FOO".to_owned(), "bar".to_owned()),
+        ]);
+        record_build_environment(
+            &mut conn,
+            build_id,
+            &BTreeMap::from([("x86_64-unknown-linux-gnu".to_owned(), variables)]),
+            &["docsrs".to_owned(), "foo".to_owned()],
+        )
+        .await?;
+
+        let page = kuchikiki::parse_html().one(web.get(&url).await?.text().await?);
+        let rows: Vec<Vec<String>> = page
+            .select("#build-environment tbody tr")
+            .unwrap()
+            .map(|row| {
+                row.as_node()
+                    .select("td")
+                    .unwrap()
+                    .map(|cell| cell.text_contents())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![vec!["x86_64-unknown-linux-gnu", "DOCS_RS=1FOO=bar"]]
+        );
+        assert!(page.text_contents().contains("cfgs: docsrs foo"));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn live_build_log_for_in_progress_build() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .builds(vec![
+                FakeBuild::default()
+                    .build_status(BuildStatus::InProgress)
+                    .s3_build_log("documenting foo"),
+            ])
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+        // another target that is still building only has live log chunks.
+        for offset in [0, 10] {
+            env.async_storage()
+                .store_one(
+                    &live_log_chunk_path(build_id, "aarch64-unknown-linux-gnu.txt", offset),
+                    "documenting bar",
+                )
+                .await?;
+        }
+
+        let page = kuchikiki::parse_html().one(
+            env.web_app()
+                .await
+                .get(&format!("/crate/foo/0.1.0/builds/{build_id}"))
+                .await?
+                .error_for_status()?
+                .text()
+                .await?,
+        );
+
+        let build_url = format!("/crate/foo/0.1.0/builds/{build_id}");
+        assert_eq!(
+            get_all_log_links(&page),
+            vec![
+                (
+                    "aarch64-unknown-linux-gnu.txt".into(),
+                    format!("{build_url}/aarch64-unknown-linux-gnu.txt")
+                ),
+                (
+                    "x86_64-unknown-linux-gnu.txt".into(),
+                    format!("{build_url}/x86_64-unknown-linux-gnu.txt")
+                ),
+            ]
+        );
+
+        let live_log = page.select_first("pre#live-build-log").unwrap();
+        assert_eq!(live_log.text_contents(), "documenting foo");
+        assert_eq!(
+            live_log.attributes.borrow().get("data-live-url"),
+            Some(
+                format!("/crate/foo/0.1.0/builds/{build_id}/x86_64-unknown-linux-gnu.txt/live")
+                    .as_str()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn live_build_log_events() -> anyhow::Result<()> {
+        let env = TestEnvironment::new().await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .builds(vec![FakeBuild::default().s3_build_log("documenting foo")])
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+
+        let web = env.web_app().await;
+        let response = web
+            .get(&format!(
+                "/crate/foo/0.1.0/builds/{build_id}/x86_64-unknown-linux-gnu.txt/live"
+            ))
+            .await?;
+        assert!(response.status().is_success());
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+        assert_eq!(
+            response.text().await?,
+            "event: reset\ndata: documenting foo\nid: 15\n\nevent: finished\ndata: \"success\"\n\n"
+        );
+
+        assert_eq!(
+            web.get(&format!(
+                "/crate/foo/0.1.0/builds/{}/x86_64-unknown-linux-gnu.txt/live",
+                build_id.0 + 1
+            ))
+            .await?
+            .status(),
+            404
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn live_build_log_of_unfinished_build_ends() -> anyhow::Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_log_live_interval(Duration::from_millis(10))
+                .build_log_live_max_duration(Duration::from_millis(100))
+                .build()?,
+        )
+        .await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .builds(vec![
+                FakeBuild::default().build_status(BuildStatus::InProgress),
+            ])
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+
+        // a target without any log yet.
+        let response = env
+            .web_app()
+            .await
+            .get(&format!(
+                "/crate/foo/0.1.0/builds/{build_id}/aarch64-unknown-linux-gnu.txt/live"
+            ))
+            .await?;
+        assert!(response.status().is_success());
+        assert_eq!(response.text().await?, "");
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn live_build_log_events_from_chunks() -> anyhow::Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_log_live_interval(Duration::from_millis(10))
+                .build()?,
+        )
+        .await?;
+        let rid = env
+            .fake_release()
+            .await
+            .name("foo")
+            .version(V0_1)
+            .builds(vec![FakeBuild::default().s3_build_log("documenting foo")])
+            .create()
+            .await?;
+
+        let mut conn = env.async_db().async_conn().await;
+        let build_id = build_ids_for_release(&mut conn, rid).await[0];
+        // the complete log is already stored, so the chunks must be read first.
+        let storage = env.async_storage();
+        for (offset, chunk) in [(0, "documenting "), (12, "foo")] {
+            storage
+                .store_one(
+                    &live_log_chunk_path(build_id, "x86_64-unknown-linux-gnu.txt", offset),
+                    chunk,
+                )
+                .await?;
+        }
+
+        let url = format!("/crate/foo/0.1.0/builds/{build_id}/x86_64-unknown-linux-gnu.txt/live");
+        let events = |last_event_id: Option<&'static str>| {
+            let web = env.web_app();
+            let url = url.clone();
+            async move {
+                let mut request = Request::builder().uri(url);
+                if let Some(id) = last_event_id {
+                    request = request.header("last-event-id", id);
+                }
+                let response = web
+                    .await
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await?;
+                anyhow::Ok(response.text().await?)
+            }
+        };
+
+        assert_eq!(
+            events(None).await?,
+            "event: reset\ndata: documenting foo\nid: 15\n\n\
+             event: finished\ndata: \"success\"\n\n"
+        );
+        // a reconnecting client only gets the lines it doesn't have.
+        assert_eq!(
+            events(Some("12")).await?,
+            "event: log\ndata: foo\nid: 15\n\nevent: finished\ndata: \"success\"\n\n"
+        );
+        assert_eq!(
+            events(Some("15")).await?,
+            "event: finished\ndata: \"success\"\n\n"
+        );
+
+        Ok(())
+    }
 }
@@ -1,7 +1,7 @@
 use crate::{
-    AsyncBuildQueue, Config,
+    AsyncBuildQueue, Config, RegistryApi,
     db::{
-        BuildId,
+        BuildId, LimitRequest, Overrides,
         types::{BuildStatus, version::Version},
     },
     docbuilder::Limits,
@@ -27,7 +27,8 @@ use axum_extra::{
 use chrono::{DateTime, Utc};
 use constant_time_eq::constant_time_eq;
 use http::StatusCode;
-use std::sync::Arc;
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Build {
@@ -143,13 +144,11 @@ async fn build_trigger_check(
 // FUTURE: move to a crate-global enum with all special priorities?
 const TRIGGERED_REBUILD_PRIORITY: i32 = 5;
 
-pub(crate) async fn build_trigger_rebuild_handler(
-    Path((name, version)): Path<(String, Version)>,
-    mut conn: DbConnection,
-    Extension(build_queue): Extension<Arc<AsyncBuildQueue>>,
-    Extension(config): Extension<Arc<Config>>,
+/// Check the token crates.io uses to authenticate with our build endpoints.
+fn check_cratesio_token(
+    config: &Config,
     opt_auth_header: Option<TypedHeader<Authorization<Bearer>>>,
-) -> JsonAxumResult<impl IntoResponse> {
+) -> JsonAxumResult<()> {
     let expected_token =
         config
             .cratesio_token
@@ -168,6 +167,18 @@ pub(crate) async fn build_trigger_rebuild_handler(
         )));
     }
 
+    Ok(())
+}
+
+pub(crate) async fn build_trigger_rebuild_handler(
+    Path((name, version)): Path<(String, Version)>,
+    mut conn: DbConnection,
+    Extension(build_queue): Extension<Arc<AsyncBuildQueue>>,
+    Extension(config): Extension<Arc<Config>>,
+    opt_auth_header: Option<TypedHeader<Authorization<Bearer>>>,
+) -> JsonAxumResult<impl IntoResponse> {
+    check_cratesio_token(&config, opt_auth_header)?;
+
     build_trigger_check(&mut conn, &name, &version, &build_queue)
         .await
         .map_err(JsonAxumNope)?;
@@ -185,6 +196,121 @@ pub(crate) async fn build_trigger_rebuild_handler(
     Ok((StatusCode::CREATED, Json(serde_json::json!({}))))
 }
 
+/// Remove a release from the build queue, or cancel its build when it's already running.
+pub(crate) async fn build_cancel_handler(
+    Path((name, version)): Path<(String, Version)>,
+    Extension(build_queue): Extension<Arc<AsyncBuildQueue>>,
+    Extension(config): Extension<Arc<Config>>,
+    opt_auth_header: Option<TypedHeader<Authorization<Bearer>>>,
+) -> JsonAxumResult<impl IntoResponse> {
+    check_cratesio_token(&config, opt_auth_header)?;
+
+    if build_queue
+        .remove_crate(&name, &version)
+        .await
+        .map_err(|e| JsonAxumNope(e.into()))?
+    {
+        return Ok((StatusCode::OK, Json(serde_json::json!({}))));
+    }
+
+    // the build server stops the build the next time it renews its lease.
+    if build_queue
+        .request_cancellation(&name, &version)
+        .await
+        .map_err(|e| JsonAxumNope(e.into()))?
+    {
+        return Ok((StatusCode::ACCEPTED, Json(serde_json::json!({}))));
+    }
+
+    Err(JsonAxumNope(AxumNope::BadRequest(anyhow!(
+        "crate {name} {version} is neither queued nor being built"
+    ))))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LimitRequestBody {
+    justification: String,
+    memory: Option<usize>,
+    targets: Option<usize>,
+    /// in seconds.
+    timeout: Option<u64>,
+}
+
+/// Store a request for higher sandbox limits, an admin approves or rejects it later.
+///
+/// Crate owners authenticate with a crates.io API token, which is only used to look up
+/// their login.
+pub(crate) async fn limit_request_handler(
+    Path(name): Path<String>,
+    mut conn: DbConnection,
+    Extension(registry_api): Extension<Arc<RegistryApi>>,
+    opt_auth_header: Option<TypedHeader<Authorization<Bearer>>>,
+    Json(body): Json<LimitRequestBody>,
+) -> JsonAxumResult<impl IntoResponse> {
+    let TypedHeader(auth_header) = opt_auth_header.ok_or(JsonAxumNope(AxumNope::Unauthorized(
+        "Missing authentication token",
+    )))?;
+    let requested_by = registry_api
+        .token_login(auth_header.token())
+        .await
+        .map_err(|e| JsonAxumNope(e.into()))?
+        .ok_or(JsonAxumNope(AxumNope::Unauthorized(
+            "The token used for authentication is not valid",
+        )))?;
+
+    let crate_id = sqlx::query_scalar!("SELECT id FROM crates WHERE name = $1", name)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| JsonAxumNope(e.into()))?
+        .ok_or(JsonAxumNope(AxumNope::CrateNotFound))?;
+
+    let is_owner = sqlx::query_scalar!(
+        r#"SELECT EXISTS (
+             SELECT 1
+             FROM owner_rels
+             INNER JOIN owners ON owners.id = owner_rels.oid
+             WHERE owner_rels.cid = $1 AND owners.login = $2
+         ) as "exists!""#,
+        crate_id,
+        requested_by,
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| JsonAxumNope(e.into()))?;
+    if !is_owner {
+        return Err(JsonAxumNope(AxumNope::BadRequest(anyhow!(
+            "{requested_by} is not an owner of {name}"
+        ))));
+    }
+
+    let limits = Overrides {
+        memory: body.memory,
+        targets: body.targets,
+        timeout: body.timeout.map(Duration::from_secs),
+    };
+    if limits == Overrides::default() {
+        return Err(JsonAxumNope(AxumNope::BadRequest(anyhow!(
+            "the request doesn't contain any limits"
+        ))));
+    }
+    if body.justification.trim().is_empty() {
+        return Err(JsonAxumNope(AxumNope::BadRequest(anyhow!(
+            "the request needs a justification"
+        ))));
+    }
+    let Some(id) =
+        LimitRequest::create(&mut conn, &name, &requested_by, &body.justification, limits)
+            .await
+            .map_err(|e| JsonAxumNope(e.into()))?
+    else {
+        return Err(JsonAxumNope(AxumNope::BadRequest(anyhow!(
+            "{name} already has a pending limit request"
+        ))));
+    };
+
+    Ok((StatusCode::CREATED, Json(serde_json::json!({ "id": id }))))
+}
+
 async fn get_builds(
     conn: &mut sqlx::PgConnection,
     name: &str,
@@ -217,7 +343,8 @@ async fn get_builds(
 mod tests {
     use super::BuildStatus;
     use crate::{
-        db::Overrides,
+        db::{LimitRequest, LimitRequestStatus, Overrides},
+        registry_api::{CrateOwner, OwnerKind},
         test::{
             AxumResponseTestExt, AxumRouterTestExt, FakeBuild, TestEnvironment, V1, V2,
             async_wrapper, fake_release_that_failed_before_build,
@@ -463,6 +590,214 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn build_cancel() -> Result<()> {
+        let correct_token = "foo137";
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .cratesio_token(Some(correct_token.into()))
+                .build()?,
+        )
+        .await?;
+
+        let cancel = |name: &str| {
+            Request::builder()
+                .uri(format!("/crate/{name}/{V1}/cancel"))
+                .method("POST")
+                .header("Authorization", &format!("Bearer {correct_token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        {
+            let response = env
+                .web_app()
+                .await
+                .post(&format!("/crate/foo/{V1}/cancel"))
+                .await?;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let build_queue = env.async_build_queue();
+        build_queue.add_crate("foo", &V1, 0, None).await?;
+        build_queue.add_crate("bar", &V1, 0, None).await?;
+        build_queue.claim_next_crate("worker-a").await?;
+
+        // queued builds are removed from the queue
+        let response = env.web_app().await.oneshot(cancel("bar")).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!build_queue.has_build_queued("bar", &V1).await?);
+
+        // running builds are cancelled by the build server
+        let response = env.web_app().await.oneshot(cancel("foo")).await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let mut conn = env.async_db().async_conn().await;
+        assert!(
+            sqlx::query_scalar!("SELECT cancel_requested FROM queue WHERE name = 'foo'")
+                .fetch_one(&mut *conn)
+                .await?
+        );
+
+        let response = env.web_app().await.oneshot(cancel("baz")).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let json: serde_json::Value = response.json().await?;
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "title": "Bad request",
+                "message": format!("crate baz {V1} is neither queued nor being built")
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn limit_request() -> Result<()> {
+        let mut crates_io = mockito::Server::new_async().await;
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .registry_api_host(crates_io.url().parse().unwrap())
+                .build()?,
+        )
+        .await?;
+
+        for (token, login) in [("owner-token", "owner"), ("someone-token", "someone")] {
+            crates_io
+                .mock("GET", "/api/v1/me")
+                .match_header("Authorization", token)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(serde_json::json!({ "user": { "login": login } }).to_string())
+                .create_async()
+                .await;
+        }
+        crates_io
+            .mock("GET", "/api/v1/me")
+            .match_header("Authorization", "invalid-token")
+            .with_status(403)
+            .create_async()
+            .await;
+
+        env.fake_release()
+            .await
+            .name("foo")
+            .version(V1)
+            .add_owner(CrateOwner {
+                login: "owner".into(),
+                avatar: "https://example.org/avatar.png".into(),
+                kind: OwnerKind::User,
+            })
+            .create()
+            .await?;
+
+        let request_with_token = |token: &str, name: &str, body: serde_json::Value| {
+            Request::builder()
+                .uri(format!("/crate/{name}/limit-requests"))
+                .method("POST")
+                .header("Authorization", &format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let request = |name: &str, body| request_with_token("owner-token", name, body);
+        let valid = serde_json::json!({
+            "justification": "the build needs more memory",
+            "memory": 8_000_000_000u64,
+        });
+
+        {
+            let response = env
+                .web_app()
+                .await
+                .oneshot(
+                    Request::builder()
+                        .uri("/crate/foo/limit-requests")
+                        .method("POST")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(valid.to_string()))
+                        .unwrap(),
+                )
+                .await?;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(request_with_token("invalid-token", "foo", valid.clone()))
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(request("bar", valid.clone()))
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(request_with_token("someone-token", "foo", valid.clone()))
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let json: serde_json::Value = response.json().await?;
+        assert_eq!(json["message"], "someone is not an owner of foo");
+
+        for (body, message) in [
+            (
+                serde_json::json!({
+                    "justification": "the build needs more memory",
+                }),
+                "the request doesn't contain any limits",
+            ),
+            (
+                serde_json::json!({
+                    "justification": " ",
+                    "targets": 10,
+                }),
+                "the request needs a justification",
+            ),
+        ] {
+            let response = env.web_app().await.oneshot(request("foo", body)).await?;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let json: serde_json::Value = response.json().await?;
+            assert_eq!(json["message"], message);
+        }
+
+        let response = env
+            .web_app()
+            .await
+            .oneshot(request("foo", valid.clone()))
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let json: serde_json::Value = response.json().await?;
+        let id = json["id"].as_i64().unwrap() as i32;
+
+        let mut conn = env.async_db().async_conn().await;
+        let stored = LimitRequest::get(&mut conn, id).await?.unwrap();
+        assert_eq!(stored.crate_name, "foo");
+        assert_eq!(stored.requested_by, "owner");
+        assert_eq!(stored.justification, "the build needs more memory");
+        assert_eq!(
+            stored.limits,
+            Overrides {
+                memory: Some(8_000_000_000),
+                ..Overrides::default()
+            }
+        );
+        assert_eq!(stored.status, LimitRequestStatus::Pending);
+
+        // only one pending request per crate
+        let response = env.web_app().await.oneshot(request("foo", valid)).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let json: serde_json::Value = response.json().await?;
+        assert_eq!(json["message"], "foo already has a pending limit request");
+
+        Ok(())
+    }
+
     #[test]
     fn build_empty_list() {
         async_wrapper(|env| async move {
@@ -0,0 +1,259 @@
+use crate::{
+    AsyncStorage, Config,
+    docbuilder::{CoverageReport, UndocumentedItem},
+    impl_axum_webpage,
+    storage::coverage_report_path,
+    web::{
+        MetaData, ReqVersion,
+        cache::CachePolicy,
+        error::{AxumNope, AxumResult},
+        escaped_uri::EscapedURI,
+        extractors::{
+            DbConnection,
+            rustdoc::{PageKind, RustdocParams},
+        },
+        file::File,
+        filters,
+        headers::CanonicalUrl,
+        match_version,
+        page::templates::{RenderBrands, RenderRegular, RenderSolid},
+    },
+};
+use anyhow::Context as _;
+use askama::Template;
+use axum::{extract::Extension, response::IntoResponse};
+use std::sync::Arc;
+
+#[derive(Debug, Clone)]
+struct CoverageItem {
+    item: UndocumentedItem,
+    /// the item in the rustdoc pages of the release.
+    url: EscapedURI,
+    /// the definition of the item in the source browser.
+    source_url: Option<EscapedURI>,
+}
+
+impl CoverageItem {
+    fn new(params: &RustdocParams, item: UndocumentedItem) -> Self {
+        let (page, anchor) = match item.page.split_once('#') {
+            Some((page, anchor)) => (page, Some(anchor)),
+            None => (item.page.as_str(), None),
+        };
+        // the route suffix is `coverage`, which would end up in the rustdoc path.
+        let mut url = params
+            .clone()
+            .with_maybe_static_route_suffix(None::<String>)
+            .with_page_kind(PageKind::Rustdoc)
+            .with_inner_path(page)
+            .rustdoc_url();
+        if let Some(anchor) = anchor {
+            url = url.with_fragment(anchor);
+        }
+
+        // files outside of the package, like generated code, can't be linked.
+        let source_url = item
+            .file
+            .as_deref()
+            .filter(|file| !file.starts_with('/'))
+            .map(|file| {
+                let url = params
+                    .clone()
+                    .with_page_kind(PageKind::Source)
+                    .with_inner_path(file)
+                    .source_url();
+                match item.line {
+                    Some(line) => url.with_fragment(line.to_string()),
+                    None => url,
+                }
+            });
+
+        Self {
+            item,
+            url,
+            source_url,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "crate/coverage.html")]
+#[derive(Debug, Clone)]
+struct CoveragePage {
+    metadata: MetaData,
+    /// `None` for releases that were built before docs.rs generated coverage reports,
+    /// or when the rustdoc JSON build failed.
+    total_items: Option<usize>,
+    undocumented: Vec<CoverageItem>,
+    canonical_url: CanonicalUrl,
+    is_latest_url: bool,
+    params: RustdocParams,
+}
+
+impl_axum_webpage! {
+    CoveragePage,
+    cache_policy = |page| if page.is_latest_url {
+        CachePolicy::ForeverInCdn
+    } else {
+        CachePolicy::ForeverInCdnAndStaleInBrowser
+    },
+}
+
+impl CoveragePage {
+    pub(crate) fn use_direct_platform_links(&self) -> bool {
+        true
+    }
+}
+
+pub(crate) async fn coverage_handler(
+    params: RustdocParams,
+    mut conn: DbConnection,
+    Extension(config): Extension<Arc<Config>>,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+) -> AxumResult<impl IntoResponse> {
+    let matched_release = match_version(&mut conn, params.name(), params.req_version())
+        .await?
+        .assume_exact_name()?
+        .into_canonical_req_version_or_else(|version| {
+            AxumNope::Redirect(
+                params.clone().with_req_version(version).coverage_url(),
+                CachePolicy::ForeverInCdn,
+            )
+        })?;
+    let params = params.apply_matched_release(&matched_release);
+    let version = matched_release.into_version();
+
+    let metadata = MetaData::from_crate(
+        &mut conn,
+        params.name(),
+        &version,
+        Some(params.req_version().clone()),
+    )
+    .await?;
+
+    let path = coverage_report_path(params.name(), &version);
+    let report: Option<CoverageReport> = if storage.exists(&path).await? {
+        let file = File::from_path(&storage, &path, &config).await?;
+        Some(serde_json::from_slice(&file.0.content).context("invalid coverage report")?)
+    } else {
+        None
+    };
+
+    let (total_items, undocumented) = match report {
+        Some(report) => (
+            Some(report.total_items),
+            report
+                .undocumented
+                .into_iter()
+                .map(|item| CoverageItem::new(&params, item))
+                .collect(),
+        ),
+        None => (None, Vec::new()),
+    };
+
+    Ok(CoveragePage {
+        metadata,
+        total_items,
+        undocumented,
+        is_latest_url: params.req_version().is_latest(),
+        canonical_url: CanonicalUrl::from_uri(
+            params
+                .clone()
+                .with_req_version(ReqVersion::Latest)
+                .coverage_url(),
+        ),
+        params,
+    }
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{AxumResponseTestExt as _, AxumRouterTestExt, async_wrapper};
+    use kuchikiki::traits::TendrilSink;
+
+    #[test]
+    fn coverage_report() {
+        async_wrapper(|env| async move {
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.1.0")
+                .create()
+                .await?;
+
+            let report = CoverageReport {
+                total_items: 10,
+                undocumented: vec![
+                    UndocumentedItem {
+                        path: "foo::Bar".into(),
+                        kind: "struct".into(),
+                        page: "foo/struct.Bar.html".into(),
+                        file: Some("src/lib.rs".into()),
+                        line: Some(12),
+                    },
+                    UndocumentedItem {
+                        path: "foo::Bar::new".into(),
+                        kind: "method".into(),
+                        page: "foo/struct.Bar.html#method.new".into(),
+                        file: Some("/rustc/library/core/src/macros.rs".into()),
+                        line: Some(1),
+                    },
+                ],
+            };
+            env.async_storage()
+                .store_one(
+                    coverage_report_path("foo", &"0.1.0".parse()?),
+                    serde_json::to_vec(&report)?,
+                )
+                .await?;
+
+            let web = env.web_app().await;
+            let page = kuchikiki::parse_html().one(
+                web.get("/crate/foo/0.1.0/coverage")
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await?,
+            );
+
+            let rows: Vec<Vec<String>> = page
+                .select("#undocumented-items tbody tr")
+                .expect("invalid selector")
+                .map(|row| {
+                    row.as_node()
+                        .select("a")
+                        .expect("invalid selector")
+                        .map(|link| link.attributes.borrow().get("href").unwrap().to_owned())
+                        .collect()
+                })
+                .collect();
+            assert_eq!(
+                rows,
+                vec![
+                    vec![
+                        "/foo/0.1.0/foo/struct.Bar.html".to_owned(),
+                        "/crate/foo/0.1.0/source/src/lib.rs#12".to_owned(),
+                    ],
+                    vec!["/foo/0.1.0/foo/struct.Bar.html#method.new".to_owned()],
+                ]
+            );
+
+            // releases without a report still get a page.
+            env.fake_release()
+                .await
+                .name("foo")
+                .version("0.2.0")
+                .create()
+                .await?;
+            let body = web
+                .assert_success("/crate/foo/latest/coverage")
+                .await?
+                .text()
+                .await?;
+            assert!(body.contains("No documentation coverage report is available"));
+
+            Ok(())
+        })
+    }
+}
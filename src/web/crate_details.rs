@@ -171,6 +171,14 @@ impl CrateDetails {
                 -- in the metadata.
                 -- So we're only interested in successful builds here.
                 builds.rustc_version as "rustc_version?",
+                ARRAY(
+                    SELECT target
+                    FROM build_targets
+                    WHERE
+                        build_targets.build_id = builds.id AND
+                        build_targets.build_status = 'failure'
+                    ORDER BY target
+                ) as "failed_targets!",
                 doc_coverage.total_items,
                 doc_coverage.documented_items,
                 doc_coverage.total_items_needing_examples,
@@ -216,6 +224,8 @@ impl CrateDetails {
             target_name: krate.target_name.clone(),
             default_target: krate.default_target,
             doc_targets: krate.doc_targets.map(MetaData::parse_doc_targets),
+            docs_build_id: krate.latest_build_id,
+            failed_targets: krate.failed_targets,
             yanked: krate.yanked,
             rustdoc_css_file: krate
                 .rustc_version
@@ -621,6 +631,8 @@ pub(crate) async fn get_all_releases(
 struct PlatformList {
     use_direct_platform_links: bool,
     current_target: String,
+    docs_build_id: Option<BuildId>,
+    failed_targets: Vec<String>,
     params: RustdocParams,
 }
 
@@ -669,6 +681,8 @@ pub(crate) async fn get_all_platforms_inner(
         return Ok(PlatformList {
             use_direct_platform_links: is_crate_root,
             current_target: "".into(),
+            docs_build_id: None,
+            failed_targets: Vec::new(),
             params,
         }
         .into_response());
@@ -686,9 +700,35 @@ pub(crate) async fn get_all_platforms_inner(
         String::new()
     };
 
+    let docs_build = sqlx::query!(
+        r#"SELECT
+            builds.id as "id: BuildId",
+            ARRAY(
+                SELECT target
+                FROM build_targets
+                WHERE
+                    build_targets.build_id = builds.id AND
+                    build_targets.build_status = 'failure'
+                ORDER BY target
+            ) as "failed_targets!"
+         FROM builds
+         WHERE
+            builds.rid = $1 AND
+            builds.build_status = 'success'
+         ORDER BY builds.build_finished DESC
+         LIMIT 1"#,
+        matched_release.release.id.0,
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+
     Ok(PlatformList {
         use_direct_platform_links: is_crate_root,
         current_target,
+        docs_build_id: docs_build.as_ref().map(|build| build.id),
+        failed_targets: docs_build
+            .map(|build| build.failed_targets)
+            .unwrap_or_default(),
         params,
     }
     .into_response())
@@ -715,7 +755,11 @@ mod tests {
         AxumResponseTestExt, AxumRouterTestExt, FakeBuild, TestDatabase, TestEnvironment,
         async_wrapper, fake_release_that_failed_before_build,
     };
-    use crate::{db::update_build_status, registry_api::CrateOwner};
+    use crate::{
+        db::{record_build_target, update_build_status},
+        docbuilder::ResourceUsage,
+        registry_api::CrateOwner,
+    };
     use anyhow::Error;
     use kuchikiki::traits::TendrilSink;
     use pretty_assertions::assert_eq;
@@ -2024,6 +2068,75 @@ mod tests {
         check_count(crate::DEFAULT_MAX_TARGETS, 0);
     }
 
+    #[test]
+    fn failed_targets_in_platform_menu() {
+        async fn failed_links(env: &TestEnvironment, url: &str, selector: &str) -> Vec<String> {
+            let response = env.web_app().await.get(url).await.unwrap();
+            assert!(response.status().is_success());
+            kuchikiki::parse_html()
+                .one(response.text().await.unwrap())
+                .select(selector)
+                .expect("invalid selector")
+                .map(|el| {
+                    assert_eq!(el.text_contents(), "i686-pc-windows-msvc");
+                    el.attributes.borrow().get("href").unwrap().to_string()
+                })
+                .collect()
+        }
+
+        async_wrapper(|env| async move {
+            let release_id = env
+                .fake_release()
+                .await
+                .name("dummy")
+                .version("0.4.0")
+                .rustdoc_file("dummy/index.html")
+                .default_target("x86_64-unknown-linux-gnu")
+                .create()
+                .await?;
+
+            let mut conn = env.async_db().async_conn().await;
+            let build_id = sqlx::query_scalar!(
+                r#"SELECT id as "id: BuildId" FROM builds WHERE rid = $1"#,
+                release_id.0
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            for (target, status) in [
+                ("x86_64-unknown-linux-gnu", BuildStatus::Success),
+                ("i686-pc-windows-msvc", BuildStatus::Failure),
+            ] {
+                record_build_target(
+                    &mut conn,
+                    build_id,
+                    target,
+                    status,
+                    None,
+                    chrono::Utc::now(),
+                    None,
+                    &ResourceUsage::default(),
+                )
+                .await?;
+            }
+
+            let log_url = format!("/crate/dummy/0.4.0/builds/{build_id}/i686-pc-windows-msvc.txt");
+            assert_eq!(
+                failed_links(&env, "/crate/dummy/0.4.0", "#platforms li a.failed").await,
+                vec![log_url.clone()]
+            );
+            assert_eq!(
+                failed_links(&env, "/dummy/0.4.0/dummy/", "#platforms li a.failed").await,
+                vec![log_url.clone()]
+            );
+            assert_eq!(
+                failed_links(&env, "/crate/dummy/0.4.0/menus/platforms/", "li a.failed").await,
+                vec![log_url]
+            );
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn latest_url() {
         async_wrapper(|env| async move {
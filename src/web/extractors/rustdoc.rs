@@ -92,6 +92,7 @@ impl std::fmt::Debug for RustdocParams {
                 &self.build_details_url(BuildId(42), Some("log.txt")),
             )
             .field("features_url()", &self.features_url())
+            .field("coverage_url()", &self.coverage_url())
             .field("source_url()", &self.source_url())
             .field("target_redirect_url()", &self.target_redirect_url())
             .field("storage_path()", &self.storage_path())
@@ -571,6 +572,18 @@ impl RustdocParams {
         EscapedURI::from_path(path)
     }
 
+    /// The build log of one target of a build.
+    pub(crate) fn target_build_log_url(&self, id: &BuildId, target: &str) -> EscapedURI {
+        self.build_details_url(*id, Some(&format!("{target}.txt")))
+    }
+
+    pub(crate) fn build_log_live_url(&self, id: BuildId, filename: &str) -> EscapedURI {
+        EscapedURI::from_path(format!(
+            "/crate/{}/{}/builds/{}/{}/live",
+            self.name, self.req_version, id, filename
+        ))
+    }
+
     pub(crate) fn features_url(&self) -> EscapedURI {
         EscapedURI::from_path(format!(
             "/crate/{}/{}/features",
@@ -578,6 +591,13 @@ impl RustdocParams {
         ))
     }
 
+    pub(crate) fn coverage_url(&self) -> EscapedURI {
+        EscapedURI::from_path(format!(
+            "/crate/{}/{}/coverage",
+            self.name, self.req_version
+        ))
+    }
+
     pub(crate) fn source_url(&self) -> EscapedURI {
         // if the params were created for a rustdoc page,
         // the inner path is a source file path, so is not usable for
@@ -0,0 +1,146 @@
+use crate::{
+    utils::{ConfigName, get_config},
+    web::{
+        error::{AxumNope, JsonAxumNope, JsonAxumResult},
+        extractors::DbConnection,
+    },
+};
+use anyhow::anyhow;
+use axum::{Json, response::IntoResponse};
+use docsrs_metadata::{Diagnostic, Metadata};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct ValidationDiagnostic {
+    #[serde(flatten)]
+    diagnostic: Diagnostic,
+    severity: &'static str,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ValidationResponse {
+    /// `false` when there is at least one error, warnings don't make the metadata invalid.
+    valid: bool,
+    diagnostics: Vec<ValidationDiagnostic>,
+}
+
+/// Check the `[package.metadata.docs.rs]` table of a `Cargo.toml` in the request body,
+/// so crate authors can find problems before they publish.
+pub(crate) async fn validate_metadata_handler(
+    mut conn: DbConnection,
+    manifest: String,
+) -> JsonAxumResult<impl IntoResponse> {
+    // the build servers record the targets when they update their toolchain,
+    // without them we can't check the targets.
+    let available_targets: Option<Vec<String>> =
+        get_config(&mut conn, ConfigName::AvailableTargets)
+            .await
+            .map_err(|e| JsonAxumNope(e.into()))?;
+
+    let diagnostics = Metadata::validate(&manifest, available_targets.as_deref())
+        .map_err(|err| JsonAxumNope(AxumNope::BadRequest(anyhow!("invalid Cargo.toml: {err}"))))?;
+
+    Ok(Json(ValidationResponse {
+        valid: !diagnostics.iter().any(Diagnostic::is_error),
+        diagnostics: diagnostics
+            .into_iter()
+            .map(|diagnostic| ValidationDiagnostic {
+                severity: if diagnostic.is_error() {
+                    "error"
+                } else {
+                    "warning"
+                },
+                message: diagnostic.to_string(),
+                diagnostic,
+            })
+            .collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test::{AxumResponseTestExt as _, async_wrapper},
+        utils::{ConfigName, set_config},
+    };
+    use axum::{body::Body, http::Request};
+    use reqwest::StatusCode;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+
+    #[test]
+    fn validate_metadata() {
+        async_wrapper(|env| async move {
+            let web = env.web_app().await;
+            let validate = |manifest: &'static str| {
+                let web = web.clone();
+                async move {
+                    web.oneshot(
+                        Request::builder()
+                            .uri("/-/metadata/validate")
+                            .method("POST")
+                            .body(Body::from(manifest))
+                            .unwrap(),
+                    )
+                    .await
+                }
+            };
+
+            let manifest = r#"
+                [package]
+                name = "foo"
+
+                [package.metadata.docs.rs]
+                all-features = true
+                features = ["a"]
+                targets = ["x86_64-unknown-linux-gnu", "x86_64-unknown-linux-gnux"]
+            "#;
+
+            // without the targets of the toolchain only the other problems are found.
+            let response = validate(manifest).await?;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.json::<Value>().await?,
+                json!({
+                    "valid": true,
+                    "diagnostics": [{
+                        "kind": "conflicting-features",
+                        "severity": "warning",
+                        "message": "`features` has no effect, because `all-features` enables all features",
+                    }],
+                })
+            );
+
+            let mut conn = env.async_db().async_conn().await;
+            set_config(
+                &mut conn,
+                ConfigName::AvailableTargets,
+                ["x86_64-unknown-linux-gnu"],
+            )
+            .await?;
+
+            let response = validate(manifest).await?;
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = response.json::<Value>().await?;
+            assert_eq!(response["valid"], false);
+            assert_eq!(
+                response["diagnostics"][0],
+                json!({
+                    "kind": "unknown-target",
+                    "key": "targets",
+                    "target": "x86_64-unknown-linux-gnux",
+                    "severity": "error",
+                    "message": "`targets` contains `x86_64-unknown-linux-gnux`, which docs.rs can't build",
+                })
+            );
+
+            assert_eq!(
+                validate("[package").await?.status(),
+                StatusCode::BAD_REQUEST
+            );
+
+            Ok(())
+        })
+    }
+}
@@ -4,7 +4,7 @@ pub mod page;
 
 use crate::{
     db::{
-        CrateId,
+        BuildId, CrateId,
         types::{BuildStatus, version::Version},
     },
     utils::{get_correct_docsrs_style_file, report_error},
@@ -22,6 +22,7 @@ use tracing::{info, instrument};
 mod build_details;
 mod builds;
 pub(crate) mod cache;
+mod coverage;
 pub(crate) mod crate_details;
 mod csp;
 pub(crate) mod error;
@@ -33,6 +34,7 @@ pub(crate) mod headers;
 mod highlight;
 mod licenses;
 mod markdown;
+mod metadata;
 pub(crate) mod metrics;
 mod releases;
 mod routes;
@@ -674,6 +676,11 @@ pub(crate) struct MetaData {
     pub(crate) rustdoc_status: Option<bool>,
     pub(crate) default_target: Option<String>,
     pub(crate) doc_targets: Option<Vec<String>>,
+    /// The build that generated the documentation.
+    pub(crate) docs_build_id: Option<BuildId>,
+    /// Targets that failed in the build that generated the documentation,
+    /// they are not part of `doc_targets`.
+    pub(crate) failed_targets: Vec<String>,
     pub(crate) yanked: Option<bool>,
     /// CSS file to use depending on the rustdoc version used to generate this version of this
     /// crate.
@@ -698,7 +705,16 @@ impl MetaData {
                 releases.default_target,
                 releases.doc_targets,
                 releases.yanked,
-                builds.rustc_version as "rustc_version?"
+                builds.rustc_version as "rustc_version?",
+                docs_build.id as "docs_build_id?: BuildId",
+                ARRAY(
+                    SELECT target
+                    FROM build_targets
+                    WHERE
+                        build_targets.build_id = docs_build.id AND
+                        build_targets.build_status = 'failure'
+                    ORDER BY target
+                ) as "failed_targets!"
             FROM releases
             INNER JOIN crates ON crates.id = releases.crate_id
             LEFT JOIN LATERAL (
@@ -707,6 +723,14 @@ impl MetaData {
                 ORDER BY builds.build_finished
                 DESC LIMIT 1
             ) AS builds ON true
+            LEFT JOIN LATERAL (
+                SELECT id FROM builds
+                WHERE
+                    builds.rid = releases.id AND
+                    builds.build_status = 'success'
+                ORDER BY builds.build_finished
+                DESC LIMIT 1
+            ) AS docs_build ON true
             WHERE crates.name = $1 AND releases.version = $2"#,
             name,
             version.to_string(),
@@ -724,6 +748,8 @@ impl MetaData {
             rustdoc_status: row.rustdoc_status,
             default_target: row.default_target,
             doc_targets: row.doc_targets.map(MetaData::parse_doc_targets),
+            docs_build_id: row.docs_build_id,
+            failed_targets: row.failed_targets,
             yanked: row.yanked,
             rustdoc_css_file: row
                 .rustc_version
@@ -1191,6 +1217,8 @@ mod test {
                 "x86_64-unknown-linux-gnu".to_string(),
                 "arm64-unknown-linux-gnu".to_string(),
             ]),
+            docs_build_id: None,
+            failed_targets: Vec::new(),
             yanked: Some(false),
             rustdoc_css_file: Some("rustdoc.css".to_string()),
         };
@@ -1207,6 +1235,8 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "docs_build_id": null,
+            "failed_targets": [],
             "yanked": false,
             "rustdoc_css_file": "rustdoc.css",
         });
@@ -1226,6 +1256,8 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "docs_build_id": null,
+            "failed_targets": [],
             "yanked": false,
             "rustdoc_css_file": "rustdoc.css",
         });
@@ -1245,6 +1277,8 @@ mod test {
                 "x86_64-unknown-linux-gnu",
                 "arm64-unknown-linux-gnu",
             ],
+            "docs_build_id": null,
+            "failed_targets": [],
             "yanked": false,
             "rustdoc_css_file": "rustdoc.css",
         });
@@ -1263,9 +1297,11 @@ mod test {
                 &"0.1.0".parse().unwrap(),
                 Some(ReqVersion::Latest),
             )
-            .await;
+            .await
+            .unwrap();
+            assert!(metadata.docs_build_id.is_some());
             assert_eq!(
-                metadata.unwrap(),
+                metadata,
                 MetaData {
                     name: "foo".to_string(),
                     version: "0.1.0".parse().unwrap(),
@@ -1275,6 +1311,8 @@ mod test {
                     rustdoc_status: Some(true),
                     default_target: Some("x86_64-unknown-linux-gnu".to_string()),
                     doc_targets: Some(vec!["x86_64-unknown-linux-gnu".to_string()]),
+                    docs_build_id: metadata.docs_build_id,
+                    failed_targets: Vec::new(),
                     yanked: Some(false),
                     rustdoc_css_file: Some("rustdoc.css".to_string()),
                 },
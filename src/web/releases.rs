@@ -2,7 +2,7 @@
 
 use crate::{
     AsyncBuildQueue, Config, InstanceMetrics, RegistryApi,
-    build_queue::{QueuedCrate, REBUILD_PRIORITY},
+    build_queue::{BuildWorker, QueueWaitReason, QueuedCrate, REBUILD_PRIORITY},
     cdn,
     db::types::version::Version,
     impl_axum_webpage,
@@ -716,10 +716,11 @@ pub(crate) async fn activity_handler(mut conn: DbConnection) -> AxumResult<impl
 #[derive(Debug, Clone, PartialEq, Serialize)]
 struct BuildQueuePage {
     description: &'static str,
-    queue: Vec<QueuedCrate>,
+    queue: Vec<(QueuedCrate, Option<QueueWaitReason>)>,
     rebuild_queue: Vec<QueuedCrate>,
     active_cdn_deployments: Vec<String>,
     in_progress_builds: Vec<(String, Version)>,
+    workers: Vec<BuildWorker>,
     expand_rebuild_queue: bool,
 }
 
@@ -792,12 +793,22 @@ pub(crate) async fn build_queue_handler(
         }
     });
 
+    let mut wait_reasons = build_queue.wait_reasons().await?;
+    let queue = queue
+        .into_iter()
+        .map(|krate| {
+            let reason = wait_reasons.remove(&(krate.name.clone(), krate.version.clone()));
+            (krate, reason)
+        })
+        .collect();
+
     Ok(BuildQueuePage {
         description: "crate documentation scheduled to build & deploy",
         queue,
         rebuild_queue,
         active_cdn_deployments,
         in_progress_builds,
+        workers: build_queue.workers().await?,
         expand_rebuild_queue: params.expand.is_some(),
     })
 }
@@ -838,6 +849,7 @@ mod tests {
                 BuildStatus::Success,
                 None,
                 None,
+                None,
             )
             .await?;
 
@@ -1958,6 +1970,81 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_releases_queue_build_workers() {
+        async_wrapper(|env| async move {
+            let web = env.web_app().await;
+
+            let full = kuchikiki::parse_html().one(web.get("/releases/queue").await?.text().await?);
+            assert!(
+                full.select(".worker-list > strong")
+                    .expect("missing heading")
+                    .any(|el| el.text_contents().contains("no active build workers"))
+            );
+
+            let queue = env.async_build_queue();
+            queue.add_crate("foo", &V1, 0, None).await?;
+            queue.claim_next_crate("worker-a").await?;
+            queue.worker_heartbeat("worker-b").await?;
+
+            let full = kuchikiki::parse_html().one(web.get("/releases/queue").await?.text().await?);
+            let workers: Vec<_> = full
+                .select(".worker-list > li")
+                .expect("missing worker list items")
+                .map(|node| node.text_contents())
+                .collect();
+            assert_eq!(workers.len(), 2);
+            assert!(workers[0].contains("worker-a"));
+            assert!(workers[0].contains(&format!("building foo {V1}")));
+            assert!(workers[1].contains("worker-b"));
+            assert!(workers[1].contains("idle"));
+
+            Ok(())
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_releases_queue_wait_reasons() -> Result<(), Error> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .build_queue_fair_scheduling(true)
+                .build()?,
+        )
+        .await?;
+
+        let queue = env.async_build_queue();
+        for name in ["foo", "bar"] {
+            env.fake_release()
+                .await
+                .name(name)
+                .version(V1)
+                .add_owner(CrateOwner {
+                    login: "alice".into(),
+                    avatar: "https://example.org/alice".into(),
+                    kind: OwnerKind::User,
+                })
+                .create()
+                .await?;
+            queue.add_crate(name, &V2, 0, None).await?;
+        }
+
+        let web = env.web_app().await;
+        let full = kuchikiki::parse_html().one(web.get("/releases/queue").await?.text().await?);
+        let items: Vec<_> = full
+            .select(".queue-list > li")
+            .expect("missing list items")
+            .map(|node| node.text_contents())
+            .collect();
+
+        assert_eq!(items.len(), 2);
+        assert!(items[0].contains("foo"));
+        assert!(!items[0].contains("waiting"));
+        assert!(items[1].contains("bar"));
+        assert!(items[1].contains("waiting for its turn after 1 other release of alice"));
+
+        Ok(())
+    }
+
     #[test]
     fn test_releases_rebuild_queue_empty() {
         async_wrapper(|env| async move {
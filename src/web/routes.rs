@@ -233,6 +233,18 @@ pub(super) fn build_axum_routes() -> AxumRouter {
             "/crate/{name}/{version}/rebuild",
             post_internal(super::builds::build_trigger_rebuild_handler),
         )
+        .route(
+            "/crate/{name}/{version}/cancel",
+            post_internal(super::builds::build_cancel_handler),
+        )
+        .route(
+            "/crate/{name}/limit-requests",
+            post_internal(super::builds::limit_request_handler),
+        )
+        .route(
+            "/-/metadata/validate",
+            post_internal(super::metadata::validate_metadata_handler),
+        )
         .route(
             "/crate/{name}/{version}/status.json",
             get_internal(super::status::status_handler),
@@ -245,10 +257,18 @@ pub(super) fn build_axum_routes() -> AxumRouter {
             "/crate/{name}/{version}/builds/{id}/{filename}",
             get_internal(super::build_details::build_details_handler),
         )
+        .route(
+            "/crate/{name}/{version}/builds/{id}/{filename}/live",
+            get_internal(super::build_details::build_log_live_handler),
+        )
         .route_with_tsr(
             "/crate/{name}/{version}/features",
             get_internal(super::features::build_features_handler),
         )
+        .route_with_tsr(
+            "/crate/{name}/{version}/coverage",
+            get_internal(super::coverage::coverage_handler),
+        )
         .route_with_tsr(
             "/crate/{name}/{version}/source/",
             get_internal(super::source::source_browser_handler),
@@ -874,7 +874,11 @@ pub(crate) async fn download_handler(
         .assume_exact_name()?
         .into_version();
 
-    let archive_path = rustdoc_archive_path(&name, &version);
+    // files stored as content blobs are missing from the archive itself, they are
+    // downloaded from the full archive stored next to it.
+    let archive_path = storage
+        .full_archive_path(&rustdoc_archive_path(&name, &version))
+        .await?;
 
     // not all archives are set for public access yet, so we check if
     // the access is set and fix it if needed.
@@ -900,7 +904,8 @@ pub(crate) async fn download_handler(
     Ok(super::axum_cached_redirect(
         format!("{}/{}", config.s3_static_root_path, archive_path),
         CachePolicy::ForeverInCdn,
-    )?)
+    )?
+    .into_response())
 }
 
 /// Serves shared resources used by rustdoc-generated documentation.
@@ -2930,6 +2935,51 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn download_with_content_blobs() -> Result<()> {
+        let env = TestEnvironment::with_config(
+            TestEnvironment::base_config()
+                .s3_static_root_path("https://static.docs.rs")
+                .content_addressed_min_size(Some(1))
+                .build()?,
+        )
+        .await?;
+
+        env.fake_release()
+            .await
+            .name("dummy")
+            .version("0.1.0")
+            .archive_storage(true)
+            .rustdoc_file_with("dummy/index.html", b"the index")
+            .create()
+            .await?;
+
+        let web = env.web_app().await;
+        web.assert_redirect_cached_unchecked(
+            "/crate/dummy/0.1.0/download",
+            "https://static.docs.rs/rustdoc/dummy/0.1.0.zip.full.zip",
+            CachePolicy::ForeverInCdn,
+            env.config(),
+        )
+        .await?;
+
+        let storage = env.async_storage();
+        assert!(
+            storage
+                .get_public_access("rustdoc/dummy/0.1.0.zip.full.zip")
+                .await?
+        );
+        let content = storage
+            .get("rustdoc/dummy/0.1.0.zip.full.zip", usize::MAX)
+            .await?
+            .content;
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(content))?;
+        let mut index = String::new();
+        std::io::Read::read_to_string(&mut zip.by_name("dummy/index.html")?, &mut index)?;
+        assert_eq!(index, "the index");
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn download_latest_version() -> Result<()> {
         let env = TestEnvironment::with_config(
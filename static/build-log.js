@@ -0,0 +1,20 @@
+(function() {
+    // Follows the log of a running build, see `build_log_live_handler`.
+    const log = document.getElementById("live-build-log");
+    if (!log) {
+        return;
+    }
+
+    const events = new EventSource(log.dataset.liveUrl);
+    events.addEventListener("reset", ev => {
+        log.textContent = ev.data;
+    });
+    events.addEventListener("log", ev => {
+        log.textContent += ev.data;
+    });
+    events.addEventListener("finished", () => {
+        events.close();
+        // show the build details of the finished build.
+        window.location.reload();
+    });
+})();